@@ -167,6 +167,8 @@ export type HogFunctionQueueParametersFetchRequest = {
     return_queue: string
     max_tries?: number
     headers?: Record<string, string>
+    max_response_bytes?: number
+    truncate_response?: boolean
 }
 
 export type CyclotronFetchFailureKind =
@@ -185,6 +187,10 @@ export type CyclotronFetchFailureInfo = {
     headers?: Record<string, string>
     status?: number
     timestamp: DateTime
+    retry_after?: DateTime // When the destination asked for the request to be retried, via Retry-After
+    /** As on the response, for the failure's body */
+    binary?: boolean
+    truncated?: boolean
 }
 
 export type HogFunctionQueueParametersFetchResponse = {
@@ -194,6 +200,10 @@ export type HogFunctionQueueParametersFetchResponse = {
     response?: {
         status: number
         headers: Record<string, string>
+        /** The body isn't text, going by its content type, so is passed on base64 encoded */
+        binary?: boolean
+        /** The body was cut off at the max response size */
+        truncated?: boolean
     } | null
     /** On failure, the fetch worker returns a list of info about the attempts made*/
     trace?: CyclotronFetchFailureInfo[]
@@ -23,6 +23,7 @@ import {
     HogFunctionInvocationQueueParameters,
     HogFunctionInvocationSerialized,
     HogFunctionLogEntrySerialized,
+    HogFunctionQueueParametersFetchResponse,
     HogFunctionType,
 } from './types'
 
@@ -394,7 +395,10 @@ export function cyclotronJobToInvocation(job: CyclotronJob, hogFunction: HogFunc
     if (job.blob && params) {
         // Deserialize the blob into the params
         try {
-            params.body = job.blob ? Buffer.from(job.blob).toString('utf-8') : undefined
+            // Failures carry the body of their last attempt
+            const { response, trace } = params as HogFunctionQueueParametersFetchResponse
+            const encoding = (response ? response.binary : trace?.at(-1)?.binary) ? 'base64' : 'utf-8'
+            params.body = job.blob ? Buffer.from(job.blob).toString(encoding) : undefined
         } catch (e) {
             status.error('Error parsing blob', e, job.blob)
             captureException(e)
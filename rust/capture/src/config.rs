@@ -73,6 +73,24 @@ pub struct Config {
 
     #[envconfig(default = "ALL")]
     pub healthcheck_strategy: HealthStrategy,
+
+    // Forwarding headers are only read from these peers, defaults to trusting no one
+    #[envconfig(default = "")]
+    pub trusted_proxies: String,
+    #[envconfig(default = "x-forwarded-for")]
+    pub client_ip_headers: String,
+
+    // Server-side enrichment, usually left to the plugin-server
+    #[envconfig(default = "false")]
+    pub enrich_geoip: bool,
+    pub maxmind_db_path: Option<String>,
+    #[envconfig(default = "false")]
+    pub enrich_user_agent: bool,
+
+    pub ip_anonymization: Option<String>, // "<token or *>:<full|truncate|drop|hash>;<token>..."
+    pub ip_hash_salt: Option<String>,
+    pub scrub_rules: Option<String>, // "<token or *>:<pattern>,<pattern>;<token>..."
+    pub scrub_custom_patterns: Option<String>, // JSON object of pattern name to regex
 }
 
 #[derive(Envconfig, Clone)]
@@ -100,6 +118,10 @@ pub struct KafkaConfig {
     pub kafka_heatmaps_topic: String,
     #[envconfig(default = "session_recording_snapshot_item_overflow")]
     pub kafka_replay_overflow_topic: String,
+    #[envconfig(default = "otel_events_ingestion")]
+    pub kafka_otel_topic: String,
+    #[envconfig(default = "")]
+    pub kafka_partition_strategies: String, // "<data_type>:<default|token|session_id|random>;..."
     #[envconfig(default = "false")]
     pub kafka_tls: bool,
     #[envconfig(default = "")]
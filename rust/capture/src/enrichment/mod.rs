@@ -0,0 +1,198 @@
+//! Optional server-side enrichment, adding the geoip and user-agent properties usually
+//! computed by the plugin-server, for deployments that want to skip that step.
+
+pub mod user_agent;
+
+use std::collections::HashMap;
+
+use common_geoip::GeoIpClient;
+use common_types::RawEvent;
+use metrics::counter;
+use serde_json::Value;
+
+use crate::v0_request::ProcessingContext;
+
+#[derive(Default)]
+pub struct Enricher {
+    geoip: Option<GeoIpClient>,
+    parse_user_agent: bool,
+}
+
+impl Enricher {
+    pub fn new(geoip: Option<GeoIpClient>, parse_user_agent: bool) -> Self {
+        Self {
+            geoip,
+            parse_user_agent,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.geoip.is_some() || self.parse_user_agent
+    }
+
+    /// Adds enrichment properties to the event. Values already sent by the client win,
+    /// and geoip enrichment is skipped for events that set `$geoip_disable`.
+    pub fn enrich(&self, event: &mut RawEvent, context: &ProcessingContext) {
+        if let Some(geoip) = &self.geoip {
+            if !geoip_disabled(&event.properties) {
+                let ip = event
+                    .properties
+                    .get("$ip")
+                    .and_then(Value::as_str)
+                    .unwrap_or(&context.client_ip)
+                    .to_string();
+                let properties = geoip.get_geoip_properties(Some(&ip));
+                if !properties.is_empty() {
+                    counter!("capture_events_enriched_total", "source" => "geoip").increment(1);
+                }
+                add_geoip_properties(event, properties);
+            }
+        }
+
+        if self.parse_user_agent {
+            let user_agent = event
+                .properties
+                .get("$raw_user_agent")
+                .and_then(Value::as_str)
+                .map(String::from)
+                .or_else(|| context.user_agent.clone());
+            if let Some(user_agent) = user_agent {
+                let parsed = user_agent::parse(&user_agent);
+                if parsed.browser.is_some() || parsed.os.is_some() {
+                    counter!("capture_events_enriched_total", "source" => "user_agent")
+                        .increment(1);
+                    let properties = &mut event.properties;
+                    if let Some(browser) = parsed.browser {
+                        set_if_missing(properties, "$browser", browser);
+                    }
+                    if let Some(os) = parsed.os {
+                        set_if_missing(properties, "$os", os);
+                    }
+                    set_if_missing(properties, "$device_type", parsed.device_type);
+                }
+            }
+        }
+    }
+}
+
+fn geoip_disabled(properties: &HashMap<String, Value>) -> bool {
+    matches!(properties.get("$geoip_disable"), Some(Value::Bool(true)))
+}
+
+fn set_if_missing(properties: &mut HashMap<String, Value>, key: &str, value: &str) {
+    properties
+        .entry(key.to_string())
+        .or_insert_with(|| Value::String(value.to_string()));
+}
+
+/// Like the plugin-server GeoIP step, also sets the person properties: current values
+/// through `$set`, first-seen values as `$initial_*` through `$set_once`.
+fn add_geoip_properties(event: &mut RawEvent, properties: HashMap<String, String>) {
+    if properties.is_empty() {
+        return;
+    }
+    let set = event.set.get_or_insert_with(HashMap::new);
+    let set_once = event.set_once.get_or_insert_with(HashMap::new);
+    for (key, value) in properties {
+        let initial_key = key.replacen('$', "$initial_", 1);
+        set.entry(key.clone())
+            .or_insert_with(|| Value::String(value.clone()));
+        set_once
+            .entry(initial_key)
+            .or_insert_with(|| Value::String(value.clone()));
+        event
+            .properties
+            .entry(key)
+            .or_insert_with(|| Value::String(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn context(user_agent: Option<&str>) -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            user_agent: user_agent.map(String::from),
+            sent_at: None,
+            token: "token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "10.0.0.1".to_string(),
+            historical_migration: false,
+        }
+    }
+
+    #[test]
+    fn test_default_is_noop() {
+        let enricher = Enricher::default();
+        assert!(!enricher.is_enabled());
+
+        let mut event = RawEvent {
+            event: "e".to_string(),
+            ..Default::default()
+        };
+        enricher.enrich(&mut event, &context(Some("Mozilla/5.0 (iPhone)")));
+        assert!(event.properties.is_empty());
+    }
+
+    #[test]
+    fn test_user_agent_properties() {
+        let enricher = Enricher::new(None, true);
+        let mut event = RawEvent {
+            event: "e".to_string(),
+            properties: HashMap::from([("$os".to_string(), json!("Custom OS"))]),
+            ..Default::default()
+        };
+        enricher.enrich(
+            &mut event,
+            &context(Some("Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1")),
+        );
+        assert_eq!(event.properties["$browser"], json!("Mobile Safari"));
+        assert_eq!(event.properties["$device_type"], json!("Mobile"));
+        // Client-provided values are kept
+        assert_eq!(event.properties["$os"], json!("Custom OS"));
+    }
+
+    #[test]
+    fn test_raw_user_agent_property_wins() {
+        let enricher = Enricher::new(None, true);
+        let mut event = RawEvent {
+            event: "e".to_string(),
+            properties: HashMap::from([(
+                "$raw_user_agent".to_string(),
+                json!("Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0"),
+            )]),
+            ..Default::default()
+        };
+        enricher.enrich(&mut event, &context(Some("posthog-node/4.0.0")));
+        assert_eq!(event.properties["$browser"], json!("Firefox"));
+    }
+
+    #[test]
+    fn test_geoip_disable() {
+        let properties = HashMap::from([("$geoip_disable".to_string(), json!(true))]);
+        assert!(geoip_disabled(&properties));
+        assert!(!geoip_disabled(&HashMap::new()));
+    }
+
+    #[test]
+    fn test_add_geoip_properties() {
+        let mut event = RawEvent {
+            event: "e".to_string(),
+            ..Default::default()
+        };
+        add_geoip_properties(
+            &mut event,
+            HashMap::from([("$geoip_country_code".to_string(), "FR".to_string())]),
+        );
+        assert_eq!(event.properties["$geoip_country_code"], json!("FR"));
+        assert_eq!(event.set.unwrap()["$geoip_country_code"], json!("FR"));
+        assert_eq!(
+            event.set_once.unwrap()["$initial_geoip_country_code"],
+            json!("FR")
+        );
+    }
+}
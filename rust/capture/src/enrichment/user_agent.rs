@@ -0,0 +1,126 @@
+//! Minimal User-Agent classification, following the same precedence rules as posthog-js
+//! so that server-side values match what the browser SDK would have reported.
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedUserAgent {
+    pub browser: Option<&'static str>,
+    pub os: Option<&'static str>,
+    pub device_type: &'static str,
+}
+
+// Order matters: most UAs advertise several engines (Edge claims Chrome and Safari, etc.)
+const BROWSERS: [(&str, &[&str]); 9] = [
+    ("Microsoft Edge", &["Edg/", "EdgA/", "EdgiOS/", "Edge/"]),
+    ("Opera", &["OPR/", "Opera"]),
+    ("Samsung Internet", &["SamsungBrowser/"]),
+    ("Firefox", &["Firefox/", "FxiOS/"]),
+    ("Chrome iOS", &["CriOS/"]),
+    ("Chrome", &["Chrome/"]),
+    ("Mobile Safari", &["Mobile/"]),
+    ("Safari", &["Safari/"]),
+    ("Internet Explorer", &["MSIE ", "Trident/"]),
+];
+
+const OPERATING_SYSTEMS: [(&str, &[&str]); 6] = [
+    ("Windows", &["Windows"]),
+    ("iOS", &["iPhone", "iPad", "iPod"]),
+    ("Android", &["Android"]),
+    ("Chrome OS", &["CrOS"]),
+    ("Mac OS X", &["Macintosh", "Mac OS X"]),
+    ("Linux", &["Linux"]),
+];
+
+fn first_match(user_agent: &str, table: &[(&'static str, &[&str])]) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(_, needles)| needles.iter().any(|n| user_agent.contains(n)))
+        .map(|(name, _)| *name)
+}
+
+pub fn parse(user_agent: &str) -> ParsedUserAgent {
+    let os = first_match(user_agent, &OPERATING_SYSTEMS);
+    let mut browser = first_match(user_agent, &BROWSERS);
+    if browser == Some("Mobile Safari") && !user_agent.contains("Safari/") {
+        // In-app webviews on iOS carry "Mobile/" without the Safari token
+        browser = None;
+    }
+
+    let device_type = if user_agent.contains("iPad")
+        || user_agent.contains("Tablet")
+        || (user_agent.contains("Android") && !user_agent.contains("Mobile"))
+    {
+        "Tablet"
+    } else if user_agent.contains("Mobi")
+        || user_agent.contains("iPhone")
+        || user_agent.contains("iPod")
+        || user_agent.contains("Android")
+    {
+        "Mobile"
+    } else {
+        "Desktop"
+    };
+
+    ParsedUserAgent {
+        browser,
+        os,
+        device_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_desktop_browsers() {
+        let chrome = parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36");
+        assert_eq!(
+            chrome,
+            ParsedUserAgent {
+                browser: Some("Chrome"),
+                os: Some("Windows"),
+                device_type: "Desktop"
+            }
+        );
+
+        let edge = parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.2210.91");
+        assert_eq!(edge.browser, Some("Microsoft Edge"));
+
+        let safari = parse("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Safari/605.1.15");
+        assert_eq!(safari.browser, Some("Safari"));
+        assert_eq!(safari.os, Some("Mac OS X"));
+
+        let firefox =
+            parse("Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0");
+        assert_eq!(firefox.browser, Some("Firefox"));
+        assert_eq!(firefox.os, Some("Linux"));
+    }
+
+    #[test]
+    fn test_mobile_devices() {
+        let iphone = parse("Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1");
+        assert_eq!(
+            iphone,
+            ParsedUserAgent {
+                browser: Some("Mobile Safari"),
+                os: Some("iOS"),
+                device_type: "Mobile"
+            }
+        );
+
+        let android = parse("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Mobile Safari/537.36");
+        assert_eq!(android.os, Some("Android"));
+        assert_eq!(android.device_type, "Mobile");
+
+        let tablet = parse("Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36");
+        assert_eq!(tablet.device_type, "Tablet");
+    }
+
+    #[test]
+    fn test_unknown_agent() {
+        let parsed = parse("posthog-python/3.0.0");
+        assert_eq!(parsed.browser, None);
+        assert_eq!(parsed.os, None);
+        assert_eq!(parsed.device_type, "Desktop");
+    }
+}
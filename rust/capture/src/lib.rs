@@ -1,6 +1,9 @@
 pub mod api;
 pub mod config;
+pub mod enrichment;
 pub mod limiters;
+pub mod otel;
+pub mod privacy;
 pub mod prometheus;
 pub mod redis;
 pub mod router;
@@ -0,0 +1,594 @@
+//! OTLP/HTTP ingestion of logs and spans, converting each record to a `CapturedEvent`.
+//!
+//! The PostHog project token and distinct_id are read from the resource attributes
+//! (`posthog.token` and `posthog.distinct_id`, falling back to `service.name`), so exporters
+//! only need their resource configured to send to capture directly.
+
+pub mod model;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::{Buf, Bytes};
+use common_client_ip::ClientIp;
+use common_types::{CapturedEvent, RawEvent};
+use flate2::read::GzDecoder;
+use metrics::counter;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use prost::Message;
+use serde_json::{Map, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::api::CaptureError;
+use crate::otel::model::{attributes_to_map, LogsRequest, Resource, Scope, TracesRequest};
+use crate::privacy::Privacy;
+use crate::prometheus::report_dropped_events;
+use crate::router;
+use crate::token::validate_token;
+use crate::utils::uuid_v7;
+use crate::v0_request::{DataType, ProcessedEvent, ProcessedEventMetadata, GZIP_MAGIC_NUMBERS};
+
+const TOKEN_ATTRIBUTE: &str = "posthog.token";
+const DISTINCT_ID_ATTRIBUTE: &str = "posthog.distinct_id";
+const SERVICE_NAME_ATTRIBUTE: &str = "service.name";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    fn from_headers(headers: &HeaderMap) -> Result<Self, CaptureError> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .map_or("", |v| v.to_str().unwrap_or(""));
+        match content_type.split(';').next().unwrap_or("").trim() {
+            "application/x-protobuf" => Ok(Encoding::Protobuf),
+            "application/json" => Ok(Encoding::Json),
+            _ => Err(CaptureError::RequestDecodingError(String::from(
+                "unsupported content type",
+            ))),
+        }
+    }
+
+    /// OTLP expects an Export*ServiceResponse in the request encoding, all fields are optional.
+    fn response(self) -> Response {
+        match self {
+            Encoding::Protobuf => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/x-protobuf")],
+                Bytes::new(),
+            )
+                .into_response(),
+            Encoding::Json => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/json")],
+                "{}",
+            )
+                .into_response(),
+        }
+    }
+}
+
+fn decompress(body: Bytes, limit: usize) -> Result<Bytes, CaptureError> {
+    if !body.starts_with(&GZIP_MAGIC_NUMBERS) {
+        return Ok(body);
+    }
+    let mut buf = Vec::with_capacity(body.len());
+    GzDecoder::new(body.reader())
+        .take(limit as u64 + 1)
+        .read_to_end(&mut buf)
+        .map_err(|e| {
+            tracing::error!("failed to read gzip stream: {}", e);
+            CaptureError::RequestDecodingError(String::from("invalid gzip data"))
+        })?;
+    if buf.len() > limit {
+        report_dropped_events("event_too_big", 1);
+        return Err(CaptureError::EventTooBig);
+    }
+    Ok(buf.into())
+}
+
+fn decoding_error(e: prost::DecodeError) -> CaptureError {
+    tracing::error!("failed to decode protobuf payload: {}", e);
+    CaptureError::RequestDecodingError(String::from("invalid protobuf payload"))
+}
+
+/// All resources of a request must carry the same token, like batches on the v0 endpoints.
+fn extract_token<'a>(
+    resources: impl Iterator<Item = &'a Resource>,
+) -> Result<String, CaptureError> {
+    let mut token: Option<&str> = None;
+    for resource in resources {
+        match (token, resource.get_str(TOKEN_ATTRIBUTE)) {
+            (_, None) => return Err(CaptureError::NoTokenError),
+            (None, found) => token = found,
+            (Some(current), Some(found)) if current != found => {
+                return Err(CaptureError::MultipleTokensError)
+            }
+            _ => {}
+        }
+    }
+    let token = token.ok_or(CaptureError::NoTokenError)?;
+    validate_token(token)?;
+    Ok(token.to_string())
+}
+
+struct OtelContext {
+    token: String,
+    client_ip: String,
+    now: String,
+    privacy: Arc<Privacy>,
+}
+
+impl OtelContext {
+    fn event(
+        &self,
+        resource: &Resource,
+        scope: &Scope,
+        event: &str,
+        timestamp_nanos: u64,
+        mut properties: Map<String, Value>,
+    ) -> Result<ProcessedEvent, CaptureError> {
+        let distinct_id = resource
+            .get_str(DISTINCT_ID_ATTRIBUTE)
+            .or_else(|| resource.get_str(SERVICE_NAME_ATTRIBUTE))
+            .filter(|id| !id.is_empty())
+            .ok_or(CaptureError::MissingDistinctId)?
+            .to_string();
+
+        let resource_attributes = resource
+            .attributes
+            .iter()
+            .filter(|kv| kv.key != TOKEN_ATTRIBUTE)
+            .map(|kv| (kv.key.clone(), kv.value.clone()))
+            .collect();
+        properties.insert(
+            "$otel_resource".to_string(),
+            Value::Object(resource_attributes),
+        );
+        if !scope.name.is_empty() {
+            properties.insert("$otel_scope".to_string(), Value::String(scope.name.clone()));
+        }
+        properties.insert(
+            "$lib".to_string(),
+            Value::String("opentelemetry".to_string()),
+        );
+
+        let mut raw = RawEvent {
+            token: Some(self.token.clone()),
+            distinct_id: Some(Value::String(distinct_id.clone())),
+            event: event.to_string(),
+            properties: properties.into_iter().collect::<HashMap<_, _>>(),
+            timestamp: format_nanos(timestamp_nanos),
+            ..Default::default()
+        };
+        self.privacy.apply(&self.token, &mut raw, &self.now);
+        let data = serde_json::to_string(&raw).map_err(|e| {
+            tracing::error!("failed to encode data field: {}", e);
+            CaptureError::NonRetryableSinkError
+        })?;
+
+        Ok(ProcessedEvent {
+            metadata: ProcessedEventMetadata {
+                data_type: DataType::OtelMain,
+                session_id: None,
+                event_name: raw.event,
+                timestamp: raw.timestamp,
+                lib_version: None,
+            },
+            event: CapturedEvent {
+                uuid: uuid_v7(),
+                distinct_id,
+                ip: self.client_ip.clone(),
+                data,
+                now: self.now.clone(),
+                sent_at: None,
+                token: self.token.clone(),
+                is_cookieless_mode: false,
+            },
+        })
+    }
+}
+
+fn format_nanos(nanos: u64) -> Option<String> {
+    if nanos == 0 {
+        return None;
+    }
+    OffsetDateTime::from_unix_timestamp_nanos(nanos.into())
+        .ok()?
+        .format(&Rfc3339)
+        .ok()
+}
+
+fn insert_if_not_empty(properties: &mut Map<String, Value>, key: &str, value: String) {
+    if !value.is_empty() {
+        properties.insert(key.to_string(), Value::String(value));
+    }
+}
+
+fn log_events(
+    request: LogsRequest,
+    context: &OtelContext,
+) -> Result<Vec<ProcessedEvent>, CaptureError> {
+    let mut events = Vec::new();
+    for resource_logs in request.resource_logs {
+        for scope_logs in resource_logs.scope_logs {
+            for record in scope_logs.log_records {
+                let mut properties = attributes_to_map(record.attributes);
+                properties.insert("$otel_body".to_string(), record.body);
+                properties.insert(
+                    "$otel_severity_number".to_string(),
+                    record.severity_number.into(),
+                );
+                insert_if_not_empty(&mut properties, "$otel_severity_text", record.severity_text);
+                insert_if_not_empty(&mut properties, "$otel_trace_id", record.trace_id);
+                insert_if_not_empty(&mut properties, "$otel_span_id", record.span_id);
+
+                let timestamp = match record.time_unix_nano {
+                    0 => record.observed_time_unix_nano,
+                    time => time,
+                };
+                events.push(context.event(
+                    &resource_logs.resource,
+                    &scope_logs.scope,
+                    "$otel_log",
+                    timestamp,
+                    properties,
+                )?);
+            }
+        }
+    }
+    Ok(events)
+}
+
+fn span_kind(kind: i32) -> &'static str {
+    match kind {
+        1 => "internal",
+        2 => "server",
+        3 => "client",
+        4 => "producer",
+        5 => "consumer",
+        _ => "unspecified",
+    }
+}
+
+fn status_code(code: i32) -> &'static str {
+    match code {
+        1 => "ok",
+        2 => "error",
+        _ => "unset",
+    }
+}
+
+fn span_events(
+    request: TracesRequest,
+    context: &OtelContext,
+) -> Result<Vec<ProcessedEvent>, CaptureError> {
+    let mut events = Vec::new();
+    for resource_spans in request.resource_spans {
+        for scope_spans in resource_spans.scope_spans {
+            for span in scope_spans.spans {
+                let mut properties = attributes_to_map(span.attributes);
+                properties.insert("$otel_span_name".to_string(), Value::String(span.name));
+                properties.insert("$otel_span_kind".to_string(), span_kind(span.kind).into());
+                insert_if_not_empty(&mut properties, "$otel_trace_id", span.trace_id);
+                insert_if_not_empty(&mut properties, "$otel_span_id", span.span_id);
+                insert_if_not_empty(&mut properties, "$otel_parent_span_id", span.parent_span_id);
+                properties.insert(
+                    "$otel_status_code".to_string(),
+                    status_code(span.status.code).into(),
+                );
+                insert_if_not_empty(&mut properties, "$otel_status_message", span.status.message);
+                if span.end_time_unix_nano >= span.start_time_unix_nano {
+                    let duration_ms =
+                        (span.end_time_unix_nano - span.start_time_unix_nano) as f64 / 1_000_000.0;
+                    properties.insert("$otel_duration_ms".to_string(), duration_ms.into());
+                }
+
+                events.push(context.event(
+                    &resource_spans.resource,
+                    &scope_spans.scope,
+                    "$otel_span",
+                    span.start_time_unix_nano,
+                    properties,
+                )?);
+            }
+        }
+    }
+    Ok(events)
+}
+
+async fn send_events(
+    state: &router::State,
+    token: &str,
+    mut events: Vec<ProcessedEvent>,
+) -> Result<(), CaptureError> {
+    tracing::Span::current().record("batch_size", events.len());
+    if events.is_empty() {
+        return Ok(());
+    }
+    counter!("capture_events_received_total").increment(events.len() as u64);
+
+    if state.billing_limiter.is_limited(token).await {
+        // Exporters retry on 429, accept and drop the data instead
+        report_dropped_events("over_quota", events.len() as u64);
+        return Ok(());
+    }
+
+    events.retain(|e| {
+        if state
+            .token_dropper
+            .should_drop(&e.event.token, &e.event.distinct_id)
+        {
+            report_dropped_events("token_dropper", 1);
+            false
+        } else {
+            true
+        }
+    });
+    for e in events.iter_mut() {
+        e.event.ip = state
+            .privacy
+            .anonymize_ip(&e.event.token, &e.event.ip, &e.event.now);
+    }
+
+    state.sink.send_batch(events).await
+}
+
+#[instrument(skip_all, fields(token, batch_size))]
+pub async fn logs(
+    State(state): State<router::State>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, CaptureError> {
+    let encoding = Encoding::from_headers(&headers)?;
+    let body = decompress(body, state.event_size_limit)?;
+    let request: LogsRequest = match encoding {
+        Encoding::Protobuf => ExportLogsServiceRequest::decode(body)
+            .map_err(decoding_error)?
+            .into(),
+        Encoding::Json => serde_json::from_slice(&body)?,
+    };
+
+    let token = extract_token(request.resource_logs.iter().map(|r| &r.resource))?;
+    tracing::Span::current().record("token", &token);
+    let context = OtelContext {
+        token,
+        client_ip: ip.to_string(),
+        now: state.timesource.current_time(),
+        privacy: state.privacy.clone(),
+    };
+
+    let events = log_events(request, &context)?;
+    send_events(&state, &context.token, events).await?;
+    Ok(encoding.response())
+}
+
+#[instrument(skip_all, fields(token, batch_size))]
+pub async fn traces(
+    State(state): State<router::State>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, CaptureError> {
+    let encoding = Encoding::from_headers(&headers)?;
+    let body = decompress(body, state.event_size_limit)?;
+    let request: TracesRequest = match encoding {
+        Encoding::Protobuf => ExportTraceServiceRequest::decode(body)
+            .map_err(decoding_error)?
+            .into(),
+        Encoding::Json => serde_json::from_slice(&body)?,
+    };
+
+    let token = extract_token(request.resource_spans.iter().map(|r| &r.resource))?;
+    tracing::Span::current().record("token", &token);
+    let context = OtelContext {
+        token,
+        client_ip: ip.to_string(),
+        now: state.timesource.current_time(),
+        privacy: state.privacy.clone(),
+    };
+
+    let events = span_events(request, &context)?;
+    send_events(&state, &context.token, events).await?;
+    Ok(encoding.response())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::privacy::ip::IpAnonymizer;
+    use crate::privacy::scrubbing::PropertyScrubber;
+
+    fn context() -> OtelContext {
+        OtelContext {
+            token: "phc_token".to_string(),
+            client_ip: "1.2.3.4".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            privacy: Arc::new(Privacy::new(
+                IpAnonymizer::new("", None),
+                PropertyScrubber::new("", None),
+            )),
+        }
+    }
+
+    fn resource(attributes: Value) -> Value {
+        json!({"attributes": attributes})
+    }
+
+    #[test]
+    fn test_extract_token() {
+        let request: LogsRequest = serde_json::from_value(json!({"resourceLogs": [
+            {"resource": resource(json!([{"key": "posthog.token", "value": {"stringValue": "phc_token"}}]))},
+            {"resource": resource(json!([{"key": "posthog.token", "value": {"stringValue": "phc_token"}}]))},
+        ]}))
+        .unwrap();
+        assert_eq!(
+            extract_token(request.resource_logs.iter().map(|r| &r.resource)).unwrap(),
+            "phc_token"
+        );
+
+        let request: LogsRequest = serde_json::from_value(json!({"resourceLogs": [
+            {"resource": resource(json!([{"key": "posthog.token", "value": {"stringValue": "phc_token"}}]))},
+            {"resource": resource(json!([{"key": "posthog.token", "value": {"stringValue": "phc_other"}}]))},
+        ]}))
+        .unwrap();
+        assert!(matches!(
+            extract_token(request.resource_logs.iter().map(|r| &r.resource)),
+            Err(CaptureError::MultipleTokensError)
+        ));
+
+        let request: LogsRequest =
+            serde_json::from_value(json!({"resourceLogs": [{"resource": resource(json!([]))}]}))
+                .unwrap();
+        assert!(matches!(
+            extract_token(request.resource_logs.iter().map(|r| &r.resource)),
+            Err(CaptureError::NoTokenError)
+        ));
+    }
+
+    #[test]
+    fn test_log_events() {
+        let request: LogsRequest = serde_json::from_value(json!({"resourceLogs": [{
+            "resource": resource(json!([
+                {"key": "posthog.token", "value": {"stringValue": "phc_token"}},
+                {"key": "service.name", "value": {"stringValue": "billing"}}
+            ])),
+            "scopeLogs": [{"logRecords": [{
+                "timeUnixNano": "1700000000000000000",
+                "severityText": "WARN",
+                "severityNumber": 13,
+                "body": {"stringValue": "disk almost full"},
+                "attributes": [{"key": "disk", "value": {"stringValue": "/dev/sda"}}]
+            }]}]
+        }]}))
+        .unwrap();
+
+        let events = log_events(request, &context()).unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.metadata.data_type, DataType::OtelMain);
+        assert_eq!(event.event.distinct_id, "billing");
+        assert_eq!(event.event.token, "phc_token");
+
+        let data: Value = serde_json::from_str(&event.event.data).unwrap();
+        assert_eq!(data["event"], json!("$otel_log"));
+        assert_eq!(data["timestamp"], json!("2023-11-14T22:13:20Z"));
+        assert_eq!(data["properties"]["disk"], json!("/dev/sda"));
+        assert_eq!(data["properties"]["$otel_body"], json!("disk almost full"));
+        assert_eq!(data["properties"]["$otel_severity_text"], json!("WARN"));
+        assert_eq!(
+            data["properties"]["$otel_resource"],
+            json!({"service.name": "billing"})
+        );
+    }
+
+    #[test]
+    fn test_span_events() {
+        let request: TracesRequest = serde_json::from_value(json!({"resourceSpans": [{
+            "resource": resource(json!([
+                {"key": "posthog.token", "value": {"stringValue": "phc_token"}},
+                {"key": "posthog.distinct_id", "value": {"stringValue": "user-1"}}
+            ])),
+            "scopeSpans": [{"spans": [{
+                "traceId": "5b8efff798038103d269b633813fc60c",
+                "spanId": "eee19b7ec3c1b174",
+                "name": "GET /api",
+                "kind": 2,
+                "startTimeUnixNano": "1700000000000000000",
+                "endTimeUnixNano": "1700000000250000000",
+                "status": {"code": 2, "message": "boom"}
+            }]}]
+        }]}))
+        .unwrap();
+
+        let events = span_events(request, &context()).unwrap();
+        let data: Value = serde_json::from_str(&events[0].event.data).unwrap();
+        assert_eq!(events[0].event.distinct_id, "user-1");
+        assert_eq!(data["event"], json!("$otel_span"));
+        assert_eq!(data["properties"]["$otel_span_kind"], json!("server"));
+        assert_eq!(data["properties"]["$otel_duration_ms"], json!(250.0));
+        assert_eq!(data["properties"]["$otel_status_code"], json!("error"));
+        assert!(data["properties"].get("$otel_parent_span_id").is_none());
+    }
+
+    #[test]
+    fn test_missing_distinct_id() {
+        let request: LogsRequest = serde_json::from_value(json!({"resourceLogs": [{
+            "resource": resource(json!([{"key": "posthog.token", "value": {"stringValue": "phc_token"}}])),
+            "scopeLogs": [{"logRecords": [{"body": {"stringValue": "hi"}}]}]
+        }]}))
+        .unwrap();
+        assert!(matches!(
+            log_events(request, &context()),
+            Err(CaptureError::MissingDistinctId)
+        ));
+    }
+
+    #[test]
+    fn test_privacy_settings_apply() {
+        let request: LogsRequest = serde_json::from_value(json!({"resourceLogs": [{
+            "resource": resource(json!([
+                {"key": "posthog.token", "value": {"stringValue": "phc_token"}},
+                {"key": "service.name", "value": {"stringValue": "billing"}},
+                {"key": "owner", "value": {"stringValue": "ops@example.com"}}
+            ])),
+            "scopeLogs": [{"logRecords": [{
+                "body": {"stringValue": "invoice sent to jane@example.com"},
+                "attributes": [{"key": "user", "value": {"stringValue": "jane@example.com"}}]
+            }]}]
+        }]}))
+        .unwrap();
+        let context = OtelContext {
+            privacy: Arc::new(Privacy::new(
+                IpAnonymizer::new("phc_token:truncate", None),
+                PropertyScrubber::new("phc_token:email", None),
+            )),
+            ..context()
+        };
+
+        let events = log_events(request, &context).unwrap();
+        let data: Value = serde_json::from_str(&events[0].event.data).unwrap();
+        assert_eq!(data["properties"]["user"], json!("[REDACTED]"));
+        assert_eq!(
+            data["properties"]["$otel_body"],
+            json!("invoice sent to [REDACTED]")
+        );
+        assert_eq!(
+            data["properties"]["$otel_resource"],
+            json!({"service.name": "billing", "owner": "[REDACTED]"})
+        );
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            "application/json; charset=utf-8".parse().unwrap(),
+        );
+        assert_eq!(Encoding::from_headers(&headers).unwrap(), Encoding::Json);
+        headers.insert(
+            header::CONTENT_TYPE,
+            "application/x-protobuf".parse().unwrap(),
+        );
+        assert_eq!(
+            Encoding::from_headers(&headers).unwrap(),
+            Encoding::Protobuf
+        );
+        headers.insert(header::CONTENT_TYPE, "text/plain".parse().unwrap());
+        assert!(Encoding::from_headers(&headers).is_err());
+    }
+}
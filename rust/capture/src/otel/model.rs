@@ -0,0 +1,454 @@
+//! Minimal OTLP logs and traces model. OTLP/JSON payloads deserialize straight into these
+//! types, OTLP/protobuf payloads are decoded with the generated types and converted.
+
+use base64::Engine;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::common::v1 as proto_common;
+use opentelemetry_proto::tonic::common::v1::any_value::Value as ProtoValue;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Number, Value};
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LogsRequest {
+    #[serde(default)]
+    pub resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceLogs {
+    #[serde(default)]
+    pub resource: Resource,
+    #[serde(default)]
+    pub scope_logs: Vec<ScopeLogs>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopeLogs {
+    #[serde(default)]
+    pub scope: Scope,
+    #[serde(default)]
+    pub log_records: Vec<LogRecord>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+    #[serde(default, deserialize_with = "u64_from_any")]
+    pub time_unix_nano: u64,
+    #[serde(default, deserialize_with = "u64_from_any")]
+    pub observed_time_unix_nano: u64,
+    #[serde(default)]
+    pub severity_number: i32,
+    #[serde(default)]
+    pub severity_text: String,
+    #[serde(default, deserialize_with = "any_value")]
+    pub body: Value,
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+    #[serde(default)]
+    pub trace_id: String,
+    #[serde(default)]
+    pub span_id: String,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TracesRequest {
+    #[serde(default)]
+    pub resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSpans {
+    #[serde(default)]
+    pub resource: Resource,
+    #[serde(default)]
+    pub scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopeSpans {
+    #[serde(default)]
+    pub scope: Scope,
+    #[serde(default)]
+    pub spans: Vec<Span>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Span {
+    #[serde(default)]
+    pub trace_id: String,
+    #[serde(default)]
+    pub span_id: String,
+    #[serde(default)]
+    pub parent_span_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub kind: i32,
+    #[serde(default, deserialize_with = "u64_from_any")]
+    pub start_time_unix_nano: u64,
+    #[serde(default, deserialize_with = "u64_from_any")]
+    pub end_time_unix_nano: u64,
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+    #[serde(default)]
+    pub status: Status,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct Status {
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct Resource {
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+}
+
+impl Resource {
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| kv.value.as_str())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct Scope {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct KeyValue {
+    pub key: String,
+    #[serde(default, deserialize_with = "any_value")]
+    pub value: Value,
+}
+
+pub fn attributes_to_map(attributes: Vec<KeyValue>) -> Map<String, Value> {
+    attributes
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect()
+}
+
+/// The OTLP/JSON `AnyValue` wrapper, flattened into a plain JSON value on deserialization.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonAnyValue {
+    string_value: Option<String>,
+    bool_value: Option<bool>,
+    #[serde(default, deserialize_with = "i64_from_any")]
+    int_value: Option<i64>,
+    double_value: Option<f64>,
+    array_value: Option<JsonArrayValue>,
+    kvlist_value: Option<JsonKeyValueList>,
+    bytes_value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonArrayValue {
+    #[serde(default)]
+    values: Vec<JsonAnyValue>,
+}
+
+#[derive(Deserialize)]
+struct JsonKeyValueList {
+    #[serde(default)]
+    values: Vec<KeyValue>,
+}
+
+impl From<JsonAnyValue> for Value {
+    fn from(value: JsonAnyValue) -> Self {
+        if let Some(s) = value.string_value {
+            Value::String(s)
+        } else if let Some(b) = value.bool_value {
+            Value::Bool(b)
+        } else if let Some(i) = value.int_value {
+            Value::Number(i.into())
+        } else if let Some(d) = value.double_value {
+            Number::from_f64(d).map_or(Value::Null, Value::Number)
+        } else if let Some(array) = value.array_value {
+            Value::Array(array.values.into_iter().map(Value::from).collect())
+        } else if let Some(kvlist) = value.kvlist_value {
+            Value::Object(attributes_to_map(kvlist.values))
+        } else if let Some(bytes) = value.bytes_value {
+            Value::String(bytes) // Already base64 encoded in OTLP/JSON
+        } else {
+            Value::Null
+        }
+    }
+}
+
+fn any_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+    Ok(Option::<JsonAnyValue>::deserialize(deserializer)?.map_or(Value::Null, Value::from))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Unsigned(u64),
+    Signed(i64),
+    String(String),
+}
+
+// OTLP/JSON encodes 64 bits integers as strings, but some exporters send numbers
+fn i128_from_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i128>, D::Error> {
+    match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Unsigned(n)) => Ok(Some(n.into())),
+        Some(NumberOrString::Signed(n)) => Ok(Some(n.into())),
+        Some(NumberOrString::String(s)) => s.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+fn u64_from_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = i128_from_any(deserializer)?.unwrap_or_default();
+    u64::try_from(value).map_err(serde::de::Error::custom)
+}
+
+fn i64_from_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    i128_from_any(deserializer)?
+        .map(i64::try_from)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn proto_value(value: Option<proto_common::AnyValue>) -> Value {
+    match value.and_then(|v| v.value) {
+        None => Value::Null,
+        Some(ProtoValue::StringValue(s)) => Value::String(s),
+        Some(ProtoValue::BoolValue(b)) => Value::Bool(b),
+        Some(ProtoValue::IntValue(i)) => Value::Number(i.into()),
+        Some(ProtoValue::DoubleValue(d)) => Number::from_f64(d).map_or(Value::Null, Value::Number),
+        Some(ProtoValue::ArrayValue(array)) => Value::Array(
+            array
+                .values
+                .into_iter()
+                .map(|v| proto_value(Some(v)))
+                .collect(),
+        ),
+        Some(ProtoValue::KvlistValue(kvlist)) => {
+            Value::Object(attributes_to_map(proto_attributes(kvlist.values)))
+        }
+        Some(ProtoValue::BytesValue(bytes)) => {
+            Value::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+    }
+}
+
+fn proto_attributes(attributes: Vec<proto_common::KeyValue>) -> Vec<KeyValue> {
+    attributes
+        .into_iter()
+        .map(|kv| KeyValue {
+            key: kv.key,
+            value: proto_value(kv.value),
+        })
+        .collect()
+}
+
+fn proto_resource(
+    resource: Option<opentelemetry_proto::tonic::resource::v1::Resource>,
+) -> Resource {
+    Resource {
+        attributes: resource
+            .map(|r| proto_attributes(r.attributes))
+            .unwrap_or_default(),
+    }
+}
+
+fn proto_scope(scope: Option<proto_common::InstrumentationScope>) -> Scope {
+    Scope {
+        name: scope.map(|s| s.name).unwrap_or_default(),
+    }
+}
+
+impl From<ExportLogsServiceRequest> for LogsRequest {
+    fn from(request: ExportLogsServiceRequest) -> Self {
+        let resource_logs = request
+            .resource_logs
+            .into_iter()
+            .map(|resource_logs| ResourceLogs {
+                resource: proto_resource(resource_logs.resource),
+                scope_logs: resource_logs
+                    .scope_logs
+                    .into_iter()
+                    .map(|scope_logs| ScopeLogs {
+                        scope: proto_scope(scope_logs.scope),
+                        log_records: scope_logs
+                            .log_records
+                            .into_iter()
+                            .map(|record| LogRecord {
+                                time_unix_nano: record.time_unix_nano,
+                                observed_time_unix_nano: record.observed_time_unix_nano,
+                                severity_number: record.severity_number,
+                                severity_text: record.severity_text,
+                                body: proto_value(record.body),
+                                attributes: proto_attributes(record.attributes),
+                                trace_id: hex(&record.trace_id),
+                                span_id: hex(&record.span_id),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        LogsRequest { resource_logs }
+    }
+}
+
+impl From<ExportTraceServiceRequest> for TracesRequest {
+    fn from(request: ExportTraceServiceRequest) -> Self {
+        let resource_spans = request
+            .resource_spans
+            .into_iter()
+            .map(|resource_spans| ResourceSpans {
+                resource: proto_resource(resource_spans.resource),
+                scope_spans: resource_spans
+                    .scope_spans
+                    .into_iter()
+                    .map(|scope_spans| ScopeSpans {
+                        scope: proto_scope(scope_spans.scope),
+                        spans: scope_spans
+                            .spans
+                            .into_iter()
+                            .map(|span| Span {
+                                trace_id: hex(&span.trace_id),
+                                span_id: hex(&span.span_id),
+                                parent_span_id: hex(&span.parent_span_id),
+                                name: span.name,
+                                kind: span.kind,
+                                start_time_unix_nano: span.start_time_unix_nano,
+                                end_time_unix_nano: span.end_time_unix_nano,
+                                attributes: proto_attributes(span.attributes),
+                                status: span
+                                    .status
+                                    .map(|s| Status {
+                                        code: s.code,
+                                        message: s.message,
+                                    })
+                                    .unwrap_or_default(),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        TracesRequest { resource_spans }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_json_logs() {
+        let request: LogsRequest = serde_json::from_value(json!({
+            "resourceLogs": [{
+                "resource": {"attributes": [
+                    {"key": "service.name", "value": {"stringValue": "api"}}
+                ]},
+                "scopeLogs": [{
+                    "scope": {"name": "my.logger"},
+                    "logRecords": [{
+                        "timeUnixNano": "1700000000000000000",
+                        "severityNumber": 9,
+                        "severityText": "INFO",
+                        "body": {"stringValue": "hello"},
+                        "attributes": [
+                            {"key": "count", "value": {"intValue": "3"}},
+                            {"key": "ratio", "value": {"doubleValue": 0.5}},
+                            {"key": "tags", "value": {"arrayValue": {"values": [{"stringValue": "a"}]}}},
+                            {"key": "nested", "value": {"kvlistValue": {"values": [
+                                {"key": "ok", "value": {"boolValue": true}}
+                            ]}}}
+                        ],
+                        "traceId": "5b8efff798038103d269b633813fc60c",
+                        "spanId": "eee19b7ec3c1b174"
+                    }]
+                }]
+            }]
+        }))
+        .unwrap();
+
+        let resource = &request.resource_logs[0].resource;
+        assert_eq!(resource.get_str("service.name"), Some("api"));
+
+        let scope_logs = &request.resource_logs[0].scope_logs[0];
+        assert_eq!(scope_logs.scope.name, "my.logger");
+        let record = &scope_logs.log_records[0];
+        assert_eq!(record.time_unix_nano, 1_700_000_000_000_000_000);
+        assert_eq!(record.body, json!("hello"));
+        assert_eq!(record.trace_id, "5b8efff798038103d269b633813fc60c");
+        assert_eq!(
+            Value::Object(attributes_to_map(
+                serde_json::from_value(json!([
+                    {"key": "count", "value": {"intValue": 3}},
+                    {"key": "missing"}
+                ]))
+                .unwrap()
+            )),
+            json!({"count": 3, "missing": null})
+        );
+        assert_eq!(record.attributes[2].value, json!(["a"]));
+        assert_eq!(record.attributes[3].value, json!({"ok": true}));
+    }
+
+    #[test]
+    fn test_proto_conversion() {
+        use opentelemetry_proto::tonic::trace::v1 as proto_trace;
+
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![proto_trace::ResourceSpans {
+                scope_spans: vec![proto_trace::ScopeSpans {
+                    spans: vec![proto_trace::Span {
+                        trace_id: vec![0xab, 0x01],
+                        span_id: vec![0x0f],
+                        name: "GET /".to_string(),
+                        kind: 2,
+                        start_time_unix_nano: 1,
+                        end_time_unix_nano: 2,
+                        attributes: vec![proto_common::KeyValue {
+                            key: "http.status_code".to_string(),
+                            value: Some(proto_common::AnyValue {
+                                value: Some(ProtoValue::IntValue(200)),
+                            }),
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let converted = TracesRequest::from(request);
+        let span = &converted.resource_spans[0].scope_spans[0].spans[0];
+        assert_eq!(span.trace_id, "ab01");
+        assert_eq!(span.span_id, "0f");
+        assert_eq!(span.parent_span_id, "");
+        assert_eq!(span.name, "GET /");
+        assert_eq!(span.attributes[0].value, json!(200));
+    }
+}
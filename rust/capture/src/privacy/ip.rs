@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IpMode {
+    #[default]
+    Full,
+    Truncate,
+    Drop,
+    Hash,
+}
+
+impl FromStr for IpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "full" => Ok(IpMode::Full),
+            "truncate" => Ok(IpMode::Truncate),
+            "drop" => Ok(IpMode::Drop),
+            "hash" => Ok(IpMode::Hash),
+            _ => Err(format!("Unknown IP anonymization mode: {s}")),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct IpAnonymizer {
+    modes: HashMap<String, IpMode>,
+    default_mode: IpMode,
+    salt: Option<String>,
+}
+
+impl IpAnonymizer {
+    // Takes "<token or *>:<full|truncate|drop|hash>;<token>:<mode>..."
+    pub fn new(config: &str, salt: Option<String>) -> Self {
+        let mut modes = HashMap::new();
+        let mut default_mode = IpMode::Full;
+        for pair in config.split(';').filter(|p| !p.is_empty()) {
+            let Some((token, mode)) = pair.split_once(':') else {
+                warn!("No IP anonymization mode configured for {}", pair);
+                continue;
+            };
+            let mode = match IpMode::from_str(mode) {
+                Ok(IpMode::Hash) if salt.is_none() => {
+                    warn!("No salt configured, dropping IPs instead of hashing for {token}");
+                    IpMode::Drop
+                }
+                Ok(mode) => mode,
+                Err(err) => {
+                    warn!("{}", err);
+                    continue;
+                }
+            };
+            if token == "*" {
+                default_mode = mode;
+            } else {
+                modes.insert(token.to_string(), mode);
+            }
+        }
+        Self {
+            modes,
+            default_mode,
+            salt,
+        }
+    }
+
+    pub fn mode(&self, token: &str) -> IpMode {
+        self.modes.get(token).copied().unwrap_or(self.default_mode)
+    }
+
+    /// Applies the team's mode to an address. The hash salt rotates with the `day`
+    /// argument, so hashed values can't be joined across days.
+    pub fn anonymize(&self, token: &str, ip: &str, day: &str) -> String {
+        match self.mode(token) {
+            IpMode::Full => ip.to_string(),
+            IpMode::Truncate => truncate(ip),
+            IpMode::Drop => String::new(),
+            IpMode::Hash => {
+                let salt = self.salt.as_deref().unwrap_or_default();
+                let digest = Sha256::digest(format!("{salt}:{day}:{ip}").as_bytes());
+                format!("{:x}", digest)
+            }
+        }
+    }
+}
+
+/// Zeroes the last octet of IPv4 addresses and everything after the /48 prefix for IPv6.
+fn truncate(ip: &str) -> String {
+    match IpAddr::from_str(ip) {
+        Ok(IpAddr::V4(addr)) => {
+            let [a, b, c, _] = addr.octets();
+            IpAddr::from([a, b, c, 0]).to_string()
+        }
+        Ok(IpAddr::V6(addr)) => {
+            let mut segments = addr.segments();
+            segments[3..].fill(0);
+            IpAddr::from(segments).to_string()
+        }
+        // Not an address we can safely keep part of
+        Err(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let anonymizer = IpAnonymizer::new("", None);
+        assert_eq!(
+            anonymizer.anonymize("token", "1.2.3.4", "2024-01-01"),
+            "1.2.3.4"
+        );
+    }
+
+    #[test]
+    fn test_per_token_modes() {
+        let anonymizer = IpAnonymizer::new(
+            "t1:truncate;t2:drop;t3:hash;*:full",
+            Some("secret".to_string()),
+        );
+        assert_eq!(
+            anonymizer.anonymize("t1", "1.2.3.4", "2024-01-01"),
+            "1.2.3.0"
+        );
+        assert_eq!(
+            anonymizer.anonymize("t1", "2001:db8:85a3:8d3:1319:8a2e:370:7348", "2024-01-01"),
+            "2001:db8:85a3::"
+        );
+        assert_eq!(anonymizer.anonymize("t2", "1.2.3.4", "2024-01-01"), "");
+        assert_eq!(
+            anonymizer.anonymize("other", "1.2.3.4", "2024-01-01"),
+            "1.2.3.4"
+        );
+    }
+
+    #[test]
+    fn test_hash_rotates_daily() {
+        let anonymizer = IpAnonymizer::new("*:hash", Some("secret".to_string()));
+        let first = anonymizer.anonymize("token", "1.2.3.4", "2024-01-01");
+        assert_eq!(first.len(), 64);
+        assert_eq!(
+            first,
+            anonymizer.anonymize("token", "1.2.3.4", "2024-01-01")
+        );
+        assert_ne!(
+            first,
+            anonymizer.anonymize("token", "1.2.3.4", "2024-01-02")
+        );
+        assert_ne!(
+            first,
+            anonymizer.anonymize("token", "1.2.3.5", "2024-01-01")
+        );
+    }
+
+    #[test]
+    fn test_hash_without_salt_drops() {
+        let anonymizer = IpAnonymizer::new("*:hash", None);
+        assert_eq!(anonymizer.mode("token"), IpMode::Drop);
+    }
+
+    #[test]
+    fn test_invalid_mode_is_ignored() {
+        let anonymizer = IpAnonymizer::new("t1:scramble;t2:drop", None);
+        assert_eq!(anonymizer.mode("t1"), IpMode::Full);
+        assert_eq!(anonymizer.mode("t2"), IpMode::Drop);
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-team privacy controls applied before events leave capture: IP anonymization
+//! and regex-based redaction of property values.
+
+pub mod ip;
+pub mod scrubbing;
+
+use common_types::RawEvent;
+use serde_json::Value;
+
+use crate::privacy::ip::{IpAnonymizer, IpMode};
+use crate::privacy::scrubbing::PropertyScrubber;
+
+#[derive(Default)]
+pub struct Privacy {
+    pub ip: IpAnonymizer,
+    pub scrubber: PropertyScrubber,
+}
+
+impl Privacy {
+    pub fn new(ip: IpAnonymizer, scrubber: PropertyScrubber) -> Self {
+        Self { ip, scrubber }
+    }
+
+    /// Scrubs the event's properties and person properties, and anonymizes any `$ip`
+    /// property set by the client. Returns the number of scrubbed values.
+    pub fn apply(&self, token: &str, event: &mut RawEvent, now: &str) -> u64 {
+        if self.ip.mode(token) != IpMode::Full {
+            if let Some(Value::String(ip)) = event.properties.get_mut("$ip") {
+                *ip = self.ip.anonymize(token, ip, salt_day(now));
+            }
+        }
+
+        if !self.scrubber.is_enabled() {
+            return 0;
+        }
+        let mut scrubbed = self.scrubber.scrub(token, &mut event.properties);
+        if let Some(set) = event.set.as_mut() {
+            scrubbed += self.scrubber.scrub(token, set);
+        }
+        if let Some(set_once) = event.set_once.as_mut() {
+            scrubbed += self.scrubber.scrub(token, set_once);
+        }
+        scrubbed
+    }
+
+    pub fn anonymize_ip(&self, token: &str, ip: &str, now: &str) -> String {
+        self.ip.anonymize(token, ip, salt_day(now))
+    }
+}
+
+/// Hash salts rotate daily: keep the date part of the RFC3339 processing timestamp.
+fn salt_day(now: &str) -> &str {
+    now.get(..10).unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let privacy = Privacy::new(
+            IpAnonymizer::new("token:truncate", None),
+            PropertyScrubber::new("token:email", None),
+        );
+        let mut event = RawEvent {
+            event: "e".to_string(),
+            properties: HashMap::from([
+                ("$ip".to_string(), json!("1.2.3.4")),
+                ("distinct_id".to_string(), json!("me@example.com")),
+                ("email".to_string(), json!("me@example.com")),
+            ]),
+            set: Some(HashMap::from([(
+                "email".to_string(),
+                json!("me@example.com"),
+            )])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            privacy.apply("token", &mut event, "2024-01-01T00:00:00Z"),
+            2
+        );
+        assert_eq!(event.properties["$ip"], json!("1.2.3.0"));
+        assert_eq!(event.properties["email"], json!("[REDACTED]"));
+        // The distinct id still identifies the person, even though it's an email address.
+        assert_eq!(
+            event.extract_distinct_id(),
+            Some("me@example.com".to_string())
+        );
+        assert_eq!(event.set.unwrap()["email"], json!("[REDACTED]"));
+    }
+
+    #[test]
+    fn test_salt_day() {
+        assert_eq!(salt_day("2024-01-01T12:34:56Z"), "2024-01-01");
+        assert_eq!(salt_day("short"), "short");
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use metrics::counter;
+use regex::Regex;
+use serde_json::Value;
+use tracing::warn;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+const BUILTIN_PATTERNS: [(&str, &str); 2] = [
+    ("email", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+    ("credit_card", r"\b(?:\d[ -]?){12,18}\d\b"),
+];
+
+/// Properties that identify the person or session, and must reach ingestion as sent.
+const IDENTITY_PROPERTIES: [&str; 4] = [
+    "distinct_id",
+    "$anon_distinct_id",
+    "$device_id",
+    "$session_id",
+];
+
+/// Person properties nested in the event properties, which are scrubbed like any others.
+const PERSON_PROPERTIES: [&str; 2] = ["$set", "$set_once"];
+
+/// Reserved properties holding the sender's own data, such as OTLP log bodies.
+const SENDER_DATA_PREFIX: &str = "$otel_";
+
+struct Pattern {
+    name: String,
+    regex: Regex,
+}
+
+#[derive(Default)]
+pub struct PropertyScrubber {
+    rules: HashMap<String, Vec<Pattern>>,
+    default_rules: Vec<Pattern>,
+}
+
+impl PropertyScrubber {
+    // Takes "<token or *>:<pattern>,<pattern>;<token>..." where patterns are either
+    // built-in (email, credit_card) or named in `custom_patterns`, a JSON object of regexes.
+    pub fn new(config: &str, custom_patterns: Option<&str>) -> Self {
+        let mut patterns: HashMap<String, String> = BUILTIN_PATTERNS
+            .iter()
+            .map(|(name, regex)| (name.to_string(), regex.to_string()))
+            .collect();
+        if let Some(custom) = custom_patterns {
+            match serde_json::from_str::<HashMap<String, String>>(custom) {
+                Ok(custom) => patterns.extend(custom),
+                Err(err) => warn!("Failed to parse custom scrubbing patterns: {}", err),
+            }
+        }
+
+        let compile = |names: &str| -> Vec<Pattern> {
+            names
+                .split(',')
+                .filter_map(|name| {
+                    let Some(source) = patterns.get(name) else {
+                        warn!("Unknown scrubbing pattern {}", name);
+                        return None;
+                    };
+                    match Regex::new(source) {
+                        Ok(regex) => Some(Pattern {
+                            name: name.to_string(),
+                            regex,
+                        }),
+                        Err(err) => {
+                            warn!("Invalid scrubbing pattern {}: {}", name, err);
+                            None
+                        }
+                    }
+                })
+                .collect()
+        };
+
+        let mut rules = HashMap::new();
+        let mut default_rules = Vec::new();
+        for pair in config.split(';').filter(|p| !p.is_empty()) {
+            let Some((token, names)) = pair.split_once(':') else {
+                warn!("No scrubbing patterns configured for {}", pair);
+                continue;
+            };
+            if token == "*" {
+                default_rules = compile(names);
+            } else {
+                rules.insert(token.to_string(), compile(names));
+            }
+        }
+        Self {
+            rules,
+            default_rules,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty() || !self.default_rules.is_empty()
+    }
+
+    /// Redacts matching substrings in every string value, recursing into arrays and
+    /// objects. Identity properties and other reserved `$` properties are left alone, apart
+    /// from `$set` and `$set_once`. Returns the number of values that were modified.
+    pub fn scrub(&self, token: &str, properties: &mut HashMap<String, Value>) -> u64 {
+        let patterns = self.rules.get(token).unwrap_or(&self.default_rules);
+        if patterns.is_empty() {
+            return 0;
+        }
+        properties
+            .iter_mut()
+            .filter(|(key, _)| is_scrubbable(key))
+            .map(|(_, value)| scrub_value(patterns, value))
+            .sum()
+    }
+}
+
+fn is_scrubbable(key: &str) -> bool {
+    if IDENTITY_PROPERTIES.contains(&key) {
+        return false;
+    }
+    !key.starts_with('$') || PERSON_PROPERTIES.contains(&key) || key.starts_with(SENDER_DATA_PREFIX)
+}
+
+fn scrub_value(patterns: &[Pattern], value: &mut Value) -> u64 {
+    match value {
+        Value::String(s) => {
+            let mut scrubbed = false;
+            for pattern in patterns {
+                if pattern.regex.is_match(s) {
+                    *s = pattern.regex.replace_all(s, REDACTED).into_owned();
+                    counter!("capture_scrubbed_values_total", "pattern" => pattern.name.clone())
+                        .increment(1);
+                    scrubbed = true;
+                }
+            }
+            scrubbed as u64
+        }
+        Value::Array(values) => values.iter_mut().map(|v| scrub_value(patterns, v)).sum(),
+        Value::Object(map) => map.values_mut().map(|v| scrub_value(patterns, v)).sum(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn properties(value: Value) -> HashMap<String, Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_empty_config() {
+        let scrubber = PropertyScrubber::new("", None);
+        assert!(!scrubber.is_enabled());
+        let mut props = properties(json!({"email": "me@example.com"}));
+        assert_eq!(scrubber.scrub("token", &mut props), 0);
+        assert_eq!(props["email"], json!("me@example.com"));
+    }
+
+    #[test]
+    fn test_builtin_patterns() {
+        let scrubber = PropertyScrubber::new("token:email,credit_card", None);
+        let mut props = properties(json!({
+            "email": "contact me@example.com please",
+            "card": "4111 1111 1111 1111",
+            "nested": {"list": ["other@example.org", 42]},
+            "clean": "nothing to see",
+        }));
+        assert_eq!(scrubber.scrub("token", &mut props), 3);
+        assert_eq!(props["email"], json!("contact [REDACTED] please"));
+        assert_eq!(props["card"], json!("[REDACTED]"));
+        assert_eq!(props["nested"]["list"], json!(["[REDACTED]", 42]));
+        assert_eq!(props["clean"], json!("nothing to see"));
+
+        // Other tokens are untouched
+        let mut props = properties(json!({"email": "me@example.com"}));
+        assert_eq!(scrubber.scrub("other", &mut props), 0);
+    }
+
+    #[test]
+    fn test_identity_and_reserved_properties_are_kept() {
+        let scrubber = PropertyScrubber::new("token:email", None);
+        let mut props = properties(json!({
+            "distinct_id": "me@example.com",
+            "$anon_distinct_id": "me@example.com",
+            "$device_id": "me@example.com",
+            "$session_id": "me@example.com",
+            "$current_url": "https://example.com/?me@example.com",
+            "$set": {"email": "me@example.com"},
+            "email": "me@example.com",
+        }));
+        assert_eq!(scrubber.scrub("token", &mut props), 2);
+        assert_eq!(props["distinct_id"], json!("me@example.com"));
+        assert_eq!(props["$anon_distinct_id"], json!("me@example.com"));
+        assert_eq!(props["$device_id"], json!("me@example.com"));
+        assert_eq!(props["$session_id"], json!("me@example.com"));
+        assert_eq!(
+            props["$current_url"],
+            json!("https://example.com/?me@example.com")
+        );
+        assert_eq!(props["$set"]["email"], json!("[REDACTED]"));
+        assert_eq!(props["email"], json!("[REDACTED]"));
+    }
+
+    #[test]
+    fn test_custom_patterns_and_default() {
+        let scrubber = PropertyScrubber::new(
+            "*:ssn;token:email",
+            Some(r#"{"ssn": "\\d{3}-\\d{2}-\\d{4}"}"#),
+        );
+        let mut props = properties(json!({"ssn": "123-45-6789"}));
+        assert_eq!(scrubber.scrub("any", &mut props), 1);
+        assert_eq!(props["ssn"], json!("[REDACTED]"));
+
+        let mut props = properties(json!({"ssn": "123-45-6789"}));
+        assert_eq!(scrubber.scrub("token", &mut props), 0);
+    }
+
+    #[test]
+    fn test_unknown_pattern_is_ignored() {
+        let scrubber = PropertyScrubber::new("token:nope", None);
+        let mut props = properties(json!({"a": "b"}));
+        assert_eq!(scrubber.scrub("token", &mut props), 0);
+    }
+}
@@ -1,20 +1,23 @@
 use std::future::ready;
 use std::sync::Arc;
 
-use axum::extract::DefaultBodyLimit;
+use axum::extract::{DefaultBodyLimit, FromRef};
 use axum::http::Method;
 use axum::{
     routing::{get, post},
     Router,
 };
+use common_client_ip::TrustedProxies;
 use health::HealthRegistry;
 use tower::limit::ConcurrencyLimitLayer;
 use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+use crate::enrichment::Enricher;
 use crate::limiters::token_dropper::TokenDropper;
-use crate::test_endpoint;
+use crate::privacy::Privacy;
 use crate::{limiters::redis::RedisLimiter, redis::Client, sinks, time::TimeSource, v0_endpoint};
+use crate::{otel, test_endpoint};
 
 use crate::config::CaptureMode;
 use crate::prometheus::{setup_metrics_recorder, track_metrics};
@@ -30,9 +33,18 @@ pub struct State {
     pub redis: Arc<dyn Client + Send + Sync>,
     pub billing_limiter: RedisLimiter,
     pub token_dropper: Arc<TokenDropper>,
+    pub enricher: Arc<Enricher>,
+    pub privacy: Arc<Privacy>,
+    pub trusted_proxies: Arc<TrustedProxies>,
     pub event_size_limit: usize,
 }
 
+impl FromRef<State> for Arc<TrustedProxies> {
+    fn from_ref(state: &State) -> Self {
+        state.trusted_proxies.clone()
+    }
+}
+
 async fn index() -> &'static str {
     "capture"
 }
@@ -49,6 +61,9 @@ pub fn router<
     redis: Arc<R>,
     billing_limiter: RedisLimiter,
     token_dropper: TokenDropper,
+    enricher: Enricher,
+    privacy: Privacy,
+    trusted_proxies: TrustedProxies,
     metrics: bool,
     capture_mode: CaptureMode,
     concurrency_limit: Option<usize>,
@@ -61,6 +76,9 @@ pub fn router<
         billing_limiter,
         event_size_limit,
         token_dropper: Arc::new(token_dropper),
+        enricher: Arc::new(enricher),
+        privacy: Arc::new(privacy),
+        trusted_proxies: Arc::new(trusted_proxies),
     };
 
     // Very permissive CORS policy, as old SDK versions
@@ -128,6 +146,13 @@ pub fn router<
         )
         .layer(DefaultBodyLimit::max(EVENT_BODY_SIZE));
 
+    let otel_router = Router::new()
+        .route("/i/v1/logs", post(otel::logs))
+        .route("/i/v1/logs/", post(otel::logs))
+        .route("/i/v1/traces", post(otel::traces))
+        .route("/i/v1/traces/", post(otel::traces))
+        .layer(DefaultBodyLimit::max(BATCH_BODY_SIZE));
+
     let status_router = Router::new()
         .route("/", get(index))
         .route("/_readiness", get(index))
@@ -152,6 +177,7 @@ pub fn router<
         CaptureMode::Events => Router::new()
             .merge(batch_router)
             .merge(event_router)
+            .merge(otel_router)
             .merge(test_router),
         CaptureMode::Recordings => Router::new().merge(recordings_router),
     };
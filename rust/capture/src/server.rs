@@ -2,6 +2,8 @@ use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use common_client_ip::TrustedProxies;
+use common_geoip::GeoIpClient;
 use health::{ComponentStatus, HealthRegistry};
 use time::Duration;
 use tokio::net::TcpListener;
@@ -9,12 +11,16 @@ use tokio::net::TcpListener;
 use crate::config::CaptureMode;
 use crate::config::Config;
 
+use crate::enrichment::Enricher;
 use crate::limiters::overflow::OverflowLimiter;
 use crate::limiters::redis::{
     QuotaResource, RedisLimiter, OVERFLOW_LIMITER_CACHE_KEY, QUOTA_LIMITER_CACHE_KEY,
 };
 
 use crate::limiters::token_dropper::TokenDropper;
+use crate::privacy::ip::IpAnonymizer;
+use crate::privacy::scrubbing::PropertyScrubber;
+use crate::privacy::Privacy;
 use crate::redis::RedisClient;
 use crate::router;
 use crate::router::BATCH_BODY_SIZE;
@@ -149,6 +155,31 @@ where
         .map(|k| TokenDropper::new(&k))
         .unwrap_or_default();
 
+    let geoip = match (config.enrich_geoip, &config.maxmind_db_path) {
+        (false, _) => None,
+        (true, Some(path)) => {
+            Some(GeoIpClient::new(path).expect("failed to open MaxMind database"))
+        }
+        (true, None) => panic!("MAXMIND_DB_PATH is required when ENRICH_GEOIP is enabled"),
+    };
+    let enricher = Enricher::new(geoip, config.enrich_user_agent);
+
+    let privacy = Privacy::new(
+        config
+            .ip_anonymization
+            .as_deref()
+            .map(|c| IpAnonymizer::new(c, config.ip_hash_salt.clone()))
+            .unwrap_or_default(),
+        config
+            .scrub_rules
+            .as_deref()
+            .map(|c| PropertyScrubber::new(c, config.scrub_custom_patterns.as_deref()))
+            .unwrap_or_default(),
+    );
+
+    let trusted_proxies = TrustedProxies::new(&config.trusted_proxies, &config.client_ip_headers)
+        .expect("invalid trusted proxy configuration");
+
     // In Recordings capture mode, we unpack a batch of events, and then pack them back up into
     // a big blob and send to kafka all at once - so we should abort unpacking a batch if the data
     // size crosses the kafka limit. In the Events mode, we can unpack the batch and send each
@@ -170,6 +201,9 @@ where
         redis_client,
         billing_limiter,
         token_dropper,
+        enricher,
+        privacy,
+        trusted_proxies,
         config.export_prometheus,
         config.capture_mode,
         config.concurrency_limit,
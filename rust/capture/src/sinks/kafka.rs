@@ -9,6 +9,8 @@ use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord, Producer};
 use rdkafka::util::Timeout;
 use rdkafka::ClientConfig;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::time::Duration;
 use tokio::task::JoinSet;
 use tracing::log::{debug, error, info};
@@ -110,6 +112,47 @@ impl rdkafka::ClientContext for KafkaContext {
     }
 }
 
+/// How messages of a given DataType are keyed before partitioning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionStrategy {
+    /// token:distinct_id (token:ip for cookieless events), or session_id for replay
+    Default,
+    Token,
+    /// Falls back to the default key for events without a session
+    SessionId,
+    Random,
+}
+
+impl FromStr for PartitionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "default" => Ok(PartitionStrategy::Default),
+            "token" => Ok(PartitionStrategy::Token),
+            "session_id" => Ok(PartitionStrategy::SessionId),
+            "random" => Ok(PartitionStrategy::Random),
+            _ => Err(format!("Unknown partition strategy: {s}")),
+        }
+    }
+}
+
+// Takes "<data_type>:<strategy>;<data_type>:<strategy>..."
+pub fn parse_partition_strategies(
+    config: &str,
+) -> Result<HashMap<DataType, PartitionStrategy>, String> {
+    config
+        .split(';')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (data_type, strategy) = pair
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid partition strategy: {pair}"))?;
+            Ok((data_type.parse()?, strategy.parse()?))
+        })
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct KafkaSink {
     producer: FutureProducer<KafkaContext>,
@@ -121,6 +164,8 @@ pub struct KafkaSink {
     heatmaps_topic: String,
     replay_overflow_limiter: Option<RedisLimiter>,
     replay_overflow_topic: String,
+    otel_topic: String,
+    partition_strategies: HashMap<DataType, PartitionStrategy>,
 }
 
 impl KafkaSink {
@@ -132,6 +177,9 @@ impl KafkaSink {
     ) -> anyhow::Result<KafkaSink> {
         info!("connecting to Kafka brokers at {}...", config.kafka_hosts);
 
+        let partition_strategies = parse_partition_strategies(&config.kafka_partition_strategies)
+            .map_err(anyhow::Error::msg)?;
+
         let mut client_config = ClientConfig::new();
         client_config
             .set("bootstrap.servers", &config.kafka_hosts)
@@ -200,6 +248,8 @@ impl KafkaSink {
             exceptions_topic: config.kafka_exceptions_topic,
             heatmaps_topic: config.kafka_heatmaps_topic,
             replay_overflow_topic: config.kafka_replay_overflow_topic,
+            otel_topic: config.kafka_otel_topic,
+            partition_strategies,
             replay_overflow_limiter,
         })
     }
@@ -222,11 +272,24 @@ impl KafkaSink {
         let event_key = event.key();
         let session_id = metadata.session_id.clone();
         let distinct_id = event.distinct_id.clone();
+        let uuid = event.uuid.to_string();
+        let timestamp = metadata.timestamp.unwrap_or_else(|| event.now.clone());
 
         drop(event); // Events can be EXTREMELY memory hungry
 
+        // None if the data type keeps its default key, Some(None) for random partitioning
+        let custom_key: Option<Option<&str>> = match self.partition_strategies.get(&data_type) {
+            None | Some(PartitionStrategy::Default) => None,
+            Some(PartitionStrategy::Token) => Some(Some(token.as_str())),
+            Some(PartitionStrategy::SessionId) => {
+                Some(Some(session_id.as_deref().unwrap_or(event_key.as_str())))
+            }
+            Some(PartitionStrategy::Random) => Some(None),
+        };
+        let default_key = custom_key.unwrap_or(Some(event_key.as_str()));
+
         let (topic, partition_key): (&str, Option<&str>) = match data_type {
-            DataType::AnalyticsHistorical => (&self.historical_topic, Some(event_key.as_str())), // We never trigger overflow on historical events
+            DataType::AnalyticsHistorical => (&self.historical_topic, default_key), // We never trigger overflow on historical events
             DataType::AnalyticsMain => {
                 // TODO: deprecate capture-led overflow or move logic in handler
                 let is_limited = match &self.partition {
@@ -236,15 +299,13 @@ impl KafkaSink {
                 if is_limited {
                     (&self.main_topic, None) // Analytics overflow goes to the main topic without locality
                 } else {
-                    (&self.main_topic, Some(event_key.as_str()))
+                    (&self.main_topic, default_key)
                 }
             }
-            DataType::ClientIngestionWarning => (
-                &self.client_ingestion_warning_topic,
-                Some(event_key.as_str()),
-            ),
-            DataType::HeatmapMain => (&self.heatmaps_topic, Some(event_key.as_str())),
-            DataType::ExceptionMain => (&self.exceptions_topic, Some(event_key.as_str())),
+            DataType::ClientIngestionWarning => (&self.client_ingestion_warning_topic, default_key),
+            DataType::HeatmapMain => (&self.heatmaps_topic, default_key),
+            DataType::ExceptionMain => (&self.exceptions_topic, default_key),
+            DataType::OtelMain => (&self.otel_topic, default_key),
             DataType::SnapshotMain => {
                 let session_id = session_id
                     .as_deref()
@@ -254,31 +315,54 @@ impl KafkaSink {
                     Some(limiter) => limiter.is_limited(session_id).await,
                 };
 
+                let key = custom_key.unwrap_or(Some(session_id));
                 if is_overflowing {
-                    (&self.replay_overflow_topic, Some(session_id))
+                    (&self.replay_overflow_topic, key)
                 } else {
-                    (&self.main_topic, Some(session_id))
+                    (&self.main_topic, key)
                 }
             }
         };
 
+        let mut headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "token",
+                value: Some(&token),
+            })
+            .insert(Header {
+                key: "distinct_id",
+                value: Some(&distinct_id),
+            })
+            .insert(Header {
+                key: "uuid",
+                value: Some(&uuid),
+            })
+            .insert(Header {
+                key: "event",
+                value: Some(&metadata.event_name),
+            })
+            .insert(Header {
+                key: "timestamp",
+                value: Some(&timestamp),
+            })
+            .insert(Header {
+                key: "data_type",
+                value: Some(data_type.as_str()),
+            });
+        if let Some(lib_version) = &metadata.lib_version {
+            headers = headers.insert(Header {
+                key: "lib_version",
+                value: Some(lib_version),
+            });
+        }
+
         match self.producer.send_result(FutureRecord {
             topic,
             payload: Some(&payload),
             partition: None,
             key: partition_key,
             timestamp: None,
-            headers: Some(
-                OwnedHeaders::new()
-                    .insert(Header {
-                        key: "token",
-                        value: Some(&token),
-                    })
-                    .insert(Header {
-                        key: "distinct_id",
-                        value: Some(&distinct_id),
-                    }),
-            ),
+            headers: Some(headers),
         }) {
             Ok(ack) => Ok(ack),
             Err((e, _)) => match e.rdkafka_error_code() {
@@ -377,7 +461,7 @@ mod tests {
     use crate::api::CaptureError;
     use crate::config;
     use crate::limiters::overflow::OverflowLimiter;
-    use crate::sinks::kafka::KafkaSink;
+    use crate::sinks::kafka::{parse_partition_strategies, KafkaSink, PartitionStrategy};
     use crate::sinks::Event;
     use crate::utils::uuid_v7;
     use crate::v0_request::{DataType, ProcessedEvent, ProcessedEventMetadata};
@@ -385,14 +469,19 @@ mod tests {
     use health::HealthRegistry;
     use rand::distributions::Alphanumeric;
     use rand::Rng;
+    use rdkafka::consumer::{Consumer, StreamConsumer};
+    use rdkafka::message::{Headers, Message};
     use rdkafka::mocking::MockCluster;
     use rdkafka::producer::DefaultProducerContext;
     use rdkafka::types::{RDKafkaApiKey, RDKafkaRespErr};
+    use rdkafka::ClientConfig;
+    use std::collections::HashMap;
     use std::num::NonZeroU32;
     use time::Duration;
 
     async fn start_on_mocked_sink(
         message_max_bytes: Option<u32>,
+        partition_strategies: &str,
     ) -> (MockCluster<'static, DefaultProducerContext>, KafkaSink) {
         let registry = HealthRegistry::new("liveness");
         let handle = registry
@@ -417,6 +506,8 @@ mod tests {
             kafka_exceptions_topic: "events_plugin_ingestion".to_string(),
             kafka_heatmaps_topic: "events_plugin_ingestion".to_string(),
             kafka_replay_overflow_topic: "session_recording_snapshot_item_overflow".to_string(),
+            kafka_otel_topic: "otel_events_ingestion".to_string(),
+            kafka_partition_strategies: partition_strategies.to_string(),
             kafka_tls: false,
             kafka_client_id: "".to_string(),
             kafka_metadata_max_age_ms: 60000,
@@ -434,7 +525,7 @@ mod tests {
         // Uses a mocked Kafka broker that allows injecting write errors, to check error handling.
         // We test different cases in a single test to amortize the startup cost of the producer.
 
-        let (cluster, sink) = start_on_mocked_sink(Some(3000000)).await;
+        let (cluster, sink) = start_on_mocked_sink(Some(3000000), "").await;
         let distinct_id = "test_distinct_id_123".to_string();
         let event: CapturedEvent = CapturedEvent {
             uuid: uuid_v7(),
@@ -450,6 +541,9 @@ mod tests {
         let metadata = ProcessedEventMetadata {
             data_type: DataType::AnalyticsMain,
             session_id: None,
+            event_name: "test_event".to_string(),
+            timestamp: None,
+            lib_version: None,
         };
 
         let event = ProcessedEvent {
@@ -572,4 +666,102 @@ mod tests {
             Ok(()) => panic!("should have errored"),
         };
     }
+
+    #[tokio::test]
+    async fn kafka_sink_sets_headers_and_key() {
+        let uuid = uuid_v7();
+        let uuid_header = uuid.to_string();
+        let event = ProcessedEvent {
+            event: CapturedEvent {
+                uuid,
+                distinct_id: "id1".to_string(),
+                ip: "".to_string(),
+                data: "".to_string(),
+                now: "2024-01-01T00:00:01Z".to_string(),
+                sent_at: None,
+                token: "token1".to_string(),
+                is_cookieless_mode: false,
+            },
+            metadata: ProcessedEventMetadata {
+                data_type: DataType::AnalyticsMain,
+                session_id: Some("session1".to_string()),
+                event_name: "$pageview".to_string(),
+                timestamp: Some("2024-01-01T00:00:00Z".to_string()),
+                lib_version: Some("1.2.3".to_string()),
+            },
+        };
+
+        for (strategies, expected_key) in [
+            ("", Some("token1:id1")),
+            ("analytics_main:default", Some("token1:id1")),
+            ("analytics_main:token", Some("token1")),
+            ("analytics_main:session_id", Some("session1")),
+            ("analytics_main:random", None),
+        ] {
+            let (cluster, sink) = start_on_mocked_sink(None, strategies).await;
+            // Retried until the producer is healthy, as above
+            let mut sent = false;
+            for _ in 0..20 {
+                if sink.send(event.clone()).await.is_ok() {
+                    sent = true;
+                    break;
+                }
+            }
+            assert!(sent, "failed to send with {strategies:?}");
+
+            let consumer: StreamConsumer = ClientConfig::new()
+                .set("bootstrap.servers", cluster.bootstrap_servers())
+                .set("group.id", "mock")
+                .set("auto.offset.reset", "earliest")
+                .create()
+                .expect("failed to create mock consumer");
+            consumer.subscribe(&["events_plugin_ingestion"]).unwrap();
+            let message = consumer.recv().await.expect("failed to consume");
+
+            assert_eq!(
+                message.key().map(|k| std::str::from_utf8(k).unwrap()),
+                expected_key,
+                "wrong key with {strategies:?}"
+            );
+
+            let headers: HashMap<&str, &str> = message
+                .headers()
+                .expect("no headers")
+                .iter()
+                .map(|h| (h.key, std::str::from_utf8(h.value.unwrap()).unwrap()))
+                .collect();
+            assert_eq!(
+                headers,
+                HashMap::from([
+                    ("token", "token1"),
+                    ("distinct_id", "id1"),
+                    ("uuid", uuid_header.as_str()),
+                    ("event", "$pageview"),
+                    ("timestamp", "2024-01-01T00:00:00Z"),
+                    ("data_type", "analytics_main"),
+                    ("lib_version", "1.2.3"),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn partition_strategies_parsing() {
+        let strategies =
+            parse_partition_strategies("analytics_main:token; snapshot_main:random;").unwrap();
+        assert_eq!(
+            strategies.get(&DataType::AnalyticsMain),
+            Some(&PartitionStrategy::Token)
+        );
+        assert_eq!(
+            strategies.get(&DataType::SnapshotMain),
+            Some(&PartitionStrategy::Random)
+        );
+        assert_eq!(strategies.get(&DataType::HeatmapMain), None);
+
+        assert!(parse_partition_strategies("").unwrap().is_empty());
+        assert!(parse_partition_strategies("analytics_main").is_err());
+        assert!(parse_partition_strategies("analytics_main:nope").is_err());
+        assert!(parse_partition_strategies("unknown:token").is_err());
+    }
 }
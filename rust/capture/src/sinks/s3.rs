@@ -330,6 +330,9 @@ mod tests {
             metadata: ProcessedEventMetadata {
                 data_type: DataType::AnalyticsMain,
                 session_id: None,
+                event_name: "test_event".to_string(),
+                timestamp: None,
+                lib_version: None,
             },
         }
     }
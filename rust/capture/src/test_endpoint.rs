@@ -6,9 +6,9 @@ use axum::{
     http::{HeaderMap, Method},
     Json,
 };
-use axum_client_ip::InsecureClientIp;
 use base64::Engine;
 use bytes::{Buf, Bytes};
+use common_client_ip::ClientIp;
 use flate2::bufread::GzDecoder;
 use tracing::error;
 
@@ -32,7 +32,7 @@ pub const UTF8_FAILED: &str = "capture_test_utf8_failed";
 #[debug_handler]
 pub async fn test_black_hole(
     state: State<router::State>,
-    _ip: InsecureClientIp,
+    _ip: ClientIp,
     meta: Query<EventQuery>,
     headers: HeaderMap,
     _method: Method,
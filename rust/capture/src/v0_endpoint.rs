@@ -6,15 +6,17 @@ use bytes::Bytes;
 // TODO: stream this instead
 use axum::extract::{MatchedPath, Query, State};
 use axum::http::{HeaderMap, Method};
-use axum_client_ip::InsecureClientIp;
 use base64::Engine;
+use common_client_ip::ClientIp;
 use common_types::{CapturedEvent, RawEvent};
 use metrics::counter;
 use serde_json::json;
 use serde_json::Value;
 use tracing::instrument;
 
+use crate::enrichment::Enricher;
 use crate::limiters::token_dropper::TokenDropper;
+use crate::privacy::Privacy;
 use crate::prometheus::report_dropped_events;
 use crate::v0_request::{
     Compression, DataType, ProcessedEvent, ProcessedEventMetadata, ProcessingContext, RawRequest,
@@ -34,7 +36,7 @@ use crate::{
 /// endpoint should be created, that only accepts the BatchedRequest payload shape.
 async fn handle_common(
     state: &State<router::State>,
-    InsecureClientIp(ip): &InsecureClientIp,
+    ClientIp(ip): &ClientIp,
     meta: &EventQuery,
     headers: &HeaderMap,
     method: &Method,
@@ -152,7 +154,7 @@ async fn handle_common(
 #[debug_handler]
 pub async fn event(
     state: State<router::State>,
-    ip: InsecureClientIp,
+    ip: ClientIp,
     meta: Query<EventQuery>,
     headers: HeaderMap,
     method: Method,
@@ -173,11 +175,13 @@ pub async fn event(
             }))
         }
         Err(err) => Err(err),
-        Ok((context, events)) => {
+        Ok((context, mut events)) => {
             if let Err(err) = process_events(
                 state.sink.clone(),
                 state.token_dropper.clone(),
-                &events,
+                state.enricher.clone(),
+                state.privacy.clone(),
+                &mut events,
                 &context,
             )
             .await
@@ -217,7 +221,7 @@ pub async fn event(
 #[debug_handler]
 pub async fn recording(
     state: State<router::State>,
-    ip: InsecureClientIp,
+    ip: ClientIp,
     meta: Query<EventQuery>,
     headers: HeaderMap,
     method: Method,
@@ -232,7 +236,10 @@ pub async fn recording(
         Err(err) => Err(err),
         Ok((context, events)) => {
             let count = events.len() as u64;
-            if let Err(err) = process_replay_events(state.sink.clone(), events, &context).await {
+            if let Err(err) =
+                process_replay_events(state.sink.clone(), state.privacy.clone(), events, &context)
+                    .await
+            {
                 let cause = match err {
                     CaptureError::MissingDistinctId => "missing_distinct_id",
                     CaptureError::MissingSessionId => "missing_session_id",
@@ -289,7 +296,19 @@ pub fn process_single_event(
 
     let metadata = ProcessedEventMetadata {
         data_type,
-        session_id: None,
+        session_id: event
+            .properties
+            .get("$session_id")
+            .and_then(Value::as_str)
+            .map(String::from),
+        event_name: event.event.clone(),
+        timestamp: event.timestamp.clone(),
+        lib_version: event
+            .properties
+            .get("$lib_version")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .or_else(|| context.lib_version.clone()),
     };
 
     let event = CapturedEvent {
@@ -313,14 +332,31 @@ pub fn process_single_event(
 pub async fn process_events<'a>(
     sink: Arc<dyn sinks::Event + Send + Sync>,
     dropper: Arc<TokenDropper>,
-    events: &'a [RawEvent],
+    enricher: Arc<Enricher>,
+    privacy: Arc<Privacy>,
+    events: &'a mut [RawEvent],
     context: &'a ProcessingContext,
 ) -> Result<(), CaptureError> {
+    if enricher.is_enabled() {
+        for event in events.iter_mut() {
+            enricher.enrich(event, context);
+        }
+    }
+
+    // Enrichment needs the full IP, so privacy controls must run after it
+    for event in events.iter_mut() {
+        privacy.apply(&context.token, event, &context.now);
+    }
+
     let mut events: Vec<ProcessedEvent> = events
         .iter()
         .map(|e| process_single_event(e, context))
         .collect::<Result<Vec<ProcessedEvent>, CaptureError>>()?;
 
+    for e in events.iter_mut() {
+        e.event.ip = privacy.anonymize_ip(&e.event.token, &e.event.ip, &context.now);
+    }
+
     events.retain(|e| {
         if dropper.should_drop(&e.event.token, &e.event.distinct_id) {
             report_dropped_events("token_dropper", 1);
@@ -342,6 +378,7 @@ pub async fn process_events<'a>(
 #[instrument(skip_all, fields(events = events.len()))]
 pub async fn process_replay_events<'a>(
     sink: Arc<dyn sinks::Event + Send + Sync>,
+    privacy: Arc<Privacy>,
     mut events: Vec<RawEvent>,
     context: &'a ProcessingContext,
 ) -> Result<(), CaptureError> {
@@ -400,12 +437,15 @@ pub async fn process_replay_events<'a>(
                 .ok_or(CaptureError::InvalidSessionId)?
                 .to_string(),
         ),
+        event_name: String::from("$snapshot_items"),
+        timestamp: None,
+        lib_version: context.lib_version.clone(),
     };
 
     let event = CapturedEvent {
         uuid,
         distinct_id: distinct_id.clone(),
-        ip: context.client_ip.clone(),
+        ip: privacy.anonymize_ip(&context.token, &context.client_ip, &context.now),
         data: json!({
             "event": "$snapshot_items",
             "properties": {
@@ -206,7 +206,7 @@ pub struct ProcessingContext {
     pub historical_migration: bool,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum DataType {
     AnalyticsMain,
     AnalyticsHistorical,
@@ -214,6 +214,38 @@ pub enum DataType {
     HeatmapMain,
     ExceptionMain,
     SnapshotMain,
+    OtelMain,
+}
+
+impl DataType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataType::AnalyticsMain => "analytics_main",
+            DataType::AnalyticsHistorical => "analytics_historical",
+            DataType::ClientIngestionWarning => "client_ingestion_warning",
+            DataType::HeatmapMain => "heatmap_main",
+            DataType::ExceptionMain => "exception_main",
+            DataType::SnapshotMain => "snapshot_main",
+            DataType::OtelMain => "otel_main",
+        }
+    }
+}
+
+impl std::str::FromStr for DataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "analytics_main" => Ok(DataType::AnalyticsMain),
+            "analytics_historical" => Ok(DataType::AnalyticsHistorical),
+            "client_ingestion_warning" => Ok(DataType::ClientIngestionWarning),
+            "heatmap_main" => Ok(DataType::HeatmapMain),
+            "exception_main" => Ok(DataType::ExceptionMain),
+            "snapshot_main" => Ok(DataType::SnapshotMain),
+            "otel_main" => Ok(DataType::OtelMain),
+            _ => Err(format!("Unknown data type: {s}")),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -226,6 +258,10 @@ pub struct ProcessedEvent {
 pub struct ProcessedEventMetadata {
     pub data_type: DataType,
     pub session_id: Option<String>,
+    // Forwarded as Kafka headers, so that consumers don't need to parse the payload
+    pub event_name: String,
+    pub timestamp: Option<String>,
+    pub lib_version: Option<String>,
 }
 
 #[cfg(test)]
@@ -54,6 +54,8 @@ pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
         kafka_exceptions_topic: "events_plugin_ingestion".to_string(),
         kafka_heatmaps_topic: "events_plugin_ingestion".to_string(),
         kafka_replay_overflow_topic: "session_recording_snapshot_item_overflow".to_string(),
+        kafka_otel_topic: "otel_events_ingestion".to_string(),
+        kafka_partition_strategies: "".to_string(),
         kafka_tls: false,
         kafka_client_id: "".to_string(),
         kafka_metadata_max_age_ms: 60000,
@@ -72,6 +74,15 @@ pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
     s3_fallback_endpoint: None,
     s3_fallback_prefix: String::new(),
     healthcheck_strategy: HealthStrategy::All,
+    trusted_proxies: "".to_string(),
+    client_ip_headers: "x-forwarded-for".to_string(),
+    enrich_geoip: false,
+    maxmind_db_path: None,
+    enrich_user_agent: false,
+    ip_anonymization: None,
+    ip_hash_salt: None,
+    scrub_rules: None,
+    scrub_custom_patterns: None,
 });
 
 static TRACING_INIT: Once = Once::new();
@@ -6,13 +6,16 @@ use base64::engine::general_purpose;
 use base64::Engine;
 use capture::api::{CaptureError, CaptureResponse, CaptureResponseCode};
 use capture::config::CaptureMode;
+use capture::enrichment::Enricher;
 use capture::limiters::redis::{QuotaResource, RedisLimiter, QUOTA_LIMITER_CACHE_KEY};
 use capture::limiters::token_dropper::TokenDropper;
+use capture::privacy::Privacy;
 use capture::redis::MockRedisClient;
 use capture::router::router;
 use capture::sinks::Event;
 use capture::time::TimeSource;
 use capture::v0_request::{DataType, ProcessedEvent};
+use common_client_ip::TrustedProxies;
 use health::HealthRegistry;
 use serde::Deserialize;
 use serde_json::{json, Value};
@@ -119,6 +122,9 @@ async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
             redis,
             billing_limiter,
             TokenDropper::default(),
+            Enricher::default(),
+            Privacy::default(),
+            TrustedProxies::new("0.0.0.0/0,::/0", "x-forwarded-for").unwrap(),
             false,
             CaptureMode::Events,
             None,
@@ -0,0 +1,321 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ClientIpError {
+    #[error("invalid proxy CIDR: {0}")]
+    InvalidCidr(String),
+    #[error("unknown client IP header: {0}")]
+    UnknownSource(String),
+}
+
+/// A network range, e.g. `10.0.0.0/8`. A bare address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ClientIpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || ClientIpError::InvalidCidr(s.to_string());
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse::<u8>().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+/// Headers a trusted proxy can use to pass the client address along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpSource {
+    XForwardedFor,
+    XRealIp,
+    CfConnectingIp,
+    Forwarded,
+}
+
+impl FromStr for IpSource {
+    type Err = ClientIpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "x-forwarded-for" => Ok(IpSource::XForwardedFor),
+            "x-real-ip" => Ok(IpSource::XRealIp),
+            "cf-connecting-ip" => Ok(IpSource::CfConnectingIp),
+            "forwarded" => Ok(IpSource::Forwarded),
+            _ => Err(ClientIpError::UnknownSource(s.to_string())),
+        }
+    }
+}
+
+impl IpSource {
+    fn header_name(&self) -> &'static str {
+        match self {
+            IpSource::XForwardedFor => "x-forwarded-for",
+            IpSource::XRealIp => "x-real-ip",
+            IpSource::CfConnectingIp => "cf-connecting-ip",
+            IpSource::Forwarded => "forwarded",
+        }
+    }
+
+    /// Returns the addresses listed in the header, in hop order (client first).
+    fn addresses(&self, headers: &HeaderMap) -> Vec<IpAddr> {
+        let values = headers
+            .get_all(self.header_name())
+            .iter()
+            .filter_map(|v| v.to_str().ok());
+        match self {
+            IpSource::XForwardedFor => values
+                .flat_map(|v| v.split(','))
+                .filter_map(parse_ip)
+                .collect(),
+            IpSource::XRealIp | IpSource::CfConnectingIp => {
+                values.filter_map(parse_ip).take(1).collect()
+            }
+            IpSource::Forwarded => values
+                .flat_map(|v| v.split(','))
+                .filter_map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (key, value) = pair.split_once('=')?;
+                        key.trim()
+                            .eq_ignore_ascii_case("for")
+                            .then(|| parse_ip(value))?
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Parses addresses as found in forwarding headers: optionally quoted, bracketed, or with a port.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = IpAddr::from_str(value) {
+        return Some(ip);
+    }
+    if let Ok(addr) = SocketAddr::from_str(value) {
+        return Some(addr.ip());
+    }
+    IpAddr::from_str(value.trim_start_matches('[').trim_end_matches(']')).ok()
+}
+
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>,
+    sources: Vec<IpSource>,
+}
+
+impl TrustedProxies {
+    /// Takes comma-separated lists of proxy CIDRs and header names, headers being
+    /// checked in the given order.
+    pub fn new(proxies: &str, sources: &str) -> Result<Self, ClientIpError> {
+        Ok(Self {
+            proxies: split_list(proxies)
+                .map(Cidr::from_str)
+                .collect::<Result<_, _>>()?,
+            sources: split_list(sources)
+                .map(IpSource::from_str)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Returns the client address for a request received from `peer`. Headers are only
+    /// read if the peer is a trusted proxy, and multi-hop headers are walked from the
+    /// closest hop, stopping at the first address that isn't a trusted proxy.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        self.header_ip(headers).unwrap_or(peer)
+    }
+
+    fn header_ip(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        self.sources.iter().find_map(|source| {
+            let hops = source.addresses(headers);
+            hops.iter()
+                .rev()
+                .find(|ip| !self.is_trusted(**ip))
+                .or(hops.first())
+                .copied()
+        })
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// Extractor for the client IP, resolved through the `TrustedProxies` in the router state.
+///
+/// Requires the server to be started with `into_make_service_with_connect_info`. If the peer
+/// address is unavailable, as when the router is driven directly in tests, headers are read as
+/// if sent by a trusted proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    Arc<TrustedProxies>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let proxies = Arc::<TrustedProxies>::from_ref(state);
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        match peer {
+            Some(peer) => Ok(ClientIp(proxies.client_ip(peer, &parts.headers))),
+            None => proxies.header_ip(&parts.headers).map(ClientIp).ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Can't determine the client IP",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr = Cidr::from_str("10.0.0.0/8").unwrap();
+        assert!(cidr.contains(ip("10.1.2.3")));
+        assert!(cidr.contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr.contains(ip("11.0.0.1")));
+
+        let cidr = Cidr::from_str("2001:db8::/32").unwrap();
+        assert!(cidr.contains(ip("2001:db8:1::1")));
+        assert!(!cidr.contains(ip("2001:db9::1")));
+
+        assert!(Cidr::from_str("0.0.0.0/0").unwrap().contains(ip("1.2.3.4")));
+        assert!(Cidr::from_str("1.2.3.4").unwrap().contains(ip("1.2.3.4")));
+        assert!(Cidr::from_str("1.2.3.4/33").is_err());
+        assert!(Cidr::from_str("nope/8").is_err());
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let proxies = TrustedProxies::new("10.0.0.0/8", "x-forwarded-for").unwrap();
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1")]);
+        assert_eq!(proxies.client_ip(ip("2.2.2.2"), &headers), ip("2.2.2.2"));
+    }
+
+    #[test]
+    fn test_forwarded_for_skips_trusted_hops() {
+        let proxies = TrustedProxies::new("10.0.0.0/8", "x-forwarded-for").unwrap();
+        // Spoofed leftmost value must not be picked
+        let spoofed = headers(&[("x-forwarded-for", "6.6.6.6, 1.1.1.1, 10.0.0.2")]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &spoofed), ip("1.1.1.1"));
+
+        // Multiple headers are concatenated
+        let multi = headers(&[
+            ("x-forwarded-for", "6.6.6.6"),
+            ("x-forwarded-for", "1.1.1.1:1234, 10.0.0.2"),
+        ]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &multi), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn test_all_trusted_returns_leftmost() {
+        let proxies = TrustedProxies::new("0.0.0.0/0,::/0", "x-forwarded-for").unwrap();
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1, 2.2.2.2")]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &headers), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn test_source_order() {
+        let proxies =
+            TrustedProxies::new("10.0.0.0/8", "cf-connecting-ip, x-real-ip, forwarded").unwrap();
+        let all = headers(&[("x-real-ip", "2.2.2.2"), ("cf-connecting-ip", "3.3.3.3")]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &all), ip("3.3.3.3"));
+
+        let forwarded = headers(&[(
+            "forwarded",
+            r#"for=192.0.2.60;proto=http;by=10.0.0.3, for="[2001:db8:cafe::17]:4711""#,
+        )]);
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), &forwarded),
+            ip("2001:db8:cafe::17")
+        );
+
+        // Falls back to the peer if no header is present
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert_eq!(
+            TrustedProxies::new("10.0.0.0/8", "x-client-ip").unwrap_err(),
+            ClientIpError::UnknownSource("x-client-ip".to_string())
+        );
+        assert!(TrustedProxies::new("10.0.0.0/99", "").is_err());
+    }
+}
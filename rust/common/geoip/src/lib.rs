@@ -1,14 +1,11 @@
-use crate::config::Config;
 use maxminddb::Reader;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::path::Path;
 use std::str::FromStr;
 use thiserror::Error;
-use tracing::{
-    debug,
-    log::{error, info},
-};
+use tracing::{debug, info};
 
 #[derive(Error, Debug)]
 pub enum GeoIpError {
@@ -23,12 +20,12 @@ pub struct GeoIpClient {
 impl GeoIpClient {
     /// Creates a new GeoIpClient instance.
     /// Returns an error if the database can't be loaded.
-    pub fn new(config: &Config) -> Result<Self, GeoIpError> {
-        let geoip_path = config.get_maxmind_db_path();
+    pub fn new(geoip_path: impl AsRef<Path>) -> Result<Self, GeoIpError> {
+        let geoip_path = geoip_path.as_ref();
 
         info!("Attempting to open GeoIP database at: {:?}", geoip_path);
 
-        let reader = Reader::open_readfile(&geoip_path)?;
+        let reader = Reader::open_readfile(geoip_path)?;
         info!("Successfully opened GeoIP database");
 
         Ok(GeoIpClient { reader })
@@ -44,7 +41,7 @@ impl GeoIpClient {
     fn lookup_city(&self, ip: &str, addr: IpAddr) -> Option<Value> {
         match self.reader.lookup::<Value>(addr) {
             Ok(city) => {
-                info!(
+                debug!(
                     "GeoIP lookup succeeded for IP {}: Full city data: {:?}",
                     ip, city
                 );
@@ -64,7 +61,7 @@ impl GeoIpClient {
         let ip = match ip_address {
             Some(ip) if self.is_valid_ip(ip) => ip,
             _ => {
-                info!("No valid IP address provided; returning empty properties");
+                debug!("No valid IP address provided; returning empty properties");
                 return HashMap::new();
             }
         };
@@ -116,42 +113,34 @@ mod tests {
     use serde_json::json;
 
     use super::*;
-    use crate::config::Config;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
-
-    fn initialize() {
-        INIT.call_once(|| {
-            tracing_subscriber::fmt::init();
-        });
+    use std::path::PathBuf;
+
+    // The database the services use in development, see share/
+    fn test_db_path() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../..")
+            .join("share")
+            .join("GeoLite2-City.mmdb")
     }
 
     fn create_test_service() -> GeoIpClient {
-        let config = Config::default_test_config();
-        GeoIpClient::new(&config).expect("Failed to create GeoIpService")
+        GeoIpClient::new(test_db_path()).expect("Failed to create GeoIpService")
     }
 
     #[test]
     fn test_geoip_service_creation() {
-        initialize();
-        let config = Config::default_test_config();
-        let service_result = GeoIpClient::new(&config);
+        let service_result = GeoIpClient::new(test_db_path());
         assert!(service_result.is_ok());
     }
 
     #[test]
     fn test_geoip_service_creation_failure() {
-        initialize();
-        let mut config = Config::default_test_config();
-        config.maxmind_db_path = "/path/to/nonexistent/file".to_string();
-        let service_result = GeoIpClient::new(&config);
+        let service_result = GeoIpClient::new("/path/to/nonexistent/file");
         assert!(service_result.is_err());
     }
 
     #[test]
     fn test_get_geoip_properties_none() {
-        initialize();
         let service = create_test_service();
         let result = service.get_geoip_properties(None);
         assert!(result.is_empty());
@@ -159,7 +148,6 @@ mod tests {
 
     #[test]
     fn test_get_geoip_properties_localhost() {
-        initialize();
         let service = create_test_service();
         let result = service.get_geoip_properties(Some("127.0.0.1"));
         assert!(result.is_empty());
@@ -167,7 +155,6 @@ mod tests {
 
     #[test]
     fn test_get_geoip_properties_invalid_ip() {
-        initialize();
         let service = create_test_service();
         let result = service.get_geoip_properties(Some("not_an_ip"));
         assert!(result.is_empty());
@@ -175,7 +162,6 @@ mod tests {
 
     #[test]
     fn test_geoip_results() {
-        initialize();
         let service = create_test_service();
         let test_cases = vec![
             ("13.106.122.3", "Australia"),
@@ -186,12 +172,6 @@ mod tests {
 
         for (ip, expected_country) in test_cases {
             let result = service.get_geoip_properties(Some(ip));
-            info!("GeoIP lookup result for IP {}: {:?}", ip, result);
-            info!(
-                "Expected country: {}, Actual country: {:?}",
-                expected_country,
-                result.get("$geoip_country_name")
-            );
             assert_eq!(
                 result.get("$geoip_country_name"),
                 Some(&expected_country.to_string())
@@ -202,7 +182,6 @@ mod tests {
 
     #[test]
     fn test_geoip_on_local_ip() {
-        initialize();
         let service = create_test_service();
         let result = service.get_geoip_properties(Some("127.0.0.1"));
         assert!(result.is_empty());
@@ -210,7 +189,6 @@ mod tests {
 
     #[test]
     fn test_geoip_on_invalid_ip() {
-        initialize();
         let service = create_test_service();
         let result = service.get_geoip_properties(Some("999.999.999.999"));
         assert!(result.is_empty());
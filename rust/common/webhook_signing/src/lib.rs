@@ -0,0 +1,237 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+pub const ID_HEADER: &str = "webhook-id";
+pub const TIMESTAMP_HEADER: &str = "webhook-timestamp";
+pub const SIGNATURE_HEADER: &str = "webhook-signature";
+
+const SIGNATURE_VERSION: &str = "v1";
+const MAX_ACTIVE_KEYS: usize = 2;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SigningKeysError {
+    #[error("at least one signing secret is required")]
+    NoKeys,
+    #[error("at most {MAX_ACTIVE_KEYS} signing secrets can be active, got {0}")]
+    TooManyKeys(usize),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    #[error("invalid {TIMESTAMP_HEADER} header")]
+    InvalidTimestamp,
+    #[error("timestamp is outside of the tolerance window")]
+    TimestampOutOfTolerance,
+    #[error("no signature matched")]
+    NoMatchingSignature,
+}
+
+/// The active secrets for a destination: one normally, two while rotating.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SigningKeys {
+    secrets: Vec<Vec<u8>>,
+}
+
+impl SigningKeys {
+    pub fn new<S: AsRef<[u8]>>(secrets: &[S]) -> Result<Self, SigningKeysError> {
+        match secrets.len() {
+            0 => Err(SigningKeysError::NoKeys),
+            n if n > MAX_ACTIVE_KEYS => Err(SigningKeysError::TooManyKeys(n)),
+            _ => Ok(Self {
+                secrets: secrets.iter().map(|s| s.as_ref().to_vec()).collect(),
+            }),
+        }
+    }
+
+    /// Build the `webhook-signature` header value for a request, with one signature per active
+    /// secret. `id` identifies the delivery and must be sent as `webhook-id`, and `timestamp` is in
+    /// seconds since the epoch, and must be sent as `webhook-timestamp`.
+    pub fn sign(&self, id: &str, timestamp: i64, body: &[u8]) -> String {
+        self.secrets
+            .iter()
+            .map(|secret| {
+                let signature =
+                    STANDARD.encode(mac(secret, id, timestamp, body).finalize().into_bytes());
+                format!("{SIGNATURE_VERSION},{signature}")
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Check the headers of a received webhook against our secrets. The request is accepted if
+    /// any of its signatures was made with any of our secrets, and its timestamp is within
+    /// `tolerance` of `now` (seconds since the epoch).
+    pub fn verify(
+        &self,
+        id_header: &str,
+        timestamp_header: &str,
+        signature_header: &str,
+        body: &[u8],
+        now: i64,
+        tolerance: Duration,
+    ) -> Result<(), VerifyError> {
+        let timestamp = timestamp_header
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| VerifyError::InvalidTimestamp)?;
+        if now.abs_diff(timestamp) > tolerance.as_secs() {
+            return Err(VerifyError::TimestampOutOfTolerance);
+        }
+
+        let signatures: Vec<Vec<u8>> = signature_header
+            .split_whitespace()
+            .filter_map(|s| s.split_once(','))
+            .filter(|(version, _)| *version == SIGNATURE_VERSION)
+            .filter_map(|(_, signature)| STANDARD.decode(signature).ok())
+            .collect();
+
+        for secret in &self.secrets {
+            for signature in &signatures {
+                // verify_slice compares in constant time.
+                if mac(secret, id_header, timestamp, body)
+                    .verify_slice(signature)
+                    .is_ok()
+                {
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(VerifyError::NoMatchingSignature)
+    }
+}
+
+/// Parse a comma separated list of secrets, e.g. `new-secret,old-secret`.
+impl FromStr for SigningKeys {
+    type Err = SigningKeysError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let secrets: Vec<&str> = s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+        Self::new(&secrets)
+    }
+}
+
+/// Never print the secrets themselves.
+impl fmt::Debug for SigningKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKeys")
+            .field("secrets", &self.secrets.len())
+            .finish()
+    }
+}
+
+fn mac(secret: &[u8], id: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    mac.update(b".");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "msg_1";
+    const NOW: i64 = 1_700_000_000;
+    const TOLERANCE: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keys: SigningKeys = "secret".parse().unwrap();
+        let signature = keys.sign(ID, NOW, b"{\"a\":1}");
+
+        assert!(signature.starts_with("v1,"));
+        assert_eq!(
+            keys.verify(
+                ID,
+                &NOW.to_string(),
+                &signature,
+                b"{\"a\":1}",
+                NOW,
+                TOLERANCE
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            keys.verify(
+                ID,
+                &NOW.to_string(),
+                &signature,
+                b"{\"a\":2}",
+                NOW,
+                TOLERANCE
+            ),
+            Err(VerifyError::NoMatchingSignature)
+        );
+        assert_eq!(
+            keys.verify(
+                "msg_2",
+                &NOW.to_string(),
+                &signature,
+                b"{\"a\":1}",
+                NOW,
+                TOLERANCE
+            ),
+            Err(VerifyError::NoMatchingSignature)
+        );
+        assert_eq!(
+            keys.verify(ID, "1", &signature, b"{\"a\":1}", NOW, TOLERANCE),
+            Err(VerifyError::TimestampOutOfTolerance)
+        );
+        assert_eq!(
+            keys.verify(ID, "nope", &signature, b"{\"a\":1}", NOW, TOLERANCE),
+            Err(VerifyError::InvalidTimestamp)
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let sender: SigningKeys = "new,old".parse().unwrap();
+        let signature = sender.sign(ID, NOW, b"body");
+        assert_eq!(signature.split(' ').count(), 2);
+
+        // Receivers that only know one of the secrets still accept the request.
+        for secrets in ["old", "new", "other,new"] {
+            let receiver: SigningKeys = secrets.parse().unwrap();
+            assert_eq!(
+                receiver.verify(ID, &NOW.to_string(), &signature, b"body", NOW, TOLERANCE),
+                Ok(())
+            );
+        }
+
+        let receiver: SigningKeys = "other".parse().unwrap();
+        assert_eq!(
+            receiver.verify(ID, &NOW.to_string(), &signature, b"body", NOW, TOLERANCE),
+            Err(VerifyError::NoMatchingSignature)
+        );
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!("".parse::<SigningKeys>(), Err(SigningKeysError::NoKeys));
+        assert_eq!(
+            "a,b,c".parse::<SigningKeys>(),
+            Err(SigningKeysError::TooManyKeys(3))
+        );
+        assert_eq!(
+            format!("{:?}", "a, b".parse::<SigningKeys>().unwrap()),
+            "SigningKeys { secrets: 2 }"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+-- Jobs waiting for their parents to finish, see the job dependencies migration. This has its own
+-- migration because new enum values can't be used in the transaction that adds them.
+ALTER TYPE JobState ADD VALUE IF NOT EXISTS 'waiting';
@@ -0,0 +1,26 @@
+-- Jobs can depend on other jobs. A job with dependencies is created in the 'waiting' state, and
+-- made available once all of its parents have finished. If a parent fails, the job's
+-- on_parent_failure policy decides whether it's failed without running, or run anyway.
+CREATE TYPE ParentFailurePolicy AS ENUM(
+    'fail',
+    'run'
+);
+
+ALTER TABLE cyclotron_jobs
+    ADD COLUMN on_parent_failure ParentFailurePolicy NOT NULL DEFAULT 'fail';
+
+CREATE TABLE IF NOT EXISTS cyclotron_job_dependencies (
+    job_id UUID NOT NULL REFERENCES cyclotron_jobs(id) ON DELETE CASCADE,
+    -- Not a foreign key, as the janitor deletes finished parents before their dependents are done.
+    -- A parent that no longer exists has finished.
+    parent_id UUID NOT NULL,
+    PRIMARY KEY (job_id, parent_id)
+);
+
+-- When a job finishes, we look up the jobs waiting on it
+CREATE INDEX idx_cyclotron_job_dependencies_parent_id ON cyclotron_job_dependencies(parent_id);
+
+-- The janitor periodically resolves waiting jobs
+CREATE INDEX idx_cyclotron_jobs_waiting ON cyclotron_jobs(state)
+WHERE
+    state = 'waiting';
@@ -0,0 +1,26 @@
+-- Recurring jobs. The janitor materialises a job from each schedule whose next_run has passed,
+-- and advances next_run, in the same transaction.
+CREATE TABLE IF NOT EXISTS cyclotron_schedules (
+    id UUID PRIMARY KEY,
+    team_id INT NOT NULL,
+    function_id UUID,
+    created TIMESTAMPTZ NOT NULL,
+    queue_name TEXT NOT NULL,
+    priority SMALLINT NOT NULL,
+    -- A cron expression, with or without a leading seconds field, evaluated in `timezone`
+    cron TEXT NOT NULL,
+    timezone TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    next_run TIMESTAMPTZ NOT NULL,
+    last_run TIMESTAMPTZ,
+    vm_state BYTEA,
+    metadata BYTEA,
+    parameters BYTEA,
+    blob BYTEA
+);
+
+CREATE INDEX IF NOT EXISTS idx_cyclotron_schedules_next_run ON cyclotron_schedules (next_run)
+WHERE
+    enabled;
+
+CREATE INDEX IF NOT EXISTS idx_cyclotron_schedules_team_id ON cyclotron_schedules (team_id);
@@ -0,0 +1,4 @@
+-- Lets fair dequeues find the teams with available jobs in a queue without scanning every job
+CREATE INDEX IF NOT EXISTS idx_cyclotron_jobs_available_teams ON cyclotron_jobs (queue_name, team_id)
+WHERE
+    state = 'available';
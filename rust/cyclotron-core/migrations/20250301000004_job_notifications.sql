@@ -0,0 +1,24 @@
+-- Wake workers listening on the cyclotron_jobs channel whenever a job becomes available, with the
+-- job's queue name as the payload. Postgres collapses identical notifications within a
+-- transaction, so bulk inserts notify once per queue.
+CREATE OR REPLACE FUNCTION cyclotron_notify_job_available() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('cyclotron_jobs', NEW.queue_name);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER cyclotron_jobs_notify_insert
+AFTER INSERT ON cyclotron_jobs
+FOR EACH ROW
+WHEN (NEW.state = 'available')
+EXECUTE FUNCTION cyclotron_notify_job_available();
+
+CREATE TRIGGER cyclotron_jobs_notify_available
+AFTER UPDATE OF state, queue_name ON cyclotron_jobs
+FOR EACH ROW
+WHEN (
+    NEW.state = 'available'
+    AND (OLD.state <> 'available' OR OLD.queue_name <> NEW.queue_name)
+)
+EXECUTE FUNCTION cyclotron_notify_job_available();
@@ -0,0 +1,13 @@
+-- Idempotency keys outlive the jobs that claimed them, so producers retrying after a job has
+-- already finished and been cleaned up still don't create a duplicate. The janitor deletes keys
+-- once they're older than its retention window, after which they can be reused.
+CREATE TABLE IF NOT EXISTS cyclotron_idempotency_keys (
+    team_id INT NOT NULL,
+    queue_name TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    job_id UUID NOT NULL,
+    created TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (team_id, queue_name, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_cyclotron_idempotency_keys_created ON cyclotron_idempotency_keys (created);
@@ -0,0 +1,21 @@
+-- cyclotron_jobs can be partitioned by state, so workers only ever scan the unfinished jobs, and
+-- deleting finished jobs doesn't bloat the table they dequeue from. Finished jobs are further
+-- partitioned by the hour of their last transition, so the janitor can drop a whole hour of them at
+-- once rather than deleting them row by row.
+--
+-- Rewriting the jobs table in a migration would block every worker until it's done, so this is
+-- opt-in, and done by the janitor while workers keep running, see `Janitor::partition_jobs_table`.
+-- It builds a partitioned copy of the table, cyclotron_jobs_partitioned, and backfills it a batch
+-- at a time, while this trigger function keeps rows already copied up to date. Once the copy is
+-- complete, the two tables are swapped.
+CREATE OR REPLACE FUNCTION cyclotron_mirror_job() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        DELETE FROM cyclotron_jobs_partitioned WHERE id = OLD.id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        INSERT INTO cyclotron_jobs_partitioned SELECT NEW.*;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,68 @@
+-- Finished jobs are deleted by the janitor soon after they finish, so whoever created a job has to
+-- catch it before then to see how it turned out. Queues listed in cyclotron_result_retention have
+-- the final state, parameters and blob of every job that finishes in them copied into
+-- cyclotron_job_results, where they're kept for the queue's retention, and the janitor deletes
+-- them once they expire.
+CREATE TABLE IF NOT EXISTS cyclotron_result_retention (
+    queue_name TEXT PRIMARY KEY,
+    retention_seconds BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS cyclotron_job_results (
+    job_id UUID PRIMARY KEY,
+    team_id INT NOT NULL,
+    function_id UUID,
+    queue_name TEXT NOT NULL,
+    state JobState NOT NULL,
+    parameters BYTEA,
+    blob BYTEA,
+    finished TIMESTAMPTZ NOT NULL,
+    expires TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_cyclotron_job_results_expires ON cyclotron_job_results (expires);
+
+CREATE OR REPLACE FUNCTION cyclotron_retain_job_result() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO cyclotron_job_results
+        (job_id, team_id, function_id, queue_name, state, parameters, blob, finished, expires)
+    SELECT
+        NEW.id,
+        NEW.team_id,
+        NEW.function_id,
+        NEW.queue_name,
+        NEW.state,
+        NEW.parameters,
+        NEW.blob,
+        NEW.last_transition,
+        NEW.last_transition + r.retention_seconds * INTERVAL '1 second'
+    FROM cyclotron_result_retention r
+    WHERE r.queue_name = NEW.queue_name
+    ON CONFLICT (job_id) DO UPDATE SET
+        state = EXCLUDED.state,
+        parameters = EXCLUDED.parameters,
+        blob = EXCLUDED.blob,
+        finished = EXCLUDED.finished,
+        expires = EXCLUDED.expires;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- If the jobs table is partitioned, finishing a job moves it into cyclotron_jobs_finished, which
+-- fires insert triggers rather than update triggers, see the partitioning migration. Otherwise, it
+-- fires the update trigger. Jobs are never moved between finished states, so between them these
+-- catch every job exactly once.
+CREATE TRIGGER cyclotron_jobs_retain_result
+AFTER INSERT ON cyclotron_jobs
+FOR EACH ROW
+WHEN (NEW.state IN ('completed', 'failed'))
+EXECUTE FUNCTION cyclotron_retain_job_result();
+
+CREATE TRIGGER cyclotron_jobs_retain_result_update
+AFTER UPDATE OF state ON cyclotron_jobs
+FOR EACH ROW
+WHEN (
+    NEW.state IN ('completed', 'failed')
+    AND OLD.state NOT IN ('completed', 'failed')
+)
+EXECUTE FUNCTION cyclotron_retain_job_result();
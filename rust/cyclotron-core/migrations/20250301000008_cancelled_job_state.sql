@@ -0,0 +1,4 @@
+-- Jobs cancelled by `QueueManager::cancel_jobs`, which finish like failed jobs, but aren't counted
+-- as failures. This has its own migration because new enum values can't be used in the
+-- transaction that adds them.
+ALTER TYPE JobState ADD VALUE IF NOT EXISTS 'cancelled';
@@ -0,0 +1,19 @@
+-- Cancelled jobs have finished too, so their results are kept like those of completed and failed
+-- jobs, see the job results migration.
+DROP TRIGGER IF EXISTS cyclotron_jobs_retain_result ON cyclotron_jobs;
+DROP TRIGGER IF EXISTS cyclotron_jobs_retain_result_update ON cyclotron_jobs;
+
+CREATE TRIGGER cyclotron_jobs_retain_result
+AFTER INSERT ON cyclotron_jobs
+FOR EACH ROW
+WHEN (NEW.state IN ('completed', 'failed', 'cancelled'))
+EXECUTE FUNCTION cyclotron_retain_job_result();
+
+CREATE TRIGGER cyclotron_jobs_retain_result_update
+AFTER UPDATE OF state ON cyclotron_jobs
+FOR EACH ROW
+WHEN (
+    NEW.state IN ('completed', 'failed', 'cancelled')
+    AND OLD.state NOT IN ('completed', 'failed', 'cancelled')
+)
+EXECUTE FUNCTION cyclotron_retain_job_result();
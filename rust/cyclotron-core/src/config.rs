@@ -53,6 +53,10 @@ pub struct WorkerConfig {
     pub max_bytes_buffered: Option<usize>, // Defaults to 10MB
     #[serde(alias = "flushLoopIntervalMs")]
     pub flush_loop_interval_ms: Option<u64>, // Defaults to 10
+    #[serde(alias = "fairDequeue")]
+    pub fair_dequeue: Option<bool>, // Defaults to false - take turns between teams when dequeuing
+    #[serde(alias = "maxRunningPerTeam")]
+    pub max_running_per_team: Option<u32>, // Defaults to no limit. Implies fair dequeuing
 }
 
 impl WorkerConfig {
@@ -75,4 +79,8 @@ impl WorkerConfig {
     pub fn max_bytes_buffered(&self) -> usize {
         self.max_bytes_buffered.unwrap_or(10_000_000)
     }
+
+    pub fn fair_dequeue(&self) -> bool {
+        self.fair_dequeue.unwrap_or(false) || self.max_running_per_team.is_some()
+    }
 }
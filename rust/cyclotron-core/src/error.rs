@@ -10,6 +10,10 @@ pub enum QueueError {
     TimedOutWaitingForCapacity,
     #[error(transparent)]
     JobError(#[from] JobError),
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+    #[error("Unknown schedule id: {0}")]
+    UnknownScheduleId(Uuid),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -25,3 +29,16 @@ pub enum JobError {
     #[error("Update dropped before being flushed.")]
     UpdateDropped,
 }
+
+impl QueueError {
+    // Updating a row in a partitioned table can move it to another partition, and anything waiting
+    // on its lock then fails with a serialization failure rather than seeing the updated row. The
+    // jobs table is partitioned by state, so this happens whenever a job finishes under someone
+    // else's lock. Retrying sees the job where it ended up.
+    pub fn is_serialization_failure(&self) -> bool {
+        match self {
+            QueueError::SqlxError(sqlx::Error::Database(e)) => e.code().as_deref() == Some("40001"),
+            _ => false,
+        }
+    }
+}
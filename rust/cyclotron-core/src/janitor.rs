@@ -1,16 +1,40 @@
 use crate::DEAD_LETTER_QUEUE;
-use chrono::Duration;
+use chrono::{Duration, Utc};
 use sqlx::PgPool;
+use tracing::info;
+use uuid::Uuid;
 
 use crate::{
     ops::{
-        janitor::{delete_completed_and_failed_jobs, detect_poison_pills, reset_stalled_jobs},
-        meta::{count_total_waiting_jobs, dead_letter, run_migrations},
+        admin::{list_dead_letters, oldest_available_jobs, queue_depths, requeue_dead_letter},
+        dependencies::resolve_missed_waiting_jobs,
+        janitor::{
+            create_finished_partitions, delete_completed_and_failed_jobs,
+            delete_expired_idempotency_keys, delete_unpartitioned_finished_jobs,
+            detect_poison_pills, drop_finished_partitions, reset_stalled_jobs,
+        },
+        meta::{
+            count_total_waiting_jobs, dead_letter, retry_serialization_failures, run_migrations,
+        },
+        partitioning::{
+            backfill_partitioned_jobs, jobs_table_partitioned, prepare_partitioned_jobs,
+            swap_partitioned_jobs,
+        },
+        results::delete_expired_results,
+        schedules::materialise_due_schedules,
     },
-    types::AggregatedDelete,
+    types::{AggregatedDelete, DeadLetter, OldestAvailableJob, QueueDepth},
     PoolConfig, QueueError,
 };
 
+// How far ahead hourly partitions for finished jobs are created, so a janitor that's down for a
+// while doesn't leave jobs piling up in the default partition
+const FINISHED_PARTITIONS_AHEAD_HOURS: i64 = 3;
+
+// How long after a partition's hour ends before it's dropped. A job's last transition is the start
+// of the transaction that finished it, so this has to outlast any transaction finishing jobs.
+const FINISHED_PARTITION_GRACE_MINUTES: i64 = 10;
+
 // Thin layer on top of the raw janitor operations - mostly just avoids users having to take a dep on sqlx
 pub struct Janitor {
     pub pool: PgPool,
@@ -36,6 +60,112 @@ impl Janitor {
         delete_completed_and_failed_jobs(&self.pool).await
     }
 
+    pub async fn jobs_table_partitioned(&self) -> Result<bool, QueueError> {
+        jobs_table_partitioned(&self.pool).await
+    }
+
+    // Converts the jobs table to the partitioned layout `rotate_finished_partitions` needs, while
+    // workers keep running. Jobs are copied to a partitioned table `batch_size` at a time, and the
+    // tables swapped once they all have been, which only blocks workers for as long as it takes to
+    // drop the old one. Writing jobs costs twice as much until then. Safe to call again if it
+    // fails part way, and does nothing if the table is already partitioned.
+    pub async fn partition_jobs_table(&self, batch_size: i64) -> Result<(), QueueError> {
+        let mut txn = self.pool.begin().await?;
+        let prepared = prepare_partitioned_jobs(&mut txn).await?;
+        txn.commit().await?;
+        if !prepared {
+            return Ok(());
+        }
+
+        let mut last = None;
+        let mut batches = 0;
+        while let Some(id) = backfill_partitioned_jobs(&self.pool, last, batch_size).await? {
+            last = Some(id);
+            batches += 1;
+            if batches % 100 == 0 {
+                info!(
+                    "Copied {} batches of jobs to the partitioned table",
+                    batches
+                );
+            }
+        }
+
+        let mut txn = self.pool.begin().await?;
+        swap_partitioned_jobs(&mut txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    // An alternative to `delete_completed_and_failed_jobs`, for when finished jobs should be
+    // dropped an hour at a time rather than deleted row by row. Finished jobs are kept until the
+    // hour they finished in is over. Returns the dropped and deleted jobs, aggregated the same way.
+    // Needs the jobs table to be partitioned, see `partition_jobs_table`.
+    pub async fn rotate_finished_partitions(&self) -> Result<Vec<AggregatedDelete>, QueueError> {
+        let now = Utc::now();
+
+        let mut txn = self.pool.begin().await?;
+        create_finished_partitions(
+            &mut txn,
+            now + Duration::hours(FINISHED_PARTITIONS_AHEAD_HOURS),
+        )
+        .await?;
+        txn.commit().await?;
+
+        let mut txn = self.pool.begin().await?;
+        let mut deleted = drop_finished_partitions(
+            &mut txn,
+            now - Duration::minutes(FINISHED_PARTITION_GRACE_MINUTES),
+        )
+        .await?;
+        txn.commit().await?;
+
+        deleted.extend(delete_unpartitioned_finished_jobs(&self.pool).await?);
+        Ok(deleted)
+    }
+
+    // Resolves waiting jobs whose parents finished without resolving them, `batch_size` at a time.
+    // Run before deleting finished jobs, since a deleted parent can't be told apart from a
+    // completed one.
+    pub async fn resolve_waiting_jobs(&self, batch_size: i64) -> Result<u64, QueueError> {
+        let mut resolved = 0;
+        loop {
+            let (taken, batch_resolved) = retry_serialization_failures(|| async move {
+                let mut txn = self.pool.begin().await?;
+                let batch = resolve_missed_waiting_jobs(&mut txn, batch_size).await?;
+                txn.commit().await?;
+                Ok::<_, QueueError>(batch)
+            })
+            .await?;
+            resolved += batch_resolved;
+            if (taken as i64) < batch_size {
+                return Ok(resolved);
+            }
+        }
+    }
+
+    // Creates jobs for up to `limit` due schedules. Safe to run from multiple janitors at once,
+    // only one of them will create any jobs.
+    pub async fn run_schedules(&self, limit: i64) -> Result<u64, QueueError> {
+        let mut txn = self.pool.begin().await?;
+        let created = materialise_due_schedules(&mut txn, limit).await?;
+        txn.commit().await?;
+        Ok(created)
+    }
+
+    // Keys older than `retention` can be reused to create new jobs
+    pub async fn delete_expired_idempotency_keys(
+        &self,
+        retention: Duration,
+    ) -> Result<u64, QueueError> {
+        delete_expired_idempotency_keys(&self.pool, retention).await
+    }
+
+    // Results are kept for the retention of the queue they finished in, see
+    // `QueueManager::set_result_retention`
+    pub async fn delete_expired_results(&self) -> Result<u64, QueueError> {
+        delete_expired_results(&self.pool).await
+    }
+
     pub async fn reset_stalled_jobs(&self, timeout: Duration) -> Result<u64, QueueError> {
         reset_stalled_jobs(&self.pool, timeout).await
     }
@@ -74,4 +204,28 @@ impl Janitor {
 
         Ok(result.unwrap_or(0) as u64)
     }
+
+    pub async fn queue_depths(&self) -> Result<Vec<QueueDepth>, QueueError> {
+        queue_depths(&self.pool).await
+    }
+
+    pub async fn oldest_available_jobs(&self) -> Result<Vec<OldestAvailableJob>, QueueError> {
+        oldest_available_jobs(&self.pool).await
+    }
+
+    pub async fn list_dead_letters(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DeadLetter>, QueueError> {
+        list_dead_letters(&self.pool, limit, offset).await
+    }
+
+    // Returns false if the job isn't in the dead letter queue
+    pub async fn requeue_dead_letter(&self, job: Uuid) -> Result<bool, QueueError> {
+        let mut txn = self.pool.begin().await?;
+        let requeued = requeue_dead_letter(&mut txn, job).await?;
+        txn.commit().await?;
+        Ok(requeued)
+    }
 }
@@ -6,10 +6,18 @@ mod ops;
 mod types;
 pub use types::AggregatedDelete;
 pub use types::Bytes;
+pub use types::DeadLetter;
 pub use types::Job;
+pub use types::JobFilter;
 pub use types::JobInit;
 pub use types::JobState;
+pub use types::JobStatus;
 pub use types::JobUpdate;
+pub use types::OldestAvailableJob;
+pub use types::ParentFailurePolicy;
+pub use types::QueueDepth;
+pub use types::Schedule;
+pub use types::ScheduleInit;
 
 // Errors
 mod error;
@@ -23,6 +31,7 @@ mod manager;
 pub use manager::QueueManager;
 
 // Worker
+mod notifier;
 mod worker;
 // A handle to a released job update, that can be awaited to block waiting for the flush to complete
 pub use worker::FlushHandle;
@@ -8,10 +8,19 @@ use uuid::Uuid;
 use crate::{
     config::{DEFAULT_QUEUE_DEPTH_LIMIT, DEFAULT_SHARD_HEALTH_CHECK_INTERVAL},
     ops::{
-        manager::{bulk_create_jobs, create_job},
-        meta::count_total_waiting_jobs,
+        dependencies::{create_dependencies, job_exists},
+        manager::{
+            bulk_create_jobs, cancel_jobs, create_job, insert_jobs, reschedule_jobs,
+            set_jobs_paused,
+        },
+        meta::{count_total_waiting_jobs, retry_serialization_failures},
+        results::{get_job_status, set_result_retention},
+        schedules::{
+            create_schedule, delete_schedule, get_schedule, list_schedules, set_schedule_enabled,
+            update_schedule,
+        },
     },
-    JobInit, ManagerConfig, QueueError,
+    JobFilter, JobInit, JobStatus, ManagerConfig, QueueError, Schedule, ScheduleInit,
 };
 
 pub struct Shard {
@@ -60,13 +69,15 @@ impl QueueManager {
         }
     }
 
+    // Jobs with dependencies can only depend on jobs in the same shard, so they're created in
+    // the shard their first parent is in. Jobs with a missing parent are treated as though the
+    // parent finished, which is also how a parent that was already cleaned up looks.
     pub async fn create_job(&self, init: JobInit) -> Result<Uuid, QueueError> {
         // TODO - here is where a lot of shard health and failover logic will go, eventually.
-        let next = self
-            .next_shard
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let shards = self.shards.read().await;
-        let shard = &shards[next % shards.len()];
+        let shard = &shards[self
+            .pick_shard(&shards, std::slice::from_ref(&init))
+            .await?];
         shard.create_job(init).await
     }
 
@@ -75,20 +86,27 @@ impl QueueManager {
         init: JobInit,
         timeout: Option<Duration>,
     ) -> Result<Uuid, QueueError> {
-        let next = self
-            .next_shard
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let shards = self.shards.read().await;
-        let shard = &shards[next % shards.len()];
+        let shard = &shards[self
+            .pick_shard(&shards, std::slice::from_ref(&init))
+            .await?];
         shard.create_job_blocking(init, timeout).await
     }
 
+    // All jobs in a bulk insert go to the same shard, so if any have dependencies, the first one's
+    // first parent decides which. The exception is jobs with an idempotency key and no
+    // dependencies, which go to their key's shard. Jobs are only inserted atomically per shard.
     pub async fn bulk_create_jobs(&self, inits: Vec<JobInit>) -> Result<Vec<Uuid>, QueueError> {
-        let next = self
-            .next_shard
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let shards = self.shards.read().await;
-        shards[next % shards.len()].bulk_create_jobs(&inits).await
+        let mut ids = vec![Uuid::nil(); inits.len()];
+        for (shard, jobs) in self.split_by_shard(&shards, inits).await? {
+            let (indexes, inits): (Vec<usize>, Vec<JobInit>) = jobs.into_iter().unzip();
+            let created = shards[shard].bulk_create_jobs(&inits).await?;
+            for (i, id) in indexes.into_iter().zip(created) {
+                ids[i] = id;
+            }
+        }
+        Ok(ids)
     }
 
     pub async fn bulk_create_jobs_blocking(
@@ -96,14 +114,270 @@ impl QueueManager {
         inits: Vec<JobInit>,
         timeout: Option<Duration>,
     ) -> Result<Vec<Uuid>, QueueError> {
+        let shards = self.shards.read().await;
+        let mut ids = vec![Uuid::nil(); inits.len()];
+        for (shard, jobs) in self.split_by_shard(&shards, inits).await? {
+            let (indexes, inits): (Vec<usize>, Vec<JobInit>) = jobs.into_iter().unzip();
+            let created = shards[shard]
+                .bulk_create_jobs_blocking(&inits, timeout)
+                .await?;
+            for (i, id) in indexes.into_iter().zip(created) {
+                ids[i] = id;
+            }
+        }
+        Ok(ids)
+    }
+
+    // Create a parent job, and children that only become available once it finishes. Returns
+    // the parent's id and the childrens' ids, in order.
+    pub async fn create_fan_out(
+        &self,
+        parent: JobInit,
+        children: Vec<JobInit>,
+    ) -> Result<(Uuid, Vec<Uuid>), QueueError> {
+        let shards = self.shards.read().await;
+        let shard = &shards[self
+            .pick_shard(&shards, std::slice::from_ref(&parent))
+            .await?];
+        let (parent_ids, child_ids) = shard.create_with_parents(&[parent], children).await?;
+        Ok((parent_ids[0], child_ids))
+    }
+
+    // Create parent jobs, and a child that only becomes available once all of them finish.
+    // Returns the parents' ids, in order, and the child's id.
+    pub async fn create_fan_in(
+        &self,
+        parents: Vec<JobInit>,
+        child: JobInit,
+    ) -> Result<(Vec<Uuid>, Uuid), QueueError> {
+        let shards = self.shards.read().await;
+        let shard = &shards[self.pick_shard(&shards, &parents).await?];
+        let (parent_ids, child_ids) = shard.create_with_parents(&parents, vec![child]).await?;
+        Ok((parent_ids, child_ids[0]))
+    }
+
+    // Bulk management operations apply across every shard, and leave running jobs alone - the
+    // worker running them decides what happens to them. They return the number of jobs changed,
+    // and are retried if a job they're waiting on moves partitions under them.
+
+    // Cancelled jobs finish without running, and their dependents are cancelled in turn, unless
+    // they run whatever happens to their parents
+    pub async fn cancel_jobs(&self, filter: JobFilter) -> Result<u64, QueueError> {
+        let shards = self.shards.read().await;
+        let mut cancelled = 0;
+        let filter = &filter;
+        for shard in shards.iter() {
+            cancelled += retry_serialization_failures(|| async move {
+                let mut txn = shard.pool.begin().await?;
+                let cancelled = cancel_jobs(&mut txn, filter).await?;
+                txn.commit().await?;
+                Ok::<_, QueueError>(cancelled)
+            })
+            .await?;
+        }
+        Ok(cancelled)
+    }
+
+    pub async fn pause_jobs(&self, filter: JobFilter) -> Result<u64, QueueError> {
+        let shards = self.shards.read().await;
+        let mut paused = 0;
+        for shard in shards.iter() {
+            paused += retry_serialization_failures(|| set_jobs_paused(&shard.pool, &filter, true))
+                .await?;
+        }
+        Ok(paused)
+    }
+
+    pub async fn resume_jobs(&self, filter: JobFilter) -> Result<u64, QueueError> {
+        let shards = self.shards.read().await;
+        let mut resumed = 0;
+        for shard in shards.iter() {
+            resumed +=
+                retry_serialization_failures(|| set_jobs_paused(&shard.pool, &filter, false))
+                    .await?;
+        }
+        Ok(resumed)
+    }
+
+    pub async fn reschedule_jobs(
+        &self,
+        filter: JobFilter,
+        scheduled: DateTime<Utc>,
+    ) -> Result<u64, QueueError> {
+        let shards = self.shards.read().await;
+        let mut rescheduled = 0;
+        for shard in shards.iter() {
+            rescheduled +=
+                retry_serialization_failures(|| reschedule_jobs(&shard.pool, &filter, scheduled))
+                    .await?;
+        }
+        Ok(rescheduled)
+    }
+
+    // Jobs created from a schedule are created in the schedule's shard.
+    pub async fn create_schedule(&self, init: ScheduleInit) -> Result<Uuid, QueueError> {
         let next = self
             .next_shard
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let shards = self.shards.read().await;
-        shards[next % shards.len()]
-            .bulk_create_jobs_blocking(&inits, timeout)
-            .await
+        create_schedule(&shards[next % shards.len()].pool, init).await
+    }
+
+    pub async fn get_schedule(&self, id: Uuid) -> Result<Option<Schedule>, QueueError> {
+        let shards = self.shards.read().await;
+        for shard in shards.iter() {
+            if let Some(schedule) = get_schedule(&shard.pool, id).await? {
+                return Ok(Some(schedule));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn list_schedules(&self, team_id: i32) -> Result<Vec<Schedule>, QueueError> {
+        let shards = self.shards.read().await;
+        let mut schedules = Vec::new();
+        for shard in shards.iter() {
+            schedules.extend(list_schedules(&shard.pool, team_id).await?);
+        }
+        Ok(schedules)
     }
+
+    pub async fn update_schedule(&self, id: Uuid, init: ScheduleInit) -> Result<(), QueueError> {
+        let shards = self.shards.read().await;
+        for shard in shards.iter() {
+            if update_schedule(&shard.pool, id, init.clone()).await? {
+                return Ok(());
+            }
+        }
+        Err(QueueError::UnknownScheduleId(id))
+    }
+
+    pub async fn set_schedule_enabled(&self, id: Uuid, enabled: bool) -> Result<(), QueueError> {
+        let shards = self.shards.read().await;
+        for shard in shards.iter() {
+            let mut txn = shard.pool.begin().await?;
+            if set_schedule_enabled(&mut txn, id, enabled).await? {
+                txn.commit().await?;
+                return Ok(());
+            }
+        }
+        Err(QueueError::UnknownScheduleId(id))
+    }
+
+    pub async fn delete_schedule(&self, id: Uuid) -> Result<(), QueueError> {
+        let shards = self.shards.read().await;
+        for shard in shards.iter() {
+            if delete_schedule(&shard.pool, id).await? {
+                return Ok(());
+            }
+        }
+        Err(QueueError::UnknownScheduleId(id))
+    }
+
+    // Returns None if the job doesn't exist, or has finished and been cleaned up without its result
+    // being retained, or its retained result has expired. Callers waiting on a job they created
+    // should poll this, and set a result retention on the queue the job will finish in.
+    pub async fn get_job_status(&self, id: Uuid) -> Result<Option<JobStatus>, QueueError> {
+        let shards = self.shards.read().await;
+        for shard in shards.iter() {
+            if let Some(status) = get_job_status(&shard.pool, id).await? {
+                return Ok(Some(status));
+            }
+        }
+        Ok(None)
+    }
+
+    // Jobs finishing in the queue have their results kept for `retention` after they finish, see
+    // `get_job_status`. Jobs can finish in a different queue to the one they were created in, e.g.
+    // a fetch job finishes in its return queue. Passing None stops retaining results for the queue.
+    pub async fn set_result_retention(
+        &self,
+        queue_name: &str,
+        retention: Option<Duration>,
+    ) -> Result<(), QueueError> {
+        let shards = self.shards.read().await;
+        for shard in shards.iter() {
+            set_result_retention(&shard.pool, queue_name, retention).await?;
+        }
+        Ok(())
+    }
+
+    // Jobs with dependencies go to their first parent's shard. Otherwise, jobs with an idempotency
+    // key go to their key's shard, see `key_shard`, and the rest are spread across shards in turn.
+    async fn pick_shard(&self, shards: &[Shard], inits: &[JobInit]) -> Result<usize, QueueError> {
+        let parent = inits.iter().find_map(|i| i.dependencies.first());
+        if let Some(parent) = parent {
+            for (i, shard) in shards.iter().enumerate() {
+                if job_exists(&shard.pool, *parent).await? {
+                    return Ok(i);
+                }
+            }
+        }
+
+        if let Some(shard) = inits.iter().find_map(|i| key_shard(i, shards.len())) {
+            return Ok(shard);
+        }
+
+        let next = self
+            .next_shard
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(next % shards.len())
+    }
+
+    // Splits a bulk insert into the jobs going to each shard, with their index in `inits`, see
+    // `bulk_create_jobs`
+    async fn split_by_shard(
+        &self,
+        shards: &[Shard],
+        inits: Vec<JobInit>,
+    ) -> Result<Vec<(usize, Vec<(usize, JobInit)>)>, QueueError> {
+        let picked = self.pick_shard(shards, &inits).await?;
+        let mut split: Vec<(usize, Vec<(usize, JobInit)>)> = Vec::new();
+        for (i, init) in inits.into_iter().enumerate() {
+            let shard = if init.dependencies.is_empty() {
+                key_shard(&init, shards.len()).unwrap_or(picked)
+            } else {
+                picked
+            };
+            match split.iter_mut().find(|(s, _)| *s == shard) {
+                Some((_, jobs)) => jobs.push((i, init)),
+                None => split.push((shard, vec![(i, init)])),
+            }
+        }
+        Ok(split)
+    }
+}
+
+// The jobs an insert created, with their ids. Jobs deduplicated by their idempotency key are left
+// out, they already have whatever dependencies they were created with.
+fn created_jobs<'a>(inserted: &[(Uuid, bool)], inits: &'a [JobInit]) -> Vec<(Uuid, &'a JobInit)> {
+    inserted
+        .iter()
+        .zip(inits)
+        .filter(|((_, created), _)| *created)
+        .map(|((id, _), init)| (*id, init))
+        .collect()
+}
+
+// Idempotency keys are only unique within a shard, so a job with a key has to be created in the
+// same shard every time, whichever manager creates it. Hashed with FNV-1a rather than std's
+// hasher, which isn't guaranteed to hash the same way across releases.
+fn key_shard(init: &JobInit, shard_count: usize) -> Option<usize> {
+    let key = init.idempotency_key.as_ref()?;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let bytes = init
+        .team_id
+        .to_le_bytes()
+        .into_iter()
+        .chain(init.queue_name.bytes())
+        // Keeps queue "a" with key "bc" apart from queue "ab" with key "c"
+        .chain(std::iter::once(0))
+        .chain(key.bytes());
+    for byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    Some((hash % shard_count as u64) as usize)
 }
 
 impl Shard {
@@ -119,7 +393,7 @@ impl Shard {
     // Inserts a job, failing if the shard is at capacity
     pub async fn create_job(&self, init: JobInit) -> Result<Uuid, QueueError> {
         self.insert_guard().await?;
-        create_job(&self.pool, init).await
+        self.insert_one(init).await
     }
 
     // Inserts a vec of jobs, failing if the shard is at capacity. Note "capacity" here just
@@ -127,7 +401,7 @@ impl Shard {
     // 1000, we still insert all 1000.
     pub async fn bulk_create_jobs(&self, inits: &[JobInit]) -> Result<Vec<Uuid>, QueueError> {
         self.insert_guard().await?;
-        bulk_create_jobs(&self.pool, inits).await
+        self.insert_many(inits).await
     }
 
     // Inserts a job, blocking until there's capacity (or until the timeout is reached)
@@ -146,7 +420,7 @@ impl Shard {
             }
         }
 
-        create_job(&self.pool, init).await
+        self.insert_one(init).await
     }
 
     // As above, with the same caveats about what "capacity" means
@@ -165,7 +439,60 @@ impl Shard {
             }
         }
 
-        bulk_create_jobs(&self.pool, inits).await
+        self.insert_many(inits).await
+    }
+
+    // Inserts parent jobs, and children depending on all of them, in one transaction, failing
+    // if the shard is at capacity
+    pub async fn create_with_parents(
+        &self,
+        parents: &[JobInit],
+        children: Vec<JobInit>,
+    ) -> Result<(Vec<Uuid>, Vec<Uuid>), QueueError> {
+        self.insert_guard().await?;
+        // Creating dependencies locks their parents, which can move partitions as they finish
+        retry_serialization_failures(|| {
+            let mut children = children.clone();
+            async move {
+                let mut txn = self.pool.begin().await?;
+                let parent_ids = insert_jobs(&mut *txn, parents).await?;
+                create_dependencies(&mut txn, &created_jobs(&parent_ids, parents)).await?;
+                let parent_ids: Vec<Uuid> = parent_ids.into_iter().map(|(id, _)| id).collect();
+                for child in children.iter_mut() {
+                    child.dependencies.extend_from_slice(&parent_ids);
+                }
+                let child_ids = insert_jobs(&mut *txn, &children).await?;
+                create_dependencies(&mut txn, &created_jobs(&child_ids, &children)).await?;
+                txn.commit().await?;
+                let child_ids = child_ids.into_iter().map(|(id, _)| id).collect();
+                Ok::<_, QueueError>((parent_ids, child_ids))
+            }
+        })
+        .await
+    }
+
+    async fn insert_one(&self, init: JobInit) -> Result<Uuid, QueueError> {
+        if init.dependencies.is_empty() {
+            return create_job(&self.pool, init).await;
+        }
+        let inits = [init];
+        Ok(self.insert_many(&inits).await?[0])
+    }
+
+    // Jobs with dependencies have to be created in the same transaction as their dependencies
+    async fn insert_many(&self, inits: &[JobInit]) -> Result<Vec<Uuid>, QueueError> {
+        if inits.iter().all(|i| i.dependencies.is_empty()) {
+            return bulk_create_jobs(&self.pool, inits).await;
+        }
+        // Creating dependencies locks their parents, which can move partitions as they finish
+        retry_serialization_failures(|| async move {
+            let mut txn = self.pool.begin().await?;
+            let ids = insert_jobs(&mut *txn, inits).await?;
+            create_dependencies(&mut txn, &created_jobs(&ids, inits)).await?;
+            txn.commit().await?;
+            Ok::<_, QueueError>(ids.into_iter().map(|(id, _)| id).collect())
+        })
+        .await
     }
 
     pub async fn insert_guard(&self) -> Result<(), QueueError> {
@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::Notify;
+use tracing::{error, warn};
+
+// The channel the triggers added in the job_notifications migration publish to, with the
+// queue name of the job that became available as the payload
+const JOB_CHANNEL: &str = "cyclotron_jobs";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// How often the listen loop checks whether the worker that owns it has been dropped
+const OWNER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Turns postgres notifications into per-queue wakeups. The listening connection is only opened
+// once someone first waits, so workers that never wait don't hold an extra connection.
+#[derive(Default)]
+pub struct JobNotifier {
+    started: AtomicBool,
+    queues: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+impl JobNotifier {
+    pub fn start(self: &Arc<Self>, pool: &PgPool) {
+        if !self.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(listen_loop(pool.clone(), Arc::downgrade(self)));
+        }
+    }
+
+    // TRICKY - we use notify_one, which stores a wakeup if no-one is waiting yet, so a
+    // notification that arrives between a worker finding the queue empty and it starting to
+    // wait isn't lost. The cost is that concurrent waiters on one queue are woken one at a time.
+    pub fn queue(&self, queue: &str) -> Arc<Notify> {
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(queue.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn notify(&self, queue: &str) {
+        if let Some(notify) = self.queues.lock().unwrap().get(queue) {
+            notify.notify_one();
+        }
+    }
+
+    // Used when notifications might have been missed, so every waiter goes and polls
+    fn notify_all(&self) {
+        for notify in self.queues.lock().unwrap().values() {
+            notify.notify_one();
+        }
+    }
+}
+
+async fn listen_loop(pool: PgPool, notifier: Weak<JobNotifier>) {
+    let mut listener: Option<PgListener> = None;
+    loop {
+        let Some(notifier) = notifier.upgrade() else {
+            return; // The worker was dropped
+        };
+
+        let active = match listener.as_mut() {
+            Some(active) => active,
+            None => match connect(&pool).await {
+                Ok(connected) => listener.insert(connected),
+                Err(e) => {
+                    warn!(
+                        "failed to listen for job notifications, polling instead: {}",
+                        e
+                    );
+                    notifier.notify_all();
+                    drop(notifier);
+                    tokio::time::sleep(RECONNECT_INTERVAL).await;
+                    continue;
+                }
+            },
+        };
+
+        // try_recv is cancel safe, so timing out here loses nothing
+        match tokio::time::timeout(OWNER_CHECK_INTERVAL, active.try_recv()).await {
+            Err(_) => {}
+            Ok(Ok(Some(notification))) => notifier.notify(notification.payload()),
+            // The connection was lost, and will be re-established on the next try_recv, but
+            // anything sent in the meantime is gone
+            Ok(Ok(None)) => {
+                warn!("lost job notification connection, reconnecting");
+                notifier.notify_all();
+            }
+            Ok(Err(e)) => {
+                error!("error receiving job notifications: {}", e);
+                listener = None;
+                notifier.notify_all();
+                drop(notifier);
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn connect(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(JOB_CHANNEL).await?;
+    Ok(listener)
+}
@@ -0,0 +1,114 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    error::QueueError,
+    types::{DeadLetter, OldestAvailableJob, QueueDepth},
+    DEAD_LETTER_QUEUE,
+};
+
+pub async fn queue_depths<'c, E>(executor: E) -> Result<Vec<QueueDepth>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_as(
+        r#"
+SELECT queue_name, state, team_id, COUNT(*) AS count
+FROM cyclotron_jobs
+GROUP BY queue_name, state, team_id
+ORDER BY queue_name, state, team_id
+        "#,
+    )
+    .fetch_all(executor)
+    .await?)
+}
+
+// Jobs scheduled in the future aren't late yet, so they're not counted. The dead letter queue is
+// left out, nothing ever dequeues from it.
+pub async fn oldest_available_jobs<'c, E>(
+    executor: E,
+) -> Result<Vec<OldestAvailableJob>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_as(
+        r#"
+SELECT queue_name, MIN(scheduled) AS scheduled
+FROM cyclotron_jobs
+WHERE state = 'available' AND scheduled <= NOW() AND queue_name != $1
+GROUP BY queue_name
+ORDER BY queue_name
+        "#,
+    )
+    .bind(DEAD_LETTER_QUEUE)
+    .fetch_all(executor)
+    .await?)
+}
+
+// Most recently dead lettered first
+pub async fn list_dead_letters<'c, E>(
+    executor: E,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<DeadLetter>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_as(
+        r#"
+SELECT
+    m.job_id,
+    j.team_id,
+    j.function_id,
+    m.original_queue_name,
+    m.reason,
+    m.dlq_time
+FROM cyclotron_dead_letter_metadata m
+JOIN cyclotron_jobs j ON j.id = m.job_id
+WHERE j.queue_name = $1
+ORDER BY m.dlq_time DESC, m.job_id
+LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(DEAD_LETTER_QUEUE)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(executor)
+    .await?)
+}
+
+// Move a job out of the dead letter queue and back onto the queue it came from, to be run now.
+// Its janitor touch count is reset, so it isn't immediately detected as a poison pill again. Its
+// dead letter metadata is only deleted once the job has been moved, so a job with metadata that
+// isn't in the dead letter queue, e.g. because it was already requeued some other way, keeps it.
+// Returns false if the job isn't in the dead letter queue.
+pub async fn requeue_dead_letter(conn: &mut PgConnection, job: Uuid) -> Result<bool, QueueError> {
+    let result = sqlx::query(
+        r#"
+UPDATE cyclotron_jobs
+SET
+    queue_name = m.original_queue_name,
+    state = 'available',
+    scheduled = NOW(),
+    janitor_touch_count = 0,
+    last_transition = NOW()
+FROM cyclotron_dead_letter_metadata m
+WHERE cyclotron_jobs.id = $1 AND m.job_id = $1 AND cyclotron_jobs.queue_name = $2
+        "#,
+    )
+    .bind(job)
+    .bind(DEAD_LETTER_QUEUE)
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query("DELETE FROM cyclotron_dead_letter_metadata WHERE job_id = $1")
+        .bind(job)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(true)
+}
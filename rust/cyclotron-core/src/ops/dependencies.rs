@@ -0,0 +1,243 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    error::QueueError,
+    types::{JobInit, JobState},
+};
+
+// Insert the dependencies of newly created jobs, and resolve any whose parents have already
+// finished. `jobs` are the jobs created in the same transaction, with their ids. Jobs deduplicated
+// by their idempotency key weren't created, and already have whatever dependencies they had.
+pub async fn create_dependencies(
+    conn: &mut PgConnection,
+    jobs: &[(Uuid, &JobInit)],
+) -> Result<(), QueueError> {
+    let mut job_ids = Vec::new();
+    let mut parent_ids = Vec::new();
+    let mut waiting = Vec::new();
+    for (id, job) in jobs {
+        if job.dependencies.is_empty() {
+            continue;
+        }
+        waiting.push(*id);
+        for parent in &job.dependencies {
+            job_ids.push(*id);
+            parent_ids.push(*parent);
+        }
+    }
+
+    if waiting.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+INSERT INTO cyclotron_job_dependencies (job_id, parent_id)
+SELECT * FROM UNNEST($1::uuid[], $2::uuid[])
+ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(&job_ids)
+    .bind(&parent_ids)
+    .execute(&mut *conn)
+    .await?;
+
+    // TRICKY - a parent finishing concurrently either committed before we take this lock, and we
+    // see it as finished below, or waits for us to commit before it can be updated, and then sees
+    // our dependencies when resolving its own dependents.
+    sqlx::query("SELECT id FROM cyclotron_jobs WHERE id = ANY($1) ORDER BY id FOR SHARE")
+        .bind(&parent_ids)
+        .execute(&mut *conn)
+        .await?;
+
+    // Nothing depends on jobs we just created, so there's nothing to cascade to.
+    resolve_waiting_jobs(&mut *conn, &waiting).await?;
+
+    Ok(())
+}
+
+// A job's dependencies are only needed while it's waiting, so are deleted once it's resolved or
+// cancelled. The jobs table may be partitioned, so they can't cascade from it.
+pub async fn delete_dependencies<'c, E>(executor: E, jobs: &[Uuid]) -> Result<(), QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    if jobs.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query("DELETE FROM cyclotron_job_dependencies WHERE job_id = ANY($1)")
+        .bind(jobs)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn job_exists<'c, E>(executor: E, id: Uuid) -> Result<bool, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(
+        sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM cyclotron_jobs WHERE id = $1)")
+            .bind(id)
+            .fetch_one(executor)
+            .await?,
+    )
+}
+
+// Resolve the jobs waiting on `parents`, which just finished. Jobs failed or cancelled along with a
+// parent have finished too, so their own dependents are resolved in turn. Returns the number of
+// jobs resolved.
+pub async fn resolve_dependents(
+    conn: &mut PgConnection,
+    parents: Vec<Uuid>,
+) -> Result<u64, QueueError> {
+    let mut parents = parents;
+    let mut resolved = 0;
+    while !parents.is_empty() {
+        let dependents: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT DISTINCT job_id FROM cyclotron_job_dependencies WHERE parent_id = ANY($1)",
+        )
+        .bind(&parents)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        if dependents.is_empty() {
+            break;
+        }
+
+        let (count, failed) = resolve_waiting_jobs(&mut *conn, &dependents).await?;
+        resolved += count;
+        parents = failed;
+    }
+
+    Ok(resolved)
+}
+
+// Resolve up to `limit` waiting jobs that could have been resolved, but weren't, and aren't being
+// resolved by someone else. Jobs are resolved when their parents finish, so this only catches jobs
+// whose resolution was missed, e.g. because two of their parents finished at the same time. Only
+// jobs that can be resolved are locked, so this doesn't hold up jobs still waiting on running
+// parents. Returns the number of jobs taken, which is less than `limit` once there are none left,
+// and the number of jobs resolved, including their dependents.
+pub async fn resolve_missed_waiting_jobs(
+    conn: &mut PgConnection,
+    limit: i64,
+) -> Result<(usize, u64), QueueError> {
+    let missed: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+SELECT j.id FROM cyclotron_jobs j
+WHERE
+    j.state = 'waiting'
+    AND (
+        NOT EXISTS (
+            SELECT 1 FROM cyclotron_job_dependencies d
+            JOIN cyclotron_jobs p ON p.id = d.parent_id
+            WHERE d.job_id = j.id AND p.state NOT IN ('completed', 'failed', 'cancelled')
+        )
+        OR (
+            j.on_parent_failure = 'fail'
+            AND EXISTS (
+                SELECT 1 FROM cyclotron_job_dependencies d
+                JOIN cyclotron_jobs p ON p.id = d.parent_id
+                WHERE d.job_id = j.id AND p.state IN ('failed', 'cancelled')
+            )
+        )
+    )
+ORDER BY j.id
+LIMIT $1
+FOR UPDATE OF j SKIP LOCKED
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (count, failed) = resolve_waiting_jobs(&mut *conn, &missed).await?;
+    Ok((
+        missed.len(),
+        count + resolve_dependents(conn, failed).await?,
+    ))
+}
+
+// Make waiting jobs whose parents have all finished available, or fail them if a parent failed
+// and their policy says so, or cancel them if a parent was cancelled. Returns the number of jobs
+// resolved, and the ids of those that were failed or cancelled.
+async fn resolve_waiting_jobs(
+    conn: &mut PgConnection,
+    jobs: &[Uuid],
+) -> Result<(u64, Vec<Uuid>), QueueError> {
+    // Lock the jobs first, in a statement of its own: if another transaction is resolving them,
+    // we wait for it, and then read the parent states it committed in the query below.
+    let locked: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+SELECT id FROM cyclotron_jobs
+WHERE id = ANY($1) AND state = 'waiting'
+ORDER BY id
+FOR UPDATE
+        "#,
+    )
+    .bind(jobs)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if locked.is_empty() {
+        return Ok((0, Vec::new()));
+    }
+
+    let resolved: Vec<(Uuid, JobState)> = sqlx::query_as(
+        r#"
+WITH parents AS (
+    SELECT
+        j.id,
+        j.on_parent_failure,
+        -- Finished parents are deleted by the janitor, so missing parents have finished
+        COALESCE(bool_and(p.state IS NULL OR p.state IN ('completed', 'failed', 'cancelled')), true) AS all_finished,
+        COALESCE(bool_or(p.state = 'failed'), false) AS any_failed,
+        COALESCE(bool_or(p.state = 'cancelled'), false) AS any_cancelled
+    FROM cyclotron_jobs j
+    LEFT JOIN cyclotron_job_dependencies d ON d.job_id = j.id
+    LEFT JOIN cyclotron_jobs p ON p.id = d.parent_id
+    WHERE j.id = ANY($1)
+    GROUP BY j.id, j.on_parent_failure
+),
+resolved AS (
+    UPDATE cyclotron_jobs
+    SET
+        state = CASE
+            WHEN parents.any_failed AND parents.on_parent_failure = 'fail' THEN 'failed'::JobState
+            WHEN parents.any_cancelled AND parents.on_parent_failure = 'fail' THEN 'cancelled'::JobState
+            ELSE 'available'::JobState
+        END,
+        last_transition = NOW(),
+        transition_count = transition_count + 1
+    FROM parents
+    WHERE
+        cyclotron_jobs.id = parents.id
+        AND (
+            parents.all_finished
+            OR ((parents.any_failed OR parents.any_cancelled) AND parents.on_parent_failure = 'fail')
+        )
+    RETURNING cyclotron_jobs.id, cyclotron_jobs.state
+)
+SELECT id, state FROM resolved
+        "#,
+    )
+    .bind(&locked)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let ids: Vec<Uuid> = resolved.iter().map(|(id, _)| *id).collect();
+    delete_dependencies(&mut *conn, &ids).await?;
+
+    let count = resolved.len() as u64;
+    let failed = resolved
+        .into_iter()
+        .filter(|(_, state)| matches!(state, JobState::Failed | JobState::Cancelled))
+        .map(|(id, _)| id)
+        .collect();
+
+    Ok((count, failed))
+}
@@ -1,9 +1,21 @@
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, DurationRound, NaiveDateTime, Utc};
+use sqlx::PgConnection;
+use tracing::info;
 use uuid::Uuid;
 
 use crate::error::QueueError;
 use crate::types::AggregatedDelete;
 
+// Finished jobs are partitioned by the hour of their last transition, see the partitioning
+// migration. Hourly partitions are named for the hour they start at, e.g.
+// cyclotron_jobs_finished_p2024080412 holds jobs that finished between 12:00 and 13:00 UTC.
+const FINISHED_PARTITION_PREFIX: &str = "cyclotron_jobs_finished_p";
+const FINISHED_PARTITION_FORMAT: &str = "%Y%m%d%H";
+
+// Held while creating or dropping partitions, so concurrent janitors don't race each other to
+// create the same partition. As with the scheduler's lock, the value is arbitrary.
+const PARTITION_LOCK_KEY: i64 = 0x6379_636c_7061_7274;
+
 // As a general rule, janitor operations are not queue specific (as in, they don't account for the
 // queue name). We can revisit this later, if we decide we need the ability to do janitor operations
 // on a per-queue basis.
@@ -14,37 +26,205 @@ pub async fn delete_completed_and_failed_jobs<'c, E>(
 where
     E: sqlx::Executor<'c, Database = sqlx::Postgres>,
 {
-    let result: Vec<AggregatedDelete> = sqlx::query_as!(
-        AggregatedDelete,
+    Ok(sqlx::query_as(
         r#"
 WITH to_delete AS (
     DELETE FROM cyclotron_jobs
-    WHERE state IN ('failed', 'completed')
+    WHERE state IN ('failed', 'completed', 'cancelled')
     RETURNING last_transition, team_id, function_id::text, state::text
-),
-aggregated_data AS (
-    SELECT
-        date_trunc('hour', last_transition) AS hour,
-        team_id,
-        function_id,
-        state,
-        COUNT(*) AS count
-    FROM to_delete
-    GROUP BY hour, team_id, function_id, state
 )
 SELECT
-    hour as "hour!",
-    team_id as "team_id!",
+    date_trunc('hour', last_transition) AS hour,
+    team_id::bigint AS team_id,
     function_id,
-    state as "state!",
-    count as "count!"
-FROM aggregated_data"#
+    state,
+    COUNT(*) AS count
+FROM to_delete
+GROUP BY hour, team_id, function_id, state
+        "#,
     )
     .fetch_all(executor)
-    .await
-    .map_err(QueueError::from)?;
+    .await?)
+}
 
-    Ok(result)
+// The hourly partitions of cyclotron_jobs_finished that currently exist, with the hour each starts at
+async fn list_finished_partitions(
+    conn: &mut PgConnection,
+) -> Result<Vec<(String, DateTime<Utc>)>, QueueError> {
+    let names: Vec<String> = sqlx::query_scalar(
+        r#"
+SELECT c.relname::text
+FROM pg_inherits i
+JOIN pg_class c ON c.oid = i.inhrelid
+WHERE i.inhparent = 'cyclotron_jobs_finished'::regclass
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(names
+        .into_iter()
+        .filter_map(|name| {
+            // chrono won't parse a time without minutes
+            let hour = name.strip_prefix(FINISHED_PARTITION_PREFIX)?;
+            let start = NaiveDateTime::parse_from_str(
+                &format!("{}00", hour),
+                &format!("{}%M", FINISHED_PARTITION_FORMAT),
+            )
+            .ok()?;
+            Some((name, start.and_utc()))
+        })
+        .collect())
+}
+
+// Create an hourly partition for finished jobs for every hour from the next one up to and
+// including the one `through` falls in. Partitions aren't created for the current hour, since
+// jobs that finished in it are already in the default partition. Must be called in a transaction.
+// Returns the number of partitions created.
+pub async fn create_finished_partitions(
+    conn: &mut PgConnection,
+    through: DateTime<Utc>,
+) -> Result<u64, QueueError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(PARTITION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    // Jobs' last transition comes from the database's clock, not ours
+    let now: DateTime<Utc> = sqlx::query_scalar("SELECT NOW()")
+        .fetch_one(&mut *conn)
+        .await?;
+    let now = now
+        .duration_trunc(Duration::hours(1))
+        .expect("an hour is a valid rounding duration");
+    let existing: Vec<DateTime<Utc>> = list_finished_partitions(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|(_, start)| start)
+        .collect();
+
+    let mut created = 0;
+    let mut start = now + Duration::hours(1);
+    while start <= through {
+        let end = start + Duration::hours(1);
+        if !existing.contains(&start) {
+            let name = format!(
+                "{}{}",
+                FINISHED_PARTITION_PREFIX,
+                start.format(FINISHED_PARTITION_FORMAT)
+            );
+            sqlx::query(&format!(
+                "CREATE TABLE {} PARTITION OF cyclotron_jobs_finished FOR VALUES FROM ('{}') TO ('{}')",
+                name,
+                start.to_rfc3339(),
+                end.to_rfc3339()
+            ))
+            .execute(&mut *conn)
+            .await?;
+            info!("Created finished job partition {}", name);
+            created += 1;
+        }
+        start = end;
+    }
+
+    Ok(created)
+}
+
+// Drop every hourly finished job partition that ended before `before`, returning the jobs in them
+// aggregated as `delete_completed_and_failed_jobs` would. `before` should leave enough time for
+// transactions that started during a partition's hour to finish writing to it. Must be called in
+// a transaction, which should be committed promptly: dropping a partition locks the whole
+// finished jobs table.
+pub async fn drop_finished_partitions(
+    conn: &mut PgConnection,
+    before: DateTime<Utc>,
+) -> Result<Vec<AggregatedDelete>, QueueError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(PARTITION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    // Rather than queueing behind a long running query, and blocking every query behind us while
+    // we wait, give up and try again next time
+    sqlx::query("SET LOCAL lock_timeout = '5s'")
+        .execute(&mut *conn)
+        .await?;
+
+    let mut deleted = Vec::new();
+    for (name, start) in list_finished_partitions(&mut *conn).await? {
+        if start + Duration::hours(1) > before {
+            continue;
+        }
+
+        let aggregated: Vec<AggregatedDelete> = sqlx::query_as(&format!(
+            r#"
+SELECT
+    date_trunc('hour', last_transition) AS hour,
+    team_id::bigint AS team_id,
+    function_id::text AS function_id,
+    state::text AS state,
+    COUNT(*) AS count
+FROM {}
+GROUP BY hour, team_id, function_id, state
+            "#,
+            name
+        ))
+        .fetch_all(&mut *conn)
+        .await?;
+
+        sqlx::query(&format!("DROP TABLE {}", name))
+            .execute(&mut *conn)
+            .await?;
+        info!("Dropped finished job partition {}", name);
+        deleted.extend(aggregated);
+    }
+
+    Ok(deleted)
+}
+
+// Like `delete_completed_and_failed_jobs`, but only deleting finished jobs that aren't in an hourly
+// partition, because they finished before it was created.
+pub async fn delete_unpartitioned_finished_jobs<'c, E>(
+    executor: E,
+) -> Result<Vec<AggregatedDelete>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_as(
+        r#"
+WITH to_delete AS (
+    DELETE FROM cyclotron_jobs_finished_default
+    RETURNING last_transition, team_id, function_id::text, state::text
+)
+SELECT
+    date_trunc('hour', last_transition) AS hour,
+    team_id::bigint AS team_id,
+    function_id,
+    state,
+    COUNT(*) AS count
+FROM to_delete
+GROUP BY hour, team_id, function_id, state
+        "#,
+    )
+    .fetch_all(executor)
+    .await?)
+}
+
+// Returns the number of keys deleted
+pub async fn delete_expired_idempotency_keys<'c, E>(
+    executor: E,
+    retention: Duration,
+) -> Result<u64, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let oldest_valid = Utc::now() - retention;
+    let result = sqlx::query("DELETE FROM cyclotron_idempotency_keys WHERE created < $1")
+        .bind(oldest_valid)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
 }
 
 // Jobs are considered stalled if their lock is held and their last_heartbeat is older than `timeout`.
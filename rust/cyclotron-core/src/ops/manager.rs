@@ -1,17 +1,35 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, Postgres, QueryBuilder};
 use uuid::Uuid;
 
 use crate::{
     error::QueueError,
-    types::{JobInit, JobState},
+    ops::dependencies::{delete_dependencies, resolve_dependents},
+    types::{JobFilter, JobInit, JobState},
 };
 
+// Jobs with dependencies wait for them, see `ops::dependencies::create_dependencies`, which must be
+// called in the same transaction the jobs are created in.
+fn initial_state(data: &JobInit) -> JobState {
+    if data.dependencies.is_empty() {
+        JobState::Available
+    } else {
+        JobState::Waiting
+    }
+}
+
 pub async fn create_job<'c, E>(executor: E, data: JobInit) -> Result<Uuid, QueueError>
 where
     E: sqlx::Executor<'c, Database = sqlx::Postgres>,
 {
+    if data.idempotency_key.is_some() {
+        return Ok(bulk_create_jobs(executor, std::slice::from_ref(&data)).await?[0]);
+    }
+
     let id = Uuid::now_v7();
-    sqlx::query!(
+    sqlx::query(
         r#"
 INSERT INTO cyclotron_jobs
     (
@@ -31,23 +49,25 @@ INSERT INTO cyclotron_jobs
         vm_state,
         metadata,
         parameters,
-        blob
+        blob,
+        on_parent_failure
     )
 VALUES
-    ($1, $2, $3, NOW(), NULL, NULL, 0, 0, NOW(), $4, $5, $6, $7, $8, $9, $10, $11)
+    ($1, $2, $3, NOW(), NULL, NULL, 0, 0, NOW(), $4, $5, $6, $7, $8, $9, $10, $11, $12)
     "#,
-        id,
-        data.team_id,
-        data.function_id,
-        data.queue_name,
-        JobState::Available as _,
-        data.scheduled,
-        data.priority,
-        data.vm_state,
-        data.metadata,
-        data.parameters,
-        data.blob
     )
+    .bind(id)
+    .bind(data.team_id)
+    .bind(data.function_id)
+    .bind(&data.queue_name)
+    .bind(initial_state(&data))
+    .bind(data.scheduled)
+    .bind(data.priority)
+    .bind(&data.vm_state)
+    .bind(&data.metadata)
+    .bind(&data.parameters)
+    .bind(&data.blob)
+    .bind(data.on_parent_failure)
     .execute(executor)
     .await?;
 
@@ -55,30 +75,69 @@ VALUES
 }
 
 pub async fn bulk_create_jobs<'c, E>(executor: E, jobs: &[JobInit]) -> Result<Vec<Uuid>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(insert_jobs(executor, jobs)
+        .await?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect())
+}
+
+// As `bulk_create_jobs`, but also returns whether each job was created, rather than deduplicated
+// by its idempotency key, against an existing job or one earlier in `jobs`.
+pub async fn insert_jobs<'c, E>(
+    executor: E,
+    jobs: &[JobInit],
+) -> Result<Vec<(Uuid, bool)>, QueueError>
 where
     E: sqlx::Executor<'c, Database = sqlx::Postgres>,
 {
     let now = Utc::now();
+    // Jobs sharing an idempotency key within the batch are only inserted once. `rows` maps each
+    // job to the row it's inserted as.
+    let mut rows = Vec::with_capacity(jobs.len());
+    let mut seen_keys = HashMap::new();
+    let mut unique = Vec::with_capacity(jobs.len());
+    for d in jobs {
+        let row = match &d.idempotency_key {
+            Some(key) => *seen_keys
+                .entry((d.team_id, d.queue_name.as_str(), key.as_str()))
+                .or_insert_with(|| {
+                    unique.push(d);
+                    unique.len() - 1
+                }),
+            None => {
+                unique.push(d);
+                unique.len() - 1
+            }
+        };
+        rows.push(row);
+    }
+
     // Flatten these jobs into a series of vecs of arguments PG can unnest
-    let mut ids = Vec::with_capacity(jobs.len());
-    let mut team_ids = Vec::with_capacity(jobs.len());
-    let mut function_ids = Vec::with_capacity(jobs.len());
-    let mut created_at = Vec::with_capacity(jobs.len());
-    let mut lock_ids = Vec::with_capacity(jobs.len());
-    let mut last_heartbeats = Vec::with_capacity(jobs.len());
-    let mut janitor_touch_counts = Vec::with_capacity(jobs.len());
-    let mut transition_counts = Vec::with_capacity(jobs.len());
-    let mut last_transitions = Vec::with_capacity(jobs.len());
-    let mut queue_names = Vec::with_capacity(jobs.len());
-    let mut states = Vec::with_capacity(jobs.len());
-    let mut scheduleds = Vec::with_capacity(jobs.len());
-    let mut priorities = Vec::with_capacity(jobs.len());
-    let mut vm_states = Vec::with_capacity(jobs.len());
-    let mut metadatas = Vec::with_capacity(jobs.len());
-    let mut parameters = Vec::with_capacity(jobs.len());
-    let mut blob = Vec::with_capacity(jobs.len());
+    let mut ids = Vec::with_capacity(unique.len());
+    let mut team_ids = Vec::with_capacity(unique.len());
+    let mut function_ids = Vec::with_capacity(unique.len());
+    let mut created_at = Vec::with_capacity(unique.len());
+    let mut lock_ids = Vec::with_capacity(unique.len());
+    let mut last_heartbeats = Vec::with_capacity(unique.len());
+    let mut janitor_touch_counts = Vec::with_capacity(unique.len());
+    let mut transition_counts = Vec::with_capacity(unique.len());
+    let mut last_transitions = Vec::with_capacity(unique.len());
+    let mut queue_names = Vec::with_capacity(unique.len());
+    let mut states = Vec::with_capacity(unique.len());
+    let mut scheduleds = Vec::with_capacity(unique.len());
+    let mut priorities = Vec::with_capacity(unique.len());
+    let mut vm_states = Vec::with_capacity(unique.len());
+    let mut metadatas = Vec::with_capacity(unique.len());
+    let mut parameters = Vec::with_capacity(unique.len());
+    let mut blob = Vec::with_capacity(unique.len());
+    let mut on_parent_failures = Vec::with_capacity(unique.len());
+    let mut idempotency_keys = Vec::with_capacity(unique.len());
 
-    for d in jobs {
+    for d in &unique {
         ids.push(Uuid::now_v7());
         team_ids.push(d.team_id);
         function_ids.push(d.function_id);
@@ -89,20 +148,101 @@ where
         transition_counts.push(0);
         last_transitions.push(now);
         queue_names.push(d.queue_name.clone());
-        states.push(JobState::Available);
+        states.push(initial_state(d));
         scheduleds.push(d.scheduled);
         priorities.push(d.priority);
         vm_states.push(d.vm_state.clone());
         metadatas.push(d.metadata.clone());
         parameters.push(d.parameters.clone());
         blob.push(d.blob.clone());
+        on_parent_failures.push(d.on_parent_failure);
+        idempotency_keys.push(d.idempotency_key.clone());
     }
 
-    // Using the "unnest" function to turn an array of rows into a set of rows
-    sqlx::query(
+    // Using the "unnest" function to turn an array of rows into a set of rows. Jobs with an
+    // idempotency key are only inserted if they claim it - otherwise the job that holds it is
+    // returned in their place.
+    //
+    // TRICKY - the no-op DO UPDATE makes the key insert return the row that's already there, even
+    // if it was committed after this statement started, which DO NOTHING would hide from us.
+    let claimed: Vec<(i32, String, String, Uuid)> = sqlx::query_as(
         r#"
-INSERT INTO cyclotron_jobs
-    (
+WITH jobs AS (
+    SELECT *
+    FROM UNNEST(
+            $1,
+            $2,
+            $3,
+            $4,
+            $5,
+            $6,
+            $7,
+            $8,
+            $9,
+            $10,
+            $11,
+            $12,
+            $13,
+            $14,
+            $15,
+            $16,
+            $17,
+            $18,
+            $19
+        ) AS j(
+            id,
+            team_id,
+            function_id,
+            created,
+            lock_id,
+            last_heartbeat,
+            janitor_touch_count,
+            transition_count,
+            last_transition,
+            queue_name,
+            state,
+            scheduled,
+            priority,
+            vm_state,
+            metadata,
+            parameters,
+            blob,
+            on_parent_failure,
+            idempotency_key
+        )
+),
+keys AS (
+    INSERT INTO cyclotron_idempotency_keys (team_id, queue_name, idempotency_key, job_id, created)
+    SELECT team_id, queue_name, idempotency_key, id, created
+    FROM jobs
+    WHERE idempotency_key IS NOT NULL
+    ON CONFLICT (team_id, queue_name, idempotency_key)
+    DO UPDATE SET idempotency_key = EXCLUDED.idempotency_key
+    RETURNING team_id, queue_name, idempotency_key, job_id
+),
+inserted AS (
+    INSERT INTO cyclotron_jobs
+        (
+            id,
+            team_id,
+            function_id,
+            created,
+            lock_id,
+            last_heartbeat,
+            janitor_touch_count,
+            transition_count,
+            last_transition,
+            queue_name,
+            state,
+            scheduled,
+            priority,
+            vm_state,
+            metadata,
+            parameters,
+            blob,
+            on_parent_failure
+        )
+    SELECT
         id,
         team_id,
         function_id,
@@ -119,28 +259,12 @@ INSERT INTO cyclotron_jobs
         vm_state,
         metadata,
         parameters,
-        blob
-    )
-SELECT *
-FROM UNNEST(
-        $1,
-        $2,
-        $3,
-        $4,
-        $5,
-        $6,
-        $7,
-        $8,
-        $9,
-        $10,
-        $11,
-        $12,
-        $13,
-        $14,
-        $15,
-        $16,
-        $17
-    )
+        blob,
+        on_parent_failure
+    FROM jobs
+    WHERE idempotency_key IS NULL OR id IN (SELECT job_id FROM keys)
+)
+SELECT team_id, queue_name, idempotency_key, job_id FROM keys
 "#,
     )
     .bind(&ids)
@@ -160,8 +284,112 @@ FROM UNNEST(
     .bind(metadatas)
     .bind(parameters)
     .bind(blob)
-    .execute(executor)
+    .bind(on_parent_failures)
+    .bind(idempotency_keys)
+    .fetch_all(executor)
     .await?;
 
-    Ok(ids)
+    let claimed: HashMap<_, _> = claimed
+        .into_iter()
+        .map(|(team_id, queue_name, key, job_id)| ((team_id, queue_name, key), job_id))
+        .collect();
+
+    let mut returned = vec![false; unique.len()];
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let d = unique[row];
+            let id = d
+                .idempotency_key
+                .as_ref()
+                .and_then(|key| claimed.get(&(d.team_id, d.queue_name.clone(), key.clone())))
+                .copied()
+                .unwrap_or(ids[row]);
+            // Only the first job inserted as a row created it, later ones were deduplicated
+            let created = id == ids[row] && !std::mem::replace(&mut returned[row], true);
+            (id, created)
+        })
+        .collect())
+}
+
+// Bulk management operations only ever touch jobs no worker holds - running jobs are left to
+// finish. A job being dequeued concurrently is either locked by the dequeue first, in which case
+// postgres re-checks our state filter once the dequeue commits and skips it, or is updated by us
+// first, in which case the dequeue no longer sees it as available.
+fn push_filter<'args>(query: &mut QueryBuilder<'args, Postgres>, filter: &'args JobFilter) {
+    match filter {
+        JobFilter::Ids(ids) => query.push(" AND id = ANY(").push_bind(ids).push(")"),
+        JobFilter::FunctionId(function_id) => {
+            query.push(" AND function_id = ").push_bind(function_id)
+        }
+        JobFilter::TeamId(team_id) => query.push(" AND team_id = ").push_bind(team_id),
+    };
+}
+
+// Cancelled jobs get their own `cancelled` state, so the janitor cleans them up without counting
+// them in failure metrics, and jobs depending on them are resolved. Returns the number of jobs
+// cancelled.
+pub async fn cancel_jobs(conn: &mut PgConnection, filter: &JobFilter) -> Result<u64, QueueError> {
+    let mut query = QueryBuilder::new(
+        r#"
+UPDATE cyclotron_jobs
+SET
+    state = 'cancelled'::JobState,
+    last_transition = NOW(),
+    transition_count = transition_count + 1
+WHERE state IN ('available', 'paused', 'waiting')"#,
+    );
+    push_filter(&mut query, filter);
+    query.push(" RETURNING id");
+
+    let cancelled: Vec<Uuid> = query.build_query_scalar().fetch_all(&mut *conn).await?;
+    let count = cancelled.len() as u64;
+    delete_dependencies(&mut *conn, &cancelled).await?;
+    resolve_dependents(conn, cancelled).await?;
+
+    Ok(count)
+}
+
+// Moves jobs between available and paused. Jobs waiting on dependencies can't be paused.
+// Returns the number of jobs moved.
+pub async fn set_jobs_paused<'c, E>(
+    executor: E,
+    filter: &JobFilter,
+    paused: bool,
+) -> Result<u64, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let (from, to) = if paused {
+        (JobState::Available, JobState::Paused)
+    } else {
+        (JobState::Paused, JobState::Available)
+    };
+
+    let mut query = QueryBuilder::new("UPDATE cyclotron_jobs SET state = ");
+    query
+        .push_bind(to)
+        .push(", last_transition = NOW(), transition_count = transition_count + 1 WHERE state = ")
+        .push_bind(from);
+    push_filter(&mut query, filter);
+
+    Ok(query.build().execute(executor).await?.rows_affected())
+}
+
+// Returns the number of jobs rescheduled.
+pub async fn reschedule_jobs<'c, E>(
+    executor: E,
+    filter: &JobFilter,
+    scheduled: DateTime<Utc>,
+) -> Result<u64, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let mut query = QueryBuilder::new("UPDATE cyclotron_jobs SET scheduled = ");
+    query
+        .push_bind(scheduled)
+        .push(" WHERE state IN ('available', 'paused', 'waiting')");
+    push_filter(&mut query, filter);
+
+    Ok(query.build().execute(executor).await?.rows_affected())
 }
@@ -1,3 +1,5 @@
+use std::future::Future;
+
 use sqlx::{postgres::PgQueryResult, PgPool};
 use uuid::Uuid;
 
@@ -37,6 +39,29 @@ pub fn throw_if_no_rows(res: PgQueryResult, job: Uuid, lock: Uuid) -> Result<(),
     }
 }
 
+// How many times an operation is retried after a job it was waiting on moved partitions, see
+// `QueueError::is_serialization_failure`. Each retry sees the jobs as they are after the move, so
+// it only fails again if another one moves under it.
+pub const MAX_SERIALIZATION_RETRIES: usize = 3;
+
+// Runs `op`, retrying it if it fails because a job it was waiting to lock moved partitions. `op`
+// must be safe to retry, e.g. by doing all its work in one transaction.
+pub async fn retry_serialization_failures<T, F, Fut>(mut op: F) -> Result<T, QueueError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, QueueError>>,
+{
+    let mut retries = 0;
+    loop {
+        match op().await {
+            Err(e) if e.is_serialization_failure() && retries < MAX_SERIALIZATION_RETRIES => {
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
 /// Run the latest cyclotron migrations. Panics if the migrations can't be run - failure to run migrations is purposefully fatal.
 pub async fn run_migrations(pool: &PgPool) {
     sqlx::migrate!("./migrations")
@@ -1,4 +1,9 @@
+pub mod admin;
+pub mod dependencies;
 pub mod janitor;
 pub mod manager;
 pub mod meta;
+pub mod partitioning;
+pub mod results;
+pub mod schedules;
 pub mod worker;
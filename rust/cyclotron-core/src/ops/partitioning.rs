@@ -0,0 +1,206 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::error::QueueError;
+
+// Held while creating or swapping in the partitioned jobs table, so concurrent janitors don't both
+// do it. As with the other advisory locks, the value is arbitrary.
+const CONVERSION_LOCK_KEY: i64 = 0x6379_636c_636f_6e76;
+
+// Indexes on the partitioned jobs table, which apply to every partition, including ones created
+// later. They're created under temporary names, since the original table still has its own, and
+// renamed when the tables are swapped. These are the original table's indexes, less the superseded
+// dequeue index, plus one on id to replace the primary key - unique constraints on a partitioned
+// table have to include the partition key. Job ids are UUIDv7s generated by cyclotron, so this only
+// loses a check, not a guarantee.
+const PARTITIONED_INDEXES: &[(&str, &str)] = &[
+    ("idx_cyclotron_jobs_id", "(id)"),
+    (
+        "idx_cyclotron_jobs_dequeue_correct_order",
+        "(queue_name, state, priority, scheduled) WHERE state = 'available'",
+    ),
+    (
+        "idx_cyclotron_jobs_available_teams",
+        "(queue_name, team_id) WHERE state = 'available'",
+    ),
+    (
+        "idx_cyclotron_jobs_waiting",
+        "(state) WHERE state = 'waiting'",
+    ),
+    ("idx_queue_team_id", "(team_id)"),
+    ("idx_queue_function_id", "(function_id)"),
+    ("idx_queue_queue_name", "(queue_name)"),
+];
+
+const TEMPORARY_INDEX_SUFFIX: &str = "_partitioned";
+
+// The trigger that keeps the partitioned copy in sync, see the partitioning migration
+const MIRROR_TRIGGER: &str = "cyclotron_jobs_mirror";
+
+pub async fn jobs_table_partitioned<'c, E>(executor: E) -> Result<bool, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_scalar(
+        "SELECT relkind = 'p' FROM pg_class WHERE oid = 'cyclotron_jobs'::regclass",
+    )
+    .fetch_one(executor)
+    .await?)
+}
+
+// Creates the empty partitioned copy of the jobs table, and the trigger that keeps jobs copied to
+// it up to date, unless they already exist. Returns false if the jobs table is already
+// partitioned, and there's nothing to do. Must be called in a transaction.
+pub async fn prepare_partitioned_jobs(conn: &mut PgConnection) -> Result<bool, QueueError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(CONVERSION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    if jobs_table_partitioned(&mut *conn).await? {
+        return Ok(false);
+    }
+
+    let exists: bool =
+        sqlx::query_scalar("SELECT to_regclass('cyclotron_jobs_partitioned') IS NOT NULL")
+            .fetch_one(&mut *conn)
+            .await?;
+    if exists {
+        return Ok(true);
+    }
+
+    // Creating the trigger waits for writes to the jobs table in flight, and blocks new ones until
+    // we commit. Rather than queueing behind a long running transaction, and blocking every worker
+    // behind us while we wait, give up and try again later.
+    sqlx::query("SET LOCAL lock_timeout = '5s'")
+        .execute(&mut *conn)
+        .await?;
+
+    let statements = [
+        "CREATE TABLE cyclotron_jobs_partitioned (LIKE cyclotron_jobs INCLUDING DEFAULTS) PARTITION BY LIST (state)",
+        // Finished jobs move here when they transition, since updating a row's partition key moves
+        // it to its new partition. The janitor creates hourly partitions ahead of time, finished
+        // jobs with no hourly partition land in the default one.
+        "CREATE TABLE cyclotron_jobs_finished PARTITION OF cyclotron_jobs_partitioned FOR VALUES IN ('completed', 'failed', 'cancelled') PARTITION BY RANGE (last_transition)",
+        "CREATE TABLE cyclotron_jobs_finished_default PARTITION OF cyclotron_jobs_finished DEFAULT",
+        // Everything else - available, running, paused and waiting jobs, and any states added later
+        "CREATE TABLE cyclotron_jobs_active PARTITION OF cyclotron_jobs_partitioned DEFAULT",
+    ];
+    for statement in statements {
+        sqlx::query(statement).execute(&mut *conn).await?;
+    }
+
+    for (name, definition) in PARTITIONED_INDEXES {
+        sqlx::query(&format!(
+            "CREATE INDEX {}{} ON cyclotron_jobs_partitioned {}",
+            name, TEMPORARY_INDEX_SUFFIX, definition
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query(&format!(
+        "CREATE TRIGGER {} AFTER INSERT OR UPDATE OR DELETE ON cyclotron_jobs FOR EACH ROW EXECUTE FUNCTION cyclotron_mirror_job()",
+        MIRROR_TRIGGER
+    ))
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(true)
+}
+
+// Copies up to `limit` jobs with ids after `after` to the partitioned copy, replacing any copies
+// the trigger already made of them. The jobs are locked while they're copied, so they can't change
+// under us, and once we commit, any change to them is mirrored. Returns the last id copied, or None
+// once there's nothing left to copy.
+pub async fn backfill_partitioned_jobs<'c, E>(
+    executor: E,
+    after: Option<Uuid>,
+    limit: i64,
+) -> Result<Option<Uuid>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_scalar(
+        r#"
+WITH batch AS (
+    SELECT * FROM cyclotron_jobs
+    WHERE id > COALESCE($1, '00000000-0000-0000-0000-000000000000'::uuid)
+    ORDER BY id
+    LIMIT $2
+    FOR SHARE
+),
+replaced AS (
+    DELETE FROM cyclotron_jobs_partitioned WHERE id IN (SELECT id FROM batch)
+),
+copied AS (
+    INSERT INTO cyclotron_jobs_partitioned SELECT * FROM batch RETURNING id
+)
+SELECT id FROM copied ORDER BY id DESC LIMIT 1
+        "#,
+    )
+    .bind(after)
+    .bind(limit)
+    .fetch_optional(executor)
+    .await?)
+}
+
+// Swaps the partitioned copy in for the jobs table, once every job has been copied. The jobs table
+// is locked while it's dropped, which is quick, and its triggers are recreated on the partitioned
+// table. Must be called in a transaction.
+pub async fn swap_partitioned_jobs(conn: &mut PgConnection) -> Result<(), QueueError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(CONVERSION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    if jobs_table_partitioned(&mut *conn).await? {
+        return Ok(());
+    }
+
+    // As above, rather than blocking every worker behind a long running transaction
+    sqlx::query("SET LOCAL lock_timeout = '5s'")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("LOCK TABLE cyclotron_jobs IN ACCESS EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await?;
+
+    // Trigger definitions name the table, so they apply to the partitioned one once it's renamed
+    let triggers: Vec<String> = sqlx::query_scalar(
+        r#"
+SELECT pg_get_triggerdef(oid)
+FROM pg_trigger
+WHERE tgrelid = 'cyclotron_jobs'::regclass AND NOT tgisinternal AND tgname <> $1
+        "#,
+    )
+    .bind(MIRROR_TRIGGER)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // Foreign keys can't reference a partitioned table without its partition key, so dependencies
+    // are deleted explicitly rather than cascading from their jobs
+    let statements = [
+        "ALTER TABLE cyclotron_job_dependencies DROP CONSTRAINT IF EXISTS cyclotron_job_dependencies_job_id_fkey",
+        "DROP TABLE cyclotron_jobs",
+        "ALTER TABLE cyclotron_jobs_partitioned RENAME TO cyclotron_jobs",
+    ];
+    for statement in statements {
+        sqlx::query(statement).execute(&mut *conn).await?;
+    }
+
+    for (name, _) in PARTITIONED_INDEXES {
+        sqlx::query(&format!(
+            "ALTER INDEX {}{} RENAME TO {}",
+            name, TEMPORARY_INDEX_SUFFIX, name
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for trigger in triggers {
+        sqlx::query(&trigger).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use chrono::Duration;
+use uuid::Uuid;
+
+use crate::{error::QueueError, types::JobStatus};
+
+// Looks for the job itself first, falling back to its retained result once the job's been deleted.
+// Finished jobs are copied into the results table as they finish, so while both exist they agree.
+pub async fn get_job_status<'c, E>(executor: E, id: Uuid) -> Result<Option<JobStatus>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query_as(
+        r#"
+SELECT id, team_id, function_id, queue_name, state, last_transition, parameters, blob
+FROM (
+    SELECT 0 AS source, id, team_id, function_id, queue_name, state, last_transition, parameters, blob
+    FROM cyclotron_jobs
+    WHERE id = $1
+    UNION ALL
+    SELECT 1 AS source, job_id, team_id, function_id, queue_name, state, finished, parameters, blob
+    FROM cyclotron_job_results
+    WHERE job_id = $1 AND expires > NOW()
+) found
+ORDER BY source
+LIMIT 1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await?)
+}
+
+// Passing None stops retaining results for the queue. Results already retained are kept until they
+// expire.
+pub async fn set_result_retention<'c, E>(
+    executor: E,
+    queue_name: &str,
+    retention: Option<Duration>,
+) -> Result<(), QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    match retention {
+        Some(retention) => {
+            sqlx::query(
+                r#"
+INSERT INTO cyclotron_result_retention (queue_name, retention_seconds)
+VALUES ($1, $2)
+ON CONFLICT (queue_name) DO UPDATE SET retention_seconds = EXCLUDED.retention_seconds
+                "#,
+            )
+            .bind(queue_name)
+            .bind(retention.num_seconds())
+            .execute(executor)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM cyclotron_result_retention WHERE queue_name = $1")
+                .bind(queue_name)
+                .execute(executor)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn delete_expired_results<'c, E>(executor: E) -> Result<u64, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let result = sqlx::query("DELETE FROM cyclotron_job_results WHERE expires <= NOW()")
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
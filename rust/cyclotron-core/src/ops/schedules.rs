@@ -0,0 +1,360 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::PgConnection;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    error::QueueError,
+    ops::manager::create_job,
+    types::{JobInit, Schedule, ScheduleInit},
+};
+
+// Held for the duration of a scheduler transaction, so only one janitor per shard materialises
+// schedules at a time. The value is arbitrary, it just has to not collide with other advisory
+// locks taken against the same database.
+const SCHEDULER_LOCK_KEY: i64 = 0x6379_636c_7363_6864;
+
+// The first time `expression` fires strictly after `after`, evaluated in `timezone`, or None if it
+// never fires again.
+pub fn next_run(
+    expression: &str,
+    timezone: &str,
+    after: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, QueueError> {
+    let tz = Tz::from_str(timezone)
+        .map_err(|_| QueueError::InvalidSchedule(format!("unknown timezone {}", timezone)))?;
+
+    // The cron crate wants a leading seconds field, which standard cron expressions don't have,
+    // and numbers days of the week differently
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let expression = if fields.len() == 5 {
+        let day_of_week = standard_day_of_week(fields[4]).ok_or_else(|| {
+            QueueError::InvalidSchedule(format!(
+                "{}: invalid day of week {}",
+                expression, fields[4]
+            ))
+        })?;
+        format!("0 {} {}", fields[..4].join(" "), day_of_week)
+    } else {
+        expression.to_string()
+    };
+    let schedule = cron::Schedule::from_str(&expression)
+        .map_err(|e| QueueError::InvalidSchedule(format!("{}: {}", expression, e)))?;
+
+    Ok(schedule
+        .after(&after.with_timezone(&tz))
+        .next()
+        .map(|t| t.with_timezone(&Utc)))
+}
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// Standard cron numbers the days of the week 0-6 from Sunday, with 7 also being Sunday, where the
+// cron crate numbers them 1-7 from Sunday. Rewrites a standard day of week field as the list of
+// days it matches, by name, which both agree on. Returns None if the field isn't valid.
+fn standard_day_of_week(field: &str) -> Option<String> {
+    if field == "*" || field == "?" {
+        return Some(field.to_string());
+    }
+
+    let mut days = [false; 7];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match (range, range.split_once('-')) {
+            ("*", _) => (0, 6),
+            (_, Some((start, end))) => (parse_day(start)?, parse_day(end)?),
+            // A single day with a step runs through to the end of the week, e.g. 1/2 is 1,3,5,7
+            (day, None) if step > 1 => (parse_day(day)?, 7),
+            (day, None) => (parse_day(day)?, parse_day(day)?),
+        };
+        if start > end {
+            return None;
+        }
+        for day in (start..=end).step_by(step) {
+            days[day % 7] = true;
+        }
+    }
+
+    Some(
+        WEEKDAYS
+            .iter()
+            .zip(days)
+            .filter(|(_, matched)| *matched)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+fn parse_day(day: &str) -> Option<usize> {
+    match day.parse::<usize>() {
+        Ok(day) => Some(day).filter(|day| *day <= 7),
+        Err(_) => WEEKDAYS
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(day)),
+    }
+}
+
+fn first_run(init: &ScheduleInit) -> Result<DateTime<Utc>, QueueError> {
+    next_run(&init.cron, &init.timezone, Utc::now())?
+        .ok_or_else(|| QueueError::InvalidSchedule(format!("{} never fires", init.cron)))
+}
+
+pub async fn create_schedule<'c, E>(executor: E, init: ScheduleInit) -> Result<Uuid, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let id = Uuid::now_v7();
+    let next_run = first_run(&init)?;
+    sqlx::query(
+        r#"
+INSERT INTO cyclotron_schedules
+    (
+        id,
+        team_id,
+        function_id,
+        created,
+        queue_name,
+        priority,
+        cron,
+        timezone,
+        enabled,
+        next_run,
+        last_run,
+        vm_state,
+        metadata,
+        parameters,
+        blob
+    )
+VALUES
+    ($1, $2, $3, NOW(), $4, $5, $6, $7, true, $8, NULL, $9, $10, $11, $12)
+    "#,
+    )
+    .bind(id)
+    .bind(init.team_id)
+    .bind(init.function_id)
+    .bind(&init.queue_name)
+    .bind(init.priority)
+    .bind(&init.cron)
+    .bind(&init.timezone)
+    .bind(next_run)
+    .bind(&init.vm_state)
+    .bind(&init.metadata)
+    .bind(&init.parameters)
+    .bind(&init.blob)
+    .execute(executor)
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn get_schedule<'c, E>(executor: E, id: Uuid) -> Result<Option<Schedule>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(
+        sqlx::query_as("SELECT * FROM cyclotron_schedules WHERE id = $1")
+            .bind(id)
+            .fetch_optional(executor)
+            .await?,
+    )
+}
+
+pub async fn list_schedules<'c, E>(executor: E, team_id: i32) -> Result<Vec<Schedule>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    Ok(
+        sqlx::query_as("SELECT * FROM cyclotron_schedules WHERE team_id = $1 ORDER BY id")
+            .bind(team_id)
+            .fetch_all(executor)
+            .await?,
+    )
+}
+
+// Replaces everything about a schedule except whether it's enabled. The next run is recalculated
+// from now. Returns false if the schedule doesn't exist.
+pub async fn update_schedule<'c, E>(
+    executor: E,
+    id: Uuid,
+    init: ScheduleInit,
+) -> Result<bool, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let next_run = first_run(&init)?;
+    let result = sqlx::query(
+        r#"
+UPDATE cyclotron_schedules
+SET
+    team_id = $2,
+    function_id = $3,
+    queue_name = $4,
+    priority = $5,
+    cron = $6,
+    timezone = $7,
+    next_run = $8,
+    vm_state = $9,
+    metadata = $10,
+    parameters = $11,
+    blob = $12
+WHERE id = $1
+    "#,
+    )
+    .bind(id)
+    .bind(init.team_id)
+    .bind(init.function_id)
+    .bind(&init.queue_name)
+    .bind(init.priority)
+    .bind(&init.cron)
+    .bind(&init.timezone)
+    .bind(next_run)
+    .bind(&init.vm_state)
+    .bind(&init.metadata)
+    .bind(&init.parameters)
+    .bind(&init.blob)
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Re-enabled schedules pick up from now, rather than creating the runs missed while disabled.
+// Returns false if the schedule doesn't exist.
+pub async fn set_schedule_enabled(
+    conn: &mut PgConnection,
+    id: Uuid,
+    enabled: bool,
+) -> Result<bool, QueueError> {
+    let Some(schedule) =
+        sqlx::query_as::<_, Schedule>("SELECT * FROM cyclotron_schedules WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?
+    else {
+        return Ok(false);
+    };
+
+    let next_run = if enabled && !schedule.enabled {
+        next_run(&schedule.cron, &schedule.timezone, Utc::now())?
+            .ok_or_else(|| QueueError::InvalidSchedule(format!("{} never fires", schedule.cron)))?
+    } else {
+        schedule.next_run
+    };
+
+    sqlx::query("UPDATE cyclotron_schedules SET enabled = $2, next_run = $3 WHERE id = $1")
+        .bind(id)
+        .bind(enabled)
+        .bind(next_run)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(true)
+}
+
+// Returns false if the schedule doesn't exist. Jobs already created from it are left alone.
+pub async fn delete_schedule<'c, E>(executor: E, id: Uuid) -> Result<bool, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let result = sqlx::query("DELETE FROM cyclotron_schedules WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Create a job for up to `limit` schedules whose next run has passed, and advance them to their
+// following run. Must be called in a transaction: the job and the advanced schedule commit
+// together, and the advisory lock guarding against other schedulers is released on commit, so
+// each run creates exactly one job. Runs missed while the scheduler was down aren't backfilled,
+// a schedule creates one job for the most overdue run and then picks up from now. Returns the
+// number of jobs created, or 0 if another scheduler holds the lock.
+pub async fn materialise_due_schedules(
+    conn: &mut PgConnection,
+    limit: i64,
+) -> Result<u64, QueueError> {
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1)")
+        .bind(SCHEDULER_LOCK_KEY)
+        .fetch_one(&mut *conn)
+        .await?;
+    if !locked {
+        return Ok(0);
+    }
+
+    let due: Vec<Schedule> = sqlx::query_as(
+        r#"
+SELECT * FROM cyclotron_schedules
+WHERE enabled AND next_run <= NOW()
+ORDER BY next_run
+LIMIT $1
+FOR UPDATE
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let now = Utc::now();
+    let mut created = 0;
+    for schedule in due {
+        let following = next_run(
+            &schedule.cron,
+            &schedule.timezone,
+            now.max(schedule.next_run),
+        );
+        let init = JobInit {
+            team_id: schedule.team_id,
+            queue_name: schedule.queue_name,
+            priority: schedule.priority,
+            scheduled: schedule.next_run,
+            function_id: schedule.function_id,
+            vm_state: schedule.vm_state,
+            parameters: schedule.parameters,
+            blob: schedule.blob,
+            metadata: schedule.metadata,
+            dependencies: Vec::new(),
+            on_parent_failure: Default::default(),
+            idempotency_key: None,
+        };
+        create_job(&mut *conn, init).await?;
+        created += 1;
+
+        match following {
+            Ok(Some(following)) => {
+                sqlx::query(
+                    "UPDATE cyclotron_schedules SET next_run = $2, last_run = $3 WHERE id = $1",
+                )
+                .bind(schedule.id)
+                .bind(following)
+                .bind(schedule.next_run)
+                .execute(&mut *conn)
+                .await?;
+            }
+            // Schedules are validated when written, so this is a cron that's run out of years, or
+            // a timezone that's been dropped from the tz database
+            result => {
+                warn!(
+                    "Disabling schedule {} with no next run: {:?}",
+                    schedule.id, result
+                );
+                sqlx::query(
+                    "UPDATE cyclotron_schedules SET enabled = false, last_run = $2 WHERE id = $1",
+                )
+                .bind(schedule.id)
+                .bind(schedule.next_run)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    Ok(created)
+}
@@ -48,6 +48,7 @@ SET
 FROM available
 WHERE
     cyclotron_jobs.id = available.id
+    AND cyclotron_jobs.state = 'available'::JobState
 RETURNING
     cyclotron_jobs.id,
     team_id,
@@ -113,6 +114,7 @@ SET
 FROM available
 WHERE
     cyclotron_jobs.id = available.id
+    AND cyclotron_jobs.state = 'available'::JobState
 RETURNING
     cyclotron_jobs.id,
     team_id,
@@ -140,6 +142,151 @@ RETURNING
     .await?)
 }
 
+// Dequeue a batch of jobs, taking turns between the teams with jobs available, rather than strictly
+// by priority, so one team with a huge backlog can't starve the rest. Teams take turns in rounds -
+// every team's best job by priority and scheduled time, then every team's second best, and so on -
+// so each gets an equal share of the batch, and the share of a team with fewer jobs available goes
+// to the rest. If `max_running_per_team` is set, teams already running that many jobs in this queue
+// are skipped, and the rest are capped at what they have left. The cap is best effort - concurrent
+// dequeues can each see a team as under it.
+//
+// To fill the batch from whichever teams have jobs, up to `max` of each team's jobs are locked
+// while the batch is picked, and skipped by concurrent dequeues until it has been.
+pub async fn dequeue_jobs_fair<'c, E>(
+    executor: E,
+    queue: &str,
+    max: usize,
+    max_running_per_team: Option<u32>,
+    with_vm_state: bool,
+) -> Result<Vec<Job>, QueueError>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let lock_id = Uuid::now_v7();
+    let vm_state = if with_vm_state {
+        "vm_state"
+    } else {
+        "NULL::bytea as vm_state"
+    };
+    // TRICKY - the teams CTE is a skip scan over idx_cyclotron_jobs_available_teams, finding each
+    // distinct team with one index lookup rather than reading every available job.
+    let query = format!(
+        r#"
+WITH RECURSIVE teams AS (
+    (
+        SELECT team_id FROM cyclotron_jobs
+        WHERE queue_name = $1 AND state = 'available'::JobState AND scheduled <= NOW()
+        ORDER BY team_id
+        LIMIT 1
+    )
+    UNION ALL
+    SELECT (
+        SELECT team_id FROM cyclotron_jobs
+        WHERE
+            queue_name = $1
+            AND state = 'available'::JobState
+            AND scheduled <= NOW()
+            AND team_id > teams.team_id
+        ORDER BY team_id
+        LIMIT 1
+    )
+    FROM teams
+    WHERE teams.team_id IS NOT NULL
+),
+running AS (
+    SELECT team_id, COUNT(*) AS running
+    FROM cyclotron_jobs
+    WHERE queue_name = $1 AND state = 'running'::JobState
+    GROUP BY team_id
+),
+allowed AS (
+    SELECT team_id, allowance
+    FROM (
+        SELECT
+            t.team_id,
+            CASE
+                WHEN $4::bigint IS NULL THEN $2::bigint
+                ELSE LEAST($2::bigint, $4::bigint - COALESCE(r.running, 0))
+            END AS allowance
+        FROM teams t
+        LEFT JOIN running r ON r.team_id = t.team_id
+        WHERE t.team_id IS NOT NULL
+    ) a
+    WHERE allowance > 0
+),
+candidates AS (
+    SELECT
+        c.id,
+        c.state,
+        c.priority,
+        c.scheduled,
+        ROW_NUMBER() OVER (PARTITION BY c.team_id ORDER BY c.priority ASC, c.scheduled ASC) AS team_rank
+    FROM allowed a
+    CROSS JOIN LATERAL (
+        SELECT id, team_id, state, priority, scheduled
+        FROM cyclotron_jobs j
+        WHERE
+            j.state = 'available'::JobState
+            AND j.queue_name = $1
+            AND j.team_id = a.team_id
+            AND j.scheduled <= NOW()
+        ORDER BY
+            j.priority ASC,
+            j.scheduled ASC
+        LIMIT GREATEST(a.allowance, 0)
+        FOR UPDATE SKIP LOCKED
+    ) c
+),
+available AS (
+    SELECT id, state
+    FROM candidates
+    ORDER BY
+        team_rank ASC,
+        priority ASC,
+        scheduled ASC
+    LIMIT $2
+)
+UPDATE cyclotron_jobs
+SET
+    state = 'running'::JobState,
+    lock_id = $3,
+    last_heartbeat = NOW(),
+    last_transition = NOW(),
+    transition_count = transition_count + 1
+FROM available
+WHERE
+    cyclotron_jobs.id = available.id
+    AND cyclotron_jobs.state = 'available'::JobState
+RETURNING
+    cyclotron_jobs.id,
+    team_id,
+    available.state,
+    queue_name,
+    priority,
+    function_id,
+    created,
+    last_transition,
+    scheduled,
+    transition_count,
+    {vm_state},
+    metadata,
+    parameters,
+    blob,
+    lock_id,
+    last_heartbeat,
+    janitor_touch_count
+    "#
+    );
+
+    Ok(sqlx::query_as(&query)
+        .bind(queue)
+        .bind(max as i64)
+        .bind(lock_id)
+        .bind(max_running_per_team.map(i64::from))
+        .fetch_all(executor)
+        .await?)
+}
+
 pub async fn get_vm_state<'c, E>(
     executor: E,
     job_id: Uuid,
@@ -154,7 +301,7 @@ where
 
     let res = sqlx::query_as!(
         VMState,
-        "SELECT vm_state FROM cyclotron_jobs WHERE id = $1 AND lock_id = $2",
+        "SELECT vm_state FROM cyclotron_jobs WHERE id = $1 AND lock_id = $2 AND state = 'running'",
         job_id,
         lock_id
     )
@@ -238,6 +385,8 @@ where
     query.push_bind(job_id);
     query.push(" AND lock_id = ");
     query.push_bind(lock_id);
+    // Locked jobs are always running, and saying so lets postgres skip the finished partitions
+    query.push(" AND state = 'running'");
 
     assert_does_update(executor, job_id, lock_id, query.build()).await?;
     Ok(())
@@ -269,7 +418,7 @@ where
     E: sqlx::Executor<'c, Database = sqlx::Postgres>,
 {
     let q = sqlx::query!(
-        "UPDATE cyclotron_jobs SET last_heartbeat = NOW() WHERE id = $1 AND lock_id = $2",
+        "UPDATE cyclotron_jobs SET last_heartbeat = NOW() WHERE id = $1 AND lock_id = $2 AND state = 'running'",
         job_id,
         lock_id
     );
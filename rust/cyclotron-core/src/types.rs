@@ -14,6 +14,10 @@ pub enum JobState {
     Completed,
     Failed,
     Paused,
+    // Waiting for its parents to finish, see `JobInit::dependencies`
+    Waiting,
+    // Finished without running, see `QueueManager::cancel_jobs`
+    Cancelled,
 }
 
 impl FromStr for JobState {
@@ -25,6 +29,8 @@ impl FromStr for JobState {
             "running" => Ok(JobState::Running),
             "completed" => Ok(JobState::Completed),
             "failed" => Ok(JobState::Failed),
+            "waiting" => Ok(JobState::Waiting),
+            "cancelled" => Ok(JobState::Cancelled),
             _ => Err(()),
         }
     }
@@ -42,9 +48,78 @@ pub struct JobInit {
     pub parameters: Option<Bytes>,
     pub blob: Option<Bytes>,
     pub metadata: Option<Bytes>,
+    // Jobs that must finish before this one is made available. Jobs can only depend on jobs in the
+    // same shard, see `QueueManager::create_fan_out` and `QueueManager::create_fan_in`
+    #[serde(default)]
+    pub dependencies: Vec<Uuid>,
+    #[serde(default)]
+    pub on_parent_failure: ParentFailurePolicy,
+    // Creating a job with the same key, team and queue as an existing one returns the existing job's
+    // id instead of creating a new job, until the key expires, see `Janitor::delete_expired_idempotency_keys`.
+    // Keys are only unique within a shard, so jobs with dependencies, which go to their parents'
+    // shard, are only deduplicated against jobs in it.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// What happens to a job when one of its parents fails, or is cancelled
+#[derive(Debug, Deserialize, Serialize, sqlx::Type, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "ParentFailurePolicy", rename_all = "lowercase")]
+pub enum ParentFailurePolicy {
+    // The job is failed without being run, or cancelled if its parent was, and its own dependents
+    // are resolved in turn
+    #[default]
+    Fail,
+    // The job is run anyway, once all of its parents have finished
+    Run,
+}
+
+// Selects jobs for bulk management operations, see `QueueManager::cancel_jobs` and friends
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum JobFilter {
+    Ids(Vec<Uuid>),
+    FunctionId(Uuid),
+    TeamId(i32),
+}
+
+// A recurring job. Every time the cron expression fires, a job is created from the rest of the
+// fields, scheduled at the time it fired.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduleInit {
+    pub team_id: i32,
+    pub function_id: Option<Uuid>,
+    pub queue_name: String,
+    pub priority: i16,
+    pub cron: String, // Standard 5 field cron, or 6/7 fields with leading seconds and trailing years
+    pub timezone: String, // An IANA timezone name, like "Europe/London", the cron is evaluated in
+    pub vm_state: Option<Bytes>,
+    pub parameters: Option<Bytes>,
+    pub blob: Option<Bytes>,
+    pub metadata: Option<Bytes>,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct Schedule {
+    pub id: Uuid,
+    pub team_id: i32,
+    pub function_id: Option<Uuid>,
+    pub created: DateTime<Utc>,
+    pub queue_name: String,
+    pub priority: i16,
+    pub cron: String,
+    pub timezone: String,
+    pub enabled: bool,
+    pub next_run: DateTime<Utc>,
+    pub last_run: Option<DateTime<Utc>>, // The scheduled time of the last job created
+    pub vm_state: Option<Bytes>,
+    pub metadata: Option<Bytes>,
+    pub parameters: Option<Bytes>,
+    pub blob: Option<Bytes>,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
 pub struct Job {
     // Job metadata
     pub id: Uuid,
@@ -76,6 +151,20 @@ pub struct Job {
     pub blob: Option<Bytes>, // An additional, binary, parameter field (for things like fetch request body)
 }
 
+// Where a job is up to, see `QueueManager::get_job_status`. For a finished job, `parameters` and
+// `blob` are whatever the last worker to run it left there, e.g. a fetch job's result and body
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub team_id: i32,
+    pub function_id: Option<Uuid>,
+    pub queue_name: String,
+    pub state: JobState,
+    pub last_transition: DateTime<Utc>,
+    pub parameters: Option<Bytes>,
+    pub blob: Option<Bytes>,
+}
+
 // A struct representing a set of updates for a job. Outer none values mean "don't update this field",
 // with nested none values meaning "set this field to null" for nullable fields
 #[derive(Debug, Deserialize, Serialize)]
@@ -120,3 +209,31 @@ pub struct AggregatedDelete {
     pub state: String,
     pub count: i64,
 }
+
+// Result of janitor's `queue_depths`, one row per queue, state and team
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct QueueDepth {
+    pub queue_name: String,
+    pub state: JobState,
+    pub team_id: i32,
+    pub count: i64,
+}
+
+// Result of janitor's `oldest_available_jobs`. `scheduled` is when the oldest job that could be
+// running right now became runnable.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct OldestAvailableJob {
+    pub queue_name: String,
+    pub scheduled: DateTime<Utc>,
+}
+
+// A job in the dead letter queue, with why and from where it was moved there
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct DeadLetter {
+    pub job_id: Uuid,
+    pub team_id: i32,
+    pub function_id: Option<Uuid>,
+    pub original_queue_name: String,
+    pub reason: String,
+    pub dlq_time: DateTime<Utc>,
+}
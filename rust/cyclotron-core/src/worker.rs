@@ -16,9 +16,16 @@ use uuid::Uuid;
 use crate::{
     config::WorkerConfig,
     error::JobError,
+    notifier::JobNotifier,
     ops::{
-        meta::{dead_letter, run_migrations},
-        worker::{dequeue_jobs, dequeue_with_vm_state, flush_job, get_vm_state, set_heartbeat},
+        dependencies::resolve_dependents,
+        meta::{
+            dead_letter, retry_serialization_failures, run_migrations, MAX_SERIALIZATION_RETRIES,
+        },
+        worker::{
+            dequeue_jobs, dequeue_jobs_fair, dequeue_with_vm_state, flush_job, get_vm_state,
+            set_heartbeat,
+        },
     },
     types::Bytes,
     Job, JobState, JobUpdate, PoolConfig, QueueError,
@@ -46,10 +53,15 @@ pub struct Worker {
     // some conditions.
     flush_batch: Arc<Mutex<FlushBatch>>,
 
+    // Wakes `wait_for_jobs` callers when jobs become available
+    notifier: Arc<JobNotifier>,
+
     pub heartbeat_window: Duration, // The worker will only pass one heartbeat to the DB per job every heartbeat_window
     pub linger: Duration,           // Updates will be held at most this long
     pub max_buffered: usize,        // Updates will be flushed after this many are buffered
     pub max_bytes: usize, // Updates will be flushed after the vm_state and blob sizes combined exceed this
+    pub fair_dequeue: bool, // Take turns between teams when dequeuing, see `ops::worker::dequeue_jobs_fair`
+    pub max_running_per_team: Option<u32>, // Skip teams running at least this many jobs in the queue, when dequeuing fairly
 }
 
 impl Worker {
@@ -64,9 +76,12 @@ impl Worker {
             running: Default::default(),
             heartbeat_window: worker_config.heartbeat_window(),
             flush_batch: Default::default(),
+            notifier: Default::default(),
             linger: worker_config.linger_time(),
             max_buffered: worker_config.max_updates_buffered(),
             max_bytes: worker_config.max_bytes_buffered(),
+            fair_dequeue: worker_config.fair_dequeue(),
+            max_running_per_team: worker_config.max_running_per_team,
         };
 
         tokio::spawn(flush_loop(
@@ -89,7 +104,15 @@ impl Worker {
     /// workers can't provide any filtering or sorting criteria - queue managers decide which jobs are run,
     /// workers just run them.
     pub async fn dequeue_jobs(&self, queue: &str, limit: usize) -> Result<Vec<Job>, QueueError> {
-        let jobs = dequeue_jobs(&self.pool, queue, limit).await?;
+        // Cancelling available jobs can move them out from under us
+        let jobs = retry_serialization_failures(|| async move {
+            if self.fair_dequeue {
+                dequeue_jobs_fair(&self.pool, queue, limit, self.max_running_per_team, false).await
+            } else {
+                dequeue_jobs(&self.pool, queue, limit).await
+            }
+        })
+        .await?;
 
         let mut running = self.running.lock().unwrap();
         for job in &jobs {
@@ -110,7 +133,15 @@ impl Worker {
         queue: &str,
         limit: usize,
     ) -> Result<Vec<Job>, QueueError> {
-        let jobs = dequeue_with_vm_state(&self.pool, queue, limit).await?;
+        // Cancelling available jobs can move them out from under us
+        let jobs = retry_serialization_failures(|| async move {
+            if self.fair_dequeue {
+                dequeue_jobs_fair(&self.pool, queue, limit, self.max_running_per_team, true).await
+            } else {
+                dequeue_with_vm_state(&self.pool, queue, limit).await
+            }
+        })
+        .await?;
 
         let mut running = self.running.lock().unwrap();
         for job in &jobs {
@@ -125,6 +156,19 @@ impl Worker {
         Ok(jobs)
     }
 
+    /// Wait until jobs may have become available in `queue`, or until `timeout` passes, returning
+    /// true if woken by a new job. Jobs becoming available are announced by postgres
+    /// notifications, but jobs whose scheduled time passes aren't, and notifications are lost while
+    /// the worker is reconnecting, so `timeout` bounds how long those take to be picked up - callers
+    /// should try to dequeue once this returns, whether or not it timed out.
+    pub async fn wait_for_jobs(&self, queue: &str, timeout: Duration) -> bool {
+        self.notifier.start(&self.pool);
+        let notify = self.notifier.queue(queue);
+        tokio::time::timeout(timeout.to_std().unwrap_or_default(), notify.notified())
+            .await
+            .is_ok()
+    }
+
     /// Retrieve the VM state for a job, if, for example, you dequeued it and then realised you
     /// need the VM state as well.
     pub async fn get_vm_state(&self, job_id: Uuid) -> Result<Option<Bytes>, QueueError> {
@@ -181,6 +225,28 @@ impl Worker {
         handle
     }
 
+    /// Gives up on every job this worker is still running, putting each back in the queue as it
+    /// was dequeued, and discarding any updates made to it since. This is for shutting down, once
+    /// jobs have had their chance to finish - anything still working on a released job will get
+    /// `UnknownJobId` errors from here on. Like `release_job`, the updates are flushed in the
+    /// background, or by `force_flush`. Returns the number of jobs released.
+    pub fn release_all_jobs(&self) -> usize {
+        let running: Vec<(Uuid, JobUpdate)> = self.running.lock().unwrap().drain().collect();
+        let released = running.len();
+
+        let now = Utc::now();
+        let mut batch = self.flush_batch.lock().unwrap();
+        for (job_id, update) in running {
+            let mut reset = JobUpdate::new(update.lock_id);
+            reset.state = Some(JobState::Available);
+            // Nobody is waiting on these
+            let (pending, _handle) = PendingUpdate::new(job_id, reset, None);
+            batch.add(pending, now);
+        }
+
+        released
+    }
+
     /// Force flush all pending updates, regardless of linger time or buffer size.
     /// Transient errors encountered during the flush will cause the operation to
     /// be aborted, and the error to be returned to the caller. If no transient errors
@@ -414,13 +480,42 @@ impl FlushBatch {
             }
         }
 
+        // Finishing a job can move it out from under another transaction waiting to lock it,
+        // e.g. a cancellation, or one resolving its dependents, and the other way around
+        let mut retries = 0;
+        let results = loop {
+            match self.try_flush(pool).await {
+                Err(e) if e.is_serialization_failure() && retries < MAX_SERIALIZATION_RETRIES => {
+                    retries += 1;
+                }
+                result => break result?,
+            }
+        };
+
+        // We only dispatch results and clear the pending set if we actually commit the transaction, otherwise
+        // the updates in this batch should be retried.
+        for (update, result) in self.pending.drain(..).zip(results) {
+            update.resolve(result);
+        }
+        Ok(())
+    }
+
+    // Flushes every pending update in one transaction, returning the result of each
+    async fn try_flush(&mut self, pool: &PgPool) -> Result<Vec<Result<(), JobError>>, QueueError> {
         let mut txn = pool.begin().await?;
         let mut results = Vec::new();
+        let mut finished = Vec::new();
         for to_flush in self.pending.iter_mut() {
             to_flush.tries += 1;
             let result = flush_job(&mut *txn, to_flush.job_id, &to_flush.update).await;
             match result {
                 Ok(()) => {
+                    if matches!(
+                        to_flush.update.state,
+                        Some(JobState::Completed | JobState::Failed | JobState::Cancelled)
+                    ) {
+                        finished.push(to_flush.job_id);
+                    }
                     results.push(Ok(()));
                 }
                 Err(QueueError::JobError(e)) => {
@@ -431,14 +526,11 @@ impl FlushBatch {
                 }
             }
         }
+        // Jobs waiting on the ones we just finished are resolved in the same transaction, so
+        // they can't miss a parent finishing.
+        resolve_dependents(&mut txn, finished).await?;
         txn.commit().await?;
-
-        // We only dispatch results and clear the pending set if we actually commit the transaction, otherwise
-        // the updates in this batch should be retried.
-        for (update, result) in self.pending.drain(..).zip(results) {
-            update.resolve(result);
-        }
-        Ok(())
+        Ok(results)
     }
 
     fn should_flush(&self, max_buffered: usize, max_bytes: usize) -> bool {
@@ -0,0 +1,98 @@
+use chrono::{Duration, Utc};
+use common::{create_new_job, dates_match};
+use cyclotron_core::{Janitor, QueueManager, Worker};
+use sqlx::PgPool;
+
+mod common;
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_queue_depths_and_oldest_jobs(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let janitor = Janitor::from_pool(db.clone());
+
+    let oldest = Utc::now() - Duration::minutes(10);
+    let mut init = create_new_job();
+    init.scheduled = oldest;
+    manager.create_job(init).await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    let mut other_team = create_new_job();
+    other_team.team_id = 2;
+    manager.create_job(other_team).await.unwrap();
+    // Jobs scheduled in the future aren't waiting yet
+    let mut future = create_new_job();
+    future.queue_name = "other".to_string();
+    future.scheduled = Utc::now() + Duration::hours(1);
+    manager.create_job(future).await.unwrap();
+
+    let depths = janitor.queue_depths().await.unwrap();
+    let depths: Vec<_> = depths
+        .iter()
+        .map(|d| (d.queue_name.as_str(), d.team_id, d.count))
+        .collect();
+    assert_eq!(
+        depths,
+        vec![("other", 1, 1), ("test", 1, 2), ("test", 2, 1)]
+    );
+
+    let oldest_jobs = janitor.oldest_available_jobs().await.unwrap();
+    assert_eq!(oldest_jobs.len(), 1);
+    assert_eq!(oldest_jobs[0].queue_name, "test");
+    assert!(dates_match(&oldest_jobs[0].scheduled, &oldest));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_list_and_requeue_dead_letters(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+    let janitor = Janitor::from_pool(db.clone());
+
+    let id = manager.create_job(create_new_job()).await.unwrap();
+    let job = worker.dequeue_jobs("test", 1).await.unwrap().pop().unwrap();
+    assert_eq!(job.id, id);
+    worker.dead_letter(id, "it broke").await.unwrap();
+
+    let dead = janitor.list_dead_letters(10, 0).await.unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].job_id, id);
+    assert_eq!(dead[0].original_queue_name, "test");
+    assert_eq!(dead[0].reason, "it broke");
+    assert!(janitor.list_dead_letters(10, 1).await.unwrap().is_empty());
+
+    assert!(janitor.requeue_dead_letter(id).await.unwrap());
+    assert!(janitor.list_dead_letters(10, 0).await.unwrap().is_empty());
+    assert_eq!(janitor.count_dlq_depth().await.unwrap(), 0);
+
+    // Requeued jobs are run again
+    let job = worker.dequeue_jobs("test", 1).await.unwrap().pop().unwrap();
+    assert_eq!(job.id, id);
+
+    // Only jobs in the dead letter queue can be requeued
+    assert!(!janitor.requeue_dead_letter(id).await.unwrap());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_requeue_keeps_metadata_of_jobs_not_dead_lettered(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+    let janitor = Janitor::from_pool(db.clone());
+
+    let id = manager.create_job(create_new_job()).await.unwrap();
+    worker.dequeue_jobs("test", 1).await.unwrap();
+    worker.dead_letter(id, "it broke").await.unwrap();
+
+    // Moved out of the dead letter queue behind the janitor's back
+    sqlx::query("UPDATE cyclotron_jobs SET queue_name = 'test' WHERE id = $1")
+        .bind(id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    assert!(!janitor.requeue_dead_letter(id).await.unwrap());
+    let metadata: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM cyclotron_dead_letter_metadata WHERE job_id = $1")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_eq!(metadata, 1);
+}
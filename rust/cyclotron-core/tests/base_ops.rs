@@ -249,3 +249,31 @@ pub async fn test_bulk_insert(db: PgPool) {
 
     assert_eq!(dequeue_jobs.len(), 1000);
 }
+
+#[sqlx::test(migrations = "./migrations")]
+pub async fn test_release_all_jobs(db: PgPool) {
+    let worker = Worker::from_pool(db.clone(), Default::default());
+    let manager = QueueManager::from_pool(db.clone());
+
+    let first = manager.create_job(create_new_job()).await.unwrap();
+    let second = manager.create_job(create_new_job()).await.unwrap();
+
+    let jobs = worker.dequeue_jobs("test", 2).await.unwrap();
+    assert_eq!(jobs.len(), 2);
+
+    // Updates made before giving up on a job are discarded
+    worker.set_queue(first, "other").unwrap();
+
+    assert_eq!(worker.release_all_jobs(), 2);
+    worker.force_flush().await.unwrap();
+
+    // Anything still working on the jobs has lost them
+    assert!(worker.set_state(first, JobState::Completed).is_err());
+
+    let jobs = worker.dequeue_jobs("test", 2).await.unwrap();
+    let mut ids: Vec<Uuid> = jobs.iter().map(|j| j.id).collect();
+    ids.sort();
+    let mut expected = vec![first, second];
+    expected.sort();
+    assert_eq!(ids, expected);
+}
@@ -15,6 +15,9 @@ pub fn create_new_job() -> JobInit {
         parameters: None,
         blob: None,
         metadata: None,
+        dependencies: Vec::new(),
+        on_parent_failure: Default::default(),
+        idempotency_key: None,
     }
 }
 
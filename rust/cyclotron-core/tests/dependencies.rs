@@ -0,0 +1,137 @@
+use common::create_new_job;
+use cyclotron_core::{Janitor, JobState, ParentFailurePolicy, QueueManager, Worker};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+mod common;
+
+async fn finish_next_job(worker: &Worker, state: JobState) -> Uuid {
+    let jobs = worker.dequeue_jobs("test", 1).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    let id = jobs[0].id;
+    worker.set_state(id, state).unwrap();
+    let handle = worker.release_job(id, None);
+    worker.force_flush().await.unwrap();
+    handle.await.unwrap();
+    id
+}
+
+async fn get_state(db: &PgPool, id: Uuid) -> JobState {
+    sqlx::query_scalar("SELECT state FROM cyclotron_jobs WHERE id = $1")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_child_waits_for_parent(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let parent = manager.create_job(create_new_job()).await.unwrap();
+    let mut child = create_new_job();
+    child.dependencies = vec![parent];
+    let child = manager.create_job(child).await.unwrap();
+
+    assert!(matches!(get_state(&db, child).await, JobState::Waiting));
+
+    // Only the parent can be dequeued
+    assert_eq!(finish_next_job(&worker, JobState::Completed).await, parent);
+    assert!(matches!(get_state(&db, child).await, JobState::Available));
+    assert_eq!(finish_next_job(&worker, JobState::Completed).await, child);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_child_of_finished_parent_is_available(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let parent = manager.create_job(create_new_job()).await.unwrap();
+    finish_next_job(&worker, JobState::Completed).await;
+
+    let mut child = create_new_job();
+    child.dependencies = vec![parent];
+    let child = manager.create_job(child).await.unwrap();
+
+    assert!(matches!(get_state(&db, child).await, JobState::Available));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_parent_failure_policies(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let parent = manager.create_job(create_new_job()).await.unwrap();
+
+    let mut failing = create_new_job();
+    failing.dependencies = vec![parent];
+    let mut running = create_new_job();
+    running.dependencies = vec![parent];
+    running.on_parent_failure = ParentFailurePolicy::Run;
+    let ids = manager
+        .bulk_create_jobs(vec![failing, running])
+        .await
+        .unwrap();
+
+    // Failures cascade through jobs failed because of their parents
+    let mut grandchild = create_new_job();
+    grandchild.dependencies = vec![ids[0]];
+    let grandchild = manager.create_job(grandchild).await.unwrap();
+
+    finish_next_job(&worker, JobState::Failed).await;
+
+    assert!(matches!(get_state(&db, ids[0]).await, JobState::Failed));
+    assert!(matches!(get_state(&db, ids[1]).await, JobState::Available));
+    assert!(matches!(get_state(&db, grandchild).await, JobState::Failed));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_fan_in(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let (parents, child) = manager
+        .create_fan_in(vec![create_new_job(), create_new_job()], create_new_job())
+        .await
+        .unwrap();
+    assert_eq!(parents.len(), 2);
+
+    finish_next_job(&worker, JobState::Completed).await;
+    assert!(matches!(get_state(&db, child).await, JobState::Waiting));
+
+    finish_next_job(&worker, JobState::Completed).await;
+    assert!(matches!(get_state(&db, child).await, JobState::Available));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_janitor_resolves_missed_jobs_in_batches(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let janitor = Janitor::from_pool(db.clone());
+
+    let finished = manager.create_job(create_new_job()).await.unwrap();
+    let running = manager.create_job(create_new_job()).await.unwrap();
+    let mut missed = Vec::new();
+    for _ in 0..3 {
+        let mut child = create_new_job();
+        child.dependencies = vec![finished];
+        missed.push(manager.create_job(child).await.unwrap());
+    }
+    let mut waiting = create_new_job();
+    waiting.dependencies = vec![running];
+    let waiting = manager.create_job(waiting).await.unwrap();
+
+    // Finish the parent behind the workers' backs, so its children are never resolved
+    sqlx::query("UPDATE cyclotron_jobs SET state = 'completed' WHERE id = $1")
+        .bind(finished)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    assert_eq!(janitor.resolve_waiting_jobs(2).await.unwrap(), 3);
+    for id in missed {
+        assert!(matches!(get_state(&db, id).await, JobState::Available));
+    }
+    assert!(matches!(get_state(&db, waiting).await, JobState::Waiting));
+    assert_eq!(janitor.resolve_waiting_jobs(2).await.unwrap(), 0);
+}
@@ -0,0 +1,152 @@
+use chrono::{Duration, Utc};
+use common::create_new_job;
+use cyclotron_core::{JobInit, JobState, QueueManager, Worker, WorkerConfig};
+use sqlx::PgPool;
+
+mod common;
+
+fn create_team_jobs(team_id: i32, count: usize, scheduled_minutes_ago: i64) -> Vec<JobInit> {
+    (0..count)
+        .map(|_| {
+            let mut job = create_new_job();
+            job.team_id = team_id;
+            job.scheduled = Utc::now() - Duration::minutes(scheduled_minutes_ago);
+            job
+        })
+        .collect()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_fair_dequeue_does_not_starve_teams(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let unfair = Worker::from_pool(db.clone(), Default::default());
+    let fair = Worker::from_pool(
+        db.clone(),
+        WorkerConfig {
+            fair_dequeue: Some(true),
+            ..Default::default()
+        },
+    );
+
+    // Team 1 has a big backlog, all scheduled before team 2's jobs
+    manager
+        .bulk_create_jobs(create_team_jobs(1, 100, 10))
+        .await
+        .unwrap();
+    manager
+        .bulk_create_jobs(create_team_jobs(2, 5, 1))
+        .await
+        .unwrap();
+
+    // Ordinarily, team 2 has to wait for team 1's whole backlog
+    let jobs = unfair.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 10);
+    assert!(jobs.iter().all(|j| j.team_id == 1));
+
+    // Fairly, they split the batch
+    let jobs = fair.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 10);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 1).count(), 5);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 2).count(), 5);
+
+    // And once team 2 runs out, team 1 gets the whole batch
+    let jobs = fair.dequeue_with_vm_state("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 10);
+    assert!(jobs.iter().all(|j| j.team_id == 1));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_fair_dequeue_caps_running_jobs_per_team(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(
+        db.clone(),
+        WorkerConfig {
+            max_running_per_team: Some(2),
+            ..Default::default()
+        },
+    );
+    worker.max_buffered = 0;
+    assert!(worker.fair_dequeue);
+
+    manager
+        .bulk_create_jobs(create_team_jobs(1, 10, 1))
+        .await
+        .unwrap();
+    manager
+        .bulk_create_jobs(create_team_jobs(2, 10, 1))
+        .await
+        .unwrap();
+
+    let jobs = worker.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 4);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 1).count(), 2);
+
+    // Both teams are at their cap
+    assert!(worker.dequeue_jobs("test", 10).await.unwrap().is_empty());
+
+    // Finishing one of team 1's jobs frees up a slot for it
+    let finished = jobs.iter().find(|j| j.team_id == 1).unwrap();
+    worker.set_state(finished.id, JobState::Completed).unwrap();
+    worker.release_job(finished.id, None).await.unwrap();
+
+    let jobs = worker.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].team_id, 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_fair_dequeue_fills_unused_shares(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(
+        db.clone(),
+        WorkerConfig {
+            max_running_per_team: Some(3),
+            ..Default::default()
+        },
+    );
+    worker.max_buffered = 0;
+
+    // Team 1 is at its cap, team 2 has fewer jobs than its share, and team 3's jobs aren't due yet
+    manager
+        .bulk_create_jobs(create_team_jobs(1, 13, 10))
+        .await
+        .unwrap();
+    let jobs = worker.dequeue_jobs("test", 3).await.unwrap();
+    assert_eq!(jobs.len(), 3);
+    manager
+        .bulk_create_jobs(create_team_jobs(2, 2, 1))
+        .await
+        .unwrap();
+    manager
+        .bulk_create_jobs(create_team_jobs(3, 10, -10))
+        .await
+        .unwrap();
+    manager
+        .bulk_create_jobs(create_team_jobs(4, 10, 1))
+        .await
+        .unwrap();
+
+    // None of them take up a share, so team 4 gets the rest of the batch, up to its cap
+    let jobs = worker.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 5);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 2).count(), 2);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 4).count(), 3);
+
+    // Without a cap, the team with more jobs fills the batch
+    let uncapped = Worker::from_pool(
+        db.clone(),
+        WorkerConfig {
+            fair_dequeue: Some(true),
+            ..Default::default()
+        },
+    );
+    manager
+        .bulk_create_jobs(create_team_jobs(2, 2, 1))
+        .await
+        .unwrap();
+    let jobs = uncapped.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 10);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 2).count(), 2);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 1).count(), 4);
+    assert_eq!(jobs.iter().filter(|j| j.team_id == 4).count(), 4);
+}
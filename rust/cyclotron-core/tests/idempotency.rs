@@ -0,0 +1,119 @@
+use chrono::Duration;
+use common::create_new_job;
+use cyclotron_core::{Janitor, JobInit, QueueManager};
+use sqlx::PgPool;
+
+mod common;
+
+fn create_keyed_job(key: &str) -> JobInit {
+    let mut job = create_new_job();
+    job.idempotency_key = Some(key.to_string());
+    job
+}
+
+async fn count_jobs(db: &PgPool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM cyclotron_jobs")
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_duplicate_keys_return_existing_job(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let first = manager.create_job(create_keyed_job("a")).await.unwrap();
+    let second = manager.create_job(create_keyed_job("a")).await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(count_jobs(&db).await, 1);
+
+    // Keys are scoped to the team and queue
+    let mut other_team = create_keyed_job("a");
+    other_team.team_id = 2;
+    let mut other_queue = create_keyed_job("a");
+    other_queue.queue_name = "other".to_string();
+    assert_ne!(manager.create_job(other_team).await.unwrap(), first);
+    assert_ne!(manager.create_job(other_queue).await.unwrap(), first);
+
+    // And jobs without keys are never deduplicated
+    manager.create_job(create_new_job()).await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    assert_eq!(count_jobs(&db).await, 5);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_bulk_create_deduplicates(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let existing = manager.create_job(create_keyed_job("a")).await.unwrap();
+
+    let ids = manager
+        .bulk_create_jobs(vec![
+            create_keyed_job("a"),
+            create_keyed_job("b"),
+            create_new_job(),
+            create_keyed_job("b"),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(ids.len(), 4);
+    assert_eq!(ids[0], existing);
+    assert_eq!(ids[1], ids[3]);
+    assert_ne!(ids[1], ids[2]);
+    assert_eq!(count_jobs(&db).await, 3);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_expired_keys_can_be_reused(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let janitor = Janitor::from_pool(db.clone());
+
+    let first = manager.create_job(create_keyed_job("a")).await.unwrap();
+
+    // Keys within the retention window are kept
+    let deleted = janitor
+        .delete_expired_idempotency_keys(Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(deleted, 0);
+    assert_eq!(
+        manager.create_job(create_keyed_job("a")).await.unwrap(),
+        first
+    );
+
+    let deleted = janitor
+        .delete_expired_idempotency_keys(Duration::zero())
+        .await
+        .unwrap();
+    assert_eq!(deleted, 1);
+    assert_ne!(
+        manager.create_job(create_keyed_job("a")).await.unwrap(),
+        first
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_duplicate_keys_keep_existing_dependencies(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let first_parent = manager.create_job(create_new_job()).await.unwrap();
+    let second_parent = manager.create_job(create_new_job()).await.unwrap();
+
+    let mut child = create_keyed_job("child");
+    child.dependencies = vec![first_parent];
+    let id = manager.create_job(child).await.unwrap();
+
+    // The existing job is returned, without picking up the duplicate's dependencies
+    let mut duplicate = create_keyed_job("child");
+    duplicate.dependencies = vec![second_parent];
+    assert_eq!(manager.create_job(duplicate).await.unwrap(), id);
+
+    let parents: Vec<uuid::Uuid> =
+        sqlx::query_scalar("SELECT parent_id FROM cyclotron_job_dependencies WHERE job_id = $1")
+            .bind(id)
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(parents, vec![first_parent]);
+}
@@ -0,0 +1,116 @@
+use chrono::{Duration, Utc};
+use common::{create_new_job, dates_match};
+use cyclotron_core::{JobFilter, JobState, QueueManager, Worker};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+mod common;
+
+async fn get_state(db: &PgPool, id: Uuid) -> JobState {
+    sqlx::query_scalar("SELECT state FROM cyclotron_jobs WHERE id = $1")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_cancel_jobs_leaves_running_jobs_alone(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(db.clone(), Default::default());
+    worker.max_buffered = 0;
+
+    let function_id = Uuid::now_v7();
+    let mut inits = vec![create_new_job(), create_new_job(), create_new_job()];
+    for init in inits.iter_mut() {
+        init.function_id = Some(function_id);
+    }
+    let ids = manager.bulk_create_jobs(inits).await.unwrap();
+    let other = manager.create_job(create_new_job()).await.unwrap();
+
+    let running = worker.dequeue_jobs("test", 1).await.unwrap();
+    assert_eq!(running[0].id, ids[0]);
+
+    let cancelled = manager
+        .cancel_jobs(JobFilter::FunctionId(function_id))
+        .await
+        .unwrap();
+    assert_eq!(cancelled, 2);
+
+    assert!(matches!(get_state(&db, ids[0]).await, JobState::Running));
+    assert!(matches!(get_state(&db, ids[1]).await, JobState::Cancelled));
+    assert!(matches!(get_state(&db, ids[2]).await, JobState::Cancelled));
+    assert!(matches!(get_state(&db, other).await, JobState::Available));
+
+    // The running job's worker can still finish it
+    worker.set_state(ids[0], JobState::Completed).unwrap();
+    worker.release_job(ids[0], None).await.unwrap();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_cancelling_a_parent_cancels_its_dependents(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let (parent, children) = manager
+        .create_fan_out(create_new_job(), vec![create_new_job()])
+        .await
+        .unwrap();
+
+    let cancelled = manager
+        .cancel_jobs(JobFilter::Ids(vec![parent]))
+        .await
+        .unwrap();
+    assert_eq!(cancelled, 1);
+    assert!(matches!(
+        get_state(&db, children[0]).await,
+        JobState::Cancelled
+    ));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_pause_and_resume_jobs(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let mut init = create_new_job();
+    init.team_id = 2;
+    let id = manager.create_job(init).await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+
+    assert_eq!(manager.pause_jobs(JobFilter::TeamId(2)).await.unwrap(), 1);
+    assert!(matches!(get_state(&db, id).await, JobState::Paused));
+
+    // Paused jobs aren't dequeued
+    let jobs = worker.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_ne!(jobs[0].id, id);
+
+    assert_eq!(manager.resume_jobs(JobFilter::TeamId(2)).await.unwrap(), 1);
+    let jobs = worker.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].id, id);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_reschedule_jobs(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let id = manager.create_job(create_new_job()).await.unwrap();
+
+    let later = Utc::now() + Duration::hours(1);
+    let rescheduled = manager
+        .reschedule_jobs(JobFilter::Ids(vec![id]), later)
+        .await
+        .unwrap();
+    assert_eq!(rescheduled, 1);
+
+    assert!(worker.dequeue_jobs("test", 10).await.unwrap().is_empty());
+
+    let scheduled = sqlx::query_scalar("SELECT scheduled FROM cyclotron_jobs WHERE id = $1")
+        .bind(id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(dates_match(&scheduled, &later));
+}
@@ -0,0 +1,59 @@
+use chrono::{Duration, Utc};
+use common::create_new_job;
+use cyclotron_core::{JobState, QueueManager, Worker};
+use sqlx::PgPool;
+
+mod common;
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_wait_for_jobs_times_out_without_jobs(db: PgPool) {
+    let worker = Worker::from_pool(db, Default::default());
+
+    let start = Utc::now();
+    assert!(
+        !worker
+            .wait_for_jobs("test", Duration::milliseconds(100))
+            .await
+    );
+    assert!(Utc::now() - start >= Duration::milliseconds(100));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_wait_for_jobs_wakes_on_new_jobs(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(db, Default::default());
+    worker.max_buffered = 0;
+
+    // The first wait starts listening, give it time to connect
+    worker
+        .wait_for_jobs("test", Duration::milliseconds(500))
+        .await;
+
+    let created = tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        manager.create_job(create_new_job()).await.unwrap();
+        manager
+    });
+
+    let start = Utc::now();
+    assert!(worker.wait_for_jobs("test", Duration::seconds(10)).await);
+    assert!(Utc::now() - start < Duration::seconds(5));
+    let manager = created.await.unwrap();
+
+    // Jobs for other queues don't wake us
+    let mut other = create_new_job();
+    other.queue_name = "other".to_string();
+    manager.create_job(other).await.unwrap();
+    let jobs = worker.dequeue_jobs("test", 1).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert!(
+        !worker
+            .wait_for_jobs("test", Duration::milliseconds(200))
+            .await
+    );
+
+    // But jobs being put back into the queue do
+    worker.set_state(jobs[0].id, JobState::Available).unwrap();
+    worker.release_job(jobs[0].id, None).await.unwrap();
+    assert!(worker.wait_for_jobs("test", Duration::seconds(10)).await);
+}
@@ -0,0 +1,232 @@
+use chrono::{Duration, DurationRound, Utc};
+use common::create_new_job;
+use cyclotron_core::{Janitor, JobFilter, JobState, QueueManager, Worker};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+mod common;
+
+async fn get_partition(db: &PgPool, id: Uuid) -> Option<String> {
+    sqlx::query_scalar("SELECT tableoid::regclass::text FROM cyclotron_jobs WHERE id = $1")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .unwrap()
+}
+
+async fn list_finished_partitions(db: &PgPool) -> Vec<String> {
+    sqlx::query_scalar(
+        r#"
+SELECT c.relname::text
+FROM pg_inherits i
+JOIN pg_class c ON c.oid = i.inhrelid
+WHERE i.inhparent = 'cyclotron_jobs_finished'::regclass
+ORDER BY c.relname
+        "#,
+    )
+    .fetch_all(db)
+    .await
+    .unwrap()
+}
+
+async fn complete_job(worker: &Worker) -> Uuid {
+    let job = worker.dequeue_jobs("test", 1).await.unwrap().pop().unwrap();
+    worker.set_state(job.id, JobState::Completed).unwrap();
+    worker.release_job(job.id, None).await.unwrap();
+    job.id
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_finished_jobs_move_partition(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(db.clone(), Default::default());
+    worker.max_buffered = 0;
+    Janitor::from_pool(db.clone())
+        .partition_jobs_table(100)
+        .await
+        .unwrap();
+
+    let id = manager.create_job(create_new_job()).await.unwrap();
+    assert_eq!(
+        get_partition(&db, id).await.as_deref(),
+        Some("cyclotron_jobs_active")
+    );
+
+    complete_job(&worker).await;
+    assert_eq!(
+        get_partition(&db, id).await.as_deref(),
+        Some("cyclotron_jobs_finished_default")
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_rotate_finished_partitions(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(db.clone(), Default::default());
+    worker.max_buffered = 0;
+    let janitor = Janitor::from_pool(db.clone());
+    janitor.partition_jobs_table(100).await.unwrap();
+
+    // A partition for a couple of hours ago, as if the janitor had created it back then
+    let hour = Utc::now().duration_trunc(Duration::hours(1)).unwrap() - Duration::hours(2);
+    let old_partition = format!("cyclotron_jobs_finished_p{}", hour.format("%Y%m%d%H"));
+    sqlx::query(&format!(
+        "CREATE TABLE {} PARTITION OF cyclotron_jobs_finished FOR VALUES FROM ('{}') TO ('{}')",
+        old_partition,
+        hour.to_rfc3339(),
+        (hour + Duration::hours(1)).to_rfc3339()
+    ))
+    .execute(&db)
+    .await
+    .unwrap();
+
+    manager.create_job(create_new_job()).await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    let old = complete_job(&worker).await;
+    let recent = complete_job(&worker).await;
+    sqlx::query("UPDATE cyclotron_jobs SET last_transition = $2 WHERE id = $1")
+        .bind(old)
+        .bind(hour + Duration::minutes(30))
+        .execute(&db)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_partition(&db, old).await.as_deref(),
+        Some(old_partition.as_str())
+    );
+
+    let deleted = janitor.rotate_finished_partitions().await.unwrap();
+    assert_eq!(deleted.len(), 2);
+    assert!(deleted
+        .iter()
+        .all(|d| d.state == "completed" && d.count == 1));
+    assert!(deleted.iter().any(|d| d.hour == hour));
+    assert_eq!(get_partition(&db, old).await, None);
+    assert_eq!(get_partition(&db, recent).await, None);
+
+    // The old partition is gone, and the next few hours' have been created
+    let partitions = list_finished_partitions(&db).await;
+    assert!(!partitions.contains(&old_partition));
+    assert_eq!(partitions.len(), 4);
+    assert!(partitions.contains(&"cyclotron_jobs_finished_default".to_string()));
+
+    // Rotating again doesn't create them twice
+    assert!(janitor
+        .rotate_finished_partitions()
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(list_finished_partitions(&db).await, partitions);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_partition_jobs_table(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut worker = Worker::from_pool(db.clone(), Default::default());
+    worker.max_buffered = 0;
+    let janitor = Janitor::from_pool(db.clone());
+
+    for _ in 0..5 {
+        manager.create_job(create_new_job()).await.unwrap();
+    }
+    let completed = complete_job(&worker).await;
+    let running = worker
+        .dequeue_jobs("test", 1)
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+        .id;
+    assert!(!janitor.jobs_table_partitioned().await.unwrap());
+
+    // Hold a lock on the jobs table, so the conversion copies every job, but can't swap the
+    // tables, as if a long running transaction got in its way
+    let mut blocker = db.begin().await.unwrap();
+    sqlx::query("SELECT 1 FROM cyclotron_jobs LIMIT 1")
+        .execute(&mut *blocker)
+        .await
+        .unwrap();
+    assert!(janitor.partition_jobs_table(2).await.is_err());
+    assert!(!janitor.jobs_table_partitioned().await.unwrap());
+    blocker.commit().await.unwrap();
+
+    // Changes made after a job was copied are mirrored, including ones the backfill can't see
+    // when it's resumed, like deletions
+    janitor.delete_completed_and_failed_jobs().await.unwrap();
+    let created = manager.create_job(create_new_job()).await.unwrap();
+
+    janitor.partition_jobs_table(2).await.unwrap();
+    assert!(janitor.jobs_table_partitioned().await.unwrap());
+    assert_eq!(get_partition(&db, completed).await, None);
+    assert_eq!(
+        get_partition(&db, running).await.as_deref(),
+        Some("cyclotron_jobs_active")
+    );
+    assert_eq!(
+        get_partition(&db, created).await.as_deref(),
+        Some("cyclotron_jobs_active")
+    );
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM cyclotron_jobs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(count, 5);
+
+    // The jobs table's triggers came with it, and jobs carry on moving through it
+    let mut listener = sqlx::postgres::PgListener::connect_with(&db).await.unwrap();
+    listener.listen("cyclotron_jobs").await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    listener.recv().await.unwrap();
+
+    worker.set_state(running, JobState::Completed).unwrap();
+    worker.release_job(running, None).await.unwrap();
+    assert_eq!(
+        get_partition(&db, running).await.as_deref(),
+        Some("cyclotron_jobs_finished_default")
+    );
+
+    // Converting again does nothing
+    janitor.partition_jobs_table(2).await.unwrap();
+    assert!(janitor.jobs_table_partitioned().await.unwrap());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_cancel_retries_moved_jobs(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    Janitor::from_pool(db.clone())
+        .partition_jobs_table(100)
+        .await
+        .unwrap();
+
+    let id = manager.create_job(create_new_job()).await.unwrap();
+
+    // Lock the job, as a worker finishing it would, and cancel it while it's locked
+    let mut txn = db.begin().await.unwrap();
+    sqlx::query("SELECT id FROM cyclotron_jobs WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .execute(&mut *txn)
+        .await
+        .unwrap();
+
+    let cancel_db = db.clone();
+    let cancel = tokio::spawn(async move {
+        QueueManager::from_pool(cancel_db)
+            .cancel_jobs(JobFilter::Ids(vec![id]))
+            .await
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    // Finishing the job moves it to another partition, out from under the cancellation
+    sqlx::query("UPDATE cyclotron_jobs SET state = 'completed' WHERE id = $1")
+        .bind(id)
+        .execute(&mut *txn)
+        .await
+        .unwrap();
+    txn.commit().await.unwrap();
+
+    assert_eq!(cancel.await.unwrap().unwrap(), 0);
+    assert_eq!(
+        get_partition(&db, id).await.as_deref(),
+        Some("cyclotron_jobs_finished_default")
+    );
+}
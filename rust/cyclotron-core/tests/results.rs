@@ -0,0 +1,116 @@
+use chrono::Duration;
+use common::create_new_job;
+use cyclotron_core::{Janitor, JobState, QueueManager, Worker};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+mod common;
+
+// Finishes the next job in `queue` the way a fetch worker does, moving it to the return queue with
+// its result in its parameters
+async fn finish_job(worker: &Worker, queue: &str, state: JobState) -> Uuid {
+    let job = worker.dequeue_jobs(queue, 1).await.unwrap().pop().unwrap();
+    worker.set_state(job.id, state).unwrap();
+    worker.set_queue(job.id, "return").unwrap();
+    worker
+        .set_parameters(job.id, Some(b"result".to_vec()))
+        .unwrap();
+    worker.set_blob(job.id, Some(b"body".to_vec())).unwrap();
+    worker.release_job(job.id, None).await.unwrap();
+    job.id
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_status_of_unfinished_job(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let id = manager.create_job(create_new_job()).await.unwrap();
+    let status = manager.get_job_status(id).await.unwrap().unwrap();
+    assert_eq!(status.id, id);
+    assert_eq!(status.queue_name, "test");
+    assert!(matches!(status.state, JobState::Available));
+
+    assert!(manager
+        .get_job_status(Uuid::now_v7())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_results_outlive_finished_jobs(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+    let janitor = Janitor::from_pool(db.clone());
+
+    // Results are retained for the queue jobs finish in, not the one they were created in
+    manager
+        .set_result_retention("return", Some(Duration::hours(1)))
+        .await
+        .unwrap();
+
+    manager.create_job(create_new_job()).await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    let mut unretained = create_new_job();
+    unretained.queue_name = "unretained".to_string();
+    manager.create_job(unretained).await.unwrap();
+
+    let completed = finish_job(&worker, "test", JobState::Completed).await;
+    let failed = finish_job(&worker, "test", JobState::Failed).await;
+
+    // The unretained job finishes in its own queue
+    let job = worker
+        .dequeue_jobs("unretained", 1)
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    worker.set_state(job.id, JobState::Completed).unwrap();
+    worker.release_job(job.id, None).await.unwrap();
+
+    janitor.delete_completed_and_failed_jobs().await.unwrap();
+
+    let status = manager.get_job_status(completed).await.unwrap().unwrap();
+    assert!(matches!(status.state, JobState::Completed));
+    assert_eq!(status.queue_name, "return");
+    assert_eq!(status.parameters, Some(b"result".to_vec()));
+    assert_eq!(status.blob, Some(b"body".to_vec()));
+
+    let status = manager.get_job_status(failed).await.unwrap().unwrap();
+    assert!(matches!(status.state, JobState::Failed));
+
+    assert!(manager.get_job_status(job.id).await.unwrap().is_none());
+
+    // Turning retention off leaves existing results alone, but stops new ones being kept
+    manager.set_result_retention("return", None).await.unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    let after = finish_job(&worker, "test", JobState::Completed).await;
+    janitor.delete_completed_and_failed_jobs().await.unwrap();
+
+    assert!(manager.get_job_status(completed).await.unwrap().is_some());
+    assert!(manager.get_job_status(after).await.unwrap().is_none());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_expired_results_are_deleted(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+    let janitor = Janitor::from_pool(db.clone());
+
+    manager
+        .set_result_retention("return", Some(Duration::zero()))
+        .await
+        .unwrap();
+    manager.create_job(create_new_job()).await.unwrap();
+    let id = finish_job(&worker, "test", JobState::Completed).await;
+
+    // The job itself is still around, so its status is still known
+    let status = manager.get_job_status(id).await.unwrap().unwrap();
+    assert!(matches!(status.state, JobState::Completed));
+
+    janitor.delete_completed_and_failed_jobs().await.unwrap();
+    assert!(manager.get_job_status(id).await.unwrap().is_none());
+
+    assert_eq!(janitor.delete_expired_results().await.unwrap(), 1);
+    assert_eq!(janitor.delete_expired_results().await.unwrap(), 0);
+}
@@ -0,0 +1,183 @@
+use chrono::{Datelike, Duration, Timelike, Utc, Weekday};
+use common::dates_match;
+use cyclotron_core::{Janitor, QueueError, QueueManager, ScheduleInit, Worker};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+mod common;
+
+fn create_new_schedule(cron: &str) -> ScheduleInit {
+    ScheduleInit {
+        team_id: 1,
+        function_id: Some(Uuid::now_v7()),
+        queue_name: "test".to_string(),
+        priority: 0,
+        cron: cron.to_string(),
+        timezone: "Europe/London".to_string(),
+        vm_state: None,
+        parameters: Some(b"{}".to_vec()),
+        blob: None,
+        metadata: None,
+    }
+}
+
+async fn make_due(db: &PgPool, id: Uuid) -> chrono::DateTime<Utc> {
+    let due = Utc::now() - Duration::minutes(5);
+    sqlx::query("UPDATE cyclotron_schedules SET next_run = $2 WHERE id = $1")
+        .bind(id)
+        .bind(due)
+        .execute(db)
+        .await
+        .unwrap();
+    due
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_schedule_crud(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    // Standard 5 field cron, every day at 9am
+    let id = manager
+        .create_schedule(create_new_schedule("0 9 * * *"))
+        .await
+        .unwrap();
+
+    let schedule = manager.get_schedule(id).await.unwrap().unwrap();
+    assert!(schedule.enabled);
+    assert!(schedule.next_run > Utc::now());
+    assert_eq!(schedule.next_run.minute(), 0);
+    assert!(schedule.last_run.is_none());
+
+    let mut update = create_new_schedule("*/5 * * * *");
+    update.priority = 3;
+    manager.update_schedule(id, update).await.unwrap();
+    let schedule = manager.get_schedule(id).await.unwrap().unwrap();
+    assert_eq!(schedule.priority, 3);
+    assert_eq!(schedule.cron, "*/5 * * * *");
+
+    manager.set_schedule_enabled(id, false).await.unwrap();
+    assert!(!manager.get_schedule(id).await.unwrap().unwrap().enabled);
+
+    assert_eq!(manager.list_schedules(1).await.unwrap().len(), 1);
+    assert!(manager.list_schedules(2).await.unwrap().is_empty());
+
+    manager.delete_schedule(id).await.unwrap();
+    assert!(manager.get_schedule(id).await.unwrap().is_none());
+    assert!(matches!(
+        manager.delete_schedule(id).await,
+        Err(QueueError::UnknownScheduleId(_))
+    ));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_invalid_schedules_rejected(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let result = manager
+        .create_schedule(create_new_schedule("not a cron"))
+        .await;
+    assert!(matches!(result, Err(QueueError::InvalidSchedule(_))));
+
+    let mut init = create_new_schedule("0 9 * * *");
+    init.timezone = "Mars/Olympus_Mons".to_string();
+    let result = manager.create_schedule(init).await;
+    assert!(matches!(result, Err(QueueError::InvalidSchedule(_))));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_days_of_week_are_numbered_from_sunday(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let next_weekday = |cron: &str| {
+        let manager = &manager;
+        let mut init = create_new_schedule(cron);
+        init.timezone = "UTC".to_string();
+        async move {
+            let id = manager.create_schedule(init).await.unwrap();
+            let schedule = manager.get_schedule(id).await.unwrap().unwrap();
+            schedule.next_run.weekday()
+        }
+    };
+
+    // Both 0 and 7 are Sunday
+    for (day, weekday) in [
+        (0, Weekday::Sun),
+        (1, Weekday::Mon),
+        (2, Weekday::Tue),
+        (3, Weekday::Wed),
+        (4, Weekday::Thu),
+        (5, Weekday::Fri),
+        (6, Weekday::Sat),
+        (7, Weekday::Sun),
+    ] {
+        assert_eq!(next_weekday(&format!("0 9 * * {}", day)).await, weekday);
+    }
+    assert_eq!(next_weekday("0 9 * * sat").await, Weekday::Sat);
+
+    let weekday = next_weekday("0 9 * * 1-5").await;
+    assert!(!matches!(weekday, Weekday::Sat | Weekday::Sun));
+    let weekday = next_weekday("0 9 * * 5-7").await;
+    assert!(matches!(
+        weekday,
+        Weekday::Fri | Weekday::Sat | Weekday::Sun
+    ));
+    let weekday = next_weekday("0 9 * * 0,6").await;
+    assert!(matches!(weekday, Weekday::Sat | Weekday::Sun));
+
+    manager
+        .create_schedule(create_new_schedule("* * * * 0"))
+        .await
+        .unwrap();
+    for cron in ["0 9 * * 8", "0 9 * * 5-1", "0 9 * * funday"] {
+        let result = manager.create_schedule(create_new_schedule(cron)).await;
+        assert!(matches!(result, Err(QueueError::InvalidSchedule(_))));
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_due_schedules_create_one_job(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let janitor = Janitor::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+
+    let init = create_new_schedule("0 9 * * *");
+    let id = manager.create_schedule(init.clone()).await.unwrap();
+
+    // Nothing is due yet
+    assert_eq!(janitor.run_schedules(100).await.unwrap(), 0);
+
+    let due = make_due(&db, id).await;
+    assert_eq!(janitor.run_schedules(100).await.unwrap(), 1);
+    // The schedule has moved on, so running again creates nothing
+    assert_eq!(janitor.run_schedules(100).await.unwrap(), 0);
+
+    let schedule = manager.get_schedule(id).await.unwrap().unwrap();
+    assert!(dates_match(&schedule.last_run.unwrap(), &due));
+    assert!(schedule.next_run > Utc::now());
+
+    let jobs = worker.dequeue_jobs("test", 10).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].function_id, init.function_id);
+    assert_eq!(jobs[0].parameters, init.parameters);
+    assert!(dates_match(&jobs[0].scheduled, &due));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn test_disabled_schedules_do_not_run(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let janitor = Janitor::from_pool(db.clone());
+
+    let id = manager
+        .create_schedule(create_new_schedule("0 9 * * *"))
+        .await
+        .unwrap();
+    make_due(&db, id).await;
+
+    manager.set_schedule_enabled(id, false).await.unwrap();
+    assert_eq!(janitor.run_schedules(100).await.unwrap(), 0);
+
+    // Re-enabling skips the missed run
+    manager.set_schedule_enabled(id, true).await.unwrap();
+    assert_eq!(janitor.run_schedules(100).await.unwrap(), 0);
+    let schedule = manager.get_schedule(id).await.unwrap().unwrap();
+    assert!(schedule.next_run > Utc::now());
+}
@@ -1,3 +1,5 @@
+use std::{collections::HashMap, num::NonZeroU32, str::FromStr};
+
 use chrono::Duration;
 use cyclotron_core::{PoolConfig, WorkerConfig};
 use envconfig::Envconfig;
@@ -34,6 +36,13 @@ pub struct Config {
     #[envconfig(default = "false")]
     pub allow_internal_ips: bool,
 
+    // Take turns between teams when dequeuing, so one team's backlog can't starve the others
+    #[envconfig(default = "false")]
+    pub fair_dequeue: bool,
+
+    // Skip teams already running this many fetches. Implies fair dequeuing
+    pub max_running_per_team: Option<u32>,
+
     #[envconfig(default = "default_worker_id")]
     pub worker_id: String,
 
@@ -49,6 +58,10 @@ pub struct Config {
     #[envconfig(default = "30")]
     pub fetch_timeout_seconds: i64,
 
+    // How long we wait for running fetches to finish on shutdown, before putting them back in the queue
+    #[envconfig(default = "30")]
+    pub drain_timeout_seconds: i64,
+
     #[envconfig(default = "10")]
     pub max_retry_attempts: u32,
 
@@ -61,9 +74,21 @@ pub struct Config {
     #[envconfig(default = "1000000")]
     pub max_response_bytes: usize,
 
+    // Request bodies bigger than this are streamed to the destination. Streamed bodies can't be
+    // resent, so 307 and 308 redirects aren't followed for them
+    #[envconfig(default = "1000000")]
+    pub stream_request_bytes: usize,
+
     #[envconfig(default = "4000")]
     pub retry_backoff_base_ms: i64,
 
+    // Requests per second this worker sends to any one host. Unlimited if unset
+    pub host_rate_limit_per_second: Option<NonZeroU32>,
+
+    // Per-host overrides of the above, as comma separated host=rate pairs
+    #[envconfig(default = "")]
+    pub host_rate_limits: HostRateLimits,
+
     #[envconfig(nested = true)]
     pub kafka: KafkaConfig,
 
@@ -84,6 +109,33 @@ pub struct Config {
     pub flush_loop_interval_ms: u64,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct HostRateLimits {
+    pub limits: HashMap<String, NonZeroU32>,
+}
+
+impl FromStr for HostRateLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = HashMap::new();
+        for pair in s.trim().split(',') {
+            if pair.is_empty() {
+                continue;
+            }
+            let Some((host, rate)) = pair.split_once('=') else {
+                return Err(format!("expected host=rate, got {}", pair));
+            };
+            let rate = rate
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid rate for {}: {}", host, e))?;
+            limits.insert(host.trim().to_ascii_lowercase(), rate);
+        }
+        Ok(HostRateLimits { limits })
+    }
+}
+
 #[allow(dead_code)]
 fn default_worker_id() -> String {
     Uuid::now_v7().to_string()
@@ -98,12 +150,16 @@ pub struct AppConfig {
     pub job_poll_interval: Duration, // How long we wait to poll for new jobs, when we're at capacity or find no new jobs
     pub concurrent_requests_limit: u32,
     pub fetch_timeout: Duration,
+    pub drain_timeout: Duration, // How long running jobs get to finish on shutdown
     pub max_retry_attempts: u32,
     pub queue_served: String,
     pub batch_size: usize,
     pub max_response_bytes: usize,
+    pub stream_request_bytes: usize, // Request bodies bigger than this are streamed
     pub retry_backoff_base: Duration, // Job retry backoff times are this * attempt count
     pub allow_internal_ips: bool,
+    pub host_rate_limit_per_second: Option<NonZeroU32>,
+    pub host_rate_limits: HashMap<String, NonZeroU32>, // Overrides the above for specific hosts
 }
 
 impl Config {
@@ -116,12 +172,16 @@ impl Config {
             job_poll_interval: Duration::seconds(self.job_poll_interval_seconds),
             concurrent_requests_limit: self.concurrent_requests_limit,
             fetch_timeout: Duration::seconds(self.fetch_timeout_seconds),
+            drain_timeout: Duration::seconds(self.drain_timeout_seconds),
             max_retry_attempts: self.max_retry_attempts,
             queue_served: self.queue_served,
             batch_size: self.batch_size,
             max_response_bytes: self.max_response_bytes,
+            stream_request_bytes: self.stream_request_bytes,
             retry_backoff_base: Duration::milliseconds(self.retry_backoff_base_ms),
             allow_internal_ips: self.allow_internal_ips,
+            host_rate_limit_per_second: self.host_rate_limit_per_second,
+            host_rate_limits: self.host_rate_limits.limits,
         };
 
         let pool_config = PoolConfig {
@@ -139,6 +199,8 @@ impl Config {
             max_updates_buffered: Some(self.max_updates_buffered),
             max_bytes_buffered: Some(self.max_bytes_buffered),
             flush_loop_interval_ms: Some(self.flush_loop_interval_ms),
+            fair_dequeue: Some(self.fair_dequeue),
+            max_running_per_team: self.max_running_per_team,
         };
 
         (app_config, pool_config, self.kafka, worker_config)
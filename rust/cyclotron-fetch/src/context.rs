@@ -9,13 +9,14 @@ use health::HealthHandle;
 use rdkafka::producer::FutureProducer;
 use tokio::sync::Semaphore;
 
-use crate::{config::AppConfig, fetch::FetchError};
+use crate::{config::AppConfig, fetch::FetchError, rate_limit::HostLimiter};
 
 pub struct AppContext {
     pub worker: Worker,
     pub client: reqwest::Client,
     pub kafka_producer: FutureProducer<KafkaContext>,
     pub concurrency_limit: Arc<Semaphore>,
+    pub host_limiter: HostLimiter,
     pub liveness: HealthHandle,
     pub config: AppConfig,
     pub metric_labels: RwLock<Vec<(String, String)>>,
@@ -52,6 +53,11 @@ impl AppContext {
             }
         };
 
+        let host_limiter = HostLimiter::new(
+            config.host_rate_limit_per_second,
+            config.host_rate_limits.clone(),
+        );
+
         let worker = Worker::new(pool_config, worker_config).await?;
 
         let labels = vec![
@@ -69,6 +75,7 @@ impl AppContext {
             client,
             kafka_producer,
             concurrency_limit,
+            host_limiter,
             liveness,
             config,
             metric_labels: RwLock::new(labels),
@@ -3,20 +3,25 @@ use std::{cmp::min, collections::HashMap, fmt::Display, sync::Arc};
 use chrono::{DateTime, Duration, Utc};
 use cyclotron_core::{Bytes, Job, JobError, JobState, QueueError, Worker};
 use futures::StreamExt;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode};
 use reqwest::Response;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::OwnedSemaphorePermit;
-use tracing::{error, instrument, warn};
+use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
 
-use crate::{context::AppContext, metrics_constants::*};
+use crate::{context::AppContext, metrics_constants::*, rate_limit::parse_retry_after};
 
 // TODO - a lot of these should maybe be configurable
 pub const DEFAULT_RETRIES: u32 = 3;
 pub const DEFAULT_ON_FINISH: OnFinish = OnFinish::Return;
 pub const HEARTBEAT_INTERVAL_MS: i64 = 5000;
+// Jobs for a rate limited host that will get a request slot this soon wait for it, rather than
+// going back to the queue
+pub const MAX_HOST_RATE_LIMIT_WAIT_MS: i64 = 250;
+// Streamed request bodies are sent this many bytes at a time
+pub const REQUEST_BODY_CHUNK_BYTES: usize = 64 * 1024;
 
 // Exclusively for errors in the worker - these will
 // never be serialised into the job queue, and indicate
@@ -70,12 +75,16 @@ pub struct FetchParameters {
     pub method: HttpMethod,
     pub return_queue: String,
     pub headers: Option<HashMap<String, String>>,
-    pub max_tries: Option<u32>,      // Defaults to 3
-    pub on_finish: Option<OnFinish>, // Defaults to Return
+    pub max_tries: Option<u32>,            // Defaults to 3
+    pub on_finish: Option<OnFinish>,       // Defaults to Return
+    pub max_response_bytes: Option<usize>, // Defaults to, and is capped at, the worker's limit
+    pub truncate_response: Option<bool>, // Defaults to false, failing with ResponseTooLarge instead
 }
 
 // What should we do when we get a result, or run out of tries for a given job?
 // Return means re-queue to the return_worker, Complete means mark as Completed/Failed
+// in the return queue. Completed jobs can be looked up with `QueueManager::get_job_status` after
+// they're cleaned up, if the return queue has a result retention set.
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum OnFinish {
@@ -88,6 +97,10 @@ pub enum OnFinish {
 #[serde(rename_all = "lowercase")]
 pub struct FetchMetadata {
     tries: u32,
+    // How many of those tries were rate limited by the destination, which don't count against the
+    // caller's max_tries
+    #[serde(default)]
+    throttled: u32,
     // The history of failures seen with this job
     trace: Vec<FetchFailure>,
 }
@@ -134,8 +147,14 @@ pub struct FetchFailure {
     pub headers: Option<HashMap<String, String>>, // If we have headers, we include them in the failure
     pub status: Option<u16>, // If we have a status, we include it in the failure
     pub timestamp: DateTime<Utc>, // Useful for users to correlate logs when debugging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<DateTime<Utc>>, // When the destination asked us to retry, via Retry-After
     #[serde(skip)] // We serialise the body seperately into blob
     pub body: Option<Bytes>, // If we have a body, we include it in the final failure (but not the trace)
+    #[serde(default)]
+    pub binary: bool, // As on FetchResponse, for the body
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 impl FetchFailure {
@@ -146,7 +165,10 @@ impl FetchFailure {
             timestamp: Utc::now(),
             headers: None,
             status: None,
+            retry_after: None,
             body: None,
+            binary: false,
+            truncated: false,
         }
     }
 
@@ -157,7 +179,10 @@ impl FetchFailure {
             timestamp: Utc::now(),
             headers: None,
             status: Some(status.as_u16()),
+            retry_after: None,
             body: None,
+            binary: false,
+            truncated: false,
         }
     }
 
@@ -175,9 +200,18 @@ impl FetchFailure {
         }
     }
 
-    pub fn with_body(self, body: Bytes) -> Self {
+    pub fn with_retry_after(self, retry_after: Option<DateTime<Utc>>) -> Self {
         Self {
-            body: Some(body),
+            retry_after,
+            ..self
+        }
+    }
+
+    pub fn with_body(self, body: ResponseBody) -> Self {
+        Self {
+            body: Some(body.body),
+            binary: body.binary,
+            truncated: body.truncated,
             ..self
         }
     }
@@ -196,7 +230,10 @@ impl From<reqwest::Error> for FetchFailure {
             timestamp: Utc::now(),
             headers: None,
             status: None,
+            retry_after: None,
             body: None,
+            binary: false,
+            truncated: false,
         }
     }
 }
@@ -210,7 +247,7 @@ pub enum FetchFailureKind {
     InvalidParameters,
     RequestError,
     FailureStatus,
-    InvalidBody, // We force bodies to be a utf8 string, for the sake of callers. TODO - we should consider letting callers enforce a body schema
+    InvalidBody, // Text bodies, by content type, have to be valid utf8. TODO - we should consider letting callers enforce a body schema
     ResponseTooLarge,
 }
 
@@ -221,6 +258,18 @@ pub struct FetchResponse {
     pub headers: HashMap<String, String>,
     #[serde(skip)] // We serialise the body seperately into blob
     pub body: Option<Bytes>, // This is only an option to let us `take` it, to avoid body copies on serialisation
+    #[serde(default)]
+    pub binary: bool, // The body isn't text, going by its content type, so isn't necessarily utf8
+    #[serde(default)]
+    pub truncated: bool, // The body was cut off at the max response size
+}
+
+// A response body, as read by `first_n_bytes_of_response`
+#[derive(Debug)]
+pub struct ResponseBody {
+    pub body: Bytes,
+    pub binary: bool,
+    pub truncated: bool,
 }
 
 #[instrument(skip_all)]
@@ -280,11 +329,45 @@ pub async fn tick(context: Arc<AppContext>) -> Result<usize, FetchError> {
     Ok(num_jobs)
 }
 
+// Waits up to the drain timeout for running jobs to finish, then puts any still running back in the
+// queue, so they're picked up again straight away rather than once the janitor notices them. Call
+// this once we've stopped dequeuing. Returns the number of jobs put back.
+pub async fn drain(context: &AppContext) -> Result<usize, FetchError> {
+    let all_permits = context
+        .concurrency_limit
+        .acquire_many(context.config.concurrent_requests_limit);
+
+    let released = match tokio::time::timeout(
+        context.config.drain_timeout.to_std().unwrap_or_default(),
+        all_permits,
+    )
+    .await
+    {
+        Ok(_) => 0,
+        Err(_) => {
+            let released = context.worker.release_all_jobs();
+            warn!("Timed out draining, releasing {} running jobs", released);
+            released
+        }
+    };
+
+    common_metrics::inc(
+        JOBS_RELEASED_ON_SHUTDOWN,
+        &context.metric_labels(),
+        released as u64,
+    );
+    context.worker.force_flush().await?;
+    info!("Drained, {} jobs released", released);
+
+    Ok(released)
+}
+
 impl From<&Job> for FetchMetadata {
     fn from(job: &Job) -> Self {
         let Some(m) = &job.metadata else {
             return FetchMetadata {
                 tries: 0,
+                throttled: 0,
                 trace: vec![],
             };
         };
@@ -292,6 +375,7 @@ impl From<&Job> for FetchMetadata {
         let Ok(m) = serde_json::from_slice(m) else {
             return FetchMetadata {
                 tries: 0,
+                throttled: 0,
                 trace: vec![],
             };
         };
@@ -323,7 +407,7 @@ impl TryFrom<&Job> for FetchParameters {
 #[instrument(skip_all)]
 pub async fn run_job(
     context: Arc<AppContext>,
-    job: Job,
+    mut job: Job,
     _permit: OwnedSemaphorePermit,
 ) -> Result<(), FetchError> {
     let labels = context.metric_labels();
@@ -374,7 +458,7 @@ pub async fn run_job(
         }
     };
 
-    let headers = match (&params.headers.unwrap_or_default()).try_into() {
+    let mut headers: HeaderMap = match (&params.headers.unwrap_or_default()).try_into() {
         Ok(h) => h,
         Err(e) => {
             warn!("Failed to parse headers: {}", e);
@@ -399,7 +483,28 @@ pub async fn run_job(
         }
     };
 
-    let body = reqwest::Body::from(job.blob.unwrap_or_default());
+    // Hosts over their rate limit get the job back later, without it counting as a try, unless
+    // it's only a short wait. This has to happen before the blob is taken below, since releasing
+    // the job leaves the blob untouched.
+    while let Some(wait) = url
+        .host_str()
+        .and_then(|host| context.host_limiter.check(host))
+    {
+        common_metrics::inc(HOST_RATE_LIMITED, &labels, 1);
+        if wait <= Duration::milliseconds(MAX_HOST_RATE_LIMIT_WAIT_MS) {
+            tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+            continue;
+        }
+        let res = delay_job(&context.worker, job.id, wait).await;
+        job_total.label(OUTCOME_LABEL, "host_rate_limited").fin();
+        return res;
+    }
+
+    let body = request_body(
+        job.blob.take().unwrap_or_default(),
+        context.config.stream_request_bytes,
+        &mut headers,
+    );
 
     let mut send_fut = context
         .client
@@ -472,16 +577,22 @@ pub async fn run_job(
 
     let body_time = common_metrics::timing_guard(BODY_FETCH_TIME, &labels);
     // We pre-emptively get the response body, because we incldued it in the failure trace, even if we got a failure status
+    let max_response_bytes = params
+        .max_response_bytes
+        .map_or(context.config.max_response_bytes, |n| {
+            min(n, context.config.max_response_bytes)
+        });
     let body = first_n_bytes_of_response(
         &context.worker,
         job.id,
         res,
-        context.config.max_response_bytes,
+        max_response_bytes,
+        params.truncate_response.unwrap_or(false),
     )
     .await?;
 
     let body = match body {
-        Ok(b) => b.into_bytes(),
+        Ok(b) => b,
         Err(e) => {
             body_time.label(OUTCOME_LABEL, "body_fetch_error").fin();
             common_metrics::inc(BODY_FETCH_FAILED, &labels, 1);
@@ -504,12 +615,19 @@ pub async fn run_job(
     };
     body_time.label(OUTCOME_LABEL, "success").fin();
     common_metrics::inc(BODY_FETCH_SUCCEEDED, &labels, 1);
+    if body.truncated {
+        common_metrics::inc(BODY_TRUNCATED, &labels, 1);
+    }
 
     // TODO - we should handle "retryable" and "permanent" failures differently, mostly
     // to be polite - retrying a permanent failure isn't a correctness problem, but it's
     // rude (and inefficient)
     if !status.is_success() {
+        let retry_after = headers
+            .get(http::header::RETRY_AFTER.as_str())
+            .and_then(|v| parse_retry_after(v, Utc::now()));
         let failure = FetchFailure::failure_status(status)
+            .with_retry_after(retry_after)
             .with_headers(headers)
             .with_body(body);
         let res = handle_fetch_failure(
@@ -531,7 +649,9 @@ pub async fn run_job(
         response: FetchResponse {
             status: status.as_u16(),
             headers,
-            body: Some(body),
+            body: Some(body.body),
+            binary: body.binary,
+            truncated: body.truncated,
         },
     };
 
@@ -580,16 +700,31 @@ where
     F: Into<FetchFailure>,
 {
     let failure: FetchFailure = failure.into();
+    let retry_after = failure.retry_after;
     let mut metadata = metadata.clone();
     metadata.tries += 1;
+    if failure.status == Some(StatusCode::TOO_MANY_REQUESTS.as_u16()) {
+        metadata.throttled += 1;
+    }
     metadata.trace.push(failure);
 
     // TODO - right now we treat all failures as retryable, but we should probably be more aggressive in
     // culling retries for permanent failures (this is less of a correctness issue and more of an efficiency/
     // politeness one). We might also want to make backoff configurable.
-    if metadata.tries < min(max_tries, context.config.max_retry_attempts) {
-        let next_available =
-            Utc::now() + (context.config.retry_backoff_base * (metadata.tries as i32));
+    //
+    // Being rate limited doesn't count against the caller's max_tries, but does count against the
+    // worker's own limit, so a destination that always says 429 can't keep a job around forever.
+    if metadata.tries - metadata.throttled < max_tries
+        && metadata.tries < context.config.max_retry_attempts
+    {
+        // If the destination told us when to come back, we do that, otherwise we back off
+        let next_available = match retry_after {
+            Some(retry_after) => {
+                common_metrics::inc(RETRY_AFTER_RESPECTED, &context.metric_labels(), 1);
+                retry_after
+            }
+            None => Utc::now() + (context.config.retry_backoff_base * (metadata.tries as i32)),
+        };
         // We back off for at most an hour (since callers can configure max retries to be very high)
         let next_available = min(next_available, Utc::now() + Duration::hours(1));
         // Add some seconds of jitter
@@ -618,6 +753,16 @@ where
     Ok(())
 }
 
+// Hands the job back to the queue to be run again after `wait`, as if it had never been picked up.
+pub async fn delay_job(worker: &Worker, job_id: Uuid, wait: Duration) -> Result<(), FetchError> {
+    // Spread delayed jobs out, so they don't all come back at once and get delayed again
+    let jitter = Duration::milliseconds((rand::random::<u64>() % 1000) as i64);
+    worker.set_state(job_id, JobState::Available)?;
+    worker.set_scheduled_at(job_id, Utc::now() + wait + jitter)?;
+    worker.release_job(job_id, None).await?;
+    Ok(())
+}
+
 // Complete the job with some result.
 pub async fn complete_job(
     worker: &Worker,
@@ -658,16 +803,25 @@ pub async fn complete_job(
     Ok(())
 }
 
-// Pulls the body, while maintaining the job heartbeat.
+// Pulls the body, while maintaining the job heartbeat. Bodies of `n` bytes or more are a
+// ResponseTooLarge failure, unless `truncate` is set, in which case longer bodies are cut off at `n`.
 pub async fn first_n_bytes_of_response(
     worker: &Worker,
     job_id: Uuid,
     response: Response,
     n: usize,
-) -> Result<Result<String, FetchFailure>, FetchError> {
+    truncate: bool,
+) -> Result<Result<ResponseBody, FetchFailure>, FetchError> {
+    let binary = is_binary_content_type(
+        response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok()),
+    );
+
     let mut body = response.bytes_stream();
-    // We deserialize into a vec<u8>, and then parse to a string
     let mut buffer = Vec::with_capacity(n / 4); // Assume most request responses will be significantly smaller than the max
+    let mut truncated = false;
 
     worker.heartbeat(job_id).await?;
 
@@ -682,7 +836,13 @@ pub async fn first_n_bytes_of_response(
 
                 buffer.extend_from_slice(&chunk);
 
-                if buffer.len() >= n {
+                if truncate && buffer.len() > n {
+                    buffer.truncate(n);
+                    truncated = true;
+                    break;
+                }
+
+                if !truncate && buffer.len() >= n {
                     return Ok(Err(
                         FetchFailure::new(FetchFailureKind::ResponseTooLarge, "Response too large")
                     ));
@@ -694,20 +854,77 @@ pub async fn first_n_bytes_of_response(
         worker.heartbeat(job_id).await?;
     }
 
-    // TODO - we can handle binary data here, but for now we force response bodies to be utf8 string
-    let body = match String::from_utf8(buffer) {
-        Ok(s) => s,
-        Err(e) => {
-            let buffer = e.into_bytes();
-            return Ok(Err(FetchFailure::new(
-                FetchFailureKind::InvalidBody,
-                "Body could not be parsed as utf8",
-            )
-            .with_body(buffer)));
+    if !binary {
+        if let Err(e) = std::str::from_utf8(&buffer) {
+            // Truncating can cut the last character in half, which isn't the body's fault
+            if truncated && e.error_len().is_none() {
+                buffer.truncate(e.valid_up_to());
+            } else {
+                return Ok(Err(FetchFailure::new(
+                    FetchFailureKind::InvalidBody,
+                    "Body could not be parsed as utf8",
+                )
+                .with_body(ResponseBody {
+                    body: buffer,
+                    binary,
+                    truncated,
+                })));
+            }
         }
-    };
+    }
+
+    Ok(Ok(ResponseBody {
+        body: buffer,
+        binary,
+        truncated,
+    }))
+}
+
+// Blobs over `stream_over` bytes are streamed to the destination a chunk at a time, rather than
+// handed to reqwest whole. Either way, the chunks share the blob's buffer, so nothing is copied.
+// Small blobs aren't streamed because reqwest can't replay a streamed body, so won't follow a 307
+// or 308 redirect for one, and returns the redirect as the response instead.
+pub fn request_body(blob: Bytes, stream_over: usize, headers: &mut HeaderMap) -> reqwest::Body {
+    if blob.len() <= stream_over {
+        return reqwest::Body::from(blob);
+    }
+
+    // Streamed bodies are sent chunked unless they have a length, which not every destination
+    // accepts
+    headers.insert(http::header::CONTENT_LENGTH, blob.len().into());
+
+    let blob = bytes::Bytes::from(blob);
+    let len = blob.len();
+    let chunks = (0..len)
+        .step_by(REQUEST_BODY_CHUNK_BYTES)
+        .map(move |start| {
+            Ok::<_, std::io::Error>(blob.slice(start..min(start + REQUEST_BODY_CHUNK_BYTES, len)))
+        });
+    reqwest::Body::wrap_stream(futures::stream::iter(chunks))
+}
 
-    Ok(Ok(body))
+// Responses are text unless their content type says otherwise, so responses without one still
+// have to be utf8, as they always have.
+pub fn is_binary_content_type(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    let is_text = mime.is_empty()
+        || mime.starts_with("text/")
+        // Covers application/json, and suffixes like application/ld+json and application/x-ndjson
+        || mime.ends_with("json")
+        || mime.ends_with("xml")
+        || mime.ends_with("javascript")
+        || mime == "application/x-www-form-urlencoded";
+
+    !is_text
 }
 
 pub async fn do_or_dead_letter<T, E>(
@@ -2,3 +2,4 @@ pub mod config;
 pub mod context;
 pub mod fetch;
 pub mod metrics_constants;
+pub mod rate_limit;
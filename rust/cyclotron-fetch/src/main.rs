@@ -3,15 +3,34 @@ use common_metrics::setup_metrics_routes;
 use cyclotron_fetch::{
     config::Config,
     context::AppContext,
-    fetch::{tick, FetchError},
+    fetch::{drain, tick, FetchError},
 };
 use envconfig::Envconfig;
 use health::HealthRegistry;
-use std::{future::ready, sync::Arc};
+use std::{
+    future::{ready, Future},
+    sync::Arc,
+};
+use tokio::signal;
 use tracing::{error, info};
 
 common_alloc::used!();
 
+async fn shutdown() {
+    let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("failed to register SIGTERM handler");
+
+    let mut interrupt = signal::unix::signal(signal::unix::SignalKind::interrupt())
+        .expect("failed to register SIGINT handler");
+
+    tokio::select! {
+        _ = term.recv() => {},
+        _ = interrupt.recv() => {},
+    };
+
+    info!("Shutting down gracefully...");
+}
+
 async fn listen(app: Router, bind: String) -> Result<(), std::io::Error> {
     let listener = tokio::net::TcpListener::bind(bind).await?;
 
@@ -36,17 +55,45 @@ async fn index(State(worker_id): State<WorkerId>) -> String {
     format!("cyclotron janitor {}", worker_id.0)
 }
 
-async fn worker_loop(context: AppContext) -> Result<(), FetchError> {
-    let context = Arc::new(context);
+// Runs until `shutdown` resolves, then stops dequeuing and drains. We only check for shutdown
+// between ticks, so jobs are never dequeued and then dropped.
+async fn worker_loop(
+    context: Arc<AppContext>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), FetchError> {
+    tokio::pin!(shutdown);
     loop {
         context.liveness.report_healthy().await;
         let started = tick(context.clone()).await?;
         info!("started {} jobs", started);
-        // This will happen if 1) there are no jobs or 2) we have no capacity to start new jobs. Either way, we should sleep for a bit
-        if started == 0 {
-            tokio::time::sleep(context.config.job_poll_interval.to_std().unwrap()).await;
+        // This will happen if 1) there are no jobs or 2) we have no capacity to start new jobs. If we're
+        // out of capacity we sleep for a bit, otherwise we wait to be told about new jobs, falling back to
+        // polling every job_poll_interval.
+        let wait = async {
+            if started > 0 {
+                return;
+            }
+            if context.concurrency_limit.available_permits() == 0 {
+                tokio::time::sleep(context.config.job_poll_interval.to_std().unwrap()).await;
+            } else {
+                context
+                    .worker
+                    .wait_for_jobs(
+                        &context.config.queue_served,
+                        context.config.job_poll_interval,
+                    )
+                    .await;
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            _ = wait => {}
         }
     }
+
+    drain(&context).await?;
+    Ok(())
 }
 
 #[tokio::main]
@@ -90,15 +137,21 @@ async fn main() {
 
     context.worker.run_migrations().await;
 
+    let context = Arc::new(context);
+
+    let limiter_context = context.clone();
+    tokio::spawn(async move { limiter_context.host_limiter.clean_state().await });
+
     let http_server = tokio::spawn(listen(app, bind));
 
-    let worker_loop = tokio::spawn(worker_loop(context));
+    let worker_loop = tokio::spawn(worker_loop(context, shutdown()));
 
     tokio::select! {
         res = worker_loop => {
-            error!("janitor loop exited");
-            if let Err(e) = res {
-                error!("janitor failed with: {}", e)
+            match res {
+                Ok(Ok(())) => info!("worker loop drained"),
+                Ok(Err(e)) => error!("worker loop failed with: {}", e),
+                Err(e) => error!("worker loop panicked: {}", e),
             }
         }
         res = http_server => {
@@ -12,6 +12,10 @@ pub const FETCH_DEAD_LETTER: &str = "cyclotron_fetch_dead_letter";
 pub const RESPONSE_RECEIVED: &str = "cyclotron_fetch_got_response";
 pub const BODY_FETCH_FAILED: &str = "cyclotron_fetch_body_fetch_failed";
 pub const BODY_FETCH_SUCCEEDED: &str = "cyclotron_fetch_body_fetch_succeeded";
+pub const BODY_TRUNCATED: &str = "cyclotron_fetch_body_truncated";
+pub const HOST_RATE_LIMITED: &str = "cyclotron_fetch_host_rate_limited";
+pub const RETRY_AFTER_RESPECTED: &str = "cyclotron_fetch_retry_after_respected";
+pub const JOBS_RELEASED_ON_SHUTDOWN: &str = "cyclotron_fetch_jobs_released_on_shutdown";
 
 // Label keys
 pub const OUTCOME_LABEL: &str = "outcome";
@@ -0,0 +1,129 @@
+use std::{collections::HashMap, num::NonZeroU32, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use governor::{
+    clock::{Clock, DefaultClock},
+    state::{keyed::DefaultKeyedStateStore, InMemoryState, NotKeyed},
+    Quota, RateLimiter,
+};
+use rand::Rng;
+
+type KeyedLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>;
+type DirectLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+// Limits how often this worker sends requests to each host, so destinations with strict API limits
+// see a steady trickle of requests rather than every job for them at once. Hosts with an override
+// get their own rate, everything else shares the default, if there is one. The limits are per
+// worker, not shared across workers.
+pub struct HostLimiter {
+    clock: DefaultClock,
+    default: Option<(KeyedLimiter, Duration)>,
+    overrides: HashMap<String, (DirectLimiter, Duration)>,
+    // When the last job turned away from each host was told to come back. Jobs turned away after
+    // it are told to come back one request interval later, so a backlog for a host comes back
+    // spread out at the host's rate, rather than all at once to be turned away again.
+    backlog: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl HostLimiter {
+    pub fn new(
+        default_per_second: Option<NonZeroU32>,
+        overrides: HashMap<String, NonZeroU32>,
+    ) -> Self {
+        let clock = DefaultClock::default();
+        let default = default_per_second.map(|rate| {
+            (
+                RateLimiter::dashmap_with_clock(Quota::per_second(rate), &clock),
+                interval(rate),
+            )
+        });
+        let overrides = overrides
+            .into_iter()
+            .map(|(host, rate)| {
+                (
+                    host.to_ascii_lowercase(),
+                    (
+                        RateLimiter::direct_with_clock(Quota::per_second(rate), &clock),
+                        interval(rate),
+                    ),
+                )
+            })
+            .collect();
+
+        Self {
+            clock,
+            default,
+            overrides,
+            backlog: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes a request slot for the host. Returns None if one was free, or how long to wait before
+    // trying again if not. Each caller turned away is given its own place in the host's backlog,
+    // so callers waiting on the same host don't all try again at the same time.
+    pub fn check(&self, host: &str) -> Option<Duration> {
+        let (res, interval) = match (self.overrides.get(host), &self.default) {
+            (Some((limiter, interval)), _) => (limiter.check(), *interval),
+            (None, Some((limiter, interval))) => (limiter.check_key(&host.to_string()), *interval),
+            (None, None) => return None,
+        };
+
+        let not_until = res.err()?;
+        let wait = Duration::from_std(not_until.wait_time_from(self.clock.now()))
+            .unwrap_or(Duration::seconds(1));
+
+        let now = Utc::now();
+        let mut backlog = self.backlog.lock().unwrap();
+        let retry_at = match backlog.get(host) {
+            Some(last) if *last + interval > now + wait => *last + interval,
+            _ => now + wait,
+        };
+        backlog.insert(host.to_string(), retry_at);
+
+        Some(retry_at - now)
+    }
+
+    // Drops state for hosts we haven't sent requests to recently, once a minute, so the limiter
+    // doesn't grow with every host we've ever seen. Needs to be spawned in a separate task.
+    pub async fn clean_state(&self) {
+        if self.default.is_none() && self.overrides.is_empty() {
+            return;
+        }
+
+        // Jittered so replicas don't all clean up at once
+        let interval_secs = rand::thread_rng().gen_range(60..70);
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            if let Some((limiter, _)) = &self.default {
+                limiter.retain_recent();
+                limiter.shrink_to_fit();
+            }
+
+            let now = Utc::now();
+            let mut backlog = self.backlog.lock().unwrap();
+            backlog.retain(|_, retry_at| *retry_at > now);
+            backlog.shrink_to_fit();
+        }
+    }
+}
+
+// The time between requests to a host limited to `rate` requests per second
+fn interval(rate: NonZeroU32) -> Duration {
+    Duration::microseconds(1_000_000 / rate.get() as i64)
+}
+
+// Retry-After is either a number of seconds, or an HTTP date. Returns None if the header can't be
+// parsed, in which case we fall back to our own backoff.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u32>() {
+        return Some(now + Duration::seconds(seconds as i64));
+    }
+
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|d| d.with_timezone(&Utc))
+}
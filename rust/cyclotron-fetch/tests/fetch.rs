@@ -1,14 +1,17 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, num::NonZeroU32, sync::Arc};
 
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
 use cyclotron_core::{QueueManager, Worker};
-use cyclotron_fetch::fetch::{tick, FetchResult, HttpMethod};
+use cyclotron_fetch::{
+    fetch::{drain, tick, FetchResult, HttpMethod},
+    rate_limit::HostLimiter,
+};
 use httpmock::{Method, MockServer};
 use serde_json::json;
 use sqlx::PgPool;
 use utils::{
     construct_job, construct_params, get_app_test_context, make_immediately_available,
-    wait_on_no_running, wait_on_return,
+    wait_on_free_permit, wait_on_no_running, wait_on_return,
 };
 
 mod utils;
@@ -269,6 +272,40 @@ pub async fn test_completes_fetch_with_body(db: PgPool) {
     mock.assert_hits(1);
 }
 
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_streams_large_request_body(db: PgPool) {
+    let mut context = get_app_test_context(db.clone()).await;
+    context.config.stream_request_bytes = 16;
+    let context = Arc::new(context);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    // Several chunks' worth, ending part way through one
+    let body = "streamed ".repeat(20000);
+    let mock = server.mock(|when, then| {
+        when.method(Method::POST)
+            .path("/test")
+            .header("content-length", body.len().to_string())
+            .body(&body);
+        then.status(200).body("Hello, world!");
+    });
+
+    let params = construct_params(server.url("/test"), HttpMethod::Post);
+    let job = construct_job(params, Some(body.clone().into_bytes()));
+    producer.create_job(job).await.unwrap();
+
+    let started = tick(context).await.unwrap();
+    assert_eq!(started, 1);
+
+    let returned = wait_on_return(&return_worker, 1, false).await.unwrap();
+    let response: FetchResult =
+        serde_json::from_slice(returned[0].parameters.as_ref().unwrap()).unwrap();
+    assert!(response.is_success());
+
+    mock.assert_hits(1);
+}
+
 #[sqlx::test(migrations = "../cyclotron-core/migrations")]
 pub async fn test_completes_fetch_with_vm_state(db: PgPool) {
     let context = Arc::new(get_app_test_context(db.clone()).await);
@@ -310,3 +347,318 @@ pub async fn test_completes_fetch_with_vm_state(db: PgPool) {
 
     mock.assert_hits(1);
 }
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_completes_fetch_with_binary_body(db: PgPool) {
+    let context = Arc::new(get_app_test_context(db.clone()).await);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    // Not valid utf8
+    let png_header = vec![0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0xff];
+    let mock = server.mock(|when, then| {
+        when.method(Method::GET).path("/test");
+        then.status(200)
+            .header("content-type", "image/png")
+            .body(&png_header);
+    });
+
+    let params = construct_params(server.url("/test"), HttpMethod::Get);
+    let job = construct_job(params, None);
+    producer.create_job(job).await.unwrap();
+
+    let started = tick(context).await.unwrap();
+
+    assert_eq!(started, 1);
+
+    let returned = wait_on_return(&return_worker, 1, false).await.unwrap();
+
+    let response: FetchResult =
+        serde_json::from_slice(returned[0].parameters.as_ref().unwrap()).unwrap();
+
+    let FetchResult::Success { response } = response else {
+        panic!("Expected success response");
+    };
+
+    assert_eq!(response.status, 200);
+    assert!(response.binary);
+    assert!(!response.truncated);
+    assert_eq!(returned[0].blob.as_ref().unwrap(), &png_header);
+
+    mock.assert_hits(1);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_flags_binary_body_of_failure(db: PgPool) {
+    let context = Arc::new(get_app_test_context(db.clone()).await);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    let png_header = vec![0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0xff];
+    let mock = server.mock(|when, then| {
+        when.method(Method::GET).path("/test");
+        then.status(500)
+            .header("content-type", "image/png")
+            .body(&png_header);
+    });
+
+    let mut params = construct_params(server.url("/test"), HttpMethod::Get);
+    params.max_tries = Some(1);
+    let job = construct_job(params, None);
+    producer.create_job(job).await.unwrap();
+
+    let started = tick(context).await.unwrap();
+    assert_eq!(started, 1);
+
+    let returned = wait_on_return(&return_worker, 1, false).await.unwrap();
+
+    let response: FetchResult =
+        serde_json::from_slice(returned[0].parameters.as_ref().unwrap()).unwrap();
+
+    let FetchResult::Failure { trace } = response else {
+        panic!("Expected failure response");
+    };
+
+    assert_eq!(trace.len(), 1);
+    assert!(trace[0].binary);
+    assert!(!trace[0].truncated);
+    assert_eq!(returned[0].blob.as_ref().unwrap(), &png_header);
+
+    mock.assert_hits(1);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_truncates_large_response(db: PgPool) {
+    let context = Arc::new(get_app_test_context(db.clone()).await);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(Method::GET).path("/test");
+        then.status(200)
+            .header("content-type", "text/plain; charset=utf-8")
+            .body("Hello, wörld!");
+    });
+
+    let mut params = construct_params(server.url("/test"), HttpMethod::Get);
+    // Cuts the ö in half
+    params.max_response_bytes = Some(9);
+    params.truncate_response = Some(true);
+    let job = construct_job(params, None);
+    producer.create_job(job).await.unwrap();
+
+    let started = tick(context).await.unwrap();
+
+    assert_eq!(started, 1);
+
+    let returned = wait_on_return(&return_worker, 1, false).await.unwrap();
+
+    let response: FetchResult =
+        serde_json::from_slice(returned[0].parameters.as_ref().unwrap()).unwrap();
+
+    let FetchResult::Success { response } = response else {
+        panic!("Expected success response");
+    };
+
+    let body = String::from_utf8(returned[0].blob.clone().unwrap()).unwrap();
+
+    assert_eq!(response.status, 200);
+    assert!(!response.binary);
+    assert!(response.truncated);
+    assert_eq!(body, "Hello, w");
+
+    mock.assert_hits(1);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_respects_retry_after_without_using_tries(db: PgPool) {
+    let context = Arc::new(get_app_test_context(db.clone()).await);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(Method::GET).path("/test");
+        then.status(429).header("Retry-After", "120");
+    });
+
+    let mut params = construct_params(server.url("/test"), HttpMethod::Get);
+    params.max_tries = Some(1);
+    let job = construct_job(params, None);
+    let id = producer.create_job(job).await.unwrap();
+
+    let started = tick(context.clone()).await.unwrap();
+    assert_eq!(started, 1);
+    wait_on_no_running(&db, Duration::milliseconds(500)).await;
+
+    // The job is rescheduled for when the destination asked, despite only having one try
+    let scheduled: DateTime<Utc> =
+        sqlx::query_scalar("SELECT scheduled FROM cyclotron_jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert!(scheduled > Utc::now() + Duration::seconds(110));
+
+    // Until it hits the worker's own retry limit
+    for _ in 0..2 {
+        make_immediately_available(&db).await;
+        wait_on_free_permit(&context, Duration::milliseconds(500)).await;
+        let started = tick(context.clone()).await.unwrap();
+        assert_eq!(started, 1);
+        wait_on_no_running(&db, Duration::milliseconds(500)).await;
+    }
+
+    let returned = wait_on_return(&return_worker, 1, false).await.unwrap();
+    let response: FetchResult =
+        serde_json::from_slice(returned[0].parameters.as_ref().unwrap()).unwrap();
+    let FetchResult::Failure { trace } = response else {
+        panic!("Expected failure response");
+    };
+
+    assert_eq!(trace.len(), 3);
+    for attempt in trace {
+        assert_eq!(attempt.status, Some(429));
+        assert!(attempt.retry_after.is_some());
+    }
+
+    mock.assert_hits(3);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_rate_limits_requests_per_host(db: PgPool) {
+    let mut context = get_app_test_context(db.clone()).await;
+    context.host_limiter = HostLimiter::new(NonZeroU32::new(1), HashMap::new());
+    let context = Arc::new(context);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(Method::POST).path("/test");
+        then.status(200).body("Hello, world!");
+    });
+
+    let params = construct_params(server.url("/test"), HttpMethod::Post);
+    let first = producer
+        .create_job(construct_job(params.clone(), Some(b"first".to_vec())))
+        .await
+        .unwrap();
+    let second = producer
+        .create_job(construct_job(params, Some(b"second".to_vec())))
+        .await
+        .unwrap();
+
+    // The test context runs one job at a time
+    for _ in 0..2 {
+        wait_on_free_permit(&context, Duration::milliseconds(500)).await;
+        let started = tick(context.clone()).await.unwrap();
+        assert_eq!(started, 1);
+        wait_on_no_running(&db, Duration::milliseconds(500)).await;
+    }
+
+    let returned = wait_on_return(&return_worker, 1, false).await.unwrap();
+    assert_eq!(returned[0].id, first);
+    mock.assert_hits(1);
+
+    // The second job is put back for later, with its body, and without using a try
+    let (scheduled, blob, metadata): (DateTime<Utc>, Option<Vec<u8>>, Option<Vec<u8>>) =
+        sqlx::query_as(
+            "SELECT scheduled, blob, metadata FROM cyclotron_jobs WHERE id = $1 AND queue_name = 'fetch'",
+        )
+        .bind(second)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(scheduled > Utc::now());
+    assert_eq!(blob, Some(b"second".to_vec()));
+    assert_eq!(metadata, None);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_drain_waits_for_running_jobs(db: PgPool) {
+    let context = Arc::new(get_app_test_context(db.clone()).await);
+    let producer = QueueManager::from_pool(db.clone());
+    let return_worker = Worker::from_pool(db.clone(), Default::default());
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(Method::GET).path("/test");
+        then.status(200)
+            .body("Hello, world!")
+            .delay(std::time::Duration::from_millis(200));
+    });
+
+    let params = construct_params(server.url("/test"), HttpMethod::Get);
+    producer
+        .create_job(construct_job(params, None))
+        .await
+        .unwrap();
+
+    let started = tick(context.clone()).await.unwrap();
+    assert_eq!(started, 1);
+
+    // The running fetch finishes within the drain timeout, so nothing is put back
+    assert_eq!(drain(&context).await.unwrap(), 0);
+
+    wait_on_return(&return_worker, 1, false).await.unwrap();
+    mock.assert_hits(1);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+pub async fn test_drain_releases_jobs_after_timeout(db: PgPool) {
+    let mut context = get_app_test_context(db.clone()).await;
+    context.config.drain_timeout = Duration::milliseconds(100);
+    let context = Arc::new(context);
+    let producer = QueueManager::from_pool(db.clone());
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+        when.method(Method::GET).path("/test");
+        then.status(200)
+            .body("Hello, world!")
+            .delay(std::time::Duration::from_secs(5));
+    });
+
+    let params = construct_params(server.url("/test"), HttpMethod::Get);
+    let id = producer
+        .create_job(construct_job(params, None))
+        .await
+        .unwrap();
+
+    let started = tick(context.clone()).await.unwrap();
+    assert_eq!(started, 1);
+
+    assert_eq!(drain(&context).await.unwrap(), 1);
+
+    // The job is back in the fetch queue, untouched, for the next worker to pick up
+    let (state, queue_name): (String, String) =
+        sqlx::query_as("SELECT state::text, queue_name FROM cyclotron_jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_eq!(state, "available");
+    assert_eq!(queue_name, "fetch");
+}
+
+#[test]
+pub fn test_host_limiter_spreads_out_backlog() {
+    let limiter = HostLimiter::new(NonZeroU32::new(1), HashMap::new());
+    assert!(limiter.check("example.com").is_none());
+
+    // Each job turned away comes back a request interval after the one before it
+    let waits: Vec<Duration> = (0..3)
+        .map(|_| limiter.check("example.com").unwrap())
+        .collect();
+    assert!(waits[0] <= Duration::seconds(1));
+    for pair in waits.windows(2) {
+        assert!(pair[1] - pair[0] > Duration::milliseconds(900));
+    }
+
+    // Other hosts have their own backlog
+    assert!(limiter.check("posthog.com").is_none());
+}
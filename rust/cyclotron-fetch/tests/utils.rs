@@ -8,6 +8,7 @@ use cyclotron_fetch::{
     config::AppConfig,
     context::AppContext,
     fetch::{FetchParameters, HttpMethod},
+    rate_limit::HostLimiter,
 };
 use sqlx::PgPool;
 use tokio::sync::Semaphore;
@@ -26,6 +27,7 @@ pub async fn get_app_test_context(db: PgPool) -> AppContext {
 
     let config = AppConfig {
         fetch_timeout: Duration::seconds(10),
+        drain_timeout: Duration::seconds(5),
         concurrent_requests_limit: 1,
         host: "localhost".to_string(),
         port: 16,
@@ -36,17 +38,26 @@ pub async fn get_app_test_context(db: PgPool) -> AppContext {
         queue_served: FETCH_QUEUE.to_string(),
         batch_size: 1000,
         max_response_bytes: 1024 * 1024,
+        stream_request_bytes: 1024 * 1024,
         retry_backoff_base: Duration::milliseconds(1000),
         allow_internal_ips: true,
+        host_rate_limit_per_second: None,
+        host_rate_limits: Default::default(),
     };
 
     let (_, mock_producer) = create_mock_kafka().await;
 
+    let host_limiter = HostLimiter::new(
+        config.host_rate_limit_per_second,
+        config.host_rate_limits.clone(),
+    );
+
     AppContext {
         worker,
         client,
         kafka_producer: mock_producer,
         concurrency_limit,
+        host_limiter,
         liveness,
         config,
         metric_labels: Default::default(),
@@ -61,6 +72,8 @@ pub fn construct_params(url: String, method: HttpMethod) -> FetchParameters {
         headers: None,
         max_tries: None,
         on_finish: None,
+        max_response_bytes: None,
+        truncate_response: None,
     }
 }
 
@@ -75,6 +88,9 @@ pub fn construct_job(parameters: FetchParameters, body: Option<Bytes>) -> JobIni
         parameters: Some(serde_json::to_vec(&parameters).unwrap()),
         blob: body,
         metadata: None,
+        dependencies: Vec::new(),
+        on_parent_failure: Default::default(),
+        idempotency_key: None,
     }
 }
 
@@ -120,6 +136,18 @@ pub async fn wait_on_no_running(pool: &PgPool, max_time: Duration) {
     }
 }
 
+// Jobs stop being "running" in the DB slightly before their task hands back its permit, so
+// tests that tick more than once also wait for the permit before ticking again.
+pub async fn wait_on_free_permit(context: &AppContext, max_time: Duration) {
+    let start = Utc::now();
+    while context.concurrency_limit.available_permits() == 0 {
+        if Utc::now() - start > max_time {
+            panic!("Timeout waiting for a free permit");
+        }
+        tokio::task::yield_now().await;
+    }
+}
+
 pub async fn make_immediately_available(pool: &PgPool) {
     sqlx::query(
         "UPDATE cyclotron_jobs SET scheduled = NOW() - INTERVAL '1 second' WHERE state = 'available'",
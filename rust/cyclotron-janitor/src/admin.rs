@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use cyclotron_core::{DeadLetter, Janitor, QueueDepth, QueueError};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+// Read-mostly routes for operators to inspect the queue without a psql session. These are only
+// served if enabled, on their own bind address, which should only be reachable by operators.
+pub fn admin_routes(janitor: Janitor) -> Router {
+    Router::new()
+        .route("/admin/queues", get(get_queue_depths))
+        .route("/admin/queues/oldest", get(get_oldest_available_jobs))
+        .route("/admin/dead_letter", get(get_dead_letters))
+        .route(
+            "/admin/dead_letter/:job_id/requeue",
+            post(post_requeue_dead_letter),
+        )
+        .with_state(Arc::new(janitor))
+}
+
+#[derive(Serialize, Debug)]
+pub struct OldestAvailableJobResponse {
+    queue_name: String,
+    scheduled: DateTime<Utc>,
+    age_seconds: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeadLetterQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RequeueResponse {
+    requeued: Uuid,
+}
+
+type AdminResult<T> = Result<Json<T>, (StatusCode, String)>;
+
+async fn get_queue_depths(State(janitor): State<Arc<Janitor>>) -> AdminResult<Vec<QueueDepth>> {
+    Ok(Json(janitor.queue_depths().await.map_err(internal_error)?))
+}
+
+async fn get_oldest_available_jobs(
+    State(janitor): State<Arc<Janitor>>,
+) -> AdminResult<Vec<OldestAvailableJobResponse>> {
+    let now = Utc::now();
+    let oldest = janitor
+        .oldest_available_jobs()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        oldest
+            .into_iter()
+            .map(|o| OldestAvailableJobResponse {
+                age_seconds: (now - o.scheduled).num_seconds().max(0),
+                queue_name: o.queue_name,
+                scheduled: o.scheduled,
+            })
+            .collect(),
+    ))
+}
+
+async fn get_dead_letters(
+    State(janitor): State<Arc<Janitor>>,
+    Query(query): Query<DeadLetterQuery>,
+) -> AdminResult<Vec<DeadLetter>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    Ok(Json(
+        janitor
+            .list_dead_letters(limit, offset)
+            .await
+            .map_err(internal_error)?,
+    ))
+}
+
+async fn post_requeue_dead_letter(
+    State(janitor): State<Arc<Janitor>>,
+    Path(job_id): Path<Uuid>,
+) -> AdminResult<RequeueResponse> {
+    if !janitor
+        .requeue_dead_letter(job_id)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("job {} is not in the dead letter queue", job_id),
+        ));
+    }
+
+    info!("Requeued dead lettered job {}", job_id);
+    Ok(Json(RequeueResponse { requeued: job_id }))
+}
+
+fn internal_error(err: QueueError) -> (StatusCode, String) {
+    error!("admin request failed: {}", err);
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
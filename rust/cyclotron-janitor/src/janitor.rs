@@ -20,8 +20,10 @@ use crate::{
 pub struct CleanupResult {
     pub completed: u64,
     pub failed: u64,
+    pub cancelled: u64,
     pub poisoned: u64,
     pub stalled: u64,
+    pub resolved: u64,
 }
 
 pub struct Janitor {
@@ -64,33 +66,72 @@ impl Janitor {
         self.inner.run_migrations().await;
     }
 
+    // Partitions the jobs table, if finished jobs should be dropped by partition, see
+    // `cyclotron_core::Janitor::partition_jobs_table`. Can take a while on a big table.
+    pub async fn partition_jobs_table(&self) -> Result<(), QueueError> {
+        if !self.settings.partition_finished_jobs || self.inner.jobs_table_partitioned().await? {
+            return Ok(());
+        }
+
+        info!("Partitioning jobs table");
+        let _time = common_metrics::timing_guard(PARTITION_TIME, &self.metrics_labels);
+        self.inner
+            .partition_jobs_table(self.settings.partition_batch_size)
+            .await?;
+        info!("Jobs table partitioned");
+        Ok(())
+    }
+
     pub async fn run_once(&self) -> Result<CleanupResult, QueueError> {
         info!("Running janitor loop");
         let _loop_start = common_metrics::timing_guard(RUN_TIME, &self.metrics_labels);
         common_metrics::inc(RUN_STARTS, &self.metrics_labels, 1);
 
+        let resolved = {
+            let _time = common_metrics::timing_guard(RESOLVED_TIME, &self.metrics_labels);
+            self.inner
+                .resolve_waiting_jobs(self.settings.resolve_batch_size)
+                .await?
+        };
+        common_metrics::inc(RESOLVED_COUNT, &self.metrics_labels, resolved);
+
+        if resolved > 0 {
+            warn!("Resolved {} waiting jobs missed by workers", resolved);
+        }
+
         let aggregated_deletes = {
             let _time = common_metrics::timing_guard(CLEANUP_TIME, &self.metrics_labels);
-            self.inner.delete_completed_and_failed_jobs().await?
+            // Until the jobs table has been partitioned, see `partition_jobs_table`
+            if self.settings.partition_finished_jobs && self.inner.jobs_table_partitioned().await? {
+                self.inner.rotate_finished_partitions().await?
+            } else {
+                self.inner.delete_completed_and_failed_jobs().await?
+            }
         };
 
         let mut completed_count = 0u64;
         let mut failed_count = 0u64;
+        let mut cancelled_count = 0u64;
         for delete in &aggregated_deletes {
-            if delete.state == "completed" {
-                completed_count += delete.count as u64;
-            } else if delete.state == "failed" {
-                failed_count += delete.count as u64;
+            match delete.state.as_str() {
+                "completed" => completed_count += delete.count as u64,
+                "failed" => failed_count += delete.count as u64,
+                "cancelled" => cancelled_count += delete.count as u64,
+                _ => {}
             }
         }
         common_metrics::inc(COMPLETED_COUNT, &self.metrics_labels, completed_count);
         common_metrics::inc(FAILED_COUNT, &self.metrics_labels, failed_count);
+        common_metrics::inc(CANCELLED_COUNT, &self.metrics_labels, cancelled_count);
 
+        // Cancelled jobs didn't succeed or fail, someone decided they shouldn't run, so they're
+        // left out of the function's metrics
         match send_iter_to_kafka(
             &self.kafka_producer,
             APP_METRICS2_TOPIC,
             aggregated_deletes
                 .into_iter()
+                .filter(|delete| delete.state != "cancelled")
                 .map(aggregated_delete_to_app_metric2),
         )
         .await
@@ -109,6 +150,20 @@ impl Janitor {
             }
         }
 
+        let expired_keys = {
+            let _time = common_metrics::timing_guard(EXPIRED_KEYS_TIME, &self.metrics_labels);
+            self.inner
+                .delete_expired_idempotency_keys(self.settings.idempotency_key_retention)
+                .await?
+        };
+        common_metrics::inc(EXPIRED_KEYS_COUNT, &self.metrics_labels, expired_keys);
+
+        let expired_results = {
+            let _time = common_metrics::timing_guard(EXPIRED_RESULTS_TIME, &self.metrics_labels);
+            self.inner.delete_expired_results().await?
+        };
+        common_metrics::inc(EXPIRED_RESULTS_COUNT, &self.metrics_labels, expired_results);
+
         let poisoned = {
             let _time = common_metrics::timing_guard(POISONED_TIME, &self.metrics_labels);
             self.inner
@@ -156,10 +211,30 @@ impl Janitor {
         Ok(CleanupResult {
             completed: completed_count,
             failed: failed_count,
+            cancelled: cancelled_count,
             poisoned,
             stalled,
+            resolved,
         })
     }
+
+    // Runs separately from the cleanup loop, and much more often, since it bounds how late a
+    // recurring job can be created
+    pub async fn run_schedules(&self) -> Result<u64, QueueError> {
+        let created = {
+            let _time = common_metrics::timing_guard(SCHEDULED_TIME, &self.metrics_labels);
+            self.inner
+                .run_schedules(self.settings.schedule_batch_size)
+                .await?
+        };
+        common_metrics::inc(SCHEDULED_COUNT, &self.metrics_labels, created);
+
+        if created > 0 {
+            info!("Created {} jobs from schedules", created);
+        }
+
+        Ok(created)
+    }
 }
 
 fn aggregated_delete_to_app_metric2(delete: AggregatedDelete) -> AppMetric2 {
@@ -1,15 +1,23 @@
 use axum::{extract::State, routing::get, Router};
 use common_metrics::setup_metrics_routes;
-use cyclotron_janitor::{config::Config, janitor::Janitor};
+use cyclotron_janitor::{admin::admin_routes, config::Config, janitor::Janitor};
 use envconfig::Envconfig;
 use eyre::Result;
 use health::{HealthHandle, HealthRegistry};
-use std::{future::ready, time::Duration};
+use std::{future::ready, sync::Arc, time::Duration};
 use tracing::{error, info};
 
 common_alloc::used!();
 
-async fn cleanup_loop(janitor: Janitor, livenes: HealthHandle, interval_secs: u64) -> Result<()> {
+// Partitioning the jobs table gives up rather than blocking workers behind a long running
+// transaction, so it's retried until it gets through
+const PARTITION_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+async fn cleanup_loop(
+    janitor: Arc<Janitor>,
+    livenes: HealthHandle,
+    interval_secs: u64,
+) -> Result<()> {
     let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
 
     loop {
@@ -24,6 +32,38 @@ async fn cleanup_loop(janitor: Janitor, livenes: HealthHandle, interval_secs: u6
     }
 }
 
+async fn schedule_loop(
+    janitor: Arc<Janitor>,
+    liveness: HealthHandle,
+    interval_secs: u64,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = janitor.run_schedules().await {
+            error!("janitor failed running schedules with: {}", e);
+        } else {
+            liveness.report_healthy().await;
+        }
+    }
+}
+
+// Runs until the jobs table has been partitioned, if it needs to be. Workers keep running while it
+// does, and the cleanup loop deletes finished jobs as usual until it's done.
+async fn partition_jobs_table(janitor: Arc<Janitor>) {
+    loop {
+        match janitor.partition_jobs_table().await {
+            Ok(()) => return,
+            Err(e) => {
+                error!("janitor failed partitioning the jobs table with: {}", e);
+                tokio::time::sleep(PARTITION_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
 async fn listen(app: Router, bind: String) -> Result<()> {
     let listener = tokio::net::TcpListener::bind(bind).await?;
 
@@ -65,9 +105,11 @@ async fn main() {
         janitor_id, bind
     );
 
-    let janitor = Janitor::new(janitor_config, &liveness)
-        .await
-        .expect("failed to create janitor");
+    let janitor = Arc::new(
+        Janitor::new(janitor_config, &liveness)
+            .await
+            .expect("failed to create janitor"),
+    );
 
     janitor.run_migrations().await;
 
@@ -78,12 +120,40 @@ async fn main() {
         )
         .await;
 
+    let scheduler_liveness = liveness
+        .register(
+            "scheduler".to_string(),
+            Duration::from_secs(config.schedule_interval_secs * 4),
+        )
+        .await;
+
+    // The admin routes don't need kafka or the cleanup settings, just a handle on the same pool
+    let admin_server = if config.admin_enabled {
+        let admin_bind = format!("{}:{}", config.admin_host, config.admin_port);
+        info!("Serving admin routes at {}", admin_bind);
+        let admin = admin_routes(cyclotron_core::Janitor::from_pool(
+            janitor.inner.pool.clone(),
+        ));
+        tokio::spawn(listen(admin, admin_bind))
+    } else {
+        tokio::spawn(std::future::pending())
+    };
+
+    // Not awaited, the janitor carries on without it if it never finishes
+    tokio::spawn(partition_jobs_table(janitor.clone()));
+
     let janitor_loop = tokio::spawn(cleanup_loop(
-        janitor,
+        janitor.clone(),
         janitor_liveness,
         config.cleanup_interval_secs,
     ));
 
+    let scheduler_loop = tokio::spawn(schedule_loop(
+        janitor,
+        scheduler_liveness,
+        config.schedule_interval_secs,
+    ));
+
     let app = setup_metrics_routes(app(liveness, janitor_id));
     let http_server = tokio::spawn(listen(app, bind));
 
@@ -94,12 +164,24 @@ async fn main() {
                 error!("janitor failed with: {}", e)
             }
         }
+        res = scheduler_loop => {
+            error!("scheduler loop exited");
+            if let Err(e) = res {
+                error!("scheduler failed with: {}", e)
+            }
+        }
         res = http_server => {
             error!("http server exited");
             if let Err(e) = res {
                 error!("server failed with: {}", e)
             }
         }
+        res = admin_server => {
+            error!("admin server exited");
+            if let Err(e) = res {
+                error!("admin server failed with: {}", e)
+            }
+        }
     }
 
     info!("exiting");
@@ -4,14 +4,29 @@ pub const RUN_ENDS: &str = "cyclotron_janitor_run_ends";
 
 pub const COMPLETED_COUNT: &str = "cyclotron_janitor_completed_jobs";
 pub const FAILED_COUNT: &str = "cyclotron_janitor_failed_jobs";
+pub const CANCELLED_COUNT: &str = "cyclotron_janitor_cancelled_jobs";
 pub const CLEANUP_TIME: &str = "cyclotron_janitor_completed_failed_jobs_cleanup_ms";
 
+pub const RESOLVED_COUNT: &str = "cyclotron_janitor_waiting_jobs_resolved";
+pub const RESOLVED_TIME: &str = "cyclotron_janitor_waiting_jobs_resolved_ms";
+
+pub const EXPIRED_KEYS_COUNT: &str = "cyclotron_janitor_expired_idempotency_keys";
+pub const EXPIRED_KEYS_TIME: &str = "cyclotron_janitor_expired_idempotency_keys_cleanup_ms";
+
+pub const EXPIRED_RESULTS_COUNT: &str = "cyclotron_janitor_expired_job_results";
+pub const EXPIRED_RESULTS_TIME: &str = "cyclotron_janitor_expired_job_results_cleanup_ms";
+
 pub const POISONED_COUNT: &str = "cyclotron_janitor_poison_pills";
 pub const POISONED_TIME: &str = "cyclotron_janitor_poison_pills_cleanup_ms";
 
 pub const STALLED_COUNT: &str = "cyclotron_janitor_stalled_jobs_reset";
 pub const STALLED_TIME: &str = "cyclotron_janitor_stalled_jobs_reset_ms";
 
+pub const SCHEDULED_COUNT: &str = "cyclotron_janitor_scheduled_jobs_created";
+pub const SCHEDULED_TIME: &str = "cyclotron_janitor_scheduled_jobs_created_ms";
+
+pub const PARTITION_TIME: &str = "cyclotron_janitor_partition_jobs_table_ms";
+
 // The janitor should report some basic shard-level metrics
 pub const AVAILABLE_DEPTH: &str = "cyclotron_available_jobs";
 pub const AVAILABLE_DEPTH_TIME: &str = "cyclotron_available_jobs_ms";
@@ -0,0 +1,89 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use chrono::Utc;
+use cyclotron_core::{JobInit, QueueManager, Worker};
+use cyclotron_janitor::admin::admin_routes;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn request(app: &Router, method: Method, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+async fn admin_routes_test(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let worker = Worker::from_pool(db.clone(), Default::default());
+    let app = admin_routes(cyclotron_core::Janitor::from_pool(db.clone()));
+
+    let job_init = JobInit {
+        team_id: 1,
+        queue_name: "default".to_string(),
+        priority: 0,
+        scheduled: Utc::now(),
+        function_id: Some(Uuid::now_v7()),
+        vm_state: None,
+        parameters: None,
+        blob: None,
+        metadata: None,
+        dependencies: Vec::new(),
+        on_parent_failure: Default::default(),
+        idempotency_key: None,
+    };
+    manager.create_job(job_init.clone()).await.unwrap();
+    let dead = manager.create_job(job_init).await.unwrap();
+
+    let (status, body) = request(&app, Method::GET, "/admin/queues").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["queue_name"], "default");
+    assert_eq!(body[0]["state"], "available");
+    assert_eq!(body[0]["team_id"], 1);
+    assert_eq!(body[0]["count"], 2);
+
+    let (status, body) = request(&app, Method::GET, "/admin/queues/oldest").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["queue_name"], "default");
+    assert!(body[0]["age_seconds"].as_i64().unwrap() >= 0);
+
+    let jobs = worker.dequeue_jobs("default", 2).await.unwrap();
+    assert!(jobs.iter().any(|j| j.id == dead));
+    worker.dead_letter(dead, "bad input").await.unwrap();
+
+    let (status, body) = request(&app, Method::GET, "/admin/dead_letter?limit=10").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["job_id"], dead.to_string());
+    assert_eq!(body[0]["original_queue_name"], "default");
+    assert_eq!(body[0]["reason"], "bad input");
+
+    let uri = format!("/admin/dead_letter/{}/requeue", dead);
+    let (status, body) = request(&app, Method::POST, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["requeued"], dead.to_string());
+
+    let (_, body) = request(&app, Method::GET, "/admin/dead_letter").await;
+    assert!(body.as_array().unwrap().is_empty());
+
+    // Requeueing again, or requeueing a job that was never dead lettered, is a 404
+    let (status, _) = request(&app, Method::POST, &uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -2,7 +2,7 @@ use chrono::{Duration, Timelike, Utc};
 use common_kafka::kafka_messages::app_metrics2::{
     AppMetric2, Kind as AppMetric2Kind, Source as AppMetric2Source,
 };
-use cyclotron_core::{JobInit, JobState, QueueManager, Worker};
+use cyclotron_core::{JobFilter, JobInit, JobState, QueueManager, Worker};
 use cyclotron_janitor::{config::JanitorSettings, janitor::Janitor};
 use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::{ClientConfig, Message};
@@ -47,6 +47,11 @@ async fn janitor_test(db: PgPool) {
         max_touches,
         id: "test_janitor".to_string(),
         shard_id: "test_shard".to_string(),
+        schedule_batch_size: 1000,
+        idempotency_key_retention: Duration::hours(24),
+        partition_finished_jobs: false,
+        partition_batch_size: 1000,
+        resolve_batch_size: 1000,
     };
     let janitor = Janitor {
         inner: cyclotron_core::Janitor::from_pool(db.clone()),
@@ -69,6 +74,9 @@ async fn janitor_test(db: PgPool) {
         parameters: None,
         blob: None,
         metadata: None,
+        dependencies: Vec::new(),
+        on_parent_failure: Default::default(),
+        idempotency_key: None,
     };
 
     // First test - if we mark a job as completed, the janitor will clean it up
@@ -305,9 +313,17 @@ async fn janitor_test(db: PgPool) {
     worker.release_job(jobs[0].id, None).await.unwrap();
     worker.release_job(jobs[1].id, None).await.unwrap();
 
+    // Cancelled jobs are cleaned up too, but don't count as failures
+    let cancelled = manager.create_job(job_init.clone()).await.unwrap();
+    manager
+        .cancel_jobs(JobFilter::Ids(vec![cancelled]))
+        .await
+        .unwrap();
+
     let result = janitor.run_once().await.unwrap();
     assert_eq!(result.completed, 1);
     assert_eq!(result.failed, 1);
+    assert_eq!(result.cancelled, 1);
     assert_eq!(result.poisoned, 0);
     assert_eq!(result.stalled, 0);
 }
@@ -1,7 +1,10 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+
+use std::future::Future;
 
 use cyclotron_core::{
-    Job, JobInit, JobState, ManagerConfig, PoolConfig, QueueManager, Worker, WorkerConfig,
+    Job, JobFilter, JobInit, JobState, JobStatus, ManagerConfig, ParentFailurePolicy, PoolConfig,
+    QueueError, QueueManager, Worker, WorkerConfig,
 };
 use neon::{
     handle::Handle,
@@ -164,6 +167,12 @@ pub struct JsJob {
     pub vm_state: Option<String>,
     pub parameters: Option<String>,
     pub metadata: Option<String>,
+    #[serde(default)]
+    pub dependencies: Vec<Uuid>,
+    #[serde(default)]
+    pub on_parent_failure: ParentFailurePolicy,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
 
 fn create_job(mut cx: FunctionContext) -> JsResult<JsPromise> {
@@ -288,6 +297,141 @@ fn bulk_create_jobs(mut cx: FunctionContext) -> JsResult<JsPromise> {
     Ok(promise)
 }
 
+// The bulk management calls all take a JSON serialized JobFilter as their first argument, and
+// resolve to the number of jobs changed
+fn manage_jobs<F, Fut>(mut cx: FunctionContext, op: F) -> JsResult<JsPromise>
+where
+    F: FnOnce(&'static QueueManager, JobFilter) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<u64, QueueError>> + Send,
+{
+    let arg1 = cx.argument::<JsString>(0)?;
+    let filter: JobFilter = from_json_string(&mut cx, arg1)?;
+
+    let (deferred, promise) = cx.promise();
+    let channel = cx.channel();
+    let runtime = runtime(&mut cx)?;
+
+    let fut = async move {
+        let manager = match MANAGER.get() {
+            Some(manager) => manager,
+            None => {
+                deferred.settle_with(&channel, |mut cx| {
+                    throw_null_err(&mut cx, "manager not initialized")
+                });
+                return;
+            }
+        };
+        let res = op(manager, filter).await;
+        deferred.settle_with(&channel, move |mut cx| {
+            let count = res.or_else(|e| cx.throw_error(format!("{}", e)))?;
+            Ok(cx.number(count as f64))
+        });
+    };
+
+    runtime.spawn(fut);
+
+    Ok(promise)
+}
+
+fn cancel_jobs(cx: FunctionContext) -> JsResult<JsPromise> {
+    manage_jobs(cx, |manager, filter| manager.cancel_jobs(filter))
+}
+
+fn pause_jobs(cx: FunctionContext) -> JsResult<JsPromise> {
+    manage_jobs(cx, |manager, filter| manager.pause_jobs(filter))
+}
+
+fn resume_jobs(cx: FunctionContext) -> JsResult<JsPromise> {
+    manage_jobs(cx, |manager, filter| manager.resume_jobs(filter))
+}
+
+fn reschedule_jobs(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let arg = cx.argument::<JsString>(1)?.value(&mut cx);
+    let scheduled: DateTime<Utc> = arg
+        .parse()
+        .or_else(|_| cx.throw_error(format!("invalid scheduled at: {}", arg)))?;
+
+    manage_jobs(cx, move |manager, filter| {
+        manager.reschedule_jobs(filter, scheduled)
+    })
+}
+
+// Resolves to null if the job can't be found, see `QueueManager::get_job_status`
+fn get_job_status(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let arg = cx.argument::<JsString>(0)?.value(&mut cx);
+    let id: Uuid = arg
+        .parse()
+        .or_else(|_| cx.throw_error(format!("invalid job id: {}", arg)))?;
+
+    let (deferred, promise) = cx.promise();
+    let channel = cx.channel();
+    let runtime = runtime(&mut cx)?;
+
+    let fut = async move {
+        let manager = match MANAGER.get() {
+            Some(manager) => manager,
+            None => {
+                deferred.settle_with(&channel, |mut cx| {
+                    throw_null_err(&mut cx, "manager not initialized")
+                });
+                return;
+            }
+        };
+        let res = manager.get_job_status(id).await;
+        deferred.settle_with(&channel, move |mut cx| {
+            let status = res.or_else(|e| cx.throw_error(format!("{}", e)))?;
+            match status {
+                Some(status) => Ok(job_status_to_js_object(&mut cx, status)?.upcast::<JsValue>()),
+                None => Ok(cx.null().upcast::<JsValue>()),
+            }
+        });
+    };
+
+    runtime.spawn(fut);
+
+    Ok(promise)
+}
+
+// Takes the queue name, and the retention in seconds, or null to stop retaining results
+fn set_result_retention(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let queue_name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let retention = cx.argument::<JsValue>(1)?;
+    let retention =
+        if retention.is_a::<JsNull, _>(&mut cx) || retention.is_a::<JsUndefined, _>(&mut cx) {
+            None
+        } else {
+            let seconds = retention
+                .downcast_or_throw::<JsNumber, _>(&mut cx)?
+                .value(&mut cx);
+            Some(Duration::seconds(seconds as i64))
+        };
+
+    let (deferred, promise) = cx.promise();
+    let channel = cx.channel();
+    let runtime = runtime(&mut cx)?;
+
+    let fut = async move {
+        let manager = match MANAGER.get() {
+            Some(manager) => manager,
+            None => {
+                deferred.settle_with(&channel, |mut cx| {
+                    throw_null_err(&mut cx, "manager not initialized")
+                });
+                return;
+            }
+        };
+        let res = manager.set_result_retention(&queue_name, retention).await;
+        deferred.settle_with(&channel, move |mut cx| {
+            res.or_else(|e| cx.throw_error(format!("{}", e)))?;
+            Ok(cx.null())
+        });
+    };
+
+    runtime.spawn(fut);
+
+    Ok(promise)
+}
+
 fn dequeue_jobs(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let queue_name = cx.argument::<JsString>(0)?.value(&mut cx);
 
@@ -713,6 +857,60 @@ fn jobs_to_js_array<'a>(cx: &mut TaskContext<'a>, jobs: Vec<Job>) -> JsResult<'a
     Ok(js_array)
 }
 
+fn job_status_to_js_object<'a>(
+    cx: &mut TaskContext<'a>,
+    status: JobStatus,
+) -> JsResult<'a, JsObject> {
+    let js_obj = JsObject::new(cx);
+    let null = cx.null();
+
+    let js_id = cx.string(status.id.to_string());
+    js_obj.set(cx, "id", js_id)?;
+
+    let team_id = cx.number(status.team_id as f64);
+    js_obj.set(cx, "teamId", team_id)?;
+
+    if let Some(function_id) = status.function_id {
+        let js_function_id = cx.string(function_id.to_string());
+        js_obj.set(cx, "functionId", js_function_id)?;
+    } else {
+        js_obj.set(cx, "functionId", null)?;
+    }
+
+    let js_queue_name = cx.string(&status.queue_name);
+    js_obj.set(cx, "queueName", js_queue_name)?;
+
+    let js_state = cx.string(format!("{:?}", status.state).to_lowercase());
+    js_obj.set(cx, "state", js_state)?;
+
+    let js_last_transition = cx.string(status.last_transition.to_rfc3339());
+    js_obj.set(cx, "lastTransition", js_last_transition)?;
+
+    if let Some(parameters) = status.parameters {
+        let parameters = match std::str::from_utf8(&parameters) {
+            Ok(v) => v,
+            Err(e) => {
+                return cx.throw_error(format!("Invalid UTF-8 sequence in parameters: {}", e))
+            }
+        };
+        let js_parameters = cx.string(parameters);
+        js_obj.set(cx, "parameters", js_parameters)?;
+    } else {
+        js_obj.set(cx, "parameters", null)?;
+    }
+
+    if let Some(blob) = status.blob {
+        let mut js_blob = JsArrayBuffer::new(cx, blob.len())?;
+        let js_blob_slice = js_blob.as_mut_slice(cx);
+        js_blob_slice.copy_from_slice(&blob);
+        js_obj.set(cx, "blob", js_blob)?;
+    } else {
+        js_obj.set(cx, "blob", null)?;
+    }
+
+    Ok(js_obj)
+}
+
 impl JsJob {
     fn to_job_init(&self, blob: Option<Vec<u8>>) -> JobInit {
         JobInit {
@@ -725,6 +923,9 @@ impl JsJob {
             parameters: self.parameters.as_ref().map(|s| s.as_bytes().to_vec()),
             metadata: self.metadata.as_ref().map(|s| s.as_bytes().to_vec()),
             blob,
+            dependencies: self.dependencies.clone(),
+            on_parent_failure: self.on_parent_failure,
+            idempotency_key: self.idempotency_key.clone(),
         }
     }
 }
@@ -738,6 +939,12 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("maybeInitManager", maybe_init_manager)?;
     cx.export_function("createJob", create_job)?;
     cx.export_function("bulkCreateJobs", bulk_create_jobs)?;
+    cx.export_function("cancelJobs", cancel_jobs)?;
+    cx.export_function("pauseJobs", pause_jobs)?;
+    cx.export_function("resumeJobs", resume_jobs)?;
+    cx.export_function("rescheduleJobs", reschedule_jobs)?;
+    cx.export_function("getJobStatus", get_job_status)?;
+    cx.export_function("setResultRetention", set_result_retention)?;
     cx.export_function("dequeueJobs", dequeue_jobs)?;
     cx.export_function("dequeueJobsWithVmState", dequeue_with_vm_state)?;
     cx.export_function("releaseJob", release_job)?;
@@ -1,8 +1,8 @@
 // eslint-disable-next-line @typescript-eslint/no-var-requires
 const cyclotron = require('../index.node')
 
-import { convertToInternalPoolConfig, serializeObject } from './helpers'
-import { CyclotronJobInit, CyclotronPoolConfig } from './types'
+import { convertToInternalPoolConfig, deserializeObject, serializeObject } from './helpers'
+import { CyclotronJobFilter, CyclotronJobInit, CyclotronJobStatus, CyclotronPoolConfig } from './types'
 
 export class CyclotronManager {
     constructor(private config: { shards: CyclotronPoolConfig[]; shardDepthLimit: number }) {
@@ -32,6 +32,9 @@ export class CyclotronManager {
             vm_state: job.vmState ? serializeObject('vmState', job.vmState) : null,
             parameters: job.parameters ? serializeObject('parameters', job.parameters) : null,
             metadata: job.metadata ? serializeObject('metadata', job.metadata) : null,
+            dependencies: job.dependencies ?? [],
+            on_parent_failure: job.onParentFailure ?? 'fail',
+            idempotency_key: job.idempotencyKey ?? null,
         }
 
         const json = JSON.stringify(jobInitInternal)
@@ -52,6 +55,9 @@ export class CyclotronManager {
                 vm_state: job.vmState ? serializeObject('vmState', job.vmState) : null,
                 parameters: job.parameters ? serializeObject('parameters', job.parameters) : null,
                 metadata: job.metadata ? serializeObject('metadata', job.metadata) : null,
+                dependencies: job.dependencies ?? [],
+                on_parent_failure: job.onParentFailure ?? 'fail',
+                idempotency_key: job.idempotencyKey ?? null,
             }
         })
         const json = JSON.stringify(jobInitsInternal)
@@ -77,4 +83,77 @@ export class CyclotronManager {
 
         return await cyclotron.bulkCreateJobs(json, blobs, blobLengths)
     }
+
+    // The bulk operations below leave running jobs alone, and resolve to the number of jobs changed.
+
+    // Cancelled jobs finish in their own 'cancelled' state, and are left out of failure metrics.
+    async cancelJobs(filter: CyclotronJobFilter): Promise<number> {
+        return await cyclotron.cancelJobs(serializeJobFilter(filter))
+    }
+
+    async pauseJobs(filter: CyclotronJobFilter): Promise<number> {
+        return await cyclotron.pauseJobs(serializeJobFilter(filter))
+    }
+
+    async resumeJobs(filter: CyclotronJobFilter): Promise<number> {
+        return await cyclotron.resumeJobs(serializeJobFilter(filter))
+    }
+
+    async rescheduleJobs(filter: CyclotronJobFilter, scheduled: Date): Promise<number> {
+        return await cyclotron.rescheduleJobs(serializeJobFilter(filter), scheduled.toISOString())
+    }
+
+    // Resolves to null if the job doesn't exist, or has finished and been cleaned up without its result being retained,
+    // or its retained result has expired. See setResultRetention.
+    async getJobStatus(id: string): Promise<CyclotronJobStatus | null> {
+        const status = await cyclotron.getJobStatus(id)
+        if (!status) {
+            return null
+        }
+        return {
+            ...status,
+            lastTransition: new Date(status.lastTransition),
+            parameters: deserializeObject('parameters', status.parameters),
+            blob: status.blob ? new Uint8Array(status.blob) : null,
+        }
+    }
+
+    // Polls getJobStatus until the job has completed, failed or been cancelled, resolving to its final status, or null
+    // if it can't be found. Rejects if the job hasn't finished within the timeout.
+    async waitForJob(
+        id: string,
+        { timeoutMs = 30000, pollIntervalMs = 500 }: { timeoutMs?: number; pollIntervalMs?: number } = {}
+    ): Promise<CyclotronJobStatus | null> {
+        const deadline = Date.now() + timeoutMs
+        let status = await this.getJobStatus(id)
+        while (
+            status &&
+            status.state !== 'completed' &&
+            status.state !== 'failed' &&
+            status.state !== 'cancelled'
+        ) {
+            if (Date.now() >= deadline) {
+                throw new Error(`Timed out waiting for job ${id} to finish`)
+            }
+            await new Promise((resolve) => setTimeout(resolve, pollIntervalMs))
+            status = await this.getJobStatus(id)
+        }
+        return status
+    }
+
+    // Jobs finishing in the queue have their results kept for this long after they finish, so getJobStatus can still
+    // find them once the jobs themselves have been cleaned up. Jobs can finish in a different queue to the one they
+    // were created in, e.g. a fetch job finishes in its return queue. Passing null stops retaining results.
+    async setResultRetention(queueName: string, retentionSeconds: number | null): Promise<void> {
+        return await cyclotron.setResultRetention(queueName, retentionSeconds)
+    }
+}
+
+function serializeJobFilter(filter: CyclotronJobFilter): string {
+    if ('ids' in filter) {
+        return JSON.stringify({ ids: filter.ids })
+    } else if ('functionId' in filter) {
+        return JSON.stringify({ function_id: filter.functionId })
+    }
+    return JSON.stringify({ team_id: filter.teamId })
 }
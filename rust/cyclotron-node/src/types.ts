@@ -31,9 +31,16 @@ export type CyclotronWorkerTuningConfig = {
     // conditions above every this many milliseconds. Users may also call forceFlush(), which will try to flush any
     // pending updates immediately.
     flushLoopIntervalMs?: number
+    // Take turns between teams when dequeuing, rather than going strictly by priority and scheduled time, so one
+    // team's backlog can't starve the others.
+    fairDequeue?: boolean
+    // Skip teams already running this many jobs in the queue being dequeued from. Implies fairDequeue.
+    maxRunningPerTeam?: number
 }
 
-export type CyclotronJobState = 'available' | 'running' | 'completed' | 'failed' | 'paused'
+export type CyclotronJobState = 'available' | 'running' | 'completed' | 'failed' | 'paused' | 'waiting' | 'cancelled'
+
+export type CyclotronParentFailurePolicy = 'fail' | 'run'
 
 export type CyclotronJob = {
     id: string
@@ -56,7 +63,24 @@ export type CyclotronJob = {
 }
 
 export type CyclotronJobInit = Pick<CyclotronJob, 'teamId' | 'functionId' | 'queueName' | 'priority'> &
-    Pick<Partial<CyclotronJob>, 'scheduled' | 'vmState' | 'parameters' | 'metadata' | 'blob'>
+    Pick<Partial<CyclotronJob>, 'scheduled' | 'vmState' | 'parameters' | 'metadata' | 'blob'> & {
+        // Ids of jobs, in the same shard, that must finish before this one becomes available
+        dependencies?: string[]
+        onParentFailure?: CyclotronParentFailurePolicy
+        // Creating a job with the same key, team and queue as an existing one resolves to the existing job's id
+        // instead, until the key expires
+        idempotencyKey?: string
+    }
+
+// Where a job is up to. For a finished job, parameters and blob are whatever the last worker to run it left there,
+// e.g. a fetch job's result and response body
+export type CyclotronJobStatus = Pick<
+    CyclotronJob,
+    'id' | 'teamId' | 'functionId' | 'queueName' | 'state' | 'lastTransition' | 'parameters' | 'blob'
+>
+
+// Selects jobs for the manager's bulk operations. Exactly one of these should be set.
+export type CyclotronJobFilter = { ids: string[] } | { functionId: string } | { teamId: number }
 
 export type CyclotronJobUpdate = Pick<
     Partial<CyclotronJob>,
@@ -0,0 +1,15 @@
+use cyclotron_core::{JobError, QueueError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuntimeError {
+    #[error(transparent)]
+    QueueError(#[from] QueueError),
+    #[error(transparent)]
+    JobError(#[from] JobError),
+    // We failed to serialise something to the queue. Failing to deserialise something from the
+    // queue is the job's fault, not ours, and dead letters it instead
+    #[error(transparent)]
+    SerdeError(#[from] serde_json::Error),
+    #[error(transparent)]
+    TaskFailed(#[from] tokio::task::JoinError),
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cyclotron_core::{Bytes, Job, Worker};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::RuntimeError;
+
+// Does the work for jobs in one queue. A job's parameters are deserialised into `Parameters`
+// before it's handed over - jobs whose parameters don't deserialise are dead lettered without
+// reaching the handler. Jobs with no parameters are deserialised from `null`, so an `Option` or
+// `()` works for queues whose jobs don't need any.
+#[async_trait]
+pub trait Handler: Send + Sync + 'static {
+    type Parameters: DeserializeOwned + Send;
+    // The handler's own state for a job, kept across retries, see `JobContext::metadata`
+    type Metadata: DeserializeOwned + Serialize + Default + Send;
+
+    async fn handle(
+        &self,
+        job: &mut JobContext<Self::Parameters, Self::Metadata>,
+    ) -> Result<Outcome, HandlerError>;
+}
+
+// What should happen to a job once its handler is done with it
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Complete,
+    Fail,
+    // Move the job to another queue, e.g. to hand a result back to whatever created it
+    Forward(String),
+    // Run the job again at the given time. This doesn't count as a try
+    RetryAt(DateTime<Utc>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandlerError {
+    // Retried with backoff, until the queue's max tries is reached, after which the job fails
+    #[error("retryable error: {0}")]
+    Retryable(String),
+    // The job fails without being retried
+    #[error("permanent error: {0}")]
+    Permanent(String),
+}
+
+// Updates to a job can only fail if the runtime has lost track of it, which a retry might fix
+impl From<RuntimeError> for HandlerError {
+    fn from(e: RuntimeError) -> Self {
+        HandlerError::Retryable(e.to_string())
+    }
+}
+
+// A running job, as seen by its handler
+pub struct JobContext<P, M> {
+    // The job as dequeued, except its parameters and metadata, which are taken to fill in the
+    // fields below
+    pub job: Job,
+    pub parameters: P,
+    // Metadata belongs to the worker serving the job's queue, and producers can set it as an `M`.
+    // The runtime keeps it when the job is retried, and clears it when the job finishes or moves
+    // to another queue. Metadata that can't be deserialised is logged and counted, and the handler
+    // gets the default instead.
+    pub metadata: M,
+    // How many times this job has been tried before, not counting `Outcome::RetryAt`
+    pub tries: u32,
+    pub(crate) worker: Arc<Worker>,
+}
+
+// Changes made here are written when the job is released, whatever the outcome
+impl<P, M> JobContext<P, M> {
+    pub fn set_parameters<T: Serialize>(&self, parameters: &T) -> Result<(), RuntimeError> {
+        let parameters = serde_json::to_vec(parameters)?;
+        self.worker.set_parameters(self.job.id, Some(parameters))?;
+        Ok(())
+    }
+
+    pub fn set_blob(&self, blob: Option<Bytes>) -> Result<(), RuntimeError> {
+        Ok(self.worker.set_blob(self.job.id, blob)?)
+    }
+
+    pub fn set_vm_state(&self, vm_state: Option<Bytes>) -> Result<(), RuntimeError> {
+        Ok(self.worker.set_vm_state(self.job.id, vm_state)?)
+    }
+}
@@ -0,0 +1,24 @@
+// A runtime for services that work cyclotron queues. Services implement `Handler` for each queue
+// they serve, and the runtime does the rest - dequeuing, concurrency limits, heartbeats, retries,
+// dead-lettering, shutdown and metrics - around `cyclotron_core::Worker`.
+
+// Same pattern as cyclotron-core - private modules, with the public API re-exported from them
+
+// Handlers
+mod handler;
+pub use handler::Handler;
+pub use handler::HandlerError;
+pub use handler::JobContext;
+pub use handler::Outcome;
+
+// Runtime
+mod runtime;
+pub use runtime::QueueOptions;
+pub use runtime::Runtime;
+
+// Errors
+mod error;
+// Errors in the runtime itself, rather than in a handler - these mean the worker is unhealthy
+pub use error::RuntimeError;
+
+pub mod metrics_constants;
@@ -0,0 +1,13 @@
+// Metric names
+pub const JOBS_DEQUEUED: &str = "cyclotron_sdk_jobs_dequeued";
+pub const DEQUEUE_TIME: &str = "cyclotron_sdk_dequeue_ms";
+pub const AVAILABLE_PERMITS: &str = "cyclotron_sdk_available_permits";
+pub const JOB_TOTAL_TIME: &str = "cyclotron_sdk_job_total_run_ms";
+pub const JOB_ERRORS: &str = "cyclotron_sdk_job_errors";
+pub const JOBS_DEAD_LETTERED: &str = "cyclotron_sdk_jobs_dead_lettered";
+pub const JOBS_RETRIED: &str = "cyclotron_sdk_jobs_retried";
+pub const METADATA_PARSE_FAILURES: &str = "cyclotron_sdk_metadata_parse_failures";
+
+// Label keys
+pub const QUEUE_LABEL: &str = "queue";
+pub const OUTCOME_LABEL: &str = "outcome";
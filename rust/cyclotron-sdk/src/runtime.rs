@@ -0,0 +1,377 @@
+use std::{cmp::min, future::Future, sync::Arc};
+
+use chrono::{Duration, Utc};
+use cyclotron_core::{Job, JobState, Worker};
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    sync::{watch, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    error::RuntimeError,
+    handler::{Handler, HandlerError, JobContext, Outcome},
+    metrics_constants::*,
+};
+
+#[derive(Debug, Clone)]
+pub struct QueueOptions {
+    pub concurrency: usize,           // Jobs run at once, defaults to 100
+    pub batch_size: usize,            // Jobs dequeued at once, defaults to 100
+    pub max_tries: u32,               // Defaults to 3
+    pub retry_backoff_base: Duration, // Retries back off this * tries, defaults to 1 second
+    pub max_retry_backoff: Duration,  // Defaults to 1 hour
+    pub poll_interval: Duration, // How long we wait for new jobs before polling anyway, defaults to 1 second
+    pub with_vm_state: bool,     // Dequeue jobs with their vm_state, defaults to false
+}
+
+impl Default for QueueOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 100,
+            batch_size: 100,
+            max_tries: 3,
+            retry_backoff_base: Duration::seconds(1),
+            max_retry_backoff: Duration::hours(1),
+            poll_interval: Duration::seconds(1),
+            with_vm_state: false,
+        }
+    }
+}
+
+// Producers set a job's metadata to the handler's metadata, as is. While the runtime retries a job,
+// it stores the handler's metadata alongside its own bookkeeping, under this key, and unwraps it
+// again when the job is next run.
+const BOOKKEEPING_KEY: &str = "$cyclotron_sdk";
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Bookkeeping {
+    tries: u32,
+}
+
+#[derive(Serialize)]
+struct StoredMetadata<'a, M> {
+    #[serde(rename = "$cyclotron_sdk")]
+    bookkeeping: Bookkeeping,
+    handler: &'a M,
+}
+
+fn read_metadata<M: DeserializeOwned + Default>(
+    metadata: Option<&[u8]>,
+) -> Result<(M, Bookkeeping), serde_json::Error> {
+    let Some(metadata) = metadata else {
+        return Ok((M::default(), Bookkeeping::default()));
+    };
+
+    let mut value: Value = serde_json::from_slice(metadata)?;
+    let bookkeeping = match value
+        .as_object_mut()
+        .and_then(|stored| stored.remove(BOOKKEEPING_KEY))
+    {
+        Some(bookkeeping) => {
+            value = value
+                .get_mut("handler")
+                .map(Value::take)
+                .unwrap_or_default();
+            serde_json::from_value(bookkeeping)?
+        }
+        None => Bookkeeping::default(),
+    };
+
+    let handler = if value.is_null() {
+        M::default()
+    } else {
+        serde_json::from_value(value)?
+    };
+    Ok((handler, bookkeeping))
+}
+
+type QueueLoop =
+    Box<dyn FnOnce(watch::Receiver<bool>) -> BoxFuture<'static, Result<(), RuntimeError>> + Send>;
+
+// Runs handlers for a set of queues against one worker
+pub struct Runtime {
+    worker: Arc<Worker>,
+    queues: Vec<QueueLoop>,
+    drain_timeout: Duration, // How long we wait for running jobs to finish on shutdown, defaults to 30 seconds
+}
+
+impl Runtime {
+    pub fn new(worker: Worker) -> Self {
+        Self {
+            worker: Arc::new(worker),
+            queues: Vec::new(),
+            drain_timeout: Duration::seconds(30),
+        }
+    }
+
+    pub fn with_drain_timeout(self, drain_timeout: Duration) -> Self {
+        Self {
+            drain_timeout,
+            ..self
+        }
+    }
+
+    pub fn worker(&self) -> &Worker {
+        &self.worker
+    }
+
+    pub fn register<H: Handler>(&mut self, queue: &str, options: QueueOptions, handler: H) {
+        let worker = self.worker.clone();
+        let queue = queue.to_string();
+        let handler = Arc::new(handler);
+        self.queues.push(Box::new(move |shutdown| {
+            Box::pin(queue_loop(worker, queue, options, handler, shutdown))
+        }));
+    }
+
+    // Works every registered queue until `shutdown` resolves, then stops dequeuing, waits up to the
+    // drain timeout for running jobs to finish, and flushes their updates. Jobs still running after
+    // the drain timeout are put back in the queue as they were dequeued. Returns early if any queue
+    // fails to dequeue, which means the worker is unhealthy.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), RuntimeError> {
+        let (tx, rx) = watch::channel(false);
+
+        let mut loops = JoinSet::new();
+        for queue_loop in self.queues {
+            loops.spawn(queue_loop(rx.clone()));
+        }
+
+        let res = tokio::select! {
+            _ = shutdown => Ok(()),
+            Some(res) = loops.join_next() => res.map_err(RuntimeError::from).and_then(|r| r),
+        };
+
+        info!("Shutting down, draining running jobs");
+        tx.send_replace(true);
+
+        let drain = async {
+            while let Some(res) = loops.join_next().await {
+                if let Err(e) = res.map_err(RuntimeError::from).and_then(|r| r) {
+                    error!("Queue loop failed while draining: {}", e);
+                }
+            }
+        };
+        if tokio::time::timeout(self.drain_timeout.to_std().unwrap_or_default(), drain)
+            .await
+            .is_err()
+        {
+            loops.abort_all();
+            let released = self.worker.release_all_jobs();
+            warn!(
+                "Timed out waiting for running jobs to finish, released {} jobs",
+                released
+            );
+        }
+
+        self.worker.force_flush().await?;
+
+        res
+    }
+}
+
+async fn queue_loop<H: Handler>(
+    worker: Arc<Worker>,
+    queue: String,
+    options: QueueOptions,
+    handler: Arc<H>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), RuntimeError> {
+    let labels = Arc::new(vec![(QUEUE_LABEL.to_string(), queue.clone())]);
+    let options = Arc::new(options);
+    let permits = Arc::new(Semaphore::new(options.concurrency));
+
+    while !*shutdown.borrow() {
+        let available = permits.available_permits();
+        common_metrics::gauge(AVAILABLE_PERMITS, &labels, available as f64);
+
+        let limit = min(available, options.batch_size);
+        let jobs = if limit == 0 {
+            Vec::new()
+        } else {
+            let _time = common_metrics::timing_guard(DEQUEUE_TIME, &labels);
+            if options.with_vm_state {
+                worker.dequeue_with_vm_state(&queue, limit).await?
+            } else {
+                worker.dequeue_jobs(&queue, limit).await?
+            }
+        };
+        common_metrics::inc(JOBS_DEQUEUED, &labels, jobs.len() as u64);
+
+        let started = jobs.len();
+        for job in jobs {
+            // We only dequeue as many jobs as we have permits, so this never blocks
+            let permit = permits.clone().acquire_owned().await.unwrap();
+            tokio::spawn(run_job(
+                worker.clone(),
+                handler.clone(),
+                options.clone(),
+                labels.clone(),
+                job,
+                permit,
+            ));
+        }
+
+        // If we're at capacity, we wait for jobs to finish, otherwise we wait to be told about new
+        // jobs, falling back to polling
+        if started == 0 {
+            let poll_interval = options.poll_interval.to_std().unwrap_or_default();
+            tokio::select! {
+                _ = shutdown.changed() => {}
+                _ = tokio::time::sleep(poll_interval), if available == 0 => {}
+                _ = worker.wait_for_jobs(&queue, options.poll_interval), if available > 0 => {}
+            }
+        }
+    }
+
+    // Every running job holds a permit until it's released, so once we have them all, we're drained
+    let _drained = permits.acquire_many(options.concurrency as u32).await;
+
+    Ok(())
+}
+
+async fn run_job<H: Handler>(
+    worker: Arc<Worker>,
+    handler: Arc<H>,
+    options: Arc<QueueOptions>,
+    labels: Arc<Vec<(String, String)>>,
+    job: Job,
+    _permit: OwnedSemaphorePermit,
+) {
+    let job_id = job.id;
+    // Errors here are the worker's, not the job's - most likely we lost the job's lock, in which
+    // case the janitor will hand it to someone else
+    if let Err(e) = process_job(&worker, handler.as_ref(), &options, &labels, job).await {
+        error!("Error running job {}: {}", job_id, e);
+        common_metrics::inc(JOB_ERRORS, &labels, 1);
+    }
+}
+
+async fn process_job<H: Handler>(
+    worker: &Arc<Worker>,
+    handler: &H,
+    options: &QueueOptions,
+    labels: &[(String, String)],
+    mut job: Job,
+) -> Result<(), RuntimeError> {
+    let job_total = common_metrics::timing_guard(JOB_TOTAL_TIME, labels);
+    let job_id = job.id;
+
+    let parameters = job.parameters.take();
+    let parameters = match serde_json::from_slice(parameters.as_deref().unwrap_or(b"null")) {
+        Ok(p) => p,
+        Err(e) => {
+            // This is a bug in whatever created the job, so retrying won't help
+            common_metrics::inc(JOBS_DEAD_LETTERED, labels, 1);
+            let reason = format!("Could not parse job parameters: {}", e);
+            worker.dead_letter(job_id, &reason).await?;
+            job_total.label(OUTCOME_LABEL, "dead_letter").fin();
+            return Ok(());
+        }
+    };
+
+    // Unlike bad parameters, bad metadata doesn't stop the job from running, it just runs without
+    let metadata = job.metadata.take();
+    let (metadata, bookkeeping) = match read_metadata(metadata.as_deref()) {
+        Ok(read) => read,
+        Err(e) => {
+            warn!("Could not parse metadata of job {}: {}", job_id, e);
+            common_metrics::inc(METADATA_PARSE_FAILURES, labels, 1);
+            Default::default()
+        }
+    };
+
+    let mut context = JobContext {
+        job,
+        parameters,
+        metadata,
+        tries: bookkeeping.tries,
+        worker: worker.clone(),
+    };
+
+    let result = {
+        let mut handle = handler.handle(&mut context);
+        loop {
+            tokio::select! {
+                res = &mut handle => break res,
+                _ = tokio::time::sleep(worker.heartbeat_window.to_std().unwrap_or_default()) => {
+                    worker.heartbeat(job_id).await?;
+                }
+            }
+        }
+    };
+
+    let outcome = match result {
+        Ok(Outcome::Complete) => {
+            worker.set_state(job_id, JobState::Completed)?;
+            worker.set_metadata(job_id, None)?;
+            "complete"
+        }
+        Ok(Outcome::Fail) => {
+            worker.set_state(job_id, JobState::Failed)?;
+            worker.set_metadata(job_id, None)?;
+            "fail"
+        }
+        Ok(Outcome::Forward(queue)) => {
+            worker.set_state(job_id, JobState::Available)?;
+            worker.set_queue(job_id, &queue)?;
+            worker.set_metadata(job_id, None)?;
+            "forward"
+        }
+        Ok(Outcome::RetryAt(scheduled)) => {
+            let metadata = StoredMetadata {
+                bookkeeping: Bookkeeping {
+                    tries: context.tries,
+                },
+                handler: &context.metadata,
+            };
+            worker.set_state(job_id, JobState::Available)?;
+            worker.set_scheduled_at(job_id, scheduled)?;
+            worker.set_metadata(job_id, Some(serde_json::to_vec(&metadata)?))?;
+            "retry_at"
+        }
+        Err(HandlerError::Retryable(e)) if context.tries + 1 < options.max_tries => {
+            warn!("Job {} failed, retrying: {}", job_id, e);
+            common_metrics::inc(JOBS_RETRIED, labels, 1);
+            let tries = context.tries + 1;
+            let metadata = StoredMetadata {
+                bookkeeping: Bookkeeping { tries },
+                handler: &context.metadata,
+            };
+            let backoff = min(
+                options.retry_backoff_base * (tries as i32),
+                options.max_retry_backoff,
+            );
+            // Add up to one more backoff base of jitter, so jobs that failed together don't all
+            // retry together
+            let jitter_ms = options.retry_backoff_base.num_milliseconds().max(1) as u64;
+            let jitter = Duration::milliseconds((rand::random::<u64>() % jitter_ms) as i64);
+
+            worker.set_state(job_id, JobState::Available)?;
+            worker.set_scheduled_at(job_id, Utc::now() + backoff + jitter)?;
+            worker.set_metadata(job_id, Some(serde_json::to_vec(&metadata)?))?;
+            "retry"
+        }
+        Err(HandlerError::Retryable(e)) => {
+            warn!("Job {} failed, out of retries: {}", job_id, e);
+            worker.set_state(job_id, JobState::Failed)?;
+            worker.set_metadata(job_id, None)?;
+            "retries_exhausted"
+        }
+        Err(HandlerError::Permanent(e)) => {
+            warn!("Job {} failed permanently: {}", job_id, e);
+            worker.set_state(job_id, JobState::Failed)?;
+            worker.set_metadata(job_id, None)?;
+            "permanent_failure"
+        }
+    };
+
+    // Since handlers are lightweight tasks, we just block waiting on the flush here
+    worker.release_job(job_id, None).await?;
+    job_total.label(OUTCOME_LABEL, outcome).fin();
+
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use cyclotron_core::{JobInit, JobState, QueueManager, Worker};
+use cyclotron_sdk::{Handler, HandlerError, JobContext, Outcome, QueueOptions, Runtime};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+const QUEUE: &str = "test";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Greeting {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Attempts {
+    seen: u32,
+}
+
+// Forwards greetings to the return queue, with a reply in their parameters
+struct Greeter;
+
+#[async_trait]
+impl Handler for Greeter {
+    type Parameters = Greeting;
+    type Metadata = ();
+
+    async fn handle(&self, job: &mut JobContext<Greeting, ()>) -> Result<Outcome, HandlerError> {
+        job.set_parameters(&format!("hello {}", job.parameters.name))?;
+        Ok(Outcome::Forward("return".to_string()))
+    }
+}
+
+// Always fails, checking its metadata is kept between tries
+struct Flaky {
+    calls: Arc<AtomicU32>,
+}
+
+#[async_trait]
+impl Handler for Flaky {
+    type Parameters = Option<Greeting>;
+    type Metadata = Attempts;
+
+    async fn handle(
+        &self,
+        job: &mut JobContext<Option<Greeting>, Attempts>,
+    ) -> Result<Outcome, HandlerError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(job.metadata.seen, job.tries);
+        job.metadata.seen += 1;
+        Err(HandlerError::Retryable("try again".to_string()))
+    }
+}
+
+// Completes every job, recording the metadata it was given
+struct Recorder {
+    seen: Arc<std::sync::Mutex<Vec<u32>>>,
+}
+
+#[async_trait]
+impl Handler for Recorder {
+    type Parameters = Option<Greeting>;
+    type Metadata = Attempts;
+
+    async fn handle(
+        &self,
+        job: &mut JobContext<Option<Greeting>, Attempts>,
+    ) -> Result<Outcome, HandlerError> {
+        self.seen.lock().unwrap().push(job.metadata.seen);
+        Ok(Outcome::Complete)
+    }
+}
+
+fn create_job(parameters: Option<Vec<u8>>) -> JobInit {
+    JobInit {
+        team_id: 1,
+        queue_name: QUEUE.to_string(),
+        priority: 0,
+        scheduled: Utc::now() - Duration::seconds(1),
+        function_id: None,
+        vm_state: None,
+        parameters,
+        blob: None,
+        metadata: None,
+        dependencies: Vec::new(),
+        on_parent_failure: Default::default(),
+        idempotency_key: None,
+    }
+}
+
+fn fast_options() -> QueueOptions {
+    QueueOptions {
+        retry_backoff_base: Duration::zero(),
+        poll_interval: Duration::milliseconds(10),
+        ..Default::default()
+    }
+}
+
+async fn get_state(db: &PgPool, id: Uuid) -> (JobState, String) {
+    sqlx::query_as("SELECT state, queue_name FROM cyclotron_jobs WHERE id = $1")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+async fn wait_for_state(db: &PgPool, id: Uuid, state: &str) -> String {
+    let start = Utc::now();
+    loop {
+        let found: Option<String> = sqlx::query_scalar(
+            "SELECT queue_name FROM cyclotron_jobs WHERE id = $1 AND state::text = $2",
+        )
+        .bind(id)
+        .bind(state)
+        .fetch_optional(db)
+        .await
+        .unwrap();
+        if let Some(queue) = found {
+            return queue;
+        }
+        if Utc::now() - start > Duration::seconds(5) {
+            panic!("Timed out waiting for job {} to be {}", id, state);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
+fn start(runtime: Runtime) -> (oneshot::Sender<()>, tokio::task::JoinHandle<()>) {
+    let (tx, rx) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move {
+        runtime
+            .run(async move {
+                rx.await.ok();
+            })
+            .await
+            .unwrap()
+    });
+    (tx, handle)
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+async fn test_runs_typed_handler(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let params = serde_json::to_vec(&Greeting {
+        name: "world".to_string(),
+    })
+    .unwrap();
+    let id = manager.create_job(create_job(Some(params))).await.unwrap();
+
+    let mut runtime = Runtime::new(Worker::from_pool(db.clone(), Default::default()));
+    runtime.register(QUEUE, fast_options(), Greeter);
+    let (shutdown, handle) = start(runtime);
+
+    assert_eq!(wait_for_state(&db, id, "available").await, "return");
+    shutdown.send(()).unwrap();
+    handle.await.unwrap();
+
+    let parameters: Vec<u8> =
+        sqlx::query_scalar("SELECT parameters FROM cyclotron_jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    let reply: String = serde_json::from_slice(&parameters).unwrap();
+    assert_eq!(reply, "hello world");
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+async fn test_retries_then_fails(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let id = manager.create_job(create_job(None)).await.unwrap();
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let mut runtime = Runtime::new(Worker::from_pool(db.clone(), Default::default()));
+    runtime.register(
+        QUEUE,
+        fast_options(),
+        Flaky {
+            calls: calls.clone(),
+        },
+    );
+    let (shutdown, handle) = start(runtime);
+
+    wait_for_state(&db, id, "failed").await;
+    shutdown.send(()).unwrap();
+    handle.await.unwrap();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    let metadata: Option<Vec<u8>> =
+        sqlx::query_scalar("SELECT metadata FROM cyclotron_jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert!(metadata.is_none());
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+async fn test_dead_letters_bad_parameters(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let id = manager
+        .create_job(create_job(Some(b"not json".to_vec())))
+        .await
+        .unwrap();
+
+    let mut runtime = Runtime::new(Worker::from_pool(db.clone(), Default::default()));
+    runtime.register(QUEUE, fast_options(), Greeter);
+    let (shutdown, handle) = start(runtime);
+
+    assert_eq!(
+        wait_for_state(&db, id, "available").await,
+        "_cyclotron_dead_letter"
+    );
+    shutdown.send(()).unwrap();
+    handle.await.unwrap();
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+async fn test_shutdown_stops_dequeuing(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+
+    let mut runtime = Runtime::new(Worker::from_pool(db.clone(), Default::default()));
+    runtime.register(QUEUE, fast_options(), Greeter);
+    let (shutdown, handle) = start(runtime);
+
+    shutdown.send(()).unwrap();
+    handle.await.unwrap();
+
+    let params = serde_json::to_vec(&Greeting {
+        name: "world".to_string(),
+    })
+    .unwrap();
+    let id = manager.create_job(create_job(Some(params))).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let (state, queue) = get_state(&db, id).await;
+    assert!(matches!(state, JobState::Available));
+    assert_eq!(queue, QUEUE);
+}
+
+#[sqlx::test(migrations = "../cyclotron-core/migrations")]
+async fn test_handler_gets_producer_metadata(db: PgPool) {
+    let manager = QueueManager::from_pool(db.clone());
+    let mut init = create_job(None);
+    init.metadata = Some(serde_json::to_vec(&Attempts { seen: 7 }).unwrap());
+    let set = manager.create_job(init).await.unwrap();
+    let mut init = create_job(None);
+    init.metadata = Some(b"not json".to_vec());
+    let bad = manager.create_job(init).await.unwrap();
+
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut runtime = Runtime::new(Worker::from_pool(db.clone(), Default::default()));
+    runtime.register(QUEUE, fast_options(), Recorder { seen: seen.clone() });
+    let (shutdown, handle) = start(runtime);
+
+    wait_for_state(&db, set, "completed").await;
+    // Bad metadata doesn't stop the job from running
+    wait_for_state(&db, bad, "completed").await;
+    shutdown.send(()).unwrap();
+    handle.await.unwrap();
+
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort();
+    assert_eq!(seen, vec![0, 7]);
+}
@@ -10,8 +10,8 @@ use crate::{
 use axum::extract::{MatchedPath, Query, State};
 use axum::http::{HeaderMap, Method};
 use axum::{debug_handler, Json};
-use axum_client_ip::InsecureClientIp;
 use bytes::Bytes;
+use common_client_ip::ClientIp;
 use tracing::instrument;
 
 /// Feature flag evaluation endpoint.
@@ -34,7 +34,7 @@ use tracing::instrument;
 #[debug_handler]
 pub async fn flags(
     state: State<router::State>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
     Query(query_params): Query<FlagsQueryParams>,
     headers: HeaderMap,
     method: Method,
@@ -1,6 +1,6 @@
 use crate::{
     api::{errors::FlagError, types::FlagsResponse},
-    client::{database::Client, geoip::GeoIpClient},
+    client::database::Client,
     cohort::cohort_cache_manager::CohortCacheManager,
     flags::{
         flag_matching::{FeatureFlagMatcher, GroupTypeMappingCache},
@@ -13,6 +13,7 @@ use crate::{
 use axum::{extract::State, http::HeaderMap};
 use base64::{engine::general_purpose, Engine as _};
 use bytes::Bytes;
+use common_geoip::GeoIpClient;
 use derive_builder::Builder;
 use flate2::read::GzDecoder;
 use serde::{Deserialize, Serialize};
@@ -375,7 +376,8 @@ mod tests {
 
     fn create_test_geoip_service() -> GeoIpClient {
         let config = Config::default_test_config();
-        GeoIpClient::new(&config).expect("Failed to create GeoIpService for testing")
+        GeoIpClient::new(config.get_maxmind_db_path())
+            .expect("Failed to create GeoIpService for testing")
     }
 
     #[test]
@@ -4,14 +4,17 @@ use axum::{
     http::{HeaderMap, Method},
     Json,
 };
-use axum_client_ip::InsecureClientIp;
 use bytes::Bytes;
+use common_client_ip::ClientIp;
 use tracing::error;
 
-use crate::api::{
-    errors::FlagError,
-    request_handler::{decode_request, FlagsQueryParams},
-    types::FlagsResponse,
+use crate::{
+    api::{
+        errors::FlagError,
+        request_handler::{decode_request, FlagsQueryParams},
+        types::FlagsResponse,
+    },
+    router,
 };
 
 // Metrics constants for test endpoint
@@ -22,9 +25,9 @@ pub const COMPRESSION_TYPE: &str = "flags_test_compression_type";
 pub const PARSING_FAILED: &str = "flags_test_parsing_failed";
 pub const TOKEN_VALIDATION: &str = "flags_test_token_validation";
 
-#[debug_handler]
+#[debug_handler(state = router::State)]
 pub async fn test_black_hole(
-    _ip: InsecureClientIp,
+    _ip: ClientIp,
     meta: Query<FlagsQueryParams>,
     headers: HeaderMap,
     _method: Method,
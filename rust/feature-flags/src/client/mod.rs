@@ -1,3 +1,2 @@
 pub mod database;
-pub mod geoip;
 pub mod redis;
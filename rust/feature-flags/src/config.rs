@@ -104,6 +104,12 @@ pub struct Config {
 
     #[envconfig(from = "CACHE_TTL_SECONDS", default = "300")]
     pub cache_ttl_seconds: u64,
+
+    #[envconfig(from = "TRUSTED_PROXIES", default = "")]
+    pub trusted_proxies: String,
+
+    #[envconfig(from = "CLIENT_IP_HEADERS", default = "x-forwarded-for")]
+    pub client_ip_headers: String,
 }
 
 impl Config {
@@ -122,6 +128,8 @@ impl Config {
             team_ids_to_track: TeamIdsToTrack::All,
             cache_max_cohort_entries: 100_000,
             cache_ttl_seconds: 300,
+            trusted_proxies: "".to_string(),
+            client_ip_headers: "x-forwarded-for".to_string(),
         }
     }
 
@@ -165,6 +173,8 @@ mod tests {
         assert_eq!(config.max_pg_connections, 10);
         assert_eq!(config.redis_url, "redis://localhost:6379/");
         assert_eq!(config.team_ids_to_track, TeamIdsToTrack::All);
+        assert_eq!(config.trusted_proxies, "");
+        assert_eq!(config.client_ip_headers, "x-forwarded-for");
     }
 
     #[test]
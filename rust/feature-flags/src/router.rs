@@ -1,10 +1,13 @@
 use std::{future::ready, sync::Arc};
 
 use axum::{
+    extract::FromRef,
     http::Method,
     routing::{get, post},
     Router,
 };
+use common_client_ip::TrustedProxies;
+use common_geoip::GeoIpClient;
 use common_metrics::{setup_metrics_recorder, track_metrics};
 use health::HealthRegistry;
 use tower::limit::ConcurrencyLimitLayer;
@@ -15,9 +18,7 @@ use tower_http::{
 
 use crate::{
     api::{endpoint, test_endpoint},
-    client::{
-        database::Client as DatabaseClient, geoip::GeoIpClient, redis::Client as RedisClient,
-    },
+    client::{database::Client as DatabaseClient, redis::Client as RedisClient},
     cohort::cohort_cache_manager::CohortCacheManager,
     config::{Config, TeamIdsToTrack},
     metrics::metrics_utils::team_id_label_filter,
@@ -31,6 +32,13 @@ pub struct State {
     pub cohort_cache_manager: Arc<CohortCacheManager>,
     pub geoip: Arc<GeoIpClient>,
     pub team_ids_to_track: TeamIdsToTrack,
+    pub trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl FromRef<State> for Arc<TrustedProxies> {
+    fn from_ref(state: &State) -> Self {
+        state.trusted_proxies.clone()
+    }
 }
 
 pub fn router<R, D>(
@@ -53,6 +61,10 @@ where
         cohort_cache_manager: cohort_cache,
         geoip,
         team_ids_to_track: config.team_ids_to_track.clone(),
+        trusted_proxies: Arc::new(
+            TrustedProxies::new(&config.trusted_proxies, &config.client_ip_headers)
+                .expect("invalid trusted proxy configuration"),
+        ),
     };
 
     // Very permissive CORS policy, as old SDK versions
@@ -3,11 +3,11 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
+use common_geoip::GeoIpClient;
 use health::{HealthHandle, HealthRegistry};
 use tokio::net::TcpListener;
 
 use crate::client::database::get_pool;
-use crate::client::geoip::GeoIpClient;
 use crate::client::redis::RedisClient;
 use crate::cohort::cohort_cache_manager::CohortCacheManager;
 use crate::config::Config;
@@ -62,7 +62,7 @@ where
             }
         };
 
-    let geoip_service = match GeoIpClient::new(&config) {
+    let geoip_service = match GeoIpClient::new(config.get_maxmind_db_path()) {
         Ok(service) => Arc::new(service),
         Err(e) => {
             tracing::error!("Failed to create GeoIP service: {}", e);
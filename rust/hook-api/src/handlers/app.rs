@@ -1,12 +1,17 @@
 use std::convert::Infallible;
+use std::sync::Arc;
 
 use axum::{extract::DefaultBodyLimit, routing, Router};
+use serde_json::Value;
 use tower::limit::ConcurrencyLimitLayer;
 
 use hook_common::pgqueue::PgQueue;
+use hook_common::queue::JobQueue;
+use hook_common::webhook::WebhookJobParameters;
 
-use super::webhook;
+use super::{dead_letter, webhook};
 
+/// Add the routes of an api enqueuing jobs in `pg_pool`, including the dead letter routes.
 pub fn add_routes(
     router: Router,
     pg_pool: PgQueue,
@@ -14,6 +19,38 @@ pub fn add_routes(
     max_body_size: usize,
     concurrency_limit: usize,
 ) -> Router {
+    // Failed jobs are only kept around if the janitor is configured to retain them.
+    let router = router
+        .route(
+            "/dead_letter",
+            routing::get(dead_letter::list_dead_letters).with_state(pg_pool.clone()),
+        )
+        .route(
+            "/dead_letter/replay",
+            routing::post(dead_letter::replay_dead_letters).with_state(pg_pool.clone()),
+        );
+
+    add_queue_routes(
+        router,
+        Arc::new(pg_pool),
+        hog_mode,
+        max_body_size,
+        concurrency_limit,
+    )
+}
+
+/// Add the routes of an api enqueuing jobs in any `JobQueue`. Dead letters are only served for
+/// `PgQueue`, see `add_routes`.
+pub fn add_queue_routes<Q>(
+    router: Router,
+    queue: Arc<Q>,
+    hog_mode: bool,
+    max_body_size: usize,
+    concurrency_limit: usize,
+) -> Router
+where
+    Q: JobQueue<WebhookJobParameters, Value> + 'static,
+{
     let router = router
         .route("/", routing::get(index))
         .route("/_readiness", routing::get(index))
@@ -22,16 +59,16 @@ pub fn add_routes(
     if hog_mode {
         router.route(
             "/hoghook",
-            routing::post(webhook::post_hoghook)
-                .with_state(pg_pool)
+            routing::post(webhook::post_hoghook::<Q>)
+                .with_state(queue)
                 .layer::<_, Infallible>(ConcurrencyLimitLayer::new(concurrency_limit))
                 .layer(DefaultBodyLimit::max(max_body_size)),
         )
     } else {
         router.route(
             "/webhook",
-            routing::post(webhook::post_webhook)
-                .with_state(pg_pool)
+            routing::post(webhook::post_webhook::<Q>)
+                .with_state(queue)
                 .layer::<_, Infallible>(ConcurrencyLimitLayer::new(concurrency_limit))
                 .layer(DefaultBodyLimit::max(max_body_size)),
         )
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use hook_common::pgqueue::{DeadLetterFilter, DeadLetterJob, PgQueue};
+use hook_common::webhook::{WebhookJobError, WebhookJobParameters};
+use serde::Serialize;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use tracing::error;
+use url::Url;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize, Debug)]
+pub struct DeadLetterQuery {
+    team_id: Option<i64>,
+    plugin_config_id: Option<i64>,
+    hog_function_id: Option<String>,
+    target: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeadLetterJobResponse {
+    id: i64,
+    attempt: i32,
+    max_attempts: i32,
+    created_at: DateTime<Utc>,
+    last_attempt_finished_at: Option<DateTime<Utc>>,
+    target: String,
+    parameters: WebhookJobParameters,
+    metadata: Value,
+    errors: Vec<WebhookJobError>,
+}
+
+impl From<DeadLetterJob<WebhookJobParameters, Value, WebhookJobError>> for DeadLetterJobResponse {
+    fn from(job: DeadLetterJob<WebhookJobParameters, Value, WebhookJobError>) -> Self {
+        Self {
+            id: job.id,
+            attempt: job.attempt,
+            max_attempts: job.max_attempts,
+            created_at: job.created_at,
+            last_attempt_finished_at: job.last_attempt_finished_at,
+            target: job.target,
+            parameters: job.parameters.0,
+            metadata: job.metadata.0,
+            errors: job.errors.into_iter().map(|e| e.0).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct DeadLetterResponse {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    jobs: Vec<DeadLetterJobResponse>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    replayed: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The body of a request to replay failed jobs. The URL and headers, if set, replace those of
+/// every job being replayed.
+#[derive(Deserialize, Debug)]
+pub struct ReplayRequestBody {
+    ids: Vec<i64>,
+    url: Option<String>,
+    headers: Option<HashMap<String, String>>,
+}
+
+pub async fn list_dead_letters(
+    State(pg_queue): State<PgQueue>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Result<Json<DeadLetterResponse>, (StatusCode, Json<DeadLetterResponse>)> {
+    let filter = DeadLetterFilter {
+        team_id: query.team_id,
+        plugin_config_id: query.plugin_config_id,
+        hog_function_id: query.hog_function_id,
+        target: query.target,
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+        offset: query.offset.unwrap_or(0).max(0),
+    };
+
+    let jobs = pg_queue
+        .list_failed::<WebhookJobParameters, Value, WebhookJobError>(&filter)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(DeadLetterResponse {
+        jobs: jobs.into_iter().map(Into::into).collect(),
+        ..Default::default()
+    }))
+}
+
+pub async fn replay_dead_letters(
+    State(pg_queue): State<PgQueue>,
+    Json(body): Json<ReplayRequestBody>,
+) -> Result<Json<DeadLetterResponse>, (StatusCode, Json<DeadLetterResponse>)> {
+    let mut overrides = serde_json::Map::new();
+    let target = match body.url {
+        Some(url) => {
+            let hostname = Url::parse(&url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_owned))
+                .ok_or_else(|| bad_request(format!("invalid url: {}", url)))?;
+            overrides.insert("url".to_owned(), Value::String(url));
+            Some(hostname)
+        }
+        None => None,
+    };
+    if let Some(headers) = body.headers {
+        overrides.insert(
+            "headers".to_owned(),
+            serde_json::to_value(headers).expect("headers are serializable"),
+        );
+    }
+
+    let replayed = pg_queue
+        .replay_failed(&body.ids, Value::Object(overrides), target.as_deref())
+        .await
+        .map_err(internal_error)?;
+
+    metrics::counter!("webhook_api_replayed_jobs").increment(replayed.len() as u64);
+
+    Ok(Json(DeadLetterResponse {
+        replayed,
+        ..Default::default()
+    }))
+}
+
+fn bad_request(msg: String) -> (StatusCode, Json<DeadLetterResponse>) {
+    error!(msg);
+    (
+        StatusCode::BAD_REQUEST,
+        Json(DeadLetterResponse {
+            error: Some(msg),
+            ..Default::default()
+        }),
+    )
+}
+
+fn internal_error<E>(err: E) -> (StatusCode, Json<DeadLetterResponse>)
+where
+    E: std::error::Error,
+{
+    error!("internal error: {}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(DeadLetterResponse {
+            error: Some(err.to_string()),
+            ..Default::default()
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+        Router,
+    };
+    use hook_common::pgqueue::{NewJob, PgQueue, PgQueueJob, PgTransactionBatch};
+    use hook_common::webhook::{HttpMethod, WebhookJobMetadata, WebhookJobParameters};
+    use http_body_util::BodyExt; // for `collect`
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use std::collections;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use hook_common::webhook::WebhookJobError;
+
+    use crate::handlers::app::add_routes;
+
+    async fn enqueue_and_fail(queue: &PgQueue, team_id: u32) {
+        let parameters = WebhookJobParameters {
+            body: "{}".to_owned(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            url: "http://broken.example.com".to_owned(),
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
+        };
+        let metadata = WebhookJobMetadata {
+            team_id,
+            plugin_id: 2,
+            plugin_config_id: 3,
+        };
+        queue
+            .enqueue(NewJob::new(1, metadata, parameters, "broken.example.com"))
+            .await
+            .expect("failed to enqueue job");
+
+        let mut batch: PgTransactionBatch<'_, WebhookJobParameters, Value> = queue
+            .dequeue_tx("worker_id", 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        batch
+            .jobs
+            .pop()
+            .unwrap()
+            .fail(WebhookJobError::new_timeout("timed out"))
+            .await
+            .expect("failed to fail job");
+        batch.commit().await.expect("failed to commit batch");
+    }
+
+    async fn json_body(response: axum::response::Response) -> Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn list_and_replay_dead_letters(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_dead_letters", db).await;
+        enqueue_and_fail(&pg_queue, 1).await;
+        enqueue_and_fail(&pg_queue, 2).await;
+
+        let app = add_routes(Router::new(), pg_queue.clone(), false, 1_000_000, 10);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/dead_letter?team_id=1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = json_body(response).await;
+        let jobs = body["jobs"].as_array().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0]["metadata"]["team_id"], 1);
+        assert_eq!(jobs[0]["errors"][0]["type"], "Timeout Error");
+        let id = jobs[0]["id"].as_i64().unwrap();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/dead_letter/replay")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({"ids": [id], "url": "http://fixed.example.com"})
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json_body(response).await["replayed"],
+            serde_json::json!([id])
+        );
+
+        let batch: PgTransactionBatch<'_, WebhookJobParameters, Value> = pg_queue
+            .dequeue_tx("worker_id", 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find the replayed job");
+        assert_eq!(batch.jobs[0].job.target, "fixed.example.com");
+        assert_eq!(batch.jobs[0].job.parameters.url, "http://fixed.example.com");
+    }
+}
@@ -1,4 +1,5 @@
 mod app;
+mod dead_letter;
 mod webhook;
 
-pub use app::add_routes;
+pub use app::{add_queue_routes, add_routes};
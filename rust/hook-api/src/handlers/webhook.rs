@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Instant;
 
 use axum::{body::Bytes, extract::State, http::StatusCode, Json};
@@ -7,8 +8,9 @@ use serde_derive::Deserialize;
 use serde_json::Value;
 use url::Url;
 
-use hook_common::pgqueue::{NewJob, PgQueue};
-use hook_common::webhook::HttpMethod;
+use hook_common::pgqueue::NewJob;
+use hook_common::queue::JobQueue;
+use hook_common::webhook::{HttpMethod, WebhookResponseValidation};
 use serde::Serialize;
 use tracing::{debug, error};
 
@@ -32,8 +34,8 @@ fn default_max_attempts() -> u32 {
     3
 }
 
-pub async fn post_webhook(
-    State(pg_queue): State<PgQueue>,
+pub async fn post_webhook<Q: JobQueue<WebhookJobParameters, Value>>(
+    State(queue): State<Arc<Q>>,
     body: Bytes,
 ) -> Result<Json<WebhookPostResponse>, (StatusCode, Json<WebhookPostResponse>)> {
     let payload: WebhookPostRequestBody = {
@@ -51,6 +53,7 @@ pub async fn post_webhook(
 
     debug!("received payload: {:?}", payload);
 
+    payload.parameters.check().map_err(bad_request)?;
     let url_hostname = get_hostname(&payload.parameters.url)?;
     // We could cast to i32, but this ensures we are not wrapping.
     let max_attempts = i32::try_from(payload.max_attempts).map_err(|_| {
@@ -61,16 +64,18 @@ pub async fn post_webhook(
             }),
         )
     })?;
+    // Queues only need the metadata to be JSON, which is how workers read it back.
+    let metadata = serde_json::to_value(payload.metadata).map_err(internal_error)?;
     let job = NewJob::new(
         max_attempts,
-        payload.metadata,
+        metadata,
         payload.parameters,
         url_hostname.as_str(),
     );
 
     let start_time = Instant::now();
 
-    pg_queue.enqueue(job).await.map_err(internal_error)?;
+    queue.enqueue(job).await.map_err(internal_error)?;
 
     let elapsed_time = start_time.elapsed().as_secs_f64();
     metrics::histogram!("webhook_api_enqueue").record(elapsed_time);
@@ -88,6 +93,11 @@ pub struct HoghookFetchParameters {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub method: Option<HttpMethod>,
+
+    /// Which responses are a success, and which failures are retried, see
+    /// `WebhookResponseValidation`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation: Option<WebhookResponseValidation>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -102,8 +112,8 @@ struct HoghookAsyncFunctionRequest {
     args: HoghookArgs,
 }
 
-pub async fn post_hoghook(
-    State(pg_queue): State<PgQueue>,
+pub async fn post_hoghook<Q: JobQueue<WebhookJobParameters, Value>>(
+    State(queue): State<Arc<Q>>,
     body: Bytes,
 ) -> Result<Json<WebhookPostResponse>, (StatusCode, Json<WebhookPostResponse>)> {
     let payload: Value = {
@@ -163,6 +173,10 @@ pub async fn post_hoghook(
             headers: fetch_options.headers.unwrap_or_default(),
             method: fetch_options.method.unwrap_or(HttpMethod::POST),
             url,
+            batch: None,
+            auth: None,
+            validation: fetch_options.validation,
+            template: None,
         }
     } else {
         WebhookJobParameters {
@@ -170,9 +184,14 @@ pub async fn post_hoghook(
             headers: HashMap::new(),
             method: HttpMethod::POST,
             url,
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
         }
     };
 
+    parameters.check().map_err(bad_request)?;
     let url_hostname = get_hostname(&parameters.url)?;
     let max_attempts = default_max_attempts() as i32;
 
@@ -180,7 +199,7 @@ pub async fn post_hoghook(
 
     let start_time = Instant::now();
 
-    pg_queue.enqueue(job).await.map_err(internal_error)?;
+    queue.enqueue(job).await.map_err(internal_error)?;
 
     let elapsed_time = start_time.elapsed().as_secs_f64();
     metrics::histogram!("webhook_api_enqueue").record(elapsed_time);
@@ -233,14 +252,18 @@ mod tests {
         http::{self, Request, StatusCode},
         Router,
     };
+    use hook_common::cyclotron::CyclotronQueue;
     use hook_common::pgqueue::PgQueue;
-    use hook_common::webhook::{HttpMethod, WebhookJobParameters};
+    use hook_common::queue::{JobBatch, QueueJob};
+    use hook_common::webhook::{
+        HttpMethod, WebhookBatchParameters, WebhookJobParameters, WebhookResponseValidation,
+    };
     use http_body_util::BodyExt;
     use sqlx::PgPool; // for `collect`
     use std::collections;
     use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
 
-    use crate::handlers::app::add_routes;
+    use crate::handlers::app::{add_queue_routes, add_routes};
 
     const MAX_BODY_SIZE: usize = 1_000_000;
     const CONCURRENCY_LIMIT: usize = 10;
@@ -273,6 +296,10 @@ mod tests {
                                 method: HttpMethod::POST,
                                 url: "http://example.com/".to_owned(),
                                 body: r#"{"a": "b"}"#.to_owned(),
+                                batch: None,
+                                auth: None,
+                                validation: None,
+                                template: None,
                             },
                             metadata: WebhookJobMetadata {
                                 team_id: 1,
@@ -294,6 +321,70 @@ mod tests {
         assert_eq!(&body[..], b"{}");
     }
 
+    #[sqlx::test(migrations = "../cyclotron-core/migrations")]
+    async fn webhook_success_cyclotron(db: PgPool) {
+        let queue = Arc::new(CyclotronQueue::from_pool("hooks", db, Default::default()));
+        let hog_mode = false;
+
+        let app = add_queue_routes(
+            Router::new(),
+            queue.clone(),
+            hog_mode,
+            MAX_BODY_SIZE,
+            CONCURRENCY_LIMIT,
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/webhook")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&WebhookPostRequestBody {
+                            parameters: WebhookJobParameters {
+                                headers: collections::HashMap::new(),
+                                method: HttpMethod::POST,
+                                url: "http://example.com/".to_owned(),
+                                body: r#"{"a": "b"}"#.to_owned(),
+                                batch: None,
+                                auth: None,
+                                validation: None,
+                                template: None,
+                            },
+                            metadata: WebhookJobMetadata {
+                                team_id: 1,
+                                plugin_id: 2,
+                                plugin_config_id: 3,
+                            },
+                            max_attempts: 1,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The job is in the format a hook-worker with a cyclotron backend consumes.
+        let mut batch =
+            JobQueue::<WebhookJobParameters, Value>::dequeue(queue.as_ref(), "worker", 10)
+                .await
+                .unwrap()
+                .expect("no job enqueued");
+        let mut job = batch.take_jobs().pop().unwrap();
+        assert_eq!(job.parameters().url, "http://example.com/");
+        assert_eq!(job.parameters().body, r#"{"a": "b"}"#);
+        assert_eq!(job.max_attempts(), 1);
+        assert_eq!(job.target(), "example.com");
+        assert_eq!(
+            job.take_metadata(),
+            serde_json::json!({"team_id": 1, "plugin_id": 2, "plugin_config_id": 3})
+        );
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn webhook_bad_url(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
@@ -320,6 +411,62 @@ mod tests {
                                 method: HttpMethod::POST,
                                 url: "invalid".to_owned(),
                                 body: r#"{"a": "b"}"#.to_owned(),
+                                batch: None,
+                                auth: None,
+                                validation: None,
+                                template: None,
+                            },
+                            metadata: WebhookJobMetadata {
+                                team_id: 1,
+                                plugin_id: 2,
+                                plugin_config_id: 3,
+                            },
+                            max_attempts: 1,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_batch_with_validation(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+        let hog_mode = false;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            hog_mode,
+            MAX_BODY_SIZE,
+            CONCURRENCY_LIMIT,
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/webhook")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&WebhookPostRequestBody {
+                            parameters: WebhookJobParameters {
+                                headers: collections::HashMap::new(),
+                                method: HttpMethod::POST,
+                                url: "http://example.com/".to_owned(),
+                                body: r#"{"a": "b"}"#.to_owned(),
+                                batch: Some(WebhookBatchParameters {
+                                    key: "key".to_owned(),
+                                    max_size: 10,
+                                    linger_ms: 0,
+                                }),
+                                auth: None,
+                                validation: Some(WebhookResponseValidation::default()),
+                                template: None,
                             },
                             metadata: WebhookJobMetadata {
                                 team_id: 1,
@@ -423,6 +570,10 @@ mod tests {
                                 method: HttpMethod::POST,
                                 url: "http://example.com".to_owned(),
                                 body: long_string.to_string(),
+                                batch: None,
+                                auth: None,
+                                validation: None,
+                                template: None,
                             },
                             metadata: WebhookJobMetadata {
                                 team_id: 1,
@@ -487,6 +638,10 @@ mod tests {
                 r#"{"asyncFunctionRequest":{"name":"fetch","args":["http://example.com/\\u0000", {"method": "GET", "body": "\\u0000", "headers": {"k": "v"}}]}, "otherField": true, "teamId": 1, "hogFunctionId": "abc"}"#,
                 r#"{"body": "\\uFFFD", "headers": {"k": "v"}, "method": "GET", "url": "http://example.com/\\uFFFD"}"#,
             ),
+            (
+                r#"{"asyncFunctionRequest":{"name":"fetch","args":["http://example.com", {"validation": {"retry_statuses": ["500-599"], "assertions": [{"path": "$.ok", "equals": true, "retryable": true}]}}]}, "teamId": 1, "hogFunctionId": "abc"}"#,
+                r#"{"body": "", "headers": {}, "method": "POST", "url": "http://example.com", "validation": {"retry_statuses": ["500-599"], "assertions": [{"path": "$.ok", "equals": true, "retryable": true}]}}"#,
+            ),
         ];
 
         for (payload, expected_parameters) in valid_payloads {
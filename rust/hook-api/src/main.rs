@@ -1,10 +1,15 @@
+use std::sync::Arc;
+
 use axum::Router;
 use config::Config;
 use envconfig::Envconfig;
 use eyre::Result;
 
 use common_metrics::setup_metrics_routes;
+use hook_common::cyclotron::CyclotronQueue;
 use hook_common::pgqueue::PgQueue;
+use hook_common::queue::QueueBackend;
+use sqlx::postgres::PgPoolOptions;
 
 mod config;
 mod handlers;
@@ -25,24 +30,48 @@ async fn main() {
 
     let config = Config::init_from_env().expect("failed to load configuration from env");
 
-    let pg_queue = PgQueue::new(
-        // TODO: Coupling the queue name to the PgQueue object doesn't seem ideal from the api
-        // side, but we don't need more than one queue for now.
-        &config.queue_name,
-        &config.database_url,
-        config.max_pg_connections,
-        "hook-api",
-    )
-    .await
-    .expect("failed to initialize queue");
-
-    let app = handlers::add_routes(
-        Router::new(),
-        pg_queue,
-        config.hog_mode,
-        config.max_body_size,
-        config.concurrency_limit,
-    );
+    let app = match config.queue_backend {
+        QueueBackend::Pg => {
+            let pg_queue = PgQueue::new(
+                // TODO: Coupling the queue name to the PgQueue object doesn't seem ideal from the
+                // api side, but we don't need more than one queue for now.
+                &config.queue_name,
+                &config.database_url,
+                config.max_pg_connections,
+                "hook-api",
+            )
+            .await
+            .expect("failed to initialize queue");
+
+            handlers::add_routes(
+                Router::new(),
+                pg_queue,
+                config.hog_mode,
+                config.max_body_size,
+                config.concurrency_limit,
+            )
+        }
+        QueueBackend::Cyclotron => {
+            let database_url = config
+                .cyclotron_database_url
+                .as_deref()
+                .unwrap_or(&config.database_url);
+            let pool = PgPoolOptions::new()
+                .max_connections(config.max_pg_connections)
+                .connect_lazy(database_url)
+                .expect("failed to initialize cyclotron pool");
+            let queue =
+                CyclotronQueue::from_pool(&config.cyclotron_queue_name, pool, Default::default());
+
+            handlers::add_queue_routes(
+                Router::new(),
+                Arc::new(queue),
+                config.hog_mode,
+                config.max_body_size,
+                config.concurrency_limit,
+            )
+        }
+    };
     let app = setup_metrics_routes(app);
 
     match listen(app, config.bind()).await {
@@ -0,0 +1,590 @@
+//! # Cyclotron
+//!
+//! A `JobQueue` backed by a cyclotron queue, for workers written against `PgQueue`.
+//!
+//! Job parameters are stored as JSON in the cyclotron job's parameters, and the fields that
+//! cyclotron has no column for (metadata, attempts, target and errors) as JSON in its metadata.
+//! Jobs in this format are enqueued by hook-api with its cyclotron backend. Cyclotron queues are
+//! shared by every worker consuming them, so they must not be put in a queue that is also consumed
+//! by a worker expecting another format, like cyclotron-fetch. Jobs in a format we can't parse are
+//! put back a while later rather than failed.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cyclotron_core::{Bytes, FlushHandle, JobInit, JobState, QueueManager, Worker, WorkerConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::pgqueue::{DatabaseError, NewJob, RetryError, RetryInvalidError};
+use crate::queue::{JobBatch, JobQueue, QueueJob};
+
+/// How long jobs in a format we can't parse are put back for.
+const UNPARSEABLE_JOB_DELAY: time::Duration = time::Duration::from_secs(60);
+
+/// The part of a job stored in a cyclotron job's metadata.
+#[derive(Debug, Serialize, Deserialize)]
+struct HookJobState {
+    metadata: Value,
+    attempt: i32,
+    max_attempts: i32,
+    target: String,
+    #[serde(default)]
+    errors: Vec<Value>,
+}
+
+/// The update a job transitioned to, applied when its batch is committed.
+struct Transition {
+    state: JobState,
+    metadata: Bytes,
+    scheduled: Option<DateTime<Utc>>,
+    queue: Option<String>,
+}
+
+type Transitions = Arc<Mutex<HashMap<Uuid, Transition>>>;
+
+fn cyclotron_error(error: impl Into<cyclotron_core::QueueError>) -> DatabaseError {
+    DatabaseError::CyclotronError {
+        error: error.into(),
+    }
+}
+
+/// A queue of jobs in the cyclotron queue `name`.
+pub struct CyclotronQueue {
+    name: String,
+    team_id: i32,
+    manager: QueueManager,
+    worker: Arc<Worker>,
+}
+
+impl CyclotronQueue {
+    /// Initialize a `CyclotronQueue` on a single cyclotron shard.
+    pub fn from_pool(name: &str, pool: PgPool, worker_config: WorkerConfig) -> Self {
+        Self {
+            name: name.to_owned(),
+            team_id: 0,
+            manager: QueueManager::from_pool(pool.clone()),
+            worker: Arc::new(Worker::from_pool(pool, worker_config)),
+        }
+    }
+
+    /// Jobs have no team in `PgQueue`, so all jobs enqueued are for `team_id`, 0 by default.
+    pub fn team_id(mut self, team_id: i32) -> Self {
+        self.team_id = team_id;
+        self
+    }
+}
+
+#[async_trait]
+impl<J, M> JobQueue<J, M> for CyclotronQueue
+where
+    J: Serialize + for<'d> Deserialize<'d> + std::marker::Send + std::marker::Sync + 'static,
+    M: Serialize
+        + for<'d> Deserialize<'d>
+        + Default
+        + std::marker::Send
+        + std::marker::Sync
+        + 'static,
+{
+    type Batch = CyclotronBatch<J, M>;
+
+    async fn enqueue(&self, job: NewJob<J, M>) -> Result<(), DatabaseError> {
+        let parameters = serde_json::to_vec(&job.parameters.0)
+            .map_err(|error| DatabaseError::SerializationError { error })?;
+        let metadata = serde_json::to_value(&job.metadata.0)
+            .map_err(|error| DatabaseError::SerializationError { error })?;
+        let state = HookJobState {
+            metadata,
+            attempt: 0,
+            max_attempts: job.max_attempts,
+            target: job.target,
+            errors: Vec::new(),
+        };
+        let metadata = serde_json::to_vec(&state)
+            .map_err(|error| DatabaseError::SerializationError { error })?;
+
+        let init = JobInit {
+            team_id: self.team_id,
+            queue_name: self.name.clone(),
+            priority: 0,
+            scheduled: Utc::now(),
+            function_id: None,
+            vm_state: None,
+            parameters: Some(parameters),
+            blob: None,
+            metadata: Some(metadata),
+            dependencies: Vec::new(),
+            on_parent_failure: Default::default(),
+            idempotency_key: None,
+        };
+
+        self.manager
+            .create_job(init)
+            .await
+            .map_err(cyclotron_error)?;
+
+        Ok(())
+    }
+
+    async fn dequeue(
+        &self,
+        _attempted_by: &str,
+        limit: u32,
+    ) -> Result<Option<Self::Batch>, DatabaseError> {
+        let jobs = self
+            .worker
+            .dequeue_jobs(&self.name, limit as usize)
+            .await
+            .map_err(cyclotron_error)?;
+
+        let transitions = Transitions::default();
+        let mut batch = CyclotronBatch {
+            jobs: Vec::with_capacity(jobs.len()),
+            dequeued: Vec::with_capacity(jobs.len()),
+            worker: self.worker.clone(),
+            transitions: transitions.clone(),
+        };
+
+        for job in jobs {
+            let parsed = parse_job(job.parameters.as_deref(), job.metadata.as_deref());
+            let (parameters, metadata, mut state) = match parsed {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    // Jobs we can't parse were put in our queue by something that doesn't know
+                    // our format, most likely a producer sharing the queue by mistake. They're
+                    // not ours to fail, but are pushed back so we don't keep dequeuing them. The
+                    // release is flushed in the background.
+                    warn!("putting back unparseable job {}: {}", job.id, error);
+                    let scheduled = Utc::now() + UNPARSEABLE_JOB_DELAY;
+                    self.worker
+                        .set_state(job.id, JobState::Available)
+                        .and_then(|_| self.worker.set_scheduled_at(job.id, scheduled))
+                        .map_err(cyclotron_error)?;
+                    drop(self.worker.release_job(job.id, None));
+                    continue;
+                }
+            };
+
+            // Attempts are only counted when a job is released. A job lost with its worker is
+            // reset by the cyclotron janitor and keeps its attempt, like a rolled back `PgQueue`
+            // transaction.
+            state.attempt += 1;
+
+            batch.dequeued.push((job.id, job.metadata));
+            batch.jobs.push(CyclotronJob {
+                id: job.id,
+                created_at: job.created,
+                queue: job.queue_name,
+                parameters,
+                metadata,
+                state,
+                transitions: transitions.clone(),
+            });
+        }
+
+        if batch.jobs.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(batch))
+    }
+
+    /// Batches dropped without being committed are released by the worker in the background.
+    async fn flush(&self) -> Result<(), DatabaseError> {
+        self.worker.force_flush().await.map_err(cyclotron_error)
+    }
+
+    /// Anything still working on a released job gets errors trying to update it, and gives up.
+    /// The releases are written by `flush`.
+    async fn release_all(&self) -> Result<usize, DatabaseError> {
+        Ok(self.worker.release_all_jobs())
+    }
+}
+
+fn parse_job<J, M>(
+    parameters: Option<&[u8]>,
+    metadata: Option<&[u8]>,
+) -> Result<(J, M, HookJobState), serde_json::Error>
+where
+    J: for<'d> Deserialize<'d>,
+    M: for<'d> Deserialize<'d>,
+{
+    let parameters = serde_json::from_slice(parameters.unwrap_or(b"null"))?;
+    let state: HookJobState = serde_json::from_slice(metadata.unwrap_or(b"null"))?;
+    let metadata = M::deserialize(&state.metadata)?;
+
+    Ok((parameters, metadata, state))
+}
+
+/// A job dequeued from a `CyclotronQueue`.
+pub struct CyclotronJob<J, M> {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    queue: String,
+    parameters: J,
+    metadata: M,
+    /// Keeps the metadata as it was stored, as `metadata` may be taken by the worker.
+    state: HookJobState,
+    transitions: Transitions,
+}
+
+impl<J, M> CyclotronJob<J, M> {
+    fn transition(
+        self,
+        state: JobState,
+        scheduled: Option<DateTime<Utc>>,
+        queue: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let metadata = serde_json::to_vec(&self.state)
+            .map_err(|error| DatabaseError::SerializationError { error })?;
+
+        self.transitions
+            .lock()
+            .expect("poisoned transitions lock")
+            .insert(
+                self.id,
+                Transition {
+                    state,
+                    metadata,
+                    scheduled,
+                    queue: queue.map(str::to_owned),
+                },
+            );
+
+        Ok(())
+    }
+
+    fn push_error<E: Serialize>(&mut self, error: E) -> Result<(), DatabaseError> {
+        let error = serde_json::to_value(error)
+            .map_err(|error| DatabaseError::SerializationError { error })?;
+        self.state.errors.push(error);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<J, M> QueueJob for CyclotronJob<J, M>
+where
+    J: std::marker::Send,
+    M: Default + std::marker::Send,
+{
+    type Parameters = J;
+    type Metadata = M;
+
+    fn parameters(&self) -> &J {
+        &self.parameters
+    }
+
+    fn take_metadata(&mut self) -> M {
+        std::mem::take(&mut self.metadata)
+    }
+
+    fn attempt(&self) -> i32 {
+        self.state.attempt
+    }
+
+    fn max_attempts(&self) -> i32 {
+        self.state.max_attempts
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn queue(&self) -> String {
+        self.queue.to_owned()
+    }
+
+    fn target(&self) -> String {
+        self.state.target.to_owned()
+    }
+
+    async fn complete(self) -> Result<(), DatabaseError> {
+        self.transition(JobState::Completed, None, None)
+    }
+
+    async fn fail<E: Serialize + std::marker::Sync + std::marker::Send>(
+        mut self,
+        error: E,
+    ) -> Result<(), DatabaseError> {
+        self.push_error(error)?;
+        self.transition(JobState::Failed, None, None)
+    }
+
+    async fn retry<E: Serialize + std::marker::Sync + std::marker::Send>(
+        mut self,
+        error: E,
+        retry_interval: time::Duration,
+        queue: &str,
+    ) -> Result<(), RetryError<Box<Self>>> {
+        if self.state.attempt >= self.state.max_attempts {
+            return Err(RetryError::from(RetryInvalidError {
+                job: Box::new(self),
+                error: "Maximum attempts reached".to_owned(),
+            }));
+        }
+
+        self.push_error(error)?;
+        let scheduled = Utc::now() + retry_interval;
+        self.transition(JobState::Available, Some(scheduled), Some(queue))?;
+
+        Ok(())
+    }
+
+    async fn defer(mut self, interval: time::Duration) -> Result<(), DatabaseError> {
+        // Deferring doesn't use up an attempt.
+        self.state.attempt -= 1;
+        let scheduled = Utc::now() + interval;
+        self.transition(JobState::Available, Some(scheduled), None)
+    }
+}
+
+/// The jobs from one `CyclotronQueue` dequeue. Like a `PgTransactionBatch`, job transitions are
+/// only written when the batch is committed: jobs of a batch dropped before then are put back in
+/// the queue as they were dequeued.
+pub struct CyclotronBatch<J, M> {
+    jobs: Vec<CyclotronJob<J, M>>,
+    /// The id and stored metadata of every job in this batch, to release them with.
+    dequeued: Vec<(Uuid, Option<Bytes>)>,
+    worker: Arc<Worker>,
+    transitions: Transitions,
+}
+
+impl<J, M> CyclotronBatch<J, M> {
+    /// Release every job in the batch with its transition, or as it was dequeued if it has none
+    /// or the transitions are rolled back.
+    fn release_jobs(&mut self, rollback: bool) -> Vec<FlushHandle> {
+        let mut transitions = self.transitions.lock().expect("poisoned transitions lock");
+        if rollback {
+            transitions.clear();
+        }
+        let worker = &self.worker;
+
+        self.dequeued
+            .drain(..)
+            .map(|(id, metadata)| {
+                let updated = match transitions.remove(&id) {
+                    Some(transition) => worker
+                        .set_state(id, transition.state)
+                        .and_then(|_| worker.set_metadata(id, Some(transition.metadata)))
+                        .and_then(|_| match transition.scheduled {
+                            Some(scheduled) => worker.set_scheduled_at(id, scheduled),
+                            None => Ok(()),
+                        })
+                        .and_then(|_| match transition.queue {
+                            Some(queue) => worker.set_queue(id, &queue),
+                            None => Ok(()),
+                        }),
+                    None => worker
+                        .set_state(id, JobState::Available)
+                        .and_then(|_| worker.set_metadata(id, metadata)),
+                };
+
+                match updated {
+                    Ok(()) => worker.release_job(id, None),
+                    Err(error) => FlushHandle::immediate(Err(error)),
+                }
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl<J, M> JobBatch for CyclotronBatch<J, M>
+where
+    J: std::marker::Send,
+    M: Default + std::marker::Send,
+{
+    type Job = CyclotronJob<J, M>;
+
+    fn take_jobs(&mut self) -> Vec<Self::Job> {
+        std::mem::take(&mut self.jobs)
+    }
+
+    async fn commit(mut self) -> Result<(), DatabaseError> {
+        let handles = self.release_jobs(false);
+        self.worker.force_flush().await.map_err(cyclotron_error)?;
+
+        for handle in handles {
+            handle.await.map_err(cyclotron_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<J, M> Drop for CyclotronBatch<J, M> {
+    fn drop(&mut self) {
+        // The updates are flushed by the worker in the background.
+        drop(self.release_jobs(true));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Parameters {
+        url: String,
+    }
+
+    fn queue(pool: PgPool) -> CyclotronQueue {
+        CyclotronQueue::from_pool("hooks", pool, WorkerConfig::default()).team_id(1)
+    }
+
+    async fn enqueue(queue: &CyclotronQueue, max_attempts: i32) {
+        let parameters = Parameters {
+            url: "https://example.com".to_owned(),
+        };
+        let job = NewJob::new(
+            max_attempts,
+            serde_json::json!({"team_id": 1}),
+            parameters,
+            "example.com",
+        );
+        JobQueue::enqueue(queue, job)
+            .await
+            .expect("failed to enqueue");
+    }
+
+    async fn dequeue(queue: &CyclotronQueue) -> Option<CyclotronBatch<Parameters, Value>> {
+        <CyclotronQueue as JobQueue<Parameters, Value>>::dequeue(queue, "worker", 10)
+            .await
+            .expect("failed to dequeue")
+    }
+
+    #[sqlx::test(migrations = "../cyclotron-core/migrations")]
+    async fn test_can_dequeue_and_complete_job(db: PgPool) {
+        let queue = queue(db);
+        enqueue(&queue, 1).await;
+
+        let mut batch = dequeue(&queue).await.expect("no job dequeued");
+        let mut jobs = batch.take_jobs();
+        assert_eq!(jobs.len(), 1);
+
+        let mut job = jobs.pop().unwrap();
+        assert_eq!(job.parameters().url, "https://example.com");
+        assert_eq!(job.take_metadata(), serde_json::json!({"team_id": 1}));
+        assert_eq!(job.attempt(), 1);
+        assert_eq!(job.max_attempts(), 1);
+        assert_eq!(job.target(), "example.com");
+        assert_eq!(job.queue(), "hooks");
+
+        job.complete().await.expect("failed to complete");
+        batch.commit().await.expect("failed to commit");
+
+        assert!(dequeue(&queue).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "../cyclotron-core/migrations")]
+    async fn test_retried_jobs_keep_their_state(db: PgPool) {
+        let queue = queue(db);
+        enqueue(&queue, 2).await;
+
+        let mut batch = dequeue(&queue).await.expect("no job dequeued");
+        let mut job = batch.take_jobs().pop().unwrap();
+        // Taking the metadata doesn't lose it for the next attempt.
+        job.take_metadata();
+        let retried = job.retry("a reason", time::Duration::ZERO, "hooks").await;
+        assert!(retried.is_ok(), "failed to retry");
+        batch.commit().await.expect("failed to commit");
+
+        let mut batch = dequeue(&queue).await.expect("no job dequeued");
+        let mut job = batch.take_jobs().pop().unwrap();
+        assert_eq!(job.attempt(), 2);
+        assert_eq!(job.take_metadata(), serde_json::json!({"team_id": 1}));
+        assert_eq!(job.state.errors, vec![serde_json::json!("a reason")]);
+
+        // Out of attempts: the job is given back to be failed.
+        let job = match job.retry("again", time::Duration::ZERO, "hooks").await {
+            Err(RetryError::RetryInvalidError(invalid)) => *invalid.job,
+            _ => panic!("retry past max attempts should be invalid"),
+        };
+        job.fail("failed").await.expect("failed to fail");
+        batch.commit().await.expect("failed to commit");
+
+        assert!(dequeue(&queue).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "../cyclotron-core/migrations")]
+    async fn test_unparseable_jobs_are_put_back(db: PgPool) {
+        let queue = queue(db.clone());
+        let init = JobInit {
+            team_id: 1,
+            queue_name: "hooks".to_owned(),
+            priority: 0,
+            scheduled: Utc::now(),
+            function_id: None,
+            vm_state: None,
+            parameters: Some(b"not json".to_vec()),
+            blob: None,
+            metadata: None,
+            dependencies: Vec::new(),
+            on_parent_failure: Default::default(),
+            idempotency_key: None,
+        };
+        let id = queue.manager.create_job(init).await.unwrap();
+
+        assert!(dequeue(&queue).await.is_none());
+        queue.worker.force_flush().await.expect("failed to flush");
+
+        // Still in the queue, but pushed back rather than dequeued again right away
+        assert!(queue
+            .worker
+            .dequeue_jobs("hooks", 10)
+            .await
+            .unwrap()
+            .is_empty());
+        let (state, scheduled): (String, DateTime<Utc>) =
+            sqlx::query_as("SELECT state::text, scheduled FROM cyclotron_jobs WHERE id = $1")
+                .bind(id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(state, "available");
+        assert!(scheduled > Utc::now() + time::Duration::from_secs(30));
+    }
+
+    #[sqlx::test(migrations = "../cyclotron-core/migrations")]
+    async fn test_release_all_puts_jobs_in_flight_back(db: PgPool) {
+        let queue = queue(db);
+        enqueue(&queue, 1).await;
+
+        let mut batch = dequeue(&queue).await.expect("no job dequeued");
+        let _job = batch.take_jobs().pop().unwrap();
+        let released = <CyclotronQueue as JobQueue<Parameters, Value>>::release_all(&queue)
+            .await
+            .expect("failed to release");
+        assert_eq!(released, 1);
+        <CyclotronQueue as JobQueue<Parameters, Value>>::flush(&queue)
+            .await
+            .expect("failed to flush");
+
+        // Available again, without waiting for the janitor
+        let mut batch = dequeue(&queue).await.expect("released job not put back");
+        assert_eq!(batch.take_jobs().len(), 1);
+    }
+
+    #[sqlx::test(migrations = "../cyclotron-core/migrations")]
+    async fn test_dropped_batches_are_rolled_back(db: PgPool) {
+        let queue = queue(db);
+        enqueue(&queue, 1).await;
+
+        let mut batch = dequeue(&queue).await.expect("no job dequeued");
+        let job = batch.take_jobs().pop().unwrap();
+        job.complete().await.expect("failed to complete");
+        drop(batch);
+        queue.worker.force_flush().await.expect("failed to flush");
+
+        let mut batch = dequeue(&queue).await.expect("dropped job not put back");
+        // The attempt of a batch that was never committed doesn't count.
+        assert_eq!(batch.take_jobs().pop().unwrap().attempt(), 1);
+    }
+}
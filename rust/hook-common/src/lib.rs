@@ -1,3 +1,5 @@
+pub mod cyclotron;
 pub mod pgqueue;
+pub mod queue;
 pub mod retry;
 pub mod webhook;
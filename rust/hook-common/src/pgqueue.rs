@@ -20,6 +20,8 @@ pub enum ParseError {
     ParseJobStatusError(String),
     #[error("{0} is not a valid HttpMethod")]
     ParseHttpMethodError(String),
+    #[error("{0} is not a valid status range")]
+    ParseStatusRangeError(String),
     #[error("transaction was already closed")]
     TransactionAlreadyClosedError,
 }
@@ -40,6 +42,8 @@ pub enum DatabaseError {
     TransactionError { command: String, error: sqlx::Error },
     #[error("transaction was already closed")]
     TransactionAlreadyClosedError,
+    #[error("cyclotron operation failed with: {error}")]
+    CyclotronError { error: cyclotron_core::QueueError },
 }
 
 /// An error that occurs when a job cannot be retried.
@@ -176,6 +180,49 @@ RETURNING
         })
     }
 
+    /// Consume `Job` to put it back in the queue without counting this as an attempt.
+    /// Used when the worker chose not to run a job at all, e.g. because its target is saturated.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval`: The duration until the `Job` is available again. Used to set `scheduled_at`.
+    /// * `executor`: Any sqlx::Executor that can execute the UPDATE query required to mark this `Job` as available.
+    async fn defer<'c, E>(
+        self,
+        interval: time::Duration,
+        executor: E,
+    ) -> Result<RetriedJob, sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+    {
+        let base_query = r#"
+UPDATE
+    job_queue
+SET
+    status = 'available'::job_status,
+    scheduled_at = NOW() + $3,
+    attempt = attempt - 1
+WHERE
+    queue = $1
+    AND id = $2
+RETURNING
+    job_queue.*
+        "#;
+
+        sqlx::query(base_query)
+            .bind(&self.queue)
+            .bind(self.id)
+            .bind(interval)
+            .execute(executor)
+            .await?;
+
+        Ok(RetriedJob {
+            id: self.id,
+            queue: self.queue,
+            retry_queue: None,
+        })
+    }
+
     /// Consume `Job` to fail it.
     /// A `FailedJob` is finalized and cannot be used further; it is returned for reporting or inspection.
     ///
@@ -233,6 +280,8 @@ pub trait PgQueueJob {
         retry_interval: time::Duration,
         queue: &str,
     ) -> Result<RetriedJob, RetryError<Box<Self>>>;
+
+    async fn defer(mut self, interval: time::Duration) -> Result<RetriedJob, DatabaseError>;
 }
 
 /// A Job within an open PostgreSQL transaction.
@@ -352,6 +401,25 @@ impl<'c, J: std::marker::Send, M: std::marker::Send> PgQueueJob for PgTransactio
 
         Ok(retried_job)
     }
+
+    async fn defer(mut self, interval: time::Duration) -> Result<RetriedJob, DatabaseError> {
+        let mut txn_guard = self.shared_txn.lock().await;
+
+        let txn_ref = txn_guard
+            .as_deref_mut()
+            .ok_or(DatabaseError::TransactionAlreadyClosedError)?;
+
+        let deferred_job =
+            self.job
+                .defer(interval, txn_ref)
+                .await
+                .map_err(|error| DatabaseError::QueryError {
+                    command: "UPDATE".to_owned(),
+                    error,
+                })?;
+
+        Ok(deferred_job)
+    }
 }
 
 /// A Job that has failed but can still be enqueued into a PgQueue to be retried at a later point.
@@ -653,6 +721,141 @@ VALUES
 
         Ok(())
     }
+
+    /// List failed jobs (dead letters) in this PgQueue, most recently failed first.
+    /// Failed jobs are only kept around for as long as the janitor's retention allows.
+    pub async fn list_failed<
+        J: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+        M: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+        E: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+    >(
+        &self,
+        filter: &DeadLetterFilter,
+    ) -> PgQueueResult<Vec<DeadLetterJob<J, M, E>>> {
+        // Metadata filters match both webhook (`team_id`, `plugin_config_id`) and hoghook
+        // (`teamId`, `hogFunctionId`) metadata.
+        let base_query = r#"
+SELECT
+    id,
+    attempt,
+    max_attempts,
+    created_at,
+    last_attempt_finished_at,
+    COALESCE(errors, ARRAY[]::jsonb[]) AS errors,
+    metadata,
+    parameters,
+    target
+FROM
+    job_queue
+WHERE
+    queue = $1
+    AND status = 'failed'::job_status
+    AND ($2::bigint IS NULL OR COALESCE(metadata->>'team_id', metadata->>'teamId')::bigint = $2)
+    AND ($3::bigint IS NULL OR (metadata->>'plugin_config_id')::bigint = $3)
+    AND ($4::text IS NULL OR metadata->>'hogFunctionId' = $4)
+    AND ($5::text IS NULL OR target = $5)
+ORDER BY
+    last_attempt_finished_at DESC,
+    id DESC
+LIMIT $6
+OFFSET $7
+        "#;
+
+        sqlx::query_as(base_query)
+            .bind(&self.name)
+            .bind(filter.team_id)
+            .bind(filter.plugin_config_id)
+            .bind(&filter.hog_function_id)
+            .bind(&filter.target)
+            .bind(filter.limit)
+            .bind(filter.offset)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|error| DatabaseError::QueryError {
+                command: "SELECT".to_owned(),
+                error,
+            })
+    }
+
+    /// Make failed jobs available again, with a fresh set of attempts. Their error history is kept.
+    /// Returns the ids of the jobs that were replayed: ids that don't exist or aren't failed are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids`: The failed jobs to replay.
+    /// * `parameter_overrides`: A JSON object merged (shallowly) into each job's parameters, e.g. to fix a URL.
+    /// * `target`: A new target for the jobs, required if the overrides change where the job is sent.
+    pub async fn replay_failed(
+        &self,
+        ids: &[i64],
+        parameter_overrides: serde_json::Value,
+        target: Option<&str>,
+    ) -> PgQueueResult<Vec<i64>> {
+        let base_query = r#"
+UPDATE
+    job_queue
+SET
+    status = 'available'::job_status,
+    attempt = 0,
+    scheduled_at = NOW(),
+    last_attempt_finished_at = NULL,
+    reported_at = NULL,
+    parameters = parameters || $3,
+    target = COALESCE($4, target)
+WHERE
+    queue = $1
+    AND id = ANY($2)
+    AND status = 'failed'::job_status
+RETURNING
+    id
+        "#;
+
+        sqlx::query_scalar(base_query)
+            .bind(&self.name)
+            .bind(ids)
+            .bind(sqlx::types::Json(parameter_overrides))
+            .bind(target)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|error| DatabaseError::QueryError {
+                command: "UPDATE".to_owned(),
+                error,
+            })
+    }
+}
+
+/// Filters for listing failed jobs with `PgQueue::list_failed`.
+#[derive(Debug, Clone, Default)]
+pub struct DeadLetterFilter {
+    pub team_id: Option<i64>,
+    pub plugin_config_id: Option<i64>,
+    pub hog_function_id: Option<String>,
+    pub target: Option<String>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// A failed job, as listed by `PgQueue::list_failed`.
+#[derive(sqlx::FromRow, Debug)]
+pub struct DeadLetterJob<J, M, E> {
+    /// A unique id identifying a job.
+    pub id: i64,
+    /// The attempt the job failed on.
+    pub attempt: i32,
+    /// The job's number of max attempts.
+    pub max_attempts: i32,
+    /// A datetime corresponding to when the job was created.
+    pub created_at: chrono::DateTime<chrono::offset::Utc>,
+    /// A datetime corresponding to when the last attempt finished.
+    pub last_attempt_finished_at: Option<chrono::DateTime<chrono::offset::Utc>>,
+    /// The errors of every attempt, oldest first.
+    pub errors: Vec<sqlx::types::Json<E>>,
+    /// Arbitrary job metadata stored as JSON.
+    pub metadata: JobMetadata<M>,
+    /// Arbitrary job parameters stored as JSON.
+    pub parameters: JobParameters<J>,
+    /// The target of the job. E.g. an endpoint or service we are trying to reach.
+    pub target: String,
 }
 
 #[cfg(test)]
@@ -859,6 +1062,126 @@ mod tests {
         assert_eq!(retried_job.job.target, job_target);
     }
 
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_defer_does_not_count_as_attempt(db: PgPool) {
+        let job_target = job_target();
+        let worker_id = worker_id();
+        let new_job = NewJob::new(
+            1,
+            JobMetadata::default(),
+            JobParameters::default(),
+            &job_target,
+        );
+
+        let queue = PgQueue::new_from_pool("test_defer_does_not_count_as_attempt", db).await;
+
+        queue.enqueue(new_job).await.expect("failed to enqueue job");
+        let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job = batch.jobs.pop().unwrap();
+
+        job.defer(time::Duration::from_secs(0))
+            .await
+            .expect("failed to defer job");
+        batch.commit().await.expect("failed to commit transaction");
+
+        let deferred_job: PgTransactionJob<JobParameters, JobMetadata> = queue
+            .dequeue_tx(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find deferred job to dequeue")
+            .jobs
+            .pop()
+            .unwrap();
+
+        assert_eq!(deferred_job.job.attempt, 1);
+        assert_eq!(deferred_job.job.max_attempts, 1);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_list_and_replay_failed_jobs(db: PgPool) {
+        let job_target = job_target();
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_list_and_replay_failed_jobs", db).await;
+
+        for _ in 0..2 {
+            let new_job = NewJob::new(
+                1,
+                JobMetadata::default(),
+                JobParameters::default(),
+                &job_target,
+            );
+            queue.enqueue(new_job).await.expect("failed to enqueue job");
+        }
+        let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx(&worker_id, 2)
+            .await
+            .expect("failed to dequeue jobs")
+            .expect("didn't find any jobs to dequeue");
+        for job in std::mem::take(&mut batch.jobs) {
+            job.fail("a very reasonable failure reason")
+                .await
+                .expect("failed to fail job");
+        }
+        batch.commit().await.expect("failed to commit transaction");
+
+        let filter = DeadLetterFilter {
+            team_id: Some(0),
+            limit: 10,
+            ..Default::default()
+        };
+        let failed: Vec<DeadLetterJob<JobParameters, JobMetadata, String>> = queue
+            .list_failed(&filter)
+            .await
+            .expect("failed to list jobs");
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].errors.len(), 1);
+        assert_eq!(*failed[0].errors[0], "a very reasonable failure reason");
+
+        let other_team = DeadLetterFilter {
+            team_id: Some(1),
+            limit: 10,
+            ..Default::default()
+        };
+        let failed_other_team: Vec<DeadLetterJob<JobParameters, JobMetadata, String>> = queue
+            .list_failed(&other_team)
+            .await
+            .expect("failed to list jobs");
+        assert!(failed_other_team.is_empty());
+
+        let replayed = queue
+            .replay_failed(
+                &[failed[0].id, -1],
+                serde_json::json!({"url": "https://otherhost/endpoint"}),
+                Some("otherhost"),
+            )
+            .await
+            .expect("failed to replay jobs");
+        assert_eq!(replayed, vec![failed[0].id]);
+
+        let replayed_job = queue
+            .dequeue_tx::<JobParameters, JobMetadata>(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find replayed job to dequeue")
+            .jobs
+            .pop()
+            .unwrap();
+        assert_eq!(replayed_job.job.attempt, 1);
+        assert_eq!(replayed_job.job.target, "otherhost");
+        assert_eq!(
+            replayed_job.job.parameters.url,
+            "https://otherhost/endpoint"
+        );
+        assert_eq!(
+            replayed_job.job.parameters.body,
+            JobParameters::default().body
+        );
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn test_can_retry_job_to_different_queue(db: PgPool) {
         let job_target = job_target();
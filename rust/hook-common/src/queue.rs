@@ -0,0 +1,223 @@
+//! # Queue
+//!
+//! Traits that hide which job queue jobs are enqueued to and consumed from, so that hook-api and
+//! workers can move between `PgQueue` and cyclotron without being rewritten.
+use std::str::FromStr;
+use std::time;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::pgqueue::{
+    DatabaseError, NewJob, PgQueue, PgQueueJob, PgTransactionBatch, PgTransactionJob, RetryError,
+};
+
+/// The kind of queue jobs are enqueued to and consumed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueBackend {
+    Pg,
+    Cyclotron,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseQueueBackendError(String);
+
+impl FromStr for QueueBackend {
+    type Err = ParseQueueBackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pg" => Ok(QueueBackend::Pg),
+            "cyclotron" => Ok(QueueBackend::Cyclotron),
+            invalid => Err(ParseQueueBackendError(invalid.to_owned())),
+        }
+    }
+}
+
+/// A dequeued job, that must be transitioned to exactly one of completed, failed, retried or
+/// deferred.
+#[async_trait]
+pub trait QueueJob: std::marker::Send + Sized {
+    type Parameters;
+    type Metadata;
+
+    fn parameters(&self) -> &Self::Parameters;
+    /// Take the job's metadata, leaving its default in place.
+    fn take_metadata(&mut self) -> Self::Metadata;
+    /// The current attempt, starting at 1 for the first time a job is dequeued.
+    fn attempt(&self) -> i32;
+    fn max_attempts(&self) -> i32;
+    fn created_at(&self) -> DateTime<Utc>;
+    fn queue(&self) -> String;
+    fn target(&self) -> String;
+
+    async fn complete(self) -> Result<(), DatabaseError>;
+
+    async fn fail<E: serde::Serialize + std::marker::Sync + std::marker::Send>(
+        self,
+        error: E,
+    ) -> Result<(), DatabaseError>;
+
+    /// Schedule another attempt after `retry_interval` in `queue`. Jobs that reached their
+    /// maximum attempts are given back in a `RetryInvalidError` to be failed instead.
+    async fn retry<E: serde::Serialize + std::marker::Sync + std::marker::Send>(
+        self,
+        error: E,
+        retry_interval: time::Duration,
+        queue: &str,
+    ) -> Result<(), RetryError<Box<Self>>>;
+
+    /// Put the job back to run after `interval`, without using up an attempt.
+    async fn defer(self, interval: time::Duration) -> Result<(), DatabaseError>;
+}
+
+/// The jobs from one dequeue, whose transitions only take effect once committed.
+#[async_trait]
+pub trait JobBatch: std::marker::Send + Sized {
+    type Job: QueueJob;
+
+    fn take_jobs(&mut self) -> Vec<Self::Job>;
+
+    async fn commit(self) -> Result<(), DatabaseError>;
+}
+
+/// A queue of jobs with parameters `J` and metadata `M`.
+#[async_trait]
+pub trait JobQueue<J, M>: std::marker::Send + std::marker::Sync {
+    type Batch: JobBatch<Job: QueueJob<Parameters = J, Metadata = M>> + 'static;
+
+    async fn enqueue(&self, job: NewJob<J, M>) -> Result<(), DatabaseError>;
+
+    /// Dequeue up to `limit` jobs for `attempted_by`, or `None` if there are none available.
+    async fn dequeue(
+        &self,
+        attempted_by: &str,
+        limit: u32,
+    ) -> Result<Option<Self::Batch>, DatabaseError>;
+
+    /// Write out any job updates still buffered, for when the worker is shutting down. Most
+    /// queues write updates as they're made, so this does nothing by default.
+    async fn flush(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+
+    /// Put every job still in flight back in the queue, for when the worker is shutting down and
+    /// they've run out of time to finish. Returns the number of jobs put back. Queues that hold
+    /// jobs in a transaction put them back when it's rolled back, so this does nothing by default.
+    async fn release_all(&self) -> Result<usize, DatabaseError> {
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl<J, M> QueueJob for PgTransactionJob<'_, J, M>
+where
+    J: std::marker::Send,
+    M: Default + std::marker::Send,
+{
+    type Parameters = J;
+    type Metadata = M;
+
+    fn parameters(&self) -> &J {
+        &self.job.parameters
+    }
+
+    fn take_metadata(&mut self) -> M {
+        std::mem::take(&mut self.job.metadata.0)
+    }
+
+    fn attempt(&self) -> i32 {
+        self.job.attempt
+    }
+
+    fn max_attempts(&self) -> i32 {
+        self.job.max_attempts
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.job.created_at
+    }
+
+    fn queue(&self) -> String {
+        self.job.queue.to_owned()
+    }
+
+    fn target(&self) -> String {
+        self.job.target.to_owned()
+    }
+
+    async fn complete(self) -> Result<(), DatabaseError> {
+        PgQueueJob::complete(self).await.map(|_| ())
+    }
+
+    async fn fail<E: serde::Serialize + std::marker::Sync + std::marker::Send>(
+        self,
+        error: E,
+    ) -> Result<(), DatabaseError> {
+        PgQueueJob::fail(self, error).await.map(|_| ())
+    }
+
+    async fn retry<E: serde::Serialize + std::marker::Sync + std::marker::Send>(
+        self,
+        error: E,
+        retry_interval: time::Duration,
+        queue: &str,
+    ) -> Result<(), RetryError<Box<Self>>> {
+        PgQueueJob::retry(self, error, retry_interval, queue)
+            .await
+            .map(|_| ())
+    }
+
+    async fn defer(self, interval: time::Duration) -> Result<(), DatabaseError> {
+        PgQueueJob::defer(self, interval).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl<J, M> JobBatch for PgTransactionBatch<'static, J, M>
+where
+    J: std::marker::Send,
+    M: Default + std::marker::Send,
+{
+    type Job = PgTransactionJob<'static, J, M>;
+
+    fn take_jobs(&mut self) -> Vec<Self::Job> {
+        std::mem::take(&mut self.jobs)
+    }
+
+    async fn commit(self) -> Result<(), DatabaseError> {
+        PgTransactionBatch::commit(self).await
+    }
+}
+
+#[async_trait]
+impl<J, M> JobQueue<J, M> for PgQueue
+where
+    J: serde::Serialize
+        + for<'d> serde::Deserialize<'d>
+        + std::marker::Send
+        + std::marker::Sync
+        + std::marker::Unpin
+        + 'static,
+    M: serde::Serialize
+        + for<'d> serde::Deserialize<'d>
+        + Default
+        + std::marker::Send
+        + std::marker::Sync
+        + std::marker::Unpin
+        + 'static,
+{
+    type Batch = PgTransactionBatch<'static, J, M>;
+
+    async fn enqueue(&self, job: NewJob<J, M>) -> Result<(), DatabaseError> {
+        PgQueue::enqueue(self, job).await
+    }
+
+    async fn dequeue(
+        &self,
+        attempted_by: &str,
+        limit: u32,
+    ) -> Result<Option<Self::Batch>, DatabaseError> {
+        self.dequeue_tx(attempted_by, limit).await
+    }
+}
@@ -9,7 +9,7 @@ use crate::pgqueue::ParseError;
 use common_kafka::kafka_messages::app_metrics;
 
 /// Supported HTTP methods for webhooks.
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum HttpMethod {
     DELETE,
     GET,
@@ -126,6 +126,158 @@ pub struct WebhookJobParameters {
     pub headers: collections::HashMap<String, String>,
     pub method: HttpMethod,
     pub url: String,
+    /// Set to deliver this job together with other jobs, see `WebhookBatchParameters`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<WebhookBatchParameters>,
+    /// Set to authorize requests with a bearer token obtained by the worker, see `WebhookAuth`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<WebhookAuth>,
+    /// Set to decide whether a response is a success by more than its status code, see
+    /// `WebhookResponseValidation`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation: Option<WebhookResponseValidation>,
+    /// Set to fill in placeholders in the body and headers when the job is sent, see
+    /// `WebhookTemplate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<WebhookTemplate>,
+}
+
+impl WebhookJobParameters {
+    /// Check for options that can't be used together. Batched jobs are sent in one request, and
+    /// judged by their per-item statuses, so can't have their own validation or template.
+    pub fn check(&self) -> Result<(), String> {
+        if self.batch.is_some() && self.validation.is_some() {
+            return Err("batched jobs can't set validation".to_owned());
+        }
+        if self.batch.is_some() && self.template.is_some() {
+            return Err("batched jobs can't set a template".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// Authorization for destinations that only accept short-lived bearer tokens, which can't be set
+/// in the job's `headers`. The worker fetches and caches tokens per destination.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookAuth {
+    /// The OAuth2 client credentials grant. Nothing but the name of the credentials is stored with
+    /// jobs: `credentials` names a client ID and secret configured on the worker, together with the
+    /// token URL they may be sent to and the scopes jobs may request. Jobs requesting no scopes get
+    /// all of the configured ones.
+    Oauth2ClientCredentials {
+        #[serde(default)]
+        scopes: Vec<String>,
+        credentials: String,
+    },
+}
+
+/// Batched delivery for destinations that prefer to receive many payloads at once.
+/// Jobs with the same batch `key`, method, URL, headers and auth are coalesced by the worker into
+/// one request, with a body that is a JSON array of each job's body.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct WebhookBatchParameters {
+    pub key: String,
+    /// The maximum number of jobs sent in one request.
+    pub max_size: u32,
+    /// How long a job may be held back waiting for others to fill its batch, in milliseconds.
+    #[serde(default)]
+    pub linger_ms: u64,
+}
+
+/// Success criteria and retry rules for destinations that don't signal failure with status codes
+/// alone, e.g. APIs that respond with 200 and `{"ok": false}`.
+///
+/// A response is a success if its status is in `success_statuses` and all `assertions` hold.
+/// Otherwise, the job is retried if the status is in `retry_statuses`, or if the first assertion
+/// that didn't hold is `retryable`, and failed if not. Batched jobs are judged by their per-item
+/// statuses instead.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+pub struct WebhookResponseValidation {
+    /// Statuses that can be a success, 2xx if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub success_statuses: Vec<StatusRange>,
+    /// Statuses of unsuccessful responses that are retried, 429 and 5xx if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_statuses: Vec<StatusRange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assertions: Vec<WebhookResponseAssertion>,
+}
+
+/// An assertion on a JSON response body. `path` is a JSONPath of the form `$.a.b[0]['c']`. The
+/// value at `path` must equal `equals` if it's set, and only needs to exist otherwise.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct WebhookResponseAssertion {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<serde_json::Value>,
+    /// Whether to retry the job if this assertion doesn't hold.
+    #[serde(default)]
+    pub retryable: bool,
+}
+
+/// Payloads that depend on the delivery attempt, e.g. a retry count in the body, or an idempotency
+/// key header. `{{ path }}` placeholders in the body and header values are replaced when the job
+/// is sent by the value at `path` in an object with the job's `variables`, and a `job` object
+/// with its `attempt`, `max_attempts` and `created_at`. `path` is a JSONPath without the leading
+/// `$`, e.g. `{{ variables.user.id }}` or `{{ job.attempt }}`.
+///
+/// Values are written as JSON in the body, so `{"attempt": {{ job.attempt }}}` is valid JSON, and
+/// strings are written as they are in headers. Jobs with placeholders that can't be filled in are
+/// failed.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+pub struct WebhookTemplate {
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub variables: serde_json::Map<String, serde_json::Value>,
+}
+
+/// An inclusive range of HTTP status codes, written as "200-299", or "404" for a single status.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct StatusRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl StatusRange {
+    pub fn contains(&self, status: u16) -> bool {
+        (self.start..=self.end).contains(&status)
+    }
+}
+
+impl FromStr for StatusRange {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::ParseStatusRangeError(s.to_owned());
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let start = start.trim().parse::<u16>().map_err(|_| invalid())?;
+        let end = end.trim().parse::<u16>().map_err(|_| invalid())?;
+
+        if start > end {
+            return Err(invalid());
+        }
+
+        Ok(StatusRange { start, end })
+    }
+}
+
+impl TryFrom<String> for StatusRange {
+    type Error = ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<StatusRange> for String {
+    fn from(range: StatusRange) -> Self {
+        if range.start == range.end {
+            range.start.to_string()
+        } else {
+            format!("{}-{}", range.start, range.end)
+        }
+    }
 }
 
 /// `JobMetadata` required for the `WebhookWorker` to execute a webhook.
@@ -139,7 +291,7 @@ pub struct WebhookJobMetadata {
 
 /// An error originating during a Webhook Job invocation.
 /// This is to be serialized to be stored as an error whenever retrying or failing a webhook job.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct WebhookJobError {
     pub r#type: app_metrics::ErrorType,
     pub details: app_metrics::ErrorDetails,
@@ -195,6 +347,40 @@ impl WebhookJobError {
         }
     }
 
+    /// A request that was never sent because the circuit breaker for its target was open.
+    /// Reported as a connection error, as that's what tripped the breaker in the first place.
+    pub fn new_circuit_open(target: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Circuit Breaker Open".to_owned(),
+            message: Some(format!(
+                "too many consecutive failures sending to {}",
+                target
+            )),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::ConnectionError,
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+        }
+    }
+
+    /// A request that was never sent because we couldn't get a token to authorize it with.
+    pub fn new_auth(message: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Authorization Error".to_owned(),
+            message: Some(message.to_owned()),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::ConnectionError,
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+        }
+    }
+
     pub fn new_http_status(status_code: u16, message: &str) -> Self {
         let error_details = app_metrics::Error {
             name: "Bad Http Status".to_owned(),
@@ -209,6 +395,22 @@ impl WebhookJobError {
         }
     }
 
+    /// A response that didn't meet the job's `WebhookResponseValidation`, e.g. a 200 with a body
+    /// saying the request failed.
+    pub fn new_invalid_response(status_code: u16, message: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Invalid Response".to_owned(),
+            message: Some(message.to_owned()),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::BadHttpStatus(status_code),
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+        }
+    }
+
     pub fn new_parse(message: &str) -> Self {
         let error_details = app_metrics::Error {
             name: "Parse Error".to_owned(),
@@ -31,6 +31,11 @@ pub struct Config {
     #[envconfig(default = "clickhouse_app_metrics2")]
     pub app_metrics2_topic: String,
 
+    // Failed jobs are reported to app_metrics right away, but only deleted after this many days
+    // so that they can be inspected and replayed through hook-api.
+    #[envconfig(default = "0")]
+    pub failed_job_retention_days: u64,
+
     #[envconfig(nested = true)]
     pub kafka: KafkaConfig,
 }
@@ -67,6 +67,7 @@ async fn main() {
                     config.app_metrics_topic.to_owned(),
                     config.app_metrics2_topic.to_owned(),
                     config.hog_mode,
+                    Duration::from_secs(config.failed_job_retention_days * 24 * 60 * 60),
                 )
                 .expect("unable to create webhook cleaner"),
             )
@@ -43,6 +43,8 @@ pub enum WebhookCleanerError {
     KafkaProduceError { error: KafkaError },
     #[error("failed to produce to kafka (timeout)")]
     KafkaProduceCanceled,
+    #[error("failed to mark failed rows as reported: {error}")]
+    MarkReportedError { error: sqlx::Error },
     #[error("failed to delete rows: {error}")]
     DeleteRowsError { error: sqlx::Error },
     #[error("attempted to delete a different number of rows than expected")]
@@ -61,6 +63,8 @@ pub struct WebhookCleaner {
     app_metrics_topic: String,
     app_metrics2_topic: String,
     hog_mode: bool,
+    /// How long failed rows are kept (for inspection and replay) after being reported.
+    failed_job_retention: Duration,
 }
 
 #[derive(sqlx::FromRow, Debug)]
@@ -248,6 +252,7 @@ impl WebhookCleaner {
         app_metrics_topic: String,
         app_metrics2_topic: String,
         hog_mode: bool,
+        failed_job_retention: Duration,
     ) -> Result<Self> {
         let options = PgConnectOptions::from_str(database_url)
             .map_err(|error| WebhookCleanerError::PoolCreationError { error })?
@@ -262,6 +267,7 @@ impl WebhookCleaner {
             app_metrics_topic,
             app_metrics2_topic,
             hog_mode,
+            failed_job_retention,
         })
     }
 
@@ -272,6 +278,7 @@ impl WebhookCleaner {
         app_metrics_topic: String,
         app_metrics2_topic: String,
         hog_mode: bool,
+        failed_job_retention: Duration,
     ) -> Result<Self> {
         Ok(Self {
             pg_pool,
@@ -279,6 +286,7 @@ impl WebhookCleaner {
             app_metrics_topic,
             app_metrics2_topic,
             hog_mode,
+            failed_job_retention,
         })
     }
 
@@ -335,7 +343,8 @@ impl WebhookCleaner {
     ) -> Result<u64> {
         let base_query = r#"
             SELECT count(*) FROM job_queue
-            WHERE status = $1::job_status;
+            WHERE status = $1::job_status
+                AND reported_at IS NULL;
             "#;
 
         let count: i64 = sqlx::query(base_query)
@@ -403,6 +412,7 @@ impl WebhookCleaner {
                    count(*) as failures
             FROM job_queue
             WHERE status = 'failed'
+                AND reported_at IS NULL
             GROUP BY hour, team_id, plugin_config_id, last_error
             ORDER BY hour, team_id, plugin_config_id, last_error;
         "#;
@@ -427,6 +437,7 @@ impl WebhookCleaner {
                 count(*) as count
             FROM job_queue
             WHERE status = 'failed'
+                AND reported_at IS NULL
             GROUP BY hour, team_id, app_source_id, last_error
             ORDER BY hour, team_id, app_source_id, last_error;
         "#;
@@ -439,15 +450,40 @@ impl WebhookCleaner {
         Ok(rows)
     }
 
+    async fn mark_failed_rows_reported(&self, tx: &mut SerializableTxn<'_>) -> Result<u64> {
+        // This UPDATE is only safe because we are in serializable isolation mode, see the note
+        // in `start_serializable_txn`.
+        let base_query = r#"
+            UPDATE job_queue
+            SET reported_at = NOW()
+            WHERE status = 'failed'
+                AND reported_at IS NULL
+        "#;
+
+        let result = sqlx::query(base_query)
+            .execute(&mut *tx.0)
+            .await
+            .map_err(|e| WebhookCleanerError::MarkReportedError { error: e })?;
+
+        Ok(result.rows_affected())
+    }
+
     async fn delete_observed_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<u64> {
         // This DELETE is only safe because we are in serializable isolation mode, see the note
-        // in `start_serializable_txn`.
+        // in `start_serializable_txn`. Failed rows are kept until they have been reported and
+        // their retention has expired.
         let base_query = r#"
             DELETE FROM job_queue
-            WHERE status IN ('failed', 'completed')
+            WHERE status = 'completed'
+                OR (
+                    status = 'failed'
+                    AND reported_at IS NOT NULL
+                    AND last_attempt_finished_at <= NOW() - $1
+                )
         "#;
 
         let result = sqlx::query(base_query)
+            .bind(self.failed_job_retention)
             .execute(&mut *tx.0)
             .await
             .map_err(|e| WebhookCleanerError::DeleteRowsError { error: e })?;
@@ -549,25 +585,28 @@ impl WebhookCleaner {
             failed_agg_row_count
         };
 
-        let mut rows_deleted = 0;
-        if completed_row_count + failed_row_count != 0 {
-            rows_deleted = self.delete_observed_rows(&mut tx).await?;
-
-            if rows_deleted != completed_row_count + failed_row_count {
-                // This should never happen, but if it does, we want to know about it (and abort the
-                // txn).
-                error!(
-                    attempted_rows_deleted = rows_deleted,
-                    completed_row_count = completed_row_count,
-                    failed_row_count = failed_row_count,
-                    "WebhookCleaner::cleanup attempted to delete a different number of rows than expected"
-                );
-
-                self.rollback_txn(tx).await?;
-
-                return Err(WebhookCleanerError::DeleteConsistencyError);
-            }
+        let rows_reported = self.mark_failed_rows_reported(&mut tx).await?;
+        // Besides the rows we just observed, this includes failed rows from earlier runs that
+        // were retained until now.
+        let rows_deleted = self.delete_observed_rows(&mut tx).await?;
+
+        if rows_reported != failed_row_count || rows_deleted < completed_row_count {
+            // This should never happen, but if it does, we want to know about it (and abort the
+            // txn).
+            error!(
+                attempted_rows_deleted = rows_deleted,
+                attempted_rows_reported = rows_reported,
+                completed_row_count = completed_row_count,
+                failed_row_count = failed_row_count,
+                "WebhookCleaner::cleanup attempted to process a different number of rows than expected"
+            );
+
+            self.rollback_txn(tx).await?;
+
+            return Err(WebhookCleanerError::DeleteConsistencyError);
+        }
 
+        if rows_reported + rows_deleted != 0 {
             self.commit_txn(tx).await?;
         }
 
@@ -682,6 +721,7 @@ mod tests {
             APP_METRICS_TOPIC.to_owned(),
             APP_METRICS2_TOPIC.to_owned(),
             hog_mode,
+            Duration::ZERO,
         )
         .expect("unable to create webhook cleaner");
 
@@ -876,6 +916,7 @@ mod tests {
             APP_METRICS_TOPIC.to_owned(),
             APP_METRICS2_TOPIC.to_owned(),
             hog_mode,
+            Duration::ZERO,
         )
         .expect("unable to create hoghook cleaner");
 
@@ -1018,6 +1059,7 @@ mod tests {
             APP_METRICS_TOPIC.to_owned(),
             APP_METRICS2_TOPIC.to_owned(),
             hog_mode,
+            Duration::ZERO,
         )
         .expect("unable to create webhook cleaner");
 
@@ -1034,6 +1076,54 @@ mod tests {
         assert_eq!(cleanup_stats.failed_agg_row_count, 0);
     }
 
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_cleanup_impl_retains_failed_rows(db: PgPool) {
+        let (mock_cluster, mock_producer) = create_mock_kafka().await;
+        mock_cluster
+            .create_topic(APP_METRICS_TOPIC, 1, 1)
+            .expect("failed to create mock app_metrics topic");
+
+        let hog_mode = false;
+        // Long enough to retain the fixture rows, which failed in 2023.
+        let retention = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+        let webhook_cleaner = WebhookCleaner::new_from_pool(
+            db.clone(),
+            mock_producer,
+            APP_METRICS_TOPIC.to_owned(),
+            APP_METRICS2_TOPIC.to_owned(),
+            hog_mode,
+            retention,
+        )
+        .expect("unable to create webhook cleaner");
+
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+
+        // Only completed rows are deleted, failed rows are reported and kept.
+        assert_eq!(cleanup_stats.rows_processed, 6);
+        assert_eq!(cleanup_stats.failed_row_count, 7);
+
+        let reported: i64 = sqlx::query(
+            "SELECT count(*) FROM job_queue WHERE status = 'failed' AND reported_at IS NOT NULL",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap()
+        .get(0);
+        assert_eq!(reported, 7);
+
+        // Retained rows aren't reported again.
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+        assert_eq!(cleanup_stats.rows_processed, 0);
+        assert_eq!(cleanup_stats.failed_row_count, 0);
+        assert_eq!(cleanup_stats.failed_agg_row_count, 0);
+    }
+
     #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
     async fn test_serializable_isolation(db: PgPool) {
         let (_, mock_producer) = create_mock_kafka().await;
@@ -1044,6 +1134,7 @@ mod tests {
             APP_METRICS_TOPIC.to_owned(),
             APP_METRICS2_TOPIC.to_owned(),
             hog_mode,
+            Duration::ZERO,
         )
         .expect("unable to create webhook cleaner");
 
@@ -1097,6 +1188,10 @@ mod tests {
                 headers: HashMap::new(),
                 method: HttpMethod::POST,
                 url: "http://example.com".to_owned(),
+                batch: None,
+                auth: None,
+                validation: None,
+                template: None,
             };
             let job_metadata = WebhookJobMetadata {
                 team_id: 1,
@@ -1125,6 +1220,10 @@ mod tests {
                 headers: HashMap::new(),
                 method: HttpMethod::POST,
                 url: "http://example.com".to_owned(),
+                batch: None,
+                auth: None,
+                validation: None,
+                template: None,
             };
             let job_metadata = WebhookJobMetadata {
                 team_id: 1,
@@ -1139,6 +1238,11 @@ mod tests {
         assert_eq!(get_count_from_new_conn(&db, "completed").await, 8);
         assert_eq!(get_count_from_new_conn(&db, "available").await, 1);
 
+        let rows_reported = webhook_cleaner
+            .mark_failed_rows_reported(&mut tx)
+            .await
+            .unwrap();
+        assert_eq!(rows_reported, 7);
         let rows_processed = webhook_cleaner.delete_observed_rows(&mut tx).await.unwrap();
         // The 13 rows in the DB when the txn started should be deleted.
         assert_eq!(rows_processed, 13);
@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use hook_common::webhook::WebhookAuth;
+use http::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::time::Instant;
+
+use crate::worker::is_retryable_status;
+
+/// Used when a token response doesn't say when the token expires.
+const DEFAULT_TOKEN_LIFETIME: time::Duration = time::Duration::from_secs(300);
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CredentialsConfigError {
+    #[error(
+        "invalid credentials config entry {0}, expected <name>=<client_id>:<client_secret>,<token_url>[,<scopes>]"
+    )]
+    InvalidEntry(usize),
+}
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("no client credentials configured with name {0}")]
+    UnknownCredentials(String),
+    #[error("scope {1} is not allowed for client credentials {0}")]
+    ScopeNotAllowed(String, String),
+    #[error("error requesting a token from {0}: {1}")]
+    TokenRequestError(String, reqwest::Error),
+    #[error("token endpoint {0} responded with status {1}")]
+    TokenRejected(String, StatusCode),
+    #[error("invalid token response from {0}: {1}")]
+    InvalidTokenResponse(String, reqwest::Error),
+}
+
+impl AuthError {
+    /// Whether a later attempt could get a token, e.g. because the token endpoint was down.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AuthError::UnknownCredentials(_)
+            | AuthError::ScopeNotAllowed(..)
+            | AuthError::InvalidTokenResponse(..) => false,
+            AuthError::TokenRequestError(..) => true,
+            AuthError::TokenRejected(_, status) => is_retryable_status(*status),
+        }
+    }
+}
+
+/// A client ID and secret, and the only token endpoint they are ever sent to.
+#[derive(Clone, PartialEq, Eq)]
+struct ClientCredentials {
+    client_id: String,
+    client_secret: String,
+    token_url: String,
+    /// The scopes jobs may request with these credentials.
+    scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+type TokenSlot = Arc<tokio::sync::Mutex<Option<CachedToken>>>;
+
+/// Fetches and caches bearer tokens for destinations with a `WebhookAuth` descriptor.
+///
+/// Tokens are cached per descriptor, so jobs for the same destination share a token, and are
+/// refreshed `refresh_margin` before they expire. Only one request per descriptor is made at a
+/// time: concurrent jobs wait for it and use its token.
+pub struct TokenProvider {
+    credentials: HashMap<String, ClientCredentials>,
+    refresh_margin: time::Duration,
+    tokens: Mutex<HashMap<WebhookAuth, TokenSlot>>,
+}
+
+impl TokenProvider {
+    /// Parse a config string in the format
+    /// "<name>=<client_id>:<client_secret>,<token_url>[,<scope> <scope>...];...". Jobs refer to
+    /// credentials by name, so that secrets are never stored with jobs, and can't send them
+    /// anywhere but the token URL configured with them.
+    pub fn new(
+        config: &str,
+        refresh_margin: time::Duration,
+    ) -> Result<Self, CredentialsConfigError> {
+        let mut credentials = HashMap::new();
+
+        // Entries are reported by position so that secrets never end up in logs.
+        let entries = config.split(';').map(str::trim).filter(|e| !e.is_empty());
+        for (i, entry) in entries.enumerate() {
+            let (name, fields) = entry
+                .split_once('=')
+                .ok_or(CredentialsConfigError::InvalidEntry(i))?;
+            let mut fields = fields.splitn(3, ',');
+            let (client_id, client_secret) = fields
+                .next()
+                .and_then(|pair| pair.split_once(':'))
+                .ok_or(CredentialsConfigError::InvalidEntry(i))?;
+            let token_url = fields.next().unwrap_or_default().trim();
+            let scopes = fields
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect();
+
+            let (name, client_id) = (name.trim(), client_id.trim());
+            if name.is_empty() || client_id.is_empty() || token_url.is_empty() {
+                return Err(CredentialsConfigError::InvalidEntry(i));
+            }
+
+            credentials.insert(
+                name.to_owned(),
+                ClientCredentials {
+                    client_id: client_id.to_owned(),
+                    client_secret: client_secret.trim().to_owned(),
+                    token_url: token_url.to_owned(),
+                    scopes,
+                },
+            );
+        }
+
+        Ok(Self {
+            credentials,
+            refresh_margin,
+            tokens: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Get a token for `auth`, from the cache if it's not about to expire. If the destination
+    /// rejected a token, pass it as `rejected` to get a new one.
+    pub async fn token(
+        &self,
+        client: &reqwest::Client,
+        auth: &WebhookAuth,
+        rejected: Option<&str>,
+    ) -> Result<String, AuthError> {
+        let slot = self
+            .tokens
+            .lock()
+            .expect("poisoned token cache lock")
+            .entry(auth.clone())
+            .or_default()
+            .clone();
+        let mut cached = slot.lock().await;
+
+        if let Some(token) = cached.as_ref() {
+            // If the rejected token was already replaced by another job, use its replacement.
+            let is_rejected = rejected == Some(token.access_token.as_str());
+            if !is_rejected && token.expires_at > Instant::now() + self.refresh_margin {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let token = self.fetch_token(client, auth).await;
+        let labels = [("outcome", if token.is_ok() { "success" } else { "error" })];
+        metrics::counter!("webhook_auth_token_requests", &labels).increment(1);
+
+        let token = token?;
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+
+        Ok(access_token)
+    }
+
+    async fn fetch_token(
+        &self,
+        client: &reqwest::Client,
+        auth: &WebhookAuth,
+    ) -> Result<CachedToken, AuthError> {
+        let WebhookAuth::Oauth2ClientCredentials {
+            scopes,
+            credentials,
+        } = auth;
+
+        let ClientCredentials {
+            client_id,
+            client_secret,
+            token_url,
+            scopes: allowed_scopes,
+        } = self
+            .credentials
+            .get(credentials)
+            .ok_or_else(|| AuthError::UnknownCredentials(credentials.to_owned()))?;
+
+        if let Some(scope) = scopes.iter().find(|s| !allowed_scopes.contains(*s)) {
+            return Err(AuthError::ScopeNotAllowed(
+                credentials.to_owned(),
+                scope.to_owned(),
+            ));
+        }
+        let scopes = if scopes.is_empty() {
+            allowed_scopes
+        } else {
+            scopes
+        };
+
+        let mut form = vec![("grant_type", "client_credentials".to_owned())];
+        if !scopes.is_empty() {
+            form.push(("scope", scopes.join(" ")));
+        }
+
+        let requested_at = Instant::now();
+        let response = client
+            .post(token_url)
+            .basic_auth(client_id, Some(client_secret))
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AuthError::TokenRequestError(token_url.to_owned(), e))?;
+
+        if !response.status().is_success() {
+            return Err(AuthError::TokenRejected(
+                token_url.to_owned(),
+                response.status(),
+            ));
+        }
+
+        let token: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| AuthError::InvalidTokenResponse(token_url.to_owned(), e))?;
+
+        let lifetime = token
+            .expires_in
+            .map(time::Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+
+        Ok(CachedToken {
+            access_token: token.access_token,
+            expires_at: requested_at + lifetime,
+        })
+    }
+}
+
+impl Default for TokenProvider {
+    /// No credentials configured: jobs with a `WebhookAuth` descriptor fail.
+    fn default() -> Self {
+        Self {
+            credentials: HashMap::new(),
+            refresh_margin: time::Duration::ZERO,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// Never print the secrets themselves.
+impl fmt::Debug for TokenProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.credentials.keys().collect();
+        names.sort();
+        f.debug_struct("TokenProvider")
+            .field("credentials", &names)
+            .field("refresh_margin", &self.refresh_margin)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+
+    use crate::worker::build_http_client;
+
+    fn auth() -> WebhookAuth {
+        WebhookAuth::Oauth2ClientCredentials {
+            scopes: vec!["read".to_owned(), "write".to_owned()],
+            credentials: "dest".to_owned(),
+        }
+    }
+
+    fn config(server: &MockServer) -> String {
+        format!("dest=id:secret,{},read write admin", server.url("/token"))
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_cached_until_rejected() {
+        let server = MockServer::start();
+        let mut token_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/token")
+                .header("authorization", "Basic aWQ6c2VjcmV0")
+                .body("grant_type=client_credentials&scope=read+write");
+            then.status(200)
+                .json_body(serde_json::json!({"access_token": "first", "expires_in": 3600}));
+        });
+
+        let client = build_http_client(time::Duration::from_secs(1), true).unwrap();
+        let provider = TokenProvider::new(&config(&server), time::Duration::from_secs(60)).unwrap();
+        let auth = auth();
+
+        assert_eq!(provider.token(&client, &auth, None).await.unwrap(), "first");
+        assert_eq!(provider.token(&client, &auth, None).await.unwrap(), "first");
+        token_mock.assert_hits(1);
+        token_mock.delete();
+
+        let token_mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(200)
+                .json_body(serde_json::json!({"access_token": "second", "expires_in": 3600}));
+        });
+
+        // Rejecting a token that was already replaced doesn't fetch another one.
+        assert_eq!(
+            provider.token(&client, &auth, Some("first")).await.unwrap(),
+            "second"
+        );
+        assert_eq!(
+            provider.token(&client, &auth, Some("first")).await.unwrap(),
+            "second"
+        );
+        token_mock.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_refreshed_before_expiry() {
+        let server = MockServer::start();
+        let token_mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(200)
+                .json_body(serde_json::json!({"access_token": "token", "expires_in": 30}));
+        });
+
+        let client = build_http_client(time::Duration::from_secs(1), true).unwrap();
+        // Tokens that expire within the refresh margin are never used from the cache.
+        let provider = TokenProvider::new(&config(&server), time::Duration::from_secs(60)).unwrap();
+        let auth = auth();
+
+        provider.token(&client, &auth, None).await.unwrap();
+        provider.token(&client, &auth, None).await.unwrap();
+        token_mock.assert_hits(2);
+    }
+
+    #[tokio::test]
+    async fn test_token_errors() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(503);
+        });
+
+        let client = build_http_client(time::Duration::from_secs(1), true).unwrap();
+        let provider = TokenProvider::new(&config(&server), time::Duration::ZERO).unwrap();
+
+        let err = provider.token(&client, &auth(), None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            AuthError::TokenRejected(_, StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        assert!(err.is_retryable());
+
+        let err = TokenProvider::default()
+            .token(&client, &auth(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AuthError::UnknownCredentials(_)));
+        assert!(!err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_cant_choose_where_credentials_are_sent() {
+        let configured = MockServer::start();
+        let configured_mock = configured.mock(|when, then| {
+            when.method(POST)
+                .path("/token")
+                .body("grant_type=client_credentials&scope=read+write+admin");
+            then.status(200)
+                .json_body(serde_json::json!({"access_token": "token", "expires_in": 3600}));
+        });
+        let attacker = MockServer::start();
+        let attacker_mock = attacker.mock(|when, then| {
+            when.any_request();
+            then.status(200)
+                .json_body(serde_json::json!({"access_token": "stolen", "expires_in": 3600}));
+        });
+
+        let client = build_http_client(time::Duration::from_secs(1), true).unwrap();
+        let provider = TokenProvider::new(&config(&configured), time::Duration::ZERO).unwrap();
+
+        // A token URL in the job is ignored, and jobs requesting no scopes get the configured ones.
+        let auth: WebhookAuth = serde_json::from_value(serde_json::json!({
+            "type": "oauth2_client_credentials",
+            "token_url": attacker.url("/token"),
+            "credentials": "dest",
+        }))
+        .unwrap();
+        assert_eq!(provider.token(&client, &auth, None).await.unwrap(), "token");
+        configured_mock.assert_hits(1);
+        attacker_mock.assert_hits(0);
+
+        // Scopes that aren't configured are rejected before anything is sent.
+        let auth = WebhookAuth::Oauth2ClientCredentials {
+            scopes: vec!["read".to_owned(), "delete".to_owned()],
+            credentials: "dest".to_owned(),
+        };
+        let err = provider.token(&client, &auth, None).await.unwrap_err();
+        assert!(matches!(&err, AuthError::ScopeNotAllowed(_, scope) if scope == "delete"));
+        assert!(!err.is_retryable());
+        configured_mock.assert_hits(1);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(TokenProvider::new("", time::Duration::ZERO)
+            .unwrap()
+            .credentials
+            .is_empty());
+        assert_eq!(
+            TokenProvider::new("a=id:secret,https://a/token;b=id", time::Duration::ZERO)
+                .unwrap_err(),
+            CredentialsConfigError::InvalidEntry(1)
+        );
+        // Credentials without a token URL could be sent anywhere.
+        assert_eq!(
+            TokenProvider::new("a=id:secret", time::Duration::ZERO).unwrap_err(),
+            CredentialsConfigError::InvalidEntry(0)
+        );
+
+        let provider = TokenProvider::new(
+            "b=id:secret,https://b/token;a=id:secret,https://a/token,read write",
+            time::Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!(provider.credentials["a"].scopes, vec!["read", "write"]);
+        assert_eq!(provider.credentials["a"].token_url, "https://a/token");
+        assert_eq!(
+            format!("{:?}", provider),
+            "TokenProvider { credentials: [\"a\", \"b\"], refresh_margin: 0ns }"
+        );
+    }
+}
@@ -0,0 +1,588 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time;
+
+use chrono::Utc;
+use hook_common::{
+    retry::RetryPolicy,
+    webhook::{HttpMethod, WebhookJobError, WebhookJobParameters},
+};
+use http::StatusCode;
+use serde_json::Value;
+
+use crate::auth::TokenProvider;
+use crate::destinations::{Admission, DestinationLimiter};
+use crate::error::{WebhookError, WebhookRequestError, WorkerError};
+use crate::signing::WebhookSigner;
+use crate::util::first_n_bytes_of_response;
+use crate::worker::{
+    complete_job, fail_job, is_retryable_status, retry_or_fail_job, send_authorized_webhook,
+    WebhookJob, WebhookResponse, WebhookResult, MAX_RESPONSE_BODY,
+};
+
+type JobResult = Result<WebhookResult, WorkerError>;
+
+/// What jobs must have in common to be sent together. A group is sent with the method, URL,
+/// headers and auth of its first job, so jobs sharing a batch key but not these would otherwise
+/// have their bodies sent somewhere, or with credentials, they weren't meant for.
+#[derive(PartialEq, Eq, Hash)]
+struct GroupKey {
+    target: String,
+    key: String,
+    method: HttpMethod,
+    url: String,
+    /// A hash of the headers and auth, rather than a copy of them.
+    request: u64,
+}
+
+impl GroupKey {
+    fn new(target: String, key: String, parameters: &WebhookJobParameters) -> Self {
+        let mut hasher = DefaultHasher::new();
+        // Header order doesn't matter, but HashMap iteration order is arbitrary.
+        parameters
+            .headers
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(&mut hasher);
+        parameters.auth.hash(&mut hasher);
+
+        Self {
+            target,
+            key,
+            method: parameters.method,
+            url: parameters.url.clone(),
+            request: hasher.finish(),
+        }
+    }
+}
+
+/// Split a dequeued batch into jobs to be sent on their own, and groups of jobs with the same
+/// batch key and request, see `GroupKey`, each with at most `max_size` jobs. Order is kept within
+/// each group.
+///
+/// Only jobs dequeued together can be coalesced, so batching needs a `dequeue_batch_size` of at
+/// least the batch's `max_size` to be effective.
+pub(crate) fn group_batched_jobs<W: WebhookJob>(jobs: Vec<W>) -> (Vec<W>, Vec<Vec<W>>) {
+    let mut singles = Vec::new();
+    let mut groups: Vec<Vec<W>> = Vec::new();
+    // The index in `groups` of the last group for each key.
+    let mut open_groups: HashMap<GroupKey, usize> = HashMap::new();
+
+    for job in jobs {
+        // Jobs with options that can't be batched are failed when they're processed on their own.
+        if job.parameters().check().is_err() {
+            singles.push(job);
+            continue;
+        }
+        let Some(batch) = job.parameters().batch.as_ref() else {
+            singles.push(job);
+            continue;
+        };
+        let max_size = batch.max_size.max(1) as usize;
+        let key = GroupKey::new(job.target(), batch.key.clone(), job.parameters());
+
+        match open_groups.get(&key) {
+            Some(&i) if groups[i].len() < max_size => groups[i].push(job),
+            _ => {
+                open_groups.insert(key, groups.len());
+                groups.push(vec![job]);
+            }
+        }
+    }
+
+    (singles, groups)
+}
+
+/// How much longer a group that isn't full yet should wait for more jobs, if at all.
+fn linger_remaining<W: WebhookJob>(jobs: &[W]) -> Option<time::Duration> {
+    let batch = jobs.first()?.parameters().batch.as_ref()?;
+    if batch.linger_ms == 0 || jobs.len() >= batch.max_size as usize {
+        return None;
+    }
+
+    let oldest = jobs.iter().map(|job| job.created_at()).min()?;
+    let deadline = oldest + chrono::Duration::milliseconds(batch.linger_ms as i64);
+    (deadline - Utc::now())
+        .to_std()
+        .ok()
+        .filter(|remaining| !remaining.is_zero())
+}
+
+/// The body of a batched request: a JSON array with each job's body. Bodies that are not valid
+/// JSON are included as strings.
+fn batch_body<'a>(bodies: impl Iterator<Item = &'a str>) -> String {
+    Value::Array(
+        bodies
+            .map(|body| {
+                serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_owned()))
+            })
+            .collect(),
+    )
+    .to_string()
+}
+
+/// Destinations may report a status per item by responding with a JSON array of the same length
+/// as the request, where each item has a `status` field, e.g. `[{"status": 200}, {"status": 429}]`.
+/// Any other response body applies the response status to every item.
+fn parse_item_statuses(body: &str, len: usize) -> Option<Vec<StatusCode>> {
+    let items = match serde_json::from_str::<Value>(body).ok()? {
+        Value::Array(items) if items.len() == len => items,
+        _ => return None,
+    };
+
+    items
+        .iter()
+        .map(|item| {
+            let status = item.get("status")?.as_u64()?;
+            StatusCode::from_u16(u16::try_from(status).ok()?).ok()
+        })
+        .collect()
+}
+
+/// Process a group of jobs grouped by `group_batched_jobs` by sending them in one request.
+/// Returns one result per job, in the order the jobs were given.
+///
+/// Groups that are not full wait, up to the batch's linger, for more jobs to arrive. Requests that
+/// are rejected with 413 Payload Too Large are split in half and sent again, until single jobs
+/// are left. Otherwise jobs are completed, retried or failed as in `process_webhook_job`, per item
+/// if the destination reported a status for each.
+pub(crate) async fn process_webhook_job_group<W: WebhookJob>(
+    http_client: reqwest::Client,
+    jobs: Vec<W>,
+    retry_policy: &RetryPolicy,
+    signer: &WebhookSigner,
+    tokens: &TokenProvider,
+    destinations: &DestinationLimiter,
+    read_body: bool,
+) -> Vec<JobResult> {
+    let mut results: Vec<Option<JobResult>> = jobs.iter().map(|_| None).collect();
+    let Some(first) = jobs.first() else {
+        return Vec::new();
+    };
+
+    let labels = [("queue", first.queue())];
+    let target = first.target();
+    metrics::counter!("webhook_jobs_total", &labels).increment(jobs.len() as u64);
+
+    if let Some(remaining) = linger_remaining(&jobs) {
+        for (i, job) in jobs.into_iter().enumerate() {
+            results[i] = Some(defer_job(job, remaining, &labels).await);
+        }
+        return collect_results(results);
+    }
+
+    // Hold the permit (if any) until all requests for the group are done.
+    let _permit = match destinations.admit(&target) {
+        Admission::Allowed(permit) => permit,
+        Admission::Saturated => {
+            let defer_interval = retry_policy.retry_interval(1, None);
+            for (i, job) in jobs.into_iter().enumerate() {
+                results[i] = Some(defer_job(job, defer_interval, &labels).await);
+            }
+            return collect_results(results);
+        }
+        Admission::CircuitOpen(remaining) => {
+            let error = WebhookJobError::new_circuit_open(&target);
+            for (i, job) in jobs.into_iter().enumerate() {
+                results[i] = Some(
+                    retry_or_fail_job(job, retry_policy, error.clone(), Some(remaining), &labels)
+                        .await,
+                );
+                metrics::counter!("webhook_jobs_short_circuited", &labels).increment(1);
+            }
+            return collect_results(results);
+        }
+    };
+
+    let mut pending: Vec<Vec<(usize, W)>> = vec![jobs.into_iter().enumerate().collect()];
+
+    while let Some(mut chunk) = pending.pop() {
+        let now = tokio::time::Instant::now();
+        let parameters = chunk[0].1.parameters();
+        let body = batch_body(chunk.iter().map(|(_, job)| job.parameters().body.as_str()));
+
+        metrics::counter!("webhook_batches_sent", &labels).increment(1);
+        metrics::histogram!("webhook_batch_size", &labels).record(chunk.len() as f64);
+
+        let send_result =
+            send_authorized_webhook(http_client.clone(), parameters, body, signer, tokens).await;
+
+        match send_result {
+            Ok(response) => {
+                destinations.record_success(&target);
+
+                let status = response.status();
+                let body = first_n_bytes_of_response(response, MAX_RESPONSE_BODY)
+                    .await
+                    .ok();
+                let duration = now.elapsed();
+                let item_statuses = body
+                    .as_deref()
+                    .and_then(|body| parse_item_statuses(body, chunk.len()));
+
+                for (n, (i, job)) in chunk.into_iter().enumerate() {
+                    let status_code = item_statuses.as_ref().map_or(status, |s| s[n]);
+                    let response = WebhookResponse {
+                        duration,
+                        status_code,
+                        body: if read_body { body.clone() } else { None },
+                    };
+
+                    results[i] = Some(if status_code.is_success() {
+                        complete_job(job, response, &labels).await
+                    } else {
+                        let error = WebhookJobError::new_http_status(
+                            status_code.as_u16(),
+                            "batch item was not accepted",
+                        );
+                        if is_retryable_status(status_code) {
+                            retry_or_fail_job(job, retry_policy, error, None, &labels).await
+                        } else {
+                            fail_job(job, error, WebhookResult::BadResponse(response), &labels)
+                                .await
+                        }
+                    });
+                }
+            }
+            Err(WebhookError::Request(request_error))
+                if request_error.status() == Some(StatusCode::PAYLOAD_TOO_LARGE)
+                    && chunk.len() > 1 =>
+            {
+                destinations.record_success(&target);
+                metrics::counter!("webhook_batches_split", &labels).increment(1);
+
+                let second_half = chunk.split_off(chunk.len() / 2);
+                pending.push(second_half);
+                pending.push(chunk);
+            }
+            Err(WebhookError::Request(request_error)) => {
+                let error = WebhookJobError::from(&request_error);
+
+                match request_error {
+                    WebhookRequestError::RetryableRequestError { retry_after, .. } => {
+                        destinations.record_failure(&target);
+                        for (i, job) in chunk {
+                            results[i] = Some(
+                                retry_or_fail_job(
+                                    job,
+                                    retry_policy,
+                                    error.clone(),
+                                    retry_after,
+                                    &labels,
+                                )
+                                .await,
+                            );
+                        }
+                    }
+                    WebhookRequestError::NonRetryableRetryableRequestError {
+                        error: request_error,
+                        response,
+                        ..
+                    } => {
+                        destinations.record_success(&target);
+                        for (i, job) in chunk {
+                            let result = match request_error.status() {
+                                Some(status_code) => WebhookResult::BadResponse(WebhookResponse {
+                                    duration: now.elapsed(),
+                                    status_code,
+                                    body: response.clone(),
+                                }),
+                                None => WebhookResult::Error(request_error.to_string()),
+                            };
+                            results[i] = Some(fail_job(job, error.clone(), result, &labels).await);
+                        }
+                    }
+                }
+            }
+            Err(WebhookError::Auth(auth_error)) => {
+                let error = WebhookJobError::new_auth(&auth_error.to_string());
+                for (i, job) in chunk {
+                    results[i] = Some(if auth_error.is_retryable() {
+                        retry_or_fail_job(job, retry_policy, error.clone(), None, &labels).await
+                    } else {
+                        let result = WebhookResult::Error(auth_error.to_string());
+                        fail_job(job, error.clone(), result, &labels).await
+                    });
+                }
+            }
+            Err(WebhookError::Parse(parse_error)) => {
+                let error = WebhookJobError::new_parse(&parse_error.to_string());
+                for (i, job) in chunk {
+                    let result = WebhookResult::Error(parse_error.to_string());
+                    results[i] = Some(fail_job(job, error.clone(), result, &labels).await);
+                }
+            }
+        }
+    }
+
+    collect_results(results)
+}
+
+fn collect_results(results: Vec<Option<JobResult>>) -> Vec<JobResult> {
+    results
+        .into_iter()
+        .map(|result| result.expect("every job in the group has a result"))
+        .collect()
+}
+
+async fn defer_job<W: WebhookJob>(
+    job: W,
+    interval: time::Duration,
+    labels: &[(&'static str, String)],
+) -> JobResult {
+    job.defer(interval).await.inspect_err(|_| {
+        metrics::counter!("webhook_jobs_database_error", labels).increment(1);
+    })?;
+
+    metrics::counter!("webhook_jobs_deferred", labels).increment(1);
+
+    Ok(WebhookResult::WillRetry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections;
+
+    use hook_common::pgqueue::{NewJob, PgQueue, PgTransactionBatch};
+    use hook_common::queue::QueueJob;
+    use hook_common::webhook::{WebhookAuth, WebhookBatchParameters};
+    use httpmock::prelude::*;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::worker::build_http_client;
+
+    async fn enqueue_batched_jobs(queue: &PgQueue, url: &str, bodies: &[&str], max_size: u32) {
+        for body in bodies {
+            let parameters = WebhookJobParameters {
+                body: body.to_string(),
+                headers: collections::HashMap::new(),
+                method: HttpMethod::POST,
+                url: url.to_owned(),
+                batch: Some(WebhookBatchParameters {
+                    key: "events".to_owned(),
+                    max_size,
+                    linger_ms: 0,
+                }),
+                auth: None,
+                validation: None,
+                template: None,
+            };
+            queue
+                .enqueue(NewJob::new(2, json!({}), parameters, "localhost"))
+                .await
+                .expect("failed to enqueue job");
+        }
+    }
+
+    async fn dequeue<'a>(
+        queue: &PgQueue,
+        n: u32,
+    ) -> PgTransactionBatch<'a, WebhookJobParameters, Value> {
+        queue
+            .dequeue_tx("worker_id", n)
+            .await
+            .expect("failed to dequeue jobs")
+            .expect("didn't find jobs to dequeue")
+    }
+
+    #[test]
+    fn test_batch_body() {
+        assert_eq!(
+            batch_body(["{\"a\":1}", "not json"].into_iter()),
+            r#"[{"a":1},"not json"]"#
+        );
+    }
+
+    #[test]
+    fn test_parse_item_statuses() {
+        assert_eq!(
+            parse_item_statuses(r#"[{"status": 200}, {"status": 429}]"#, 2),
+            Some(vec![StatusCode::OK, StatusCode::TOO_MANY_REQUESTS])
+        );
+        assert_eq!(parse_item_statuses(r#"[{"status": 200}]"#, 2), None);
+        assert_eq!(parse_item_statuses(r#"[{"status": 200}, {}]"#, 2), None);
+        assert_eq!(parse_item_statuses("ok", 1), None);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_group_sends_one_request_with_per_item_status(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_batched_delivery", db).await;
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/batch")
+                .json_body(json!([{"n": 1}, {"n": 2}, {"n": 3}]));
+            then.status(200)
+                .body(r#"[{"status": 200}, {"status": 503}, {"status": 400}]"#);
+        });
+        let url = server.url("/batch");
+        enqueue_batched_jobs(
+            &queue,
+            &url,
+            &[r#"{"n":1}"#, r#"{"n":2}"#, r#"{"n":3}"#],
+            10,
+        )
+        .await;
+
+        let mut batch = dequeue(&queue, 10).await;
+        let (singles, mut groups) = group_batched_jobs(std::mem::take(&mut batch.jobs));
+        assert!(singles.is_empty());
+        assert_eq!(groups.len(), 1);
+
+        let results = process_webhook_job_group(
+            build_http_client(time::Duration::from_secs(1), true).unwrap(),
+            groups.pop().unwrap(),
+            &RetryPolicy::default(),
+            &WebhookSigner::default(),
+            &TokenProvider::default(),
+            &DestinationLimiter::default(),
+            false,
+        )
+        .await;
+        batch.commit().await.expect("failed to commit batch");
+
+        mock.assert_hits(1);
+        assert!(matches!(results[0], Ok(WebhookResult::Success(_))));
+        assert!(matches!(results[1], Ok(WebhookResult::WillRetry)));
+        assert!(matches!(results[2], Ok(WebhookResult::BadResponse(_))));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_group_is_split_on_payload_too_large(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_batched_delivery_split", db).await;
+        let server = MockServer::start();
+        let too_large = server.mock(|when, then| {
+            when.method(POST)
+                .path("/batch")
+                .json_body(json!([{"n": 1}, {"n": 2}]));
+            then.status(413);
+        });
+        let first = server.mock(|when, then| {
+            when.method(POST)
+                .path("/batch")
+                .json_body(json!([{"n": 1}]));
+            then.status(200);
+        });
+        let second = server.mock(|when, then| {
+            when.method(POST)
+                .path("/batch")
+                .json_body(json!([{"n": 2}]));
+            then.status(200);
+        });
+        let url = server.url("/batch");
+        enqueue_batched_jobs(&queue, &url, &[r#"{"n":1}"#, r#"{"n":2}"#], 10).await;
+
+        let mut batch = dequeue(&queue, 10).await;
+        let (_, mut groups) = group_batched_jobs(std::mem::take(&mut batch.jobs));
+
+        let results = process_webhook_job_group(
+            build_http_client(time::Duration::from_secs(1), true).unwrap(),
+            groups.pop().unwrap(),
+            &RetryPolicy::default(),
+            &WebhookSigner::default(),
+            &TokenProvider::default(),
+            &DestinationLimiter::default(),
+            false,
+        )
+        .await;
+        batch.commit().await.expect("failed to commit batch");
+
+        too_large.assert_hits(1);
+        first.assert_hits(1);
+        second.assert_hits(1);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Ok(WebhookResult::Success(_)))));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_groups_respect_max_size(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_batched_delivery_max_size", db).await;
+        enqueue_batched_jobs(&queue, "http://localhost/batch", &["1", "2", "3"], 2).await;
+
+        let mut batch = dequeue(&queue, 10).await;
+        let (singles, groups) = group_batched_jobs(std::mem::take(&mut batch.jobs));
+
+        assert!(singles.is_empty());
+        assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_groups_are_split_by_request(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_batched_delivery_requests", db).await;
+        // Same host and batch key, but different URLs
+        enqueue_batched_jobs(&queue, "http://localhost/a", &["1", "2"], 10).await;
+        enqueue_batched_jobs(&queue, "http://localhost/b", &["3"], 10).await;
+
+        let mut batch = dequeue(&queue, 10).await;
+        let (singles, groups) = group_batched_jobs(std::mem::take(&mut batch.jobs));
+
+        assert!(singles.is_empty());
+        let urls: Vec<Vec<String>> = groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|job| job.parameters().url.clone())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                vec![
+                    "http://localhost/a".to_owned(),
+                    "http://localhost/a".to_owned()
+                ],
+                vec!["http://localhost/b".to_owned()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_key_covers_headers_and_auth() {
+        let parameters = WebhookJobParameters {
+            body: "{}".to_owned(),
+            headers: collections::HashMap::from([
+                ("a".to_owned(), "1".to_owned()),
+                ("b".to_owned(), "2".to_owned()),
+            ]),
+            method: HttpMethod::POST,
+            url: "http://localhost/batch".to_owned(),
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
+        };
+        let key = |parameters: &WebhookJobParameters| {
+            GroupKey::new("localhost".to_owned(), "events".to_owned(), parameters)
+        };
+
+        let mut reordered = parameters.clone();
+        reordered.headers = collections::HashMap::from([
+            ("b".to_owned(), "2".to_owned()),
+            ("a".to_owned(), "1".to_owned()),
+        ]);
+        assert!(key(&parameters) == key(&reordered));
+
+        let mut other_headers = parameters.clone();
+        other_headers
+            .headers
+            .insert("authorization".to_owned(), "Bearer x".to_owned());
+        assert!(key(&parameters) != key(&other_headers));
+
+        let mut other_auth = parameters.clone();
+        other_auth.auth = Some(WebhookAuth::Oauth2ClientCredentials {
+            scopes: Vec::new(),
+            credentials: "dest".to_owned(),
+        });
+        assert!(key(&parameters) != key(&other_auth));
+
+        let mut other_method = parameters.clone();
+        other_method.method = HttpMethod::PUT;
+        assert!(key(&parameters) != key(&other_method));
+    }
+}
@@ -4,6 +4,7 @@ use std::time;
 use envconfig::Envconfig;
 
 use common_kafka::config::KafkaConfig;
+use hook_common::queue::QueueBackend;
 
 #[derive(Envconfig, Clone)]
 pub struct Config {
@@ -19,9 +20,23 @@ pub struct Config {
     #[envconfig(default = "worker")]
     pub worker_name: String,
 
+    /// The queue jobs are consumed from: "pg" for the `PgQueue` named `queue_name`, or
+    /// "cyclotron" for the cyclotron queue named `cyclotron_queue_name`.
+    #[envconfig(default = "pg")]
+    pub queue_backend: QueueBackend,
+
     #[envconfig(default = "default")]
     pub queue_name: NonEmptyString,
 
+    /// Jobs in this queue must be in the format written by `CyclotronQueue`, like the jobs
+    /// enqueued by hook-api with its cyclotron backend, so it can't be shared with cyclotron-fetch
+    /// workers, which consume the "fetch" queue.
+    #[envconfig(default = "hooks")]
+    pub cyclotron_queue_name: NonEmptyString,
+
+    /// Defaults to `database_url`.
+    pub cyclotron_database_url: Option<String>,
+
     #[envconfig(default = "100")]
     pub poll_interval: EnvMsDuration,
 
@@ -31,6 +46,10 @@ pub struct Config {
     #[envconfig(default = "1024")]
     pub max_concurrent_jobs: usize,
 
+    /// How long to wait for jobs in flight to finish on SIGTERM before exiting anyway.
+    #[envconfig(default = "30000")]
+    pub drain_timeout: EnvMsDuration,
+
     #[envconfig(default = "100")]
     pub max_pg_connections: u32,
 
@@ -40,6 +59,17 @@ pub struct Config {
     #[envconfig(default = "1")]
     pub dequeue_batch_size: u32,
 
+    /// Maximum requests in flight to a single target host, 0 for no limit.
+    #[envconfig(default = "0")]
+    pub max_concurrent_jobs_per_target: usize,
+
+    /// Consecutive failures to a target host that open its circuit breaker, 0 to disable.
+    #[envconfig(default = "0")]
+    pub circuit_breaker_failure_threshold: u32,
+
+    #[envconfig(default = "30000")]
+    pub circuit_breaker_open_duration: EnvMsDuration,
+
     #[envconfig(default = "false")]
     pub allow_internal_ips: bool,
 
@@ -49,6 +79,21 @@ pub struct Config {
     #[envconfig(default = "cdp_function_callbacks")]
     pub cdp_function_callbacks_topic: String,
 
+    /// Per-destination signing secrets, as "<host>:<secret>[,<secret>];...". Requests to these
+    /// hosts get HMAC-SHA256 signature headers, see `common_webhook_signing`.
+    #[envconfig(default = "")]
+    pub webhook_signing_secrets: String,
+
+    /// OAuth2 client credentials that jobs can refer to by name in their `auth` parameters, as
+    /// "<name>=<client_id>:<client_secret>,<token_url>[,<scope> <scope>...];...". Credentials are
+    /// only ever sent to their own token URL, and jobs can only request the listed scopes.
+    #[envconfig(default = "")]
+    pub webhook_oauth_credentials: String,
+
+    /// How long before they expire cached bearer tokens are refreshed.
+    #[envconfig(default = "60000")]
+    pub webhook_oauth_refresh_margin: EnvMsDuration,
+
     #[envconfig(nested = true)]
     pub kafka: KafkaConfig,
 }
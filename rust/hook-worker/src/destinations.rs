@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Whether a job for a given target may be sent right now.
+#[derive(Debug)]
+pub enum Admission {
+    /// Send the request. The permit, if any, must be held until the request is done.
+    Allowed(Option<OwnedSemaphorePermit>),
+    /// The target already has the maximum number of requests in flight.
+    Saturated,
+    /// The target's circuit breaker is open for at least this long.
+    CircuitOpen(time::Duration),
+}
+
+#[derive(Debug)]
+struct TargetState {
+    semaphore: Arc<Semaphore>,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Per-target (i.e. per-hostname) concurrency caps and circuit breakers, so that one slow or
+/// failing destination can't take up all of the worker's job slots.
+///
+/// A breaker opens after `failure_threshold` consecutive failures. Once `open_duration` has passed
+/// requests are let through again, but the failure count is only reset by a success, so the next
+/// failure re-opens the breaker straight away.
+#[derive(Debug)]
+pub struct DestinationLimiter {
+    /// Maximum requests in flight per target, 0 for no limit.
+    max_concurrent_per_target: usize,
+    /// Consecutive failures that open a breaker, 0 to disable breakers.
+    failure_threshold: u32,
+    open_duration: time::Duration,
+    targets: Mutex<HashMap<String, TargetState>>,
+}
+
+impl DestinationLimiter {
+    pub fn new(
+        max_concurrent_per_target: usize,
+        failure_threshold: u32,
+        open_duration: time::Duration,
+    ) -> Self {
+        Self {
+            max_concurrent_per_target,
+            failure_threshold,
+            open_duration,
+            targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn admit(&self, target: &str) -> Admission {
+        let mut targets = self.targets.lock().expect("poisoned destinations lock");
+        let state = self.state(&mut targets, target);
+
+        if let Some(open_until) = state.open_until {
+            let now = Instant::now();
+            if open_until > now {
+                return Admission::CircuitOpen(open_until - now);
+            }
+        }
+
+        if self.max_concurrent_per_target == 0 {
+            return Admission::Allowed(None);
+        }
+
+        match state.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Admission::Allowed(Some(permit)),
+            Err(_) => Admission::Saturated,
+        }
+    }
+
+    /// The target responded, even if with a non-retryable error status.
+    pub fn record_success(&self, target: &str) {
+        let mut targets = self.targets.lock().expect("poisoned destinations lock");
+        let state = self.state(&mut targets, target);
+
+        state.consecutive_failures = 0;
+        if state.open_until.take().is_some() {
+            metrics::counter!(
+                "webhook_circuit_breaker_transitions",
+                &[("state", "closed")]
+            )
+            .increment(1);
+            metrics::gauge!("webhook_circuit_breakers_open").decrement(1.0);
+        }
+    }
+
+    /// The target timed out, couldn't be reached, or responded with a retryable error status.
+    pub fn record_failure(&self, target: &str) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+
+        let mut targets = self.targets.lock().expect("poisoned destinations lock");
+        let state = self.state(&mut targets, target);
+
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.consecutive_failures >= self.failure_threshold {
+            if state.open_until.is_none() {
+                metrics::gauge!("webhook_circuit_breakers_open").increment(1.0);
+            }
+            state.open_until = Some(Instant::now() + self.open_duration);
+            metrics::counter!("webhook_circuit_breaker_transitions", &[("state", "open")])
+                .increment(1);
+        }
+    }
+
+    fn state<'a>(
+        &self,
+        targets: &'a mut HashMap<String, TargetState>,
+        target: &str,
+    ) -> &'a mut TargetState {
+        targets
+            .entry(target.to_owned())
+            .or_insert_with(|| TargetState {
+                semaphore: Arc::new(Semaphore::new(self.max_concurrent_per_target)),
+                consecutive_failures: 0,
+                open_until: None,
+            })
+    }
+}
+
+impl Default for DestinationLimiter {
+    /// No concurrency caps and no circuit breakers.
+    fn default() -> Self {
+        Self::new(0, 0, time::Duration::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrency_cap_is_per_target() {
+        let limiter = DestinationLimiter::new(1, 0, time::Duration::ZERO);
+
+        let permit = match limiter.admit("a.com") {
+            Admission::Allowed(Some(permit)) => permit,
+            other => panic!("unexpected admission {:?}", other),
+        };
+        assert!(matches!(limiter.admit("a.com"), Admission::Saturated));
+        assert!(matches!(limiter.admit("b.com"), Admission::Allowed(_)));
+
+        drop(permit);
+        assert!(matches!(limiter.admit("a.com"), Admission::Allowed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let open_duration = time::Duration::from_millis(50);
+        let limiter = DestinationLimiter::new(0, 2, open_duration);
+
+        limiter.record_failure("a.com");
+        assert!(matches!(limiter.admit("a.com"), Admission::Allowed(None)));
+
+        limiter.record_failure("a.com");
+        match limiter.admit("a.com") {
+            Admission::CircuitOpen(remaining) => assert!(remaining <= open_duration),
+            other => panic!("unexpected admission {:?}", other),
+        }
+        assert!(matches!(limiter.admit("b.com"), Admission::Allowed(None)));
+
+        // After the open duration requests go through, but one more failure re-opens the breaker.
+        tokio::time::sleep(open_duration).await;
+        assert!(matches!(limiter.admit("a.com"), Admission::Allowed(None)));
+        limiter.record_failure("a.com");
+        assert!(matches!(limiter.admit("a.com"), Admission::CircuitOpen(_)));
+
+        // A success closes it.
+        tokio::time::sleep(open_duration).await;
+        limiter.record_success("a.com");
+        limiter.record_failure("a.com");
+        assert!(matches!(limiter.admit("a.com"), Admission::Allowed(None)));
+    }
+}
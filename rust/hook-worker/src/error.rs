@@ -7,6 +7,8 @@ use hook_common::{pgqueue, webhook::WebhookJobError};
 use http::StatusCode;
 use thiserror::Error;
 
+use crate::auth::AuthError;
+
 /// Enumeration of error classes handled by `WebhookWorker`.
 #[derive(Error, Debug)]
 pub enum WebhookError {
@@ -14,6 +16,8 @@ pub enum WebhookError {
     Parse(#[from] WebhookParseError),
     #[error(transparent)]
     Request(#[from] WebhookRequestError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
 }
 
 /// Enumeration of parsing errors that can occur as `WebhookWorker` sets up a webhook.
@@ -1,4 +1,10 @@
+pub mod auth;
+pub mod batch;
 pub mod config;
+pub mod destinations;
 pub mod error;
+pub mod signing;
+pub mod template;
 pub mod util;
+pub mod validation;
 pub mod worker;
@@ -1,20 +1,45 @@
-//! Consume `PgQueue` jobs to run webhook calls.
+//! Consume `PgQueue` or cyclotron jobs to run webhook calls.
 use axum::routing::get;
 use axum::Router;
 use envconfig::Envconfig;
+use hook_common::cyclotron::CyclotronQueue;
 use hook_common::pgqueue::PgQueue;
+use hook_common::queue::{JobQueue, QueueBackend};
 use hook_common::retry::RetryPolicy;
+use hook_common::webhook::WebhookJobParameters;
+use rdkafka::producer::FutureProducer;
+use serde_json::Value;
+use sqlx::postgres::PgPoolOptions;
 use std::future::ready;
+use tokio::signal;
 
-use common_kafka::kafka_producer::create_kafka_producer;
+use common_kafka::kafka_producer::{create_kafka_producer, KafkaContext};
 use common_metrics::{serve, setup_metrics_routes};
-use health::HealthRegistry;
+use health::{HealthHandle, HealthRegistry};
+use hook_worker::auth::TokenProvider;
 use hook_worker::config::Config;
+use hook_worker::destinations::DestinationLimiter;
 use hook_worker::error::WorkerError;
+use hook_worker::signing::WebhookSigner;
 use hook_worker::worker::WebhookWorker;
 
 common_alloc::used!();
 
+async fn shutdown() {
+    let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("failed to register SIGTERM handler");
+
+    let mut interrupt = signal::unix::signal(signal::unix::SignalKind::interrupt())
+        .expect("failed to register SIGINT handler");
+
+    tokio::select! {
+        _ = term.recv() => {},
+        _ = interrupt.recv() => {},
+    };
+
+    tracing::info!("Shutting down gracefully...");
+}
+
 #[tokio::main]
 async fn main() -> Result<(), WorkerError> {
     tracing_subscriber::fmt::init();
@@ -38,15 +63,6 @@ async fn main() -> Result<(), WorkerError> {
         retry_policy_builder
     };
 
-    let queue = PgQueue::new(
-        config.queue_name.as_str(),
-        &config.database_url,
-        config.max_pg_connections,
-        "hook-worker",
-    )
-    .await
-    .expect("failed to initialize queue");
-
     let kafka_liveness = liveness
         .register("rdkafka".to_string(), time::Duration::seconds(30))
         .await;
@@ -54,20 +70,13 @@ async fn main() -> Result<(), WorkerError> {
         .await
         .expect("failed to create kafka producer");
 
-    let worker = WebhookWorker::new(
-        &config.worker_name,
-        &queue,
-        config.dequeue_batch_size,
-        config.poll_interval.0,
-        config.request_timeout.0,
-        config.max_concurrent_jobs,
-        retry_policy_builder.provide(),
-        config.allow_internal_ips,
-        kafka_producer,
-        config.cdp_function_callbacks_topic.to_owned(),
-        config.hog_mode,
-        worker_liveness,
-    );
+    let signer = WebhookSigner::new(&config.webhook_signing_secrets)
+        .expect("invalid webhook signing secrets");
+    let tokens = TokenProvider::new(
+        &config.webhook_oauth_credentials,
+        config.webhook_oauth_refresh_margin.0,
+    )
+    .expect("invalid webhook oauth credentials");
 
     let router = Router::new()
         .route("/", get(index))
@@ -81,11 +90,95 @@ async fn main() -> Result<(), WorkerError> {
             .expect("failed to start serving metrics");
     });
 
-    worker.run().await;
+    let retry_policy = retry_policy_builder.provide();
+    match config.queue_backend {
+        QueueBackend::Pg => {
+            let queue = PgQueue::new(
+                config.queue_name.as_str(),
+                &config.database_url,
+                config.max_pg_connections,
+                "hook-worker",
+            )
+            .await
+            .expect("failed to initialize queue");
+
+            run_worker(
+                &config,
+                &queue,
+                retry_policy,
+                kafka_producer,
+                signer,
+                tokens,
+                worker_liveness,
+            )
+            .await
+        }
+        QueueBackend::Cyclotron => {
+            let database_url = config
+                .cyclotron_database_url
+                .as_deref()
+                .unwrap_or(&config.database_url);
+            let pool = PgPoolOptions::new()
+                .max_connections(config.max_pg_connections)
+                .connect_lazy(database_url)
+                .expect("failed to initialize cyclotron pool");
+            let queue = CyclotronQueue::from_pool(
+                config.cyclotron_queue_name.as_str(),
+                pool,
+                Default::default(),
+            );
+
+            run_worker(
+                &config,
+                &queue,
+                retry_policy,
+                kafka_producer,
+                signer,
+                tokens,
+                worker_liveness,
+            )
+            .await
+        }
+    }
 
     Ok(())
 }
 
+async fn run_worker<Q: JobQueue<WebhookJobParameters, Value>>(
+    config: &Config,
+    queue: &Q,
+    retry_policy: RetryPolicy,
+    kafka_producer: FutureProducer<KafkaContext>,
+    signer: WebhookSigner,
+    tokens: TokenProvider,
+    liveness: HealthHandle,
+) {
+    let worker = WebhookWorker::new(
+        &config.worker_name,
+        queue,
+        config.dequeue_batch_size,
+        config.poll_interval.0,
+        config.request_timeout.0,
+        config.max_concurrent_jobs,
+        config.drain_timeout.0,
+        retry_policy,
+        config.allow_internal_ips,
+        kafka_producer,
+        config.cdp_function_callbacks_topic.to_owned(),
+        config.hog_mode,
+        signer,
+        tokens,
+        DestinationLimiter::new(
+            config.max_concurrent_jobs_per_target,
+            config.circuit_breaker_failure_threshold,
+            config.circuit_breaker_open_duration.0,
+        ),
+        liveness,
+    );
+
+    worker.run(shutdown()).await;
+}
+
 pub async fn index() -> &'static str {
     "rusty-hook worker"
 }
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use common_webhook_signing::{
+    SigningKeys, SigningKeysError, ID_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use reqwest::header::{HeaderMap, HeaderValue};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SignerConfigError {
+    #[error("invalid signing config entry {0}, expected <host>:<secret>[,<secret>]")]
+    InvalidEntry(usize),
+    #[error("invalid signing secrets for {0}: {1}")]
+    InvalidKeys(String, SigningKeysError),
+}
+
+/// Signs requests to destinations that have signing secrets configured. Destinations are matched
+/// by URL host; requests to any other host are sent unsigned.
+#[derive(Debug, Default, Clone)]
+pub struct WebhookSigner {
+    destinations: HashMap<String, SigningKeys>,
+}
+
+impl WebhookSigner {
+    /// Parse a config string in the format "<host>:<secret>[,<secret>];...", where a second
+    /// secret can be given while rotating.
+    pub fn new(config: &str) -> Result<Self, SignerConfigError> {
+        let mut destinations = HashMap::new();
+
+        // Entries are reported by position so that secrets never end up in logs.
+        let entries = config.split(';').map(str::trim).filter(|e| !e.is_empty());
+        for (i, entry) in entries.enumerate() {
+            let (host, secrets) = entry
+                .split_once(':')
+                .ok_or(SignerConfigError::InvalidEntry(i))?;
+            let host = host.trim().to_ascii_lowercase();
+            let keys = secrets
+                .parse::<SigningKeys>()
+                .map_err(|e| SignerConfigError::InvalidKeys(host.clone(), e))?;
+            destinations.insert(host, keys);
+        }
+
+        Ok(Self { destinations })
+    }
+
+    /// Add the id, timestamp and signature headers if the URL's host has signing secrets. Every
+    /// request gets a new id, so retries of a job are signed as separate deliveries.
+    pub fn sign(&self, url: &reqwest::Url, body: &[u8], timestamp: i64, headers: &mut HeaderMap) {
+        let Some(keys) = url.host_str().and_then(|host| self.destinations.get(host)) else {
+            return;
+        };
+
+        let id = format!("msg_{}", Uuid::now_v7().simple());
+        let signature = keys.sign(&id, timestamp, body);
+        headers.insert(
+            ID_HEADER,
+            HeaderValue::from_str(&id).expect("uuids are valid header values"),
+        );
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(
+            SIGNATURE_HEADER,
+            HeaderValue::from_str(&signature).expect("base64 signatures are valid header values"),
+        );
+
+        metrics::counter!("webhook_requests_signed").increment(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sign_configured_hosts_only() {
+        let signer = WebhookSigner::new("example.com:secret; Other.com:new,old").unwrap();
+        let body = b"{}";
+
+        let mut headers = HeaderMap::new();
+        let url = "https://example.com/hook".parse().unwrap();
+        signer.sign(&url, body, 1_700_000_000, &mut headers);
+
+        assert_eq!(headers.get(TIMESTAMP_HEADER).unwrap(), "1700000000");
+        assert!(headers
+            .get(ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("msg_"));
+        let keys: SigningKeys = "secret".parse().unwrap();
+        assert_eq!(
+            keys.verify(
+                headers.get(ID_HEADER).unwrap().to_str().unwrap(),
+                headers.get(TIMESTAMP_HEADER).unwrap().to_str().unwrap(),
+                headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(),
+                body,
+                1_700_000_000,
+                Duration::from_secs(60),
+            ),
+            Ok(())
+        );
+
+        let mut headers = HeaderMap::new();
+        let url = "https://other.com/hook".parse().unwrap();
+        signer.sign(&url, body, 1_700_000_000, &mut headers);
+        assert_eq!(
+            headers
+                .get(SIGNATURE_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .split(' ')
+                .count(),
+            2
+        );
+
+        let mut headers = HeaderMap::new();
+        let url = "https://unsigned.com/hook".parse().unwrap();
+        signer.sign(&url, body, 1_700_000_000, &mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(WebhookSigner::new("").unwrap().destinations.is_empty());
+        assert_eq!(
+            WebhookSigner::new("example.com").unwrap_err(),
+            SignerConfigError::InvalidEntry(0)
+        );
+        assert_eq!(
+            WebhookSigner::new("example.com:a,b,c").unwrap_err(),
+            SignerConfigError::InvalidKeys(
+                "example.com".to_string(),
+                SigningKeysError::TooManyKeys(3)
+            )
+        );
+    }
+}
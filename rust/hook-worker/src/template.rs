@@ -0,0 +1,134 @@
+use chrono::SecondsFormat;
+use hook_common::webhook::{WebhookJobParameters, WebhookTemplate};
+use serde_json::{json, Value};
+
+use crate::validation::select;
+use crate::worker::WebhookJob;
+
+/// Fill in the placeholders in a job's body and headers, see `WebhookTemplate`. Returns the job's
+/// parameters as they are if it has no template, and why not if a placeholder can't be filled in.
+pub(crate) fn render_parameters<W: WebhookJob>(
+    job: &W,
+) -> Result<Option<WebhookJobParameters>, String> {
+    let parameters = job.parameters();
+    let Some(template) = &parameters.template else {
+        return Ok(None);
+    };
+
+    let context = template_context(template, job);
+    let headers = parameters
+        .headers
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), render(value, &context, false)?)))
+        .collect::<Result<_, String>>()?;
+
+    Ok(Some(WebhookJobParameters {
+        body: render(&parameters.body, &context, true)?,
+        headers,
+        ..parameters.clone()
+    }))
+}
+
+fn template_context<W: WebhookJob>(template: &WebhookTemplate, job: &W) -> Value {
+    json!({
+        "variables": template.variables,
+        "job": {
+            "attempt": job.attempt(),
+            "max_attempts": job.max_attempts(),
+            "created_at": job.created_at().to_rfc3339_opts(SecondsFormat::Millis, true),
+        },
+    })
+}
+
+/// Replace each `{{ path }}` in `template` with the value at `path` in `context`. Values are
+/// written as JSON if `as_json`, otherwise strings are written without quotes.
+fn render(template: &str, context: &Value, as_json: bool) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        let end = after_open
+            .find("}}")
+            .ok_or_else(|| "unclosed {{ in template".to_owned())?;
+        let path = after_open[..end].trim();
+
+        let value = select(context, &format!("${}", path_selector(path)))
+            .map_err(|e| format!("{{{{ {} }}}}: {}", path, e))?
+            .ok_or_else(|| format!("{{{{ {} }}}}: not found", path))?;
+        match value {
+            Value::String(s) if !as_json => rendered.push_str(s),
+            value => rendered.push_str(&value.to_string()),
+        }
+
+        rest = &after_open[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Paths are written without the leading `$`, and without a leading `.` unless they start with
+/// a bracket, e.g. `job.attempt` or `['variables']['a.b']`.
+fn path_selector(path: &str) -> String {
+    if path.starts_with('[') {
+        path.to_owned()
+    } else {
+        format!(".{}", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Value {
+        json!({
+            "variables": {"user": {"id": 7, "name": "Jane \"J\" Doe"}},
+            "job": {"attempt": 2},
+        })
+    }
+
+    #[test]
+    fn test_render_body() {
+        assert_eq!(
+            render(
+                r#"{"user": {{ variables.user.id }}, "name": {{variables.user.name}}}"#,
+                &context(),
+                true
+            ),
+            Ok(r#"{"user": 7, "name": "Jane \"J\" Doe"}"#.to_owned())
+        );
+        assert_eq!(
+            render("no placeholders", &context(), true),
+            Ok("no placeholders".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_render_header() {
+        assert_eq!(
+            render(
+                "{{ variables.user.name }}/{{ job.attempt }}",
+                &context(),
+                false
+            ),
+            Ok(r#"Jane "J" Doe/2"#.to_owned())
+        );
+        assert_eq!(
+            render("{{ ['variables']['user'] }}", &context(), false),
+            Ok(r#"{"id":7,"name":"Jane \"J\" Doe"}"#.to_owned())
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        assert_eq!(
+            render("{{ variables.missing }}", &context(), true),
+            Err("{{ variables.missing }}: not found".to_owned())
+        );
+        assert!(render("{{ job.attempt", &context(), true).is_err());
+        assert!(render("{{ job..attempt }}", &context(), true).is_err());
+    }
+}
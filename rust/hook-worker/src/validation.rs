@@ -0,0 +1,297 @@
+use std::time;
+
+use hook_common::webhook::{WebhookResponseAssertion, WebhookResponseValidation};
+use http::StatusCode;
+use serde_json::Value;
+
+use crate::error::{WebhookError, WebhookRequestError};
+use crate::util::first_n_bytes_of_response;
+use crate::worker::{is_retryable_status, parse_retry_after_header, MAX_RESPONSE_BODY};
+
+/// A response to validate, whether or not its status was an error.
+pub(crate) struct ReceivedResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) body: Option<String>,
+    pub(crate) retry_after: Option<time::Duration>,
+}
+
+impl ReceivedResponse {
+    /// Get the response out of the result of `send_webhook`. Errors where no response was
+    /// received, like timeouts, are given back to be handled as usual.
+    pub(crate) async fn from_send_result(
+        result: Result<reqwest::Response, WebhookError>,
+    ) -> Result<Self, WebhookError> {
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = parse_retry_after_header(response.headers());
+                // An unreadable body is treated as a missing one, so assertions on it won't hold.
+                let body = first_n_bytes_of_response(response, MAX_RESPONSE_BODY)
+                    .await
+                    .ok();
+
+                Ok(Self {
+                    status,
+                    body,
+                    retry_after,
+                })
+            }
+            Err(WebhookError::Request(WebhookRequestError::RetryableRequestError {
+                status: Some(status),
+                response,
+                retry_after,
+                ..
+            })) => Ok(Self {
+                status,
+                body: response,
+                retry_after,
+            }),
+            Err(WebhookError::Request(
+                WebhookRequestError::NonRetryableRetryableRequestError {
+                    status: Some(status),
+                    response,
+                    ..
+                },
+            )) => Ok(Self {
+                status,
+                body: response,
+                retry_after: None,
+            }),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Success,
+    Retry(String),
+    Fail(String),
+}
+
+/// Judge a response by the job's success criteria and retry rules.
+pub(crate) fn validate_response(
+    validation: &WebhookResponseValidation,
+    status: StatusCode,
+    body: Option<&str>,
+) -> Verdict {
+    let is_success_status = if validation.success_statuses.is_empty() {
+        status.is_success()
+    } else {
+        validation
+            .success_statuses
+            .iter()
+            .any(|range| range.contains(status.as_u16()))
+    };
+
+    if !is_success_status {
+        let reason = format!("unexpected status {}", status.as_u16());
+        let is_retryable = if validation.retry_statuses.is_empty() {
+            is_retryable_status(status)
+        } else {
+            validation
+                .retry_statuses
+                .iter()
+                .any(|range| range.contains(status.as_u16()))
+        };
+
+        return if is_retryable {
+            Verdict::Retry(reason)
+        } else {
+            Verdict::Fail(reason)
+        };
+    }
+
+    if validation.assertions.is_empty() {
+        return Verdict::Success;
+    }
+
+    let body: Option<Value> = body.and_then(|body| serde_json::from_str(body).ok());
+    for assertion in &validation.assertions {
+        if let Err(reason) = check_assertion(assertion, body.as_ref()) {
+            return if assertion.retryable {
+                Verdict::Retry(reason)
+            } else {
+                Verdict::Fail(reason)
+            };
+        }
+    }
+
+    Verdict::Success
+}
+
+fn check_assertion(
+    assertion: &WebhookResponseAssertion,
+    body: Option<&Value>,
+) -> Result<(), String> {
+    let body = body.ok_or_else(|| format!("{}: response body is not JSON", assertion.path))?;
+    let value = select(body, &assertion.path)
+        .map_err(|e| format!("{}: {}", assertion.path, e))?
+        .ok_or_else(|| format!("{}: not found", assertion.path))?;
+
+    match &assertion.equals {
+        Some(expected) if expected != value => Err(format!(
+            "{}: expected {}, got {}",
+            assertion.path, expected, value
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Select a value with a JSONPath of the form `$.a.b[0]['c']`. Wildcards, filters and recursive
+/// descent are not supported, as assertions are on a single value.
+pub(crate) fn select<'a>(value: &'a Value, path: &str) -> Result<Option<&'a Value>, &'static str> {
+    let mut rest = path
+        .trim()
+        .strip_prefix('$')
+        .ok_or("path must start with $")?;
+    let mut current = value;
+
+    while !rest.is_empty() {
+        let next = if let Some(after_dot) = rest.strip_prefix('.') {
+            let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+            let (key, after_key) = after_dot.split_at(end);
+            if key.is_empty() {
+                return Err("empty key");
+            }
+            rest = after_key;
+            current.get(key)
+        } else if let Some(after_bracket) = rest.strip_prefix('[') {
+            let end = after_bracket.find(']').ok_or("unclosed [")?;
+            let (selector, after_selector) = after_bracket.split_at(end);
+            rest = &after_selector[1..];
+
+            let quoted = selector
+                .strip_prefix('\'')
+                .and_then(|s| s.strip_suffix('\''))
+                .or_else(|| selector.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+            match quoted {
+                Some(key) => current.get(key),
+                None => {
+                    let index = selector
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| "invalid index")?;
+                    current.get(index)
+                }
+            }
+        } else {
+            return Err("expected . or [");
+        };
+
+        match next {
+            Some(value) => current = value,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(current))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn validation(value: Value) -> WebhookResponseValidation {
+        serde_json::from_value(value).expect("invalid validation")
+    }
+
+    #[test]
+    fn test_select() {
+        let body = json!({"a": {"b": [{"c": 1}, {"d.e": true}]}});
+
+        assert_eq!(select(&body, "$"), Ok(Some(&body)));
+        assert_eq!(select(&body, "$.a.b[0].c"), Ok(Some(&json!(1))));
+        assert_eq!(select(&body, "$.a.b[1]['d.e']"), Ok(Some(&json!(true))));
+        assert_eq!(
+            select(&body, "$['a'][\"b\"][0]"),
+            Ok(Some(&json!({"c": 1})))
+        );
+        assert_eq!(select(&body, "$.a.b[2]"), Ok(None));
+        assert_eq!(select(&body, "$.x.y"), Ok(None));
+        assert!(select(&body, "a.b").is_err());
+        assert!(select(&body, "$.a[b").is_err());
+        assert!(select(&body, "$.a.b[x]").is_err());
+    }
+
+    #[test]
+    fn test_status_only() {
+        let default = WebhookResponseValidation::default();
+        assert_eq!(
+            validate_response(&default, StatusCode::OK, None),
+            Verdict::Success
+        );
+        assert!(matches!(
+            validate_response(&default, StatusCode::SERVICE_UNAVAILABLE, None),
+            Verdict::Retry(_)
+        ));
+        assert!(matches!(
+            validate_response(&default, StatusCode::NOT_FOUND, None),
+            Verdict::Fail(_)
+        ));
+
+        let custom = validation(json!({
+            "success_statuses": ["200-299", "404"],
+            "retry_statuses": ["409"],
+        }));
+        assert_eq!(
+            validate_response(&custom, StatusCode::NOT_FOUND, None),
+            Verdict::Success
+        );
+        assert!(matches!(
+            validate_response(&custom, StatusCode::CONFLICT, None),
+            Verdict::Retry(_)
+        ));
+        assert!(matches!(
+            validate_response(&custom, StatusCode::SERVICE_UNAVAILABLE, None),
+            Verdict::Fail(_)
+        ));
+    }
+
+    #[test]
+    fn test_assertions() {
+        let validation = validation(json!({
+            "assertions": [
+                {"path": "$.ok", "equals": true, "retryable": true},
+                {"path": "$.id"},
+            ],
+        }));
+
+        assert_eq!(
+            validate_response(
+                &validation,
+                StatusCode::OK,
+                Some(r#"{"ok": true, "id": 1}"#)
+            ),
+            Verdict::Success
+        );
+        assert_eq!(
+            validate_response(&validation, StatusCode::OK, Some(r#"{"ok": false}"#)),
+            Verdict::Retry("$.ok: expected true, got false".to_owned())
+        );
+        assert_eq!(
+            validate_response(&validation, StatusCode::OK, Some(r#"{"ok": true}"#)),
+            Verdict::Fail("$.id: not found".to_owned())
+        );
+        assert_eq!(
+            validate_response(&validation, StatusCode::OK, Some("not json")),
+            Verdict::Retry("$.ok: response body is not JSON".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_invalid_status_range() {
+        assert!(serde_json::from_value::<WebhookResponseValidation>(
+            json!({"success_statuses": ["299-200"]})
+        )
+        .is_err());
+        assert_eq!(
+            serde_json::to_value(validation(
+                json!({"success_statuses": ["200-299", "404-404"]})
+            ))
+            .unwrap(),
+            json!({"success_statuses": ["200-299", "404"]})
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::future::Future;
 use std::sync::Arc;
 use std::time;
 use std::{collections, iter};
@@ -13,66 +14,47 @@ use reqwest::{header, Client};
 use serde_json::{json, Value};
 use tokio::sync;
 use tokio::time::{sleep, Duration};
-use tracing::error;
+use tracing::{error, info, warn};
 
 use common_kafka::kafka_producer::KafkaContext;
-use hook_common::pgqueue::PgTransactionBatch;
 use hook_common::{
-    pgqueue::{Job, PgQueue, PgQueueJob, PgTransactionJob, RetryError, RetryInvalidError},
+    pgqueue::{RetryError, RetryInvalidError},
+    queue::{JobBatch, JobQueue, QueueJob},
     retry::RetryPolicy,
     webhook::{HttpMethod, WebhookJobError, WebhookJobParameters},
 };
 
+use crate::auth::TokenProvider;
+use crate::batch::{group_batched_jobs, process_webhook_job_group};
+use crate::destinations::{Admission, DestinationLimiter};
 use crate::error::{
     is_error_source, WebhookError, WebhookParseError, WebhookRequestError, WorkerError,
 };
+use crate::signing::WebhookSigner;
+use crate::template::render_parameters;
 use crate::util::first_n_bytes_of_response;
+use crate::validation::{validate_response, ReceivedResponse, Verdict};
 use common_dns::{NoPublicIPv4Error, PublicIPv4Resolver};
 
 // TODO: Either make this configurable or adjust it once we don't produce results to Kafka, where
 // our size limit is relatively low.
-const MAX_RESPONSE_BODY: usize = 256 * 1024;
+pub(crate) const MAX_RESPONSE_BODY: usize = 256 * 1024;
 
-/// A WebhookJob is any `PgQueueJob` with `WebhookJobParameters` and `Value`.
-trait WebhookJob: PgQueueJob + std::marker::Send {
-    fn parameters(&self) -> &WebhookJobParameters;
-    fn take_metadata(&mut self) -> Value;
-    fn job(&self) -> &Job<WebhookJobParameters, Value>;
-
-    fn attempt(&self) -> i32 {
-        self.job().attempt
-    }
-
-    fn queue(&self) -> String {
-        self.job().queue.to_owned()
-    }
-
-    #[allow(dead_code)]
-    fn target(&self) -> String {
-        self.job().target.to_owned()
-    }
+/// A WebhookJob is any `QueueJob` with `WebhookJobParameters` and `Value`.
+pub(crate) trait WebhookJob:
+    QueueJob<Parameters = WebhookJobParameters, Metadata = Value>
+{
 }
 
-impl WebhookJob for PgTransactionJob<'_, WebhookJobParameters, Value> {
-    fn parameters(&self) -> &WebhookJobParameters {
-        &self.job.parameters
-    }
-
-    fn take_metadata(&mut self) -> Value {
-        self.job.metadata.take()
-    }
-
-    fn job(&self) -> &Job<WebhookJobParameters, Value> {
-        &self.job
-    }
-}
+impl<T: QueueJob<Parameters = WebhookJobParameters, Metadata = Value>> WebhookJob for T {}
 
-/// A worker to poll `PgQueue` and spawn tasks to process webhooks when a job becomes available.
-pub struct WebhookWorker<'p> {
+/// A worker to poll a `JobQueue`, like `PgQueue` or `CyclotronQueue`, and spawn tasks to process
+/// webhooks when a job becomes available.
+pub struct WebhookWorker<'p, Q> {
     /// An identifier for this worker. Used to mark jobs we have consumed.
     name: String,
     /// The queue we will be dequeuing jobs from.
-    queue: &'p PgQueue,
+    queue: &'p Q,
     /// The maximum number of jobs to dequeue in one query.
     dequeue_batch_size: u32,
     /// The interval for polling the queue.
@@ -81,6 +63,8 @@ pub struct WebhookWorker<'p> {
     http_client: reqwest::Client,
     /// Maximum number of concurrent jobs being processed.
     max_concurrent_jobs: usize,
+    /// How long to wait for jobs in flight to finish when shutting down.
+    drain_timeout: time::Duration,
     /// The retry policy used to calculate retry intervals when a job fails with a retryable error.
     retry_policy: RetryPolicy,
     /// Kafka producer used to send results when in Hog mode
@@ -89,6 +73,12 @@ pub struct WebhookWorker<'p> {
     cdp_function_callbacks_topic: &'static str,
     /// Whether we are running in Hog mode or not
     hog_mode: bool,
+    /// Adds signature headers to requests for destinations with signing secrets.
+    signer: Arc<WebhookSigner>,
+    /// Fetches and caches bearer tokens for destinations that need them.
+    tokens: Arc<TokenProvider>,
+    /// Per-target concurrency caps and circuit breakers.
+    destinations: Arc<DestinationLimiter>,
     /// The liveness check handle, to call on a schedule to report healthy
     liveness: HealthHandle,
 }
@@ -112,20 +102,24 @@ pub fn build_http_client(
     client_builder.build()
 }
 
-impl<'p> WebhookWorker<'p> {
+impl<'p, Q: JobQueue<WebhookJobParameters, Value>> WebhookWorker<'p, Q> {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: &str,
-        queue: &'p PgQueue,
+        queue: &'p Q,
         dequeue_batch_size: u32,
         poll_interval: time::Duration,
         request_timeout: time::Duration,
         max_concurrent_jobs: usize,
+        drain_timeout: time::Duration,
         retry_policy: RetryPolicy,
         allow_internal_ips: bool,
         kafka_producer: FutureProducer<KafkaContext>,
         cdp_function_callbacks_topic: String,
         hog_mode: bool,
+        signer: WebhookSigner,
+        tokens: TokenProvider,
+        destinations: DestinationLimiter,
         liveness: HealthHandle,
     ) -> Self {
         let http_client = build_http_client(request_timeout, allow_internal_ips)
@@ -138,39 +132,55 @@ impl<'p> WebhookWorker<'p> {
             poll_interval,
             http_client,
             max_concurrent_jobs,
+            drain_timeout,
             retry_policy,
             kafka_producer,
             cdp_function_callbacks_topic: cdp_function_callbacks_topic.leak(),
             hog_mode,
+            signer: Arc::new(signer),
+            tokens: Arc::new(tokens),
+            destinations: Arc::new(destinations),
             liveness,
         }
     }
 
-    /// Wait until at least one job becomes available in our queue in transactional mode.
-    async fn wait_for_jobs_tx<'a>(&self) -> PgTransactionBatch<'a, WebhookJobParameters, Value> {
+    /// Wait until at least one job becomes available in our queue, or until `shutdown` resolves.
+    /// We only stop between dequeues, so a dequeue is never interrupted after taking jobs.
+    async fn wait_for_jobs<S>(&self, shutdown: &mut S) -> Option<Q::Batch>
+    where
+        S: Future<Output = ()> + Unpin,
+    {
         let mut interval = tokio::time::interval(self.poll_interval);
 
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = &mut *shutdown => return None,
+            }
             self.liveness.report_healthy().await;
 
             match self
                 .queue
-                .dequeue_tx(&self.name, self.dequeue_batch_size)
+                .dequeue(&self.name, self.dequeue_batch_size)
                 .await
             {
-                Ok(Some(batch)) => return batch,
+                Ok(Some(batch)) => return Some(batch),
                 Ok(None) => continue,
                 Err(error) => {
-                    error!("error while trying to dequeue_tx job: {}", error);
+                    error!("error while trying to dequeue job: {}", error);
                     continue;
                 }
             }
         }
     }
 
-    /// Run this worker to continuously process any jobs that become available.
-    pub async fn run(&self) {
+    /// Run this worker to continuously process any jobs that become available, until `shutdown`
+    /// resolves. We then stop dequeuing, wait up to `drain_timeout` for jobs in flight to finish,
+    /// put back any that don't, and flush the queue, so that no job waits on the janitor to be
+    /// released.
+    pub async fn run<S: Future<Output = ()>>(&self, shutdown: S) {
+        tokio::pin!(shutdown);
+
         let semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_jobs));
         let report_semaphore_utilization = || {
             metrics::gauge!("webhook_worker_saturation_percent")
@@ -185,21 +195,34 @@ impl<'p> WebhookWorker<'p> {
             //   `min(semaphore.available_permits(), dequeue_batch_size)`
             // And then dequeue only up to that many jobs. We'd then need to hand back the
             // difference in permits based on how many jobs were dequeued.
-            let batch = self.wait_for_jobs_tx().await;
-            dequeue_batch_size_histogram.record(batch.jobs.len() as f64);
+            let Some(mut batch) = self.wait_for_jobs(&mut shutdown).await else {
+                break;
+            };
+            let jobs = batch.take_jobs();
+            dequeue_batch_size_histogram.record(jobs.len() as f64);
 
             // Get enough permits for the jobs before spawning a task.
-            let permits = semaphore
-                .clone()
-                .acquire_many_owned(batch.jobs.len() as u32)
-                .await
-                .expect("semaphore has been closed");
+            let permits = tokio::select! {
+                permits = semaphore.clone().acquire_many_owned(jobs.len() as u32) => {
+                    permits.expect("semaphore has been closed")
+                }
+                _ = &mut shutdown => {
+                    // None of these jobs have started, so dropping them and their batch
+                    // puts them straight back in the queue.
+                    drop(jobs);
+                    drop(batch);
+                    break;
+                }
+            };
 
             let http_client = self.http_client.clone();
             let retry_policy = self.retry_policy.clone();
             let kafka_producer = self.kafka_producer.clone();
             let cdp_function_callbacks_topic = self.cdp_function_callbacks_topic;
             let hog_mode = self.hog_mode;
+            let signer = self.signer.clone();
+            let tokens = self.tokens.clone();
+            let destinations = self.destinations.clone();
 
             tokio::spawn(async move {
                 // Move `permits` into the closure so they will be dropped when the scope ends.
@@ -207,15 +230,38 @@ impl<'p> WebhookWorker<'p> {
 
                 process_batch(
                     batch,
+                    jobs,
                     http_client,
                     retry_policy,
                     kafka_producer,
                     cdp_function_callbacks_topic,
                     hog_mode,
+                    signer,
+                    tokens,
+                    destinations,
                 )
                 .await
             });
         }
+
+        info!("shutting down, waiting for jobs in flight to finish");
+        let all_permits = semaphore.acquire_many(self.max_concurrent_jobs as u32);
+        if tokio::time::timeout(self.drain_timeout, all_permits)
+            .await
+            .is_err()
+        {
+            match self.queue.release_all().await {
+                Ok(released) => warn!(
+                    "timed out waiting for jobs in flight to finish, released {} jobs",
+                    released
+                ),
+                Err(error) => error!("error releasing jobs in flight on shutdown: {}", error),
+            }
+        }
+
+        if let Err(error) = self.queue.flush().await {
+            error!("error flushing queue on shutdown: {}", error);
+        }
     }
 }
 
@@ -238,33 +284,80 @@ async fn log_kafka_error_and_sleep(step: &str, error: Option<KafkaError>) {
     sleep(Duration::from_secs(30)).await;
 }
 
-async fn process_batch<'a>(
-    mut batch: PgTransactionBatch<'a, WebhookJobParameters, Value>,
+#[allow(clippy::too_many_arguments)]
+async fn process_batch<B: JobBatch>(
+    batch: B,
+    jobs: Vec<B::Job>,
     http_client: Client,
     retry_policy: RetryPolicy,
     kafka_producer: FutureProducer<KafkaContext>,
     cdp_function_callbacks_topic: &'static str,
     hog_mode: bool,
-) {
-    let mut futures = Vec::with_capacity(batch.jobs.len());
-    let mut metadata_vec = Vec::with_capacity(batch.jobs.len());
-
-    // We have to `take` the Vec of jobs from the batch to avoid a borrow checker
-    // error below when we commit.
-    for mut job in std::mem::take(&mut batch.jobs) {
+    signer: Arc<WebhookSigner>,
+    tokens: Arc<TokenProvider>,
+    destinations: Arc<DestinationLimiter>,
+) where
+    B::Job: WebhookJob,
+{
+    let mut futures = Vec::with_capacity(jobs.len());
+    let mut metadata_vec = Vec::with_capacity(jobs.len());
+
+    let (jobs, groups) = group_batched_jobs(jobs);
+
+    for mut job in jobs {
         let http_client = http_client.clone();
         let retry_policy = retry_policy.clone();
+        let signer = signer.clone();
+        let tokens = tokens.clone();
+        let destinations = destinations.clone();
 
         metadata_vec.push(job.take_metadata());
 
         let read_body = hog_mode;
-        let future =
-            async move { process_webhook_job(http_client, job, &retry_policy, read_body).await };
+        let future = async move {
+            process_webhook_job(
+                http_client,
+                job,
+                &retry_policy,
+                &signer,
+                &tokens,
+                &destinations,
+                read_body,
+            )
+            .await
+        };
 
         futures.push(future);
     }
 
-    let results = join_all(futures).await;
+    // Results of batched jobs follow those of single jobs, in the same order as their metadata.
+    let mut group_futures = Vec::with_capacity(groups.len());
+    for mut group in groups {
+        let http_client = http_client.clone();
+        let retry_policy = retry_policy.clone();
+        let signer = signer.clone();
+        let tokens = tokens.clone();
+        let destinations = destinations.clone();
+
+        metadata_vec.extend(group.iter_mut().map(|job| job.take_metadata()));
+
+        let read_body = hog_mode;
+        group_futures.push(async move {
+            process_webhook_job_group(
+                http_client,
+                group,
+                &retry_policy,
+                &signer,
+                &tokens,
+                &destinations,
+                read_body,
+            )
+            .await
+        });
+    }
+
+    let (mut results, group_results) = futures::join!(join_all(futures), join_all(group_futures));
+    results.extend(group_results.into_iter().flatten());
 
     if hog_mode
         && push_hoghook_results_to_kafka(
@@ -386,6 +479,21 @@ async fn create_hoghook_kafka_payload(
 
                 object.insert("asyncFunctionResponse".to_owned(), async_function_response);
             }
+            WebhookResult::InvalidResponse(response, error) => {
+                let async_function_response = json!({
+                    "timings": [{
+                        "kind": "async_function",
+                        "duration_ms": response.duration.as_millis().try_into().unwrap_or(u32::MAX)
+                    }],
+                    "response": {
+                        "status": response.status_code.as_u16(),
+                        "body": response.body
+                    },
+                    "error": error,
+                });
+
+                object.insert("asyncFunctionResponse".to_owned(), async_function_response);
+            }
             WebhookResult::Error(error) => {
                 let async_function_response = json!({
                     "error": error,
@@ -404,15 +512,17 @@ async fn create_hoghook_kafka_payload(
     Some(serde_json::to_string(&metadata).expect("unable to serialize metadata"))
 }
 
-struct WebhookResponse {
-    duration: Duration,
-    status_code: StatusCode,
-    body: Option<String>,
+pub(crate) struct WebhookResponse {
+    pub(crate) duration: Duration,
+    pub(crate) status_code: StatusCode,
+    pub(crate) body: Option<String>,
 }
 
-enum WebhookResult {
+pub(crate) enum WebhookResult {
     Success(WebhookResponse),
     BadResponse(WebhookResponse),
+    /// A response that didn't meet the job's validation, and why.
+    InvalidResponse(WebhookResponse, String),
     WillRetry,
     Error(String),
 }
@@ -426,15 +536,27 @@ enum WebhookResult {
 /// 1. The job has attempts remaining (i.e. hasn't reached `max_attempts`), and...
 /// 2. The status code indicates retrying at a later point could resolve the issue. This means: 429 and any 5XX.
 ///
+/// Jobs with options that can't be used together, or with a template that can't be filled in, are failed without
+/// sending a request.
+///
 /// # Arguments
 ///
 /// * `client`: An HTTP client to execute the webhook job request.
-/// * `webhook_job`: The webhook job to process as dequeued from `hook_common::pgqueue::PgQueue`.
+/// * `webhook_job`: The webhook job to process as dequeued from a `hook_common::queue::JobQueue`.
 /// * `retry_policy`: The retry policy used to set retry parameters if a job fails and has remaining attempts.
+/// * `signer`: Signs the request if its destination has signing secrets.
+/// * `tokens`: Provides bearer tokens for jobs with an auth descriptor. Jobs for which no token can
+///   be obtained are retried if the token endpoint may recover, and failed otherwise.
+/// * `destinations`: Per-target concurrency caps and circuit breakers. Jobs for a saturated target
+///   are deferred without counting an attempt, and jobs for a target with an open circuit breaker
+///   are retried (or failed if out of attempts) without sending the request.
 async fn process_webhook_job<W: WebhookJob>(
     http_client: reqwest::Client,
     webhook_job: W,
     retry_policy: &RetryPolicy,
+    signer: &WebhookSigner,
+    tokens: &TokenProvider,
+    destinations: &DestinationLimiter,
     read_body: bool,
 ) -> Result<WebhookResult, WorkerError> {
     let parameters = webhook_job.parameters();
@@ -442,17 +564,121 @@ async fn process_webhook_job<W: WebhookJob>(
     let labels = [("queue", webhook_job.queue())];
     metrics::counter!("webhook_jobs_total", &labels).increment(1);
 
+    // Jobs that can't be sent as they are would fail every attempt, so are failed right away.
+    let rendered = match parameters
+        .check()
+        .and_then(|_| render_parameters(&webhook_job))
+    {
+        Ok(rendered) => rendered,
+        Err(message) => {
+            let error = WebhookJobError::new_parse(&message);
+            return fail_job(webhook_job, error, WebhookResult::Error(message), &labels).await;
+        }
+    };
+    let parameters = rendered.as_ref().unwrap_or(parameters);
+
+    let target = webhook_job.target();
+    // Hold the permit (if any) until we are done with the request, including reading the body.
+    let _permit = match destinations.admit(&target) {
+        Admission::Allowed(permit) => permit,
+        Admission::Saturated => {
+            let defer_interval = retry_policy.retry_interval(1, None);
+            webhook_job.defer(defer_interval).await.inspect_err(|_| {
+                metrics::counter!("webhook_jobs_database_error", &labels).increment(1);
+            })?;
+
+            metrics::counter!("webhook_jobs_deferred", &labels).increment(1);
+
+            return Ok(WebhookResult::WillRetry);
+        }
+        Admission::CircuitOpen(remaining) => {
+            let error = WebhookJobError::new_circuit_open(&target);
+            let result =
+                retry_or_fail_job(webhook_job, retry_policy, error, Some(remaining), &labels)
+                    .await?;
+            if matches!(result, WebhookResult::WillRetry) {
+                metrics::counter!("webhook_jobs_short_circuited", &labels).increment(1);
+            }
+            return Ok(result);
+        }
+    };
+
     let now = tokio::time::Instant::now();
 
-    let send_result = send_webhook(
+    let send_result = send_authorized_webhook(
         http_client,
-        &parameters.method,
-        &parameters.url,
-        &parameters.headers,
+        parameters,
         parameters.body.clone(),
+        signer,
+        tokens,
     )
     .await;
 
+    match &send_result {
+        Ok(_)
+        | Err(WebhookError::Request(WebhookRequestError::NonRetryableRetryableRequestError {
+            ..
+        })) => destinations.record_success(&target),
+        Err(WebhookError::Request(WebhookRequestError::RetryableRequestError { .. })) => {
+            destinations.record_failure(&target)
+        }
+        // Nothing was sent to the target.
+        Err(WebhookError::Parse(_)) | Err(WebhookError::Auth(_)) => {}
+    }
+
+    let send_result = match &parameters.validation {
+        Some(validation) => match ReceivedResponse::from_send_result(send_result).await {
+            Ok(received) => {
+                let response = WebhookResponse {
+                    duration: now.elapsed(),
+                    status_code: received.status,
+                    body: received.body,
+                };
+
+                return match validate_response(
+                    validation,
+                    response.status_code,
+                    response.body.as_deref(),
+                ) {
+                    Verdict::Success => {
+                        let response = WebhookResponse {
+                            body: response.body.filter(|_| read_body),
+                            ..response
+                        };
+                        complete_job(webhook_job, response, &labels).await
+                    }
+                    Verdict::Retry(reason) => {
+                        let error = WebhookJobError::new_invalid_response(
+                            response.status_code.as_u16(),
+                            &reason,
+                        );
+                        let failed = WebhookResult::InvalidResponse(response, reason);
+                        retry_or_fail_job_with_result(
+                            webhook_job,
+                            retry_policy,
+                            error,
+                            received.retry_after,
+                            failed,
+                            &labels,
+                        )
+                        .await
+                    }
+                    Verdict::Fail(reason) => {
+                        let error = WebhookJobError::new_invalid_response(
+                            response.status_code.as_u16(),
+                            &reason,
+                        );
+                        let failed = WebhookResult::InvalidResponse(response, reason);
+                        fail_job(webhook_job, error, failed, &labels).await
+                    }
+                };
+            }
+            // No response was received, so there's nothing to validate.
+            Err(error) => Err(error),
+        },
+        None => send_result,
+    };
+
     match send_result {
         Ok(response) => {
             let status = response.status();
@@ -461,51 +687,11 @@ async fn process_webhook_job<W: WebhookJob>(
                 match first_n_bytes_of_response(response, MAX_RESPONSE_BODY).await {
                     Ok(body) => Some(body), // Once told me...
                     Err(_) => {
-                        // TODO: Consolidate this retry-or-fail logic which is mostly repeated below.
-                        let retry_interval =
-                            retry_policy.retry_interval(webhook_job.attempt() as u32, None);
-                        let current_queue = webhook_job.queue();
-                        let retry_queue = retry_policy.retry_queue(&current_queue);
-
-                        return match webhook_job
-                            .retry(
-                                WebhookJobError::new_timeout("timeout while reading response body"),
-                                retry_interval,
-                                retry_queue,
-                            )
-                            .await
-                        {
-                            Ok(_) => {
-                                metrics::counter!("webhook_jobs_retried", &labels).increment(1);
-
-                                Ok(WebhookResult::WillRetry)
-                            }
-                            Err(RetryError::RetryInvalidError(RetryInvalidError {
-                                job: webhook_job,
-                                ..
-                            })) => {
-                                webhook_job
-                                    .fail(WebhookJobError::new_timeout(
-                                        "timeout while reading response body",
-                                    ))
-                                    .await
-                                    .inspect_err(|_| {
-                                        metrics::counter!("webhook_jobs_database_error", &labels)
-                                            .increment(1)
-                                    })?;
-
-                                metrics::counter!("webhook_jobs_failed", &labels).increment(1);
-
-                                Ok(WebhookResult::Error(
-                                    "timeout while reading response body".to_owned(),
-                                ))
-                            }
-                            Err(RetryError::DatabaseError(job_error)) => {
-                                metrics::counter!("webhook_jobs_database_error", &labels)
-                                    .increment(1);
-                                Err(WorkerError::from(job_error))
-                            }
-                        };
+                        destinations.record_failure(&target);
+                        let error =
+                            WebhookJobError::new_timeout("timeout while reading response body");
+                        return retry_or_fail_job(webhook_job, retry_policy, error, None, &labels)
+                            .await;
                     }
                 }
             } else {
@@ -513,70 +699,30 @@ async fn process_webhook_job<W: WebhookJob>(
                 None
             };
 
-            let duration = now.elapsed();
-
-            let created_at = webhook_job.job().created_at;
-            let retries = webhook_job.job().attempt - 1;
-            let labels_with_retries = [
-                ("queue", webhook_job.queue()),
-                ("retries", retries.to_string()),
-            ];
-
-            webhook_job.complete().await.inspect_err(|_| {
-                metrics::counter!("webhook_jobs_database_error", &labels).increment(1);
-            })?;
-
-            let insert_to_complete_duration = Utc::now() - created_at;
-            metrics::histogram!(
-                "webhook_jobs_insert_to_complete_duration_seconds",
-                &labels_with_retries
-            )
-            .record((insert_to_complete_duration.num_milliseconds() as f64) / 1_000_f64);
-            metrics::counter!("webhook_jobs_completed", &labels).increment(1);
-            metrics::histogram!("webhook_jobs_processing_duration_seconds", &labels)
-                .record(duration.as_secs_f64());
-
-            Ok(WebhookResult::Success(WebhookResponse {
+            let response = WebhookResponse {
                 status_code: status,
-                duration,
+                duration: now.elapsed(),
                 body,
-            }))
-        }
-        Err(WebhookError::Parse(WebhookParseError::ParseHeadersError(e))) => {
-            webhook_job
-                .fail(WebhookJobError::new_parse(&e.to_string()))
-                .await
-                .inspect_err(|_| {
-                    metrics::counter!("webhook_jobs_database_error", &labels).increment(1);
-                })?;
-
-            metrics::counter!("webhook_jobs_failed", &labels).increment(1);
-
-            Ok(WebhookResult::Error(e.to_string()))
+            };
+            complete_job(webhook_job, response, &labels).await
         }
-        Err(WebhookError::Parse(WebhookParseError::ParseHttpMethodError(e))) => {
-            webhook_job
-                .fail(WebhookJobError::new_parse(&e))
-                .await
-                .inspect_err(|_| {
-                    metrics::counter!("webhook_jobs_database_error", &labels).increment(1)
-                })?;
-
-            metrics::counter!("webhook_jobs_failed", &labels).increment(1);
-
-            Ok(WebhookResult::Error(e.to_string()))
+        Err(WebhookError::Parse(parse_error)) => {
+            let message = match parse_error {
+                WebhookParseError::ParseHeadersError(e) => e.to_string(),
+                WebhookParseError::ParseHttpMethodError(e) => e.to_string(),
+                WebhookParseError::ParseUrlError(e) => e.to_string(),
+            };
+            let error = WebhookJobError::new_parse(&message);
+            fail_job(webhook_job, error, WebhookResult::Error(message), &labels).await
         }
-        Err(WebhookError::Parse(WebhookParseError::ParseUrlError(e))) => {
-            webhook_job
-                .fail(WebhookJobError::new_parse(&e.to_string()))
-                .await
-                .inspect_err(|_| {
-                    metrics::counter!("webhook_jobs_database_error", &labels).increment(1)
-                })?;
-
-            metrics::counter!("webhook_jobs_failed", &labels).increment(1);
-
-            Ok(WebhookResult::Error(e.to_string()))
+        Err(WebhookError::Auth(auth_error)) => {
+            let error = WebhookJobError::new_auth(&auth_error.to_string());
+            if auth_error.is_retryable() {
+                retry_or_fail_job(webhook_job, retry_policy, error, None, &labels).await
+            } else {
+                let result = WebhookResult::Error(auth_error.to_string());
+                fail_job(webhook_job, error, result, &labels).await
+            }
         }
         Err(WebhookError::Request(request_error)) => {
             let webhook_job_error = WebhookJobError::from(&request_error);
@@ -588,72 +734,205 @@ async fn process_webhook_job<W: WebhookJob>(
                     response, // Grab the response so we can send it back to hog for debug
                     ..
                 } => {
-                    let retry_interval =
-                        retry_policy.retry_interval(webhook_job.attempt() as u32, retry_after);
-                    let current_queue = webhook_job.queue();
-                    let retry_queue = retry_policy.retry_queue(&current_queue);
-
-                    match webhook_job
-                        .retry(webhook_job_error, retry_interval, retry_queue)
-                        .await
-                    {
-                        Ok(_) => {
-                            metrics::counter!("webhook_jobs_retried", &labels).increment(1);
-
-                            Ok(WebhookResult::WillRetry)
-                        }
-                        Err(RetryError::RetryInvalidError(RetryInvalidError {
-                            job: webhook_job,
-                            ..
-                        })) => {
-                            webhook_job
-                                .fail(WebhookJobError::from(&error))
-                                .await
-                                .inspect_err(|_| {
-                                    metrics::counter!("webhook_jobs_database_error", &labels)
-                                        .increment(1);
-                                })?;
-
-                            metrics::counter!("webhook_jobs_failed", &labels).increment(1);
-
-                            match error.status() {
-                                Some(status) => Ok(WebhookResult::BadResponse(WebhookResponse {
-                                    duration: now.elapsed(),
-                                    status_code: status,
-                                    body: response,
-                                })),
-                                None => Ok(WebhookResult::Error(error.to_string())),
-                            }
-                        }
-                        Err(RetryError::DatabaseError(job_error)) => {
-                            metrics::counter!("webhook_jobs_database_error", &labels).increment(1);
-                            Err(WorkerError::from(job_error))
-                        }
-                    }
+                    let failed = bad_response_result(&error, response, now.elapsed());
+                    retry_or_fail_job_with_result(
+                        webhook_job,
+                        retry_policy,
+                        webhook_job_error,
+                        retry_after,
+                        failed,
+                        &labels,
+                    )
+                    .await
                 }
                 WebhookRequestError::NonRetryableRetryableRequestError {
                     error, response, ..
                 } => {
-                    webhook_job.fail(webhook_job_error).await.inspect_err(|_| {
-                        metrics::counter!("webhook_jobs_database_error", &labels).increment(1);
-                    })?;
-
-                    metrics::counter!("webhook_jobs_failed", &labels).increment(1);
-
-                    match error.status() {
-                        Some(status) => Ok(WebhookResult::BadResponse(WebhookResponse {
-                            duration: now.elapsed(),
-                            status_code: status,
-                            body: response,
-                        })),
-                        None => Ok(WebhookResult::Error(error.to_string())),
-                    }
+                    let failed = bad_response_result(&error, response, now.elapsed());
+                    fail_job(webhook_job, webhook_job_error, failed, &labels).await
                 }
             }
         }
     }
 }
 
+/// The result of a request that failed, with the response if there was one.
+fn bad_response_result(
+    error: &reqwest::Error,
+    body: Option<String>,
+    duration: Duration,
+) -> WebhookResult {
+    match error.status() {
+        Some(status) => WebhookResult::BadResponse(WebhookResponse {
+            duration,
+            status_code: status,
+            body,
+        }),
+        None => WebhookResult::Error(error.to_string()),
+    }
+}
+
+pub(crate) async fn complete_job<W: WebhookJob>(
+    job: W,
+    response: WebhookResponse,
+    labels: &[(&'static str, String)],
+) -> Result<WebhookResult, WorkerError> {
+    let created_at = job.created_at();
+    let retries = job.attempt() - 1;
+    let labels_with_retries = [("queue", job.queue()), ("retries", retries.to_string())];
+
+    job.complete().await.inspect_err(|_| {
+        metrics::counter!("webhook_jobs_database_error", labels).increment(1);
+    })?;
+
+    let insert_to_complete_duration = Utc::now() - created_at;
+    metrics::histogram!(
+        "webhook_jobs_insert_to_complete_duration_seconds",
+        &labels_with_retries
+    )
+    .record((insert_to_complete_duration.num_milliseconds() as f64) / 1_000_f64);
+    metrics::counter!("webhook_jobs_completed", labels).increment(1);
+    metrics::histogram!("webhook_jobs_processing_duration_seconds", labels)
+        .record(response.duration.as_secs_f64());
+
+    Ok(WebhookResult::Success(response))
+}
+
+pub(crate) async fn fail_job<W: WebhookJob>(
+    job: W,
+    error: WebhookJobError,
+    result: WebhookResult,
+    labels: &[(&'static str, String)],
+) -> Result<WebhookResult, WorkerError> {
+    job.fail(error).await.inspect_err(|_| {
+        metrics::counter!("webhook_jobs_database_error", labels).increment(1);
+    })?;
+
+    metrics::counter!("webhook_jobs_failed", labels).increment(1);
+
+    Ok(result)
+}
+
+pub(crate) async fn retry_or_fail_job<W: WebhookJob>(
+    job: W,
+    retry_policy: &RetryPolicy,
+    error: WebhookJobError,
+    retry_after: Option<time::Duration>,
+    labels: &[(&'static str, String)],
+) -> Result<WebhookResult, WorkerError> {
+    let message = error.details.error.message.clone().unwrap_or_default();
+    retry_or_fail_job_with_result(
+        job,
+        retry_policy,
+        error,
+        retry_after,
+        WebhookResult::Error(message),
+        labels,
+    )
+    .await
+}
+
+/// Retry a job, or fail it with `failed` as its result if it's out of attempts.
+pub(crate) async fn retry_or_fail_job_with_result<W: WebhookJob>(
+    job: W,
+    retry_policy: &RetryPolicy,
+    error: WebhookJobError,
+    retry_after: Option<time::Duration>,
+    failed: WebhookResult,
+    labels: &[(&'static str, String)],
+) -> Result<WebhookResult, WorkerError> {
+    let retry_interval = retry_policy.retry_interval(job.attempt() as u32, retry_after);
+    let current_queue = job.queue();
+    let retry_queue = retry_policy.retry_queue(&current_queue);
+
+    match job.retry(error.clone(), retry_interval, retry_queue).await {
+        Ok(_) => {
+            metrics::counter!("webhook_jobs_retried", labels).increment(1);
+
+            Ok(WebhookResult::WillRetry)
+        }
+        Err(RetryError::RetryInvalidError(RetryInvalidError { job, .. })) => {
+            fail_job(*job, error, failed, labels).await
+        }
+        Err(RetryError::DatabaseError(job_error)) => {
+            metrics::counter!("webhook_jobs_database_error", labels).increment(1);
+            Err(WorkerError::from(job_error))
+        }
+    }
+}
+
+/// Send a webhook, with a bearer token if the job has an auth descriptor. A 401 response means the
+/// token was revoked or expired early, so a new token is fetched and the request sent once more.
+///
+/// # Arguments
+///
+/// * `client`: An HTTP client to execute the HTTP request, and token requests.
+/// * `parameters`: The job's parameters, used for everything but the body.
+/// * `body`: The body of the request, which differs from the job's for batched requests.
+/// * `signer`: Adds signature headers if the destination has signing secrets.
+/// * `tokens`: Provides bearer tokens.
+pub(crate) async fn send_authorized_webhook(
+    client: reqwest::Client,
+    parameters: &WebhookJobParameters,
+    body: String,
+    signer: &WebhookSigner,
+    tokens: &TokenProvider,
+) -> Result<reqwest::Response, WebhookError> {
+    let Some(auth) = &parameters.auth else {
+        return send_webhook(
+            client,
+            &parameters.method,
+            &parameters.url,
+            &parameters.headers,
+            body,
+            signer,
+        )
+        .await;
+    };
+
+    let token = tokens.token(&client, auth, None).await?;
+    let result = send_webhook(
+        client.clone(),
+        &parameters.method,
+        &parameters.url,
+        &with_bearer_token(&parameters.headers, &token),
+        body.clone(),
+        signer,
+    )
+    .await;
+
+    match result {
+        Err(WebhookError::Request(error)) if error.status() == Some(StatusCode::UNAUTHORIZED) => {
+            metrics::counter!("webhook_requests_reauthorized").increment(1);
+
+            let token = tokens.token(&client, auth, Some(&token)).await?;
+            send_webhook(
+                client,
+                &parameters.method,
+                &parameters.url,
+                &with_bearer_token(&parameters.headers, &token),
+                body,
+                signer,
+            )
+            .await
+        }
+        result => result,
+    }
+}
+
+fn with_bearer_token(
+    headers: &collections::HashMap<String, String>,
+    token: &str,
+) -> collections::HashMap<String, String> {
+    let mut headers: collections::HashMap<String, String> = headers
+        .iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case("authorization"))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    headers.insert("Authorization".to_owned(), format!("Bearer {}", token));
+    headers
+}
+
 /// Make an HTTP request to a webhook endpoint.
 ///
 /// # Arguments
@@ -663,18 +942,21 @@ async fn process_webhook_job<W: WebhookJob>(
 /// * `url`: The URL we are targetting with our request. Parsing this URL fail.
 /// * `headers`: Key, value pairs of HTTP headers in a `std::collections::HashMap`. Can fail if headers are not valid.
 /// * `body`: The body of the request. Ownership is required.
-async fn send_webhook(
+/// * `signer`: Adds signature headers if the destination has signing secrets.
+pub(crate) async fn send_webhook(
     client: reqwest::Client,
     method: &HttpMethod,
     url: &str,
     headers: &collections::HashMap<String, String>,
     body: String,
+    signer: &WebhookSigner,
 ) -> Result<reqwest::Response, WebhookError> {
     let method: http::Method = method.into();
     let url: reqwest::Url = (url).parse().map_err(WebhookParseError::ParseUrlError)?;
-    let headers: reqwest::header::HeaderMap = (headers)
+    let mut headers: reqwest::header::HeaderMap = (headers)
         .try_into()
         .map_err(WebhookParseError::ParseHeadersError)?;
+    signer.sign(&url, body.as_bytes(), Utc::now().timestamp(), &mut headers);
     let body = reqwest::Body::from(body);
 
     let response = client
@@ -734,7 +1016,7 @@ async fn send_webhook(
     }
 }
 
-fn is_retryable_status(status: StatusCode) -> bool {
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
     status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
 }
 
@@ -745,7 +1027,9 @@ fn is_retryable_status(status: StatusCode) -> bool {
 /// # Arguments
 ///
 /// * `header_map`: A `&reqwest::HeaderMap` of response headers that could contain Retry-After.
-fn parse_retry_after_header(header_map: &reqwest::header::HeaderMap) -> Option<time::Duration> {
+pub(crate) fn parse_retry_after_header(
+    header_map: &reqwest::header::HeaderMap,
+) -> Option<time::Duration> {
     let retry_after_header = header_map.get(reqwest::header::RETRY_AFTER);
 
     let retry_after = match retry_after_header {
@@ -785,8 +1069,10 @@ mod tests {
     // See: https://github.com/rust-lang/rust/issues/46379.
     use common_kafka::test::create_mock_kafka;
     use health::HealthRegistry;
-    use hook_common::pgqueue::{DatabaseError, NewJob};
-    use hook_common::webhook::WebhookJobMetadata;
+    use hook_common::pgqueue::{DatabaseError, NewJob, PgQueue, PgTransactionBatch};
+    use hook_common::webhook::{
+        WebhookBatchParameters, WebhookJobMetadata, WebhookResponseValidation,
+    };
     use sqlx::PgPool;
 
     /// Use process id as a worker id for tests.
@@ -852,6 +1138,10 @@ mod tests {
             headers: collections::HashMap::new(),
             method: HttpMethod::POST,
             url: "localhost".to_owned(),
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
         };
         let webhook_job_metadata = WebhookJobMetadata {
             team_id: 1,
@@ -883,15 +1173,22 @@ mod tests {
             time::Duration::from_millis(100),
             time::Duration::from_millis(5000),
             10,
+            time::Duration::from_millis(1000),
             RetryPolicy::default(),
             false,
             mock_producer,
             "cdp_function_callbacks".to_string(),
             hog_mode,
+            WebhookSigner::default(),
+            TokenProvider::default(),
+            DestinationLimiter::default(),
             liveness,
         );
 
-        let mut batch = worker.wait_for_jobs_tx().await;
+        let mut batch = worker
+            .wait_for_jobs(&mut std::future::pending())
+            .await
+            .unwrap();
         let consumed_job = batch.jobs.pop().unwrap();
 
         assert_eq!(consumed_job.job.attempt, 1);
@@ -940,11 +1237,15 @@ mod tests {
             time::Duration::from_millis(100),
             time::Duration::from_millis(5000),
             10,
+            time::Duration::from_millis(1000),
             RetryPolicy::default(),
             false,
             mock_producer,
             topic.to_string(),
             hog_mode,
+            WebhookSigner::default(),
+            TokenProvider::default(),
+            DestinationLimiter::default(),
             liveness,
         );
 
@@ -962,6 +1263,10 @@ mod tests {
             headers: collections::HashMap::new(),
             method: HttpMethod::POST,
             url: server.url("/200"),
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
         };
 
         enqueue_job(
@@ -973,15 +1278,23 @@ mod tests {
         .await
         .expect("failed to enqueue job");
 
-        let batch = worker.wait_for_jobs_tx().await;
+        let mut batch = worker
+            .wait_for_jobs(&mut std::future::pending())
+            .await
+            .unwrap();
+        let jobs = batch.take_jobs();
 
         process_batch(
             batch,
+            jobs,
             worker.http_client.clone(),
             worker.retry_policy.clone(),
             worker.kafka_producer.clone(),
             worker.cdp_function_callbacks_topic,
             hog_mode,
+            worker.signer.clone(),
+            worker.tokens.clone(),
+            worker.destinations.clone(),
         )
         .await;
 
@@ -1056,11 +1369,15 @@ mod tests {
             time::Duration::from_millis(100),
             time::Duration::from_millis(5000),
             10,
+            time::Duration::from_millis(1000),
             RetryPolicy::default(),
             false,
             mock_producer,
             topic.to_string(),
             hog_mode,
+            WebhookSigner::default(),
+            TokenProvider::default(),
+            DestinationLimiter::default(),
             liveness,
         );
 
@@ -1078,6 +1395,10 @@ mod tests {
             headers: collections::HashMap::new(),
             method: HttpMethod::POST,
             url: server.url("/500"),
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
         };
 
         enqueue_job(
@@ -1089,15 +1410,23 @@ mod tests {
         .await
         .expect("failed to enqueue job");
 
-        let batch = worker.wait_for_jobs_tx().await;
+        let mut batch = worker
+            .wait_for_jobs(&mut std::future::pending())
+            .await
+            .unwrap();
+        let jobs = batch.take_jobs();
 
         process_batch(
             batch,
+            jobs,
             worker.http_client.clone(),
             worker.retry_policy.clone(),
             worker.kafka_producer.clone(),
             worker.cdp_function_callbacks_topic,
             hog_mode,
+            worker.signer.clone(),
+            worker.tokens.clone(),
+            worker.destinations.clone(),
         )
         .await;
 
@@ -1145,6 +1474,105 @@ mod tests {
         );
     }
 
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_hoghook_retries_unsuccessful_200(db: PgPool) {
+        use httpmock::prelude::*;
+
+        let worker_id = worker_id();
+        let queue_name = "test_hoghook_retries_unsuccessful_200".to_string();
+        let queue = PgQueue::new_from_pool(&queue_name, db.clone()).await;
+        let topic = "cdp_function_callbacks";
+
+        let server = MockServer::start();
+
+        let registry = HealthRegistry::new("liveness");
+        let liveness = registry
+            .register("worker".to_string(), ::time::Duration::seconds(30))
+            .await;
+
+        let (_mock_cluster, mock_producer) = create_mock_kafka().await;
+        let hog_mode = true;
+        let worker = WebhookWorker::new(
+            &worker_id,
+            &queue,
+            1,
+            time::Duration::from_millis(100),
+            time::Duration::from_millis(5000),
+            10,
+            time::Duration::from_millis(1000),
+            RetryPolicy::default(),
+            false,
+            mock_producer,
+            topic.to_string(),
+            hog_mode,
+            WebhookSigner::default(),
+            TokenProvider::default(),
+            DestinationLimiter::default(),
+            liveness,
+        );
+
+        server.mock(|when, then| {
+            when.method(POST).path("/ok");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"ok": false}"#);
+        });
+
+        let webhook_job_parameters = WebhookJobParameters {
+            body: "".to_owned(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            url: server.url("/ok"),
+            batch: None,
+            auth: None,
+            validation: Some(
+                serde_json::from_value(json!({
+                    "assertions": [{"path": "$.ok", "equals": true, "retryable": true}]
+                }))
+                .unwrap(),
+            ),
+            template: None,
+        };
+
+        enqueue_job(
+            &queue,
+            2,
+            webhook_job_parameters,
+            serde_json::to_value(json!({"someOtherField": true})).unwrap(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let mut batch = worker
+            .wait_for_jobs(&mut std::future::pending())
+            .await
+            .unwrap();
+        let jobs = batch.take_jobs();
+
+        process_batch(
+            batch,
+            jobs,
+            worker.http_client.clone(),
+            worker.retry_policy.clone(),
+            worker.kafka_producer.clone(),
+            worker.cdp_function_callbacks_topic,
+            hog_mode,
+            worker.signer.clone(),
+            worker.tokens.clone(),
+            worker.destinations.clone(),
+        )
+        .await;
+
+        // The job is back in the queue for another attempt, rather than completed.
+        let (status, attempt): (String, i32) =
+            sqlx::query_as("SELECT status::text, attempt FROM job_queue")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(status, "available");
+        assert_eq!(attempt, 1);
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn test_hoghook_drops_large_payloads(db: PgPool) {
         use httpmock::prelude::*;
@@ -1170,6 +1598,10 @@ mod tests {
             headers: collections::HashMap::new(),
             method: HttpMethod::POST,
             url: mock_url,
+            batch: None,
+            auth: None,
+            validation: None,
+            template: None,
         };
 
         let webhook_job_metadata = json!({"hugeField": "a".repeat(2 * 1024 * 1024)});
@@ -1197,27 +1629,204 @@ mod tests {
             time::Duration::from_millis(100),
             time::Duration::from_millis(5000),
             10,
+            time::Duration::from_millis(1000),
             RetryPolicy::default(),
             false,
             mock_producer,
             topic.to_string(),
             hog_mode,
+            WebhookSigner::default(),
+            TokenProvider::default(),
+            DestinationLimiter::default(),
             liveness,
         );
 
-        let batch = worker.wait_for_jobs_tx().await;
+        let mut batch = worker
+            .wait_for_jobs(&mut std::future::pending())
+            .await
+            .unwrap();
+        let jobs = batch.take_jobs();
 
         process_batch(
             batch,
+            jobs,
             worker.http_client,
             worker.retry_policy,
             worker.kafka_producer,
             worker.cdp_function_callbacks_topic,
             hog_mode,
+            worker.signer,
+            worker.tokens,
+            worker.destinations,
         )
         .await;
     }
 
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_validation_retries_unsuccessful_200(db: PgPool) {
+        use httpmock::prelude::*;
+
+        let queue = PgQueue::new_from_pool("test_validation", db).await;
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/ok-false");
+            then.status(200).json_body(json!({"ok": false}));
+        });
+
+        let validation = serde_json::from_value(json!({
+            "assertions": [{"path": "$.ok", "equals": true, "retryable": true}],
+        }))
+        .unwrap();
+        let parameters = WebhookJobParameters {
+            body: "{}".to_owned(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            url: server.url("/ok-false"),
+            batch: None,
+            auth: None,
+            validation: Some(validation),
+            template: None,
+        };
+        enqueue_job(&queue, 2, parameters, json!({}))
+            .await
+            .expect("failed to enqueue job");
+
+        let mut batch: PgTransactionBatch<'_, WebhookJobParameters, Value> = queue
+            .dequeue_tx(&worker_id(), 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+
+        let result = process_webhook_job(
+            localhost_client(),
+            batch.jobs.pop().unwrap(),
+            &RetryPolicy::default(),
+            &WebhookSigner::default(),
+            &TokenProvider::default(),
+            &DestinationLimiter::default(),
+            true,
+        )
+        .await
+        .expect("failed to process job");
+        batch.commit().await.expect("failed to commit batch");
+
+        mock.assert();
+        assert!(matches!(result, WebhookResult::WillRetry));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_template_is_filled_in_when_sent(db: PgPool) {
+        use httpmock::prelude::*;
+
+        let queue = PgQueue::new_from_pool("test_template", db).await;
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/templated")
+                .header("x-idempotency-key", "order-42-1")
+                .json_body(json!({"order": 42, "attempt": 1}));
+            then.status(200);
+        });
+
+        let template = serde_json::from_value(json!({"variables": {"order": 42}})).unwrap();
+        let parameters = WebhookJobParameters {
+            body: r#"{"order": {{ variables.order }}, "attempt": {{ job.attempt }}}"#.to_owned(),
+            headers: collections::HashMap::from([(
+                "X-Idempotency-Key".to_owned(),
+                "order-{{ variables.order }}-{{ job.attempt }}".to_owned(),
+            )]),
+            method: HttpMethod::POST,
+            url: server.url("/templated"),
+            batch: None,
+            auth: None,
+            validation: None,
+            template: Some(template),
+        };
+        enqueue_job(&queue, 2, parameters, json!({}))
+            .await
+            .expect("failed to enqueue job");
+
+        let mut batch: PgTransactionBatch<'_, WebhookJobParameters, Value> = queue
+            .dequeue_tx(&worker_id(), 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+
+        let result = process_webhook_job(
+            localhost_client(),
+            batch.jobs.pop().unwrap(),
+            &RetryPolicy::default(),
+            &WebhookSigner::default(),
+            &TokenProvider::default(),
+            &DestinationLimiter::default(),
+            false,
+        )
+        .await
+        .expect("failed to process job");
+        batch.commit().await.expect("failed to commit batch");
+
+        mock.assert();
+        assert!(matches!(result, WebhookResult::Success(_)));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_batched_jobs_with_validation_are_failed(db: PgPool) {
+        use httpmock::prelude::*;
+
+        let queue = PgQueue::new_from_pool("test_batch_validation", db).await;
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/batch");
+            then.status(200);
+        });
+
+        let parameters = WebhookJobParameters {
+            body: "{}".to_owned(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            url: server.url("/batch"),
+            batch: Some(WebhookBatchParameters {
+                key: "key".to_owned(),
+                max_size: 10,
+                linger_ms: 0,
+            }),
+            auth: None,
+            validation: Some(WebhookResponseValidation::default()),
+            template: None,
+        };
+        enqueue_job(&queue, 2, parameters, json!({}))
+            .await
+            .expect("failed to enqueue job");
+
+        let mut batch: PgTransactionBatch<'_, WebhookJobParameters, Value> = queue
+            .dequeue_tx(&worker_id(), 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+
+        // It isn't grouped with other batched jobs, and is failed without being sent
+        let (singles, groups) = group_batched_jobs(std::mem::take(&mut batch.jobs));
+        assert!(groups.is_empty());
+
+        let result = process_webhook_job(
+            localhost_client(),
+            singles.into_iter().next().unwrap(),
+            &RetryPolicy::default(),
+            &WebhookSigner::default(),
+            &TokenProvider::default(),
+            &DestinationLimiter::default(),
+            false,
+        )
+        .await
+        .expect("failed to process job");
+        batch.commit().await.expect("failed to commit batch");
+
+        mock.assert_hits(0);
+        assert!(
+            matches!(result, WebhookResult::Error(message) if message == "batched jobs can't set validation")
+        );
+    }
+
     #[tokio::test]
     async fn test_send_webhook() {
         let method = HttpMethod::POST;
@@ -1225,9 +1834,16 @@ mod tests {
         let headers = collections::HashMap::new();
         let body = "a very relevant request body";
 
-        let response = send_webhook(localhost_client(), &method, url, &headers, body.to_owned())
-            .await
-            .expect("send_webhook failed");
+        let response = send_webhook(
+            localhost_client(),
+            &method,
+            url,
+            &headers,
+            body.to_owned(),
+            &WebhookSigner::default(),
+        )
+        .await
+        .expect("send_webhook failed");
 
         assert_eq!(response.status(), StatusCode::OK);
         assert_eq!(
@@ -1236,6 +1852,110 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_send_webhook_signs_configured_destinations() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/signed")
+                .header_exists("webhook-timestamp")
+                .header_exists("webhook-signature");
+            then.status(200);
+        });
+
+        let signer = WebhookSigner::new("127.0.0.1:secret").unwrap();
+        let headers = collections::HashMap::new();
+
+        let response = send_webhook(
+            localhost_client(),
+            &HttpMethod::POST,
+            &server.url("/signed"),
+            &headers,
+            "{}".to_owned(),
+            &signer,
+        )
+        .await
+        .expect("send_webhook failed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_send_authorized_webhook_refreshes_rejected_tokens() {
+        use hook_common::webhook::WebhookAuth;
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let mut token_mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(200)
+                .json_body(json!({"access_token": "revoked", "expires_in": 3600}));
+        });
+        let rejected = server.mock(|when, then| {
+            when.method(POST)
+                .path("/hook")
+                .header("authorization", "Bearer revoked");
+            then.status(401);
+        });
+        let accepted = server.mock(|when, then| {
+            when.method(POST)
+                .path("/hook")
+                .header("authorization", "Bearer fresh");
+            then.status(200);
+        });
+
+        let client = localhost_client();
+        let tokens = TokenProvider::new(
+            &format!("dest=id:secret,{}", server.url("/token")),
+            Duration::ZERO,
+        )
+        .unwrap();
+        let auth = WebhookAuth::Oauth2ClientCredentials {
+            scopes: Vec::new(),
+            credentials: "dest".to_owned(),
+        };
+        // Cache the token that is about to be revoked.
+        tokens.token(&client, &auth, None).await.unwrap();
+        token_mock.delete();
+        let token_mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(200)
+                .json_body(json!({"access_token": "fresh", "expires_in": 3600}));
+        });
+
+        let parameters = WebhookJobParameters {
+            body: "{}".to_owned(),
+            headers: collections::HashMap::from([(
+                "authorization".to_owned(),
+                "Bearer static".to_owned(),
+            )]),
+            method: HttpMethod::POST,
+            url: server.url("/hook"),
+            batch: None,
+            auth: Some(auth),
+            validation: None,
+            template: None,
+        };
+
+        let response = send_authorized_webhook(
+            client,
+            &parameters,
+            parameters.body.clone(),
+            &WebhookSigner::default(),
+            &tokens,
+        )
+        .await
+        .expect("send_authorized_webhook failed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        rejected.assert_hits(1);
+        accepted.assert_hits(1);
+        token_mock.assert_hits(1);
+    }
+
     #[tokio::test]
     async fn test_error_message_contains_response_body() {
         let method = HttpMethod::POST;
@@ -1243,9 +1963,16 @@ mod tests {
         let headers = collections::HashMap::new();
         let body = "this is an error message";
 
-        let err = send_webhook(localhost_client(), &method, url, &headers, body.to_owned())
-            .await
-            .expect_err("request didn't fail when it should have failed");
+        let err = send_webhook(
+            localhost_client(),
+            &method,
+            url,
+            &headers,
+            body.to_owned(),
+            &WebhookSigner::default(),
+        )
+        .await
+        .expect_err("request didn't fail when it should have failed");
 
         assert!(matches!(err, WebhookError::Request(..)));
         if let WebhookError::Request(request_error) = err {
@@ -1268,9 +1995,16 @@ mod tests {
         // TODO: Make this configurable and change it here too.
         let body = (0..512 * 1024).map(|_| "a").collect::<Vec<_>>().concat();
 
-        let err = send_webhook(localhost_client(), &method, url, &headers, body.to_owned())
-            .await
-            .expect_err("request didn't fail when it should have failed");
+        let err = send_webhook(
+            localhost_client(),
+            &method,
+            url,
+            &headers,
+            body.to_owned(),
+            &WebhookSigner::default(),
+        )
+        .await
+        .expect_err("request didn't fail when it should have failed");
 
         assert!(matches!(err, WebhookError::Request(..)));
         if let WebhookError::Request(request_error) = err {
@@ -1295,9 +2029,16 @@ mod tests {
         let filtering_client =
             build_http_client(Duration::from_secs(1), false).expect("failed to create client");
 
-        let err = send_webhook(filtering_client, &method, url, &headers, body.to_owned())
-            .await
-            .expect_err("request didn't fail when it should have failed");
+        let err = send_webhook(
+            filtering_client,
+            &method,
+            url,
+            &headers,
+            body.to_owned(),
+            &WebhookSigner::default(),
+        )
+        .await
+        .expect_err("request didn't fail when it should have failed");
 
         assert!(matches!(err, WebhookError::Request(..)));
         if let WebhookError::Request(request_error) = err {
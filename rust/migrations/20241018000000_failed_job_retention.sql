@@ -0,0 +1,6 @@
+-- Set by the janitor once a failed job has been reported to app_metrics, so that failed jobs can be
+-- retained (and replayed) for a while without being reported again.
+ALTER TABLE job_queue ADD COLUMN reported_at TIMESTAMPTZ DEFAULT NULL;
+
+-- Needed to list failed jobs (dead letters) and to delete them once their retention expires.
+CREATE INDEX idx_queue_failed_partial ON job_queue(queue, last_attempt_finished_at) WHERE status = 'failed' :: job_status;